@@ -0,0 +1,133 @@
+//! Lark ambiguity report
+//!
+//! The complexity analysis already points out rule shapes that parse the
+//! same text several ways; this report adds the lexer's side, which depends
+//! on the tokenizer. Two lexemes overlap when some text matches both, like a
+//! keyword and an identifier terminal: the lexer keeps both alive until the
+//! parser decides, which multiplies the work per token and can accept text
+//! under the lexeme it was not meant for. Each pair of lexemes of a grammar
+//! is checked by compiling their intersection; the tokens its first mask
+//! allows are the ones both lexemes compete for. Pairs are reported whether
+//! or not the parser can expect both at the same position.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use llguidance::api::TopLevelGrammar;
+use llguidance::toktrie::{TokTrie, TokenId};
+use llguidance::{Matcher, ParserFactory};
+
+use crate::analysis;
+
+/// Lexeme pairs checked at most; each compiles a grammar and computes a mask
+const MAX_PAIRS: usize = 400;
+/// Tokens listed per overlapping pair
+const MAX_EXAMPLE_TOKENS: usize = 5;
+/// Competing tokens listed
+const MAX_COMPETING_TOKENS: usize = 20;
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct AmbiguityReport {
+    /// Rules that can parse the same text several ways, with the reason
+    ambiguous_rules: Vec<String>,
+    /// Pairs of lexemes that some text matches both of
+    overlapping_terminals: Vec<Overlap>,
+    /// Tokens that start text matching several lexemes, most contested first
+    competing_tokens: Vec<CompetingToken>,
+    /// Whether some pairs went unchecked, past MAX_PAIRS
+    truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Overlap {
+    terminals: [String; 2],
+    /// Some tokens that can start text matching both
+    tokens: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct CompetingToken {
+    token: String,
+    /// Lexemes that text starting with the token can match, in pairs that overlap
+    lexemes: Vec<String>,
+}
+
+impl AmbiguityReport {
+    pub(crate) fn collect(
+        factory: &ParserFactory,
+        trie: &TokTrie,
+        grammar: &TopLevelGrammar,
+    ) -> Self {
+        let mut report = AmbiguityReport {
+            ambiguous_rules: analysis::ambiguous_rules(grammar),
+            ..Default::default()
+        };
+        let mut competing: BTreeMap<TokenId, Vec<String>> = BTreeMap::new();
+        let mut pairs = 0;
+        for lexemes in analysis::lark_lexemes(grammar) {
+            for (i, a) in lexemes.iter().enumerate() {
+                for b in &lexemes[i + 1..] {
+                    // Different literals never match the same text
+                    if a.literal.is_some() && b.literal.is_some() && a.literal != b.literal {
+                        continue;
+                    }
+                    if pairs == MAX_PAIRS {
+                        report.truncated = true;
+                        continue;
+                    }
+                    pairs += 1;
+                    let tokens = overlap(factory, trie, &a.lark, &b.lark);
+                    if tokens.is_empty() {
+                        continue;
+                    }
+                    for &token in &tokens {
+                        let lexemes = competing.entry(token).or_default();
+                        for label in [&a.label, &b.label] {
+                            if !lexemes.contains(label) {
+                                lexemes.push(label.clone());
+                            }
+                        }
+                    }
+                    report.overlapping_terminals.push(Overlap {
+                        terminals: [a.label.clone(), b.label.clone()],
+                        tokens: tokens
+                            .iter()
+                            .take(MAX_EXAMPLE_TOKENS)
+                            .map(|&token| crate::display_token(trie.token(token)))
+                            .collect(),
+                    });
+                }
+            }
+        }
+
+        // Stable, so equally contested tokens stay in ID order
+        let mut competing: Vec<_> = competing.into_iter().collect();
+        competing.sort_by_key(|(_, lexemes)| Reverse(lexemes.len()));
+        report.competing_tokens = competing
+            .into_iter()
+            .take(MAX_COMPETING_TOKENS)
+            .map(|(token, lexemes)| CompetingToken {
+                token: crate::display_token(trie.token(token)),
+                lexemes,
+            })
+            .collect();
+        report
+    }
+}
+
+/// Tokens other than EOS that can start text matching both Lark expressions
+fn overlap(factory: &ParserFactory, trie: &TokTrie, a: &str, b: &str) -> Vec<TokenId> {
+    let grammar = TopLevelGrammar::from_lark(format!("start: OVERLAP\nOVERLAP: ({}) & ({})", a, b));
+    // An intersection the compiler rejects leaves the pair unreported; the
+    // grammar's own compile errors surface when it is loaded
+    let mut matcher = Matcher::new(factory.create_parser(grammar));
+    let Ok(mask) = matcher.compute_mask() else {
+        return Vec::new();
+    };
+    let eos = trie.eos_token();
+    (0..trie.vocab_size() as TokenId)
+        .filter(|&token| token != eos && mask.is_allowed(token))
+        .collect()
+}
@@ -0,0 +1,994 @@
+//! Grammar complexity report
+//!
+//! A static estimate of how expensive a grammar is to enforce, read from its
+//! source rather than measured by running it: how many rules and lexemes it
+//! has, roughly how many NFA states its lexer needs, which rules are
+//! recursive, and shapes that are known to make Earley parsing ambiguous or
+//! the lexer DFA large. Lark grammars are parsed loosely (the compiler
+//! reports real syntax errors); JSON schemas are measured by their subschemas.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use llguidance::api::TopLevelGrammar;
+
+/// Lexer size from which a grammar is `high` cost
+const HIGH_LEXER_STATES: usize = 20_000;
+/// Lexer size from which a grammar is at least `medium` cost
+const MEDIUM_LEXER_STATES: usize = 1_000;
+/// A counted repetition this large after an unbounded one can blow up the lexer DFA
+const DFA_BLOWUP_COUNT: usize = 8;
+/// States assumed for a JSON string, number or other built-in lexeme
+const JSON_LEXEME_STATES: usize = 16;
+/// Terminal references inlined within one another before giving up
+const MAX_INLINE_DEPTH: usize = 32;
+
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct GrammarAnalysis {
+    /// Grammar rules (nonterminals, or subschemas of a JSON schema)
+    rules: usize,
+    /// Named Lark terminals
+    terminals: usize,
+    /// Distinct lexemes the lexer matches, named or inline
+    lexemes: usize,
+    /// Estimated NFA states of all lexemes together
+    lexer_states: usize,
+    /// Rules that reference themselves, directly or through other rules
+    recursive_rules: Vec<String>,
+    /// Shapes likely to make parsing ambiguous or the lexer large
+    hints: Vec<String>,
+    /// `low`: no recursion and a small lexer, so each step is cheap;
+    /// `medium`: recursive or a larger lexer; `high`: any hint, or a lexer
+    /// large enough to make the first masks slow
+    cost_class: &'static str,
+}
+
+/// Analyze every grammar of `grammar`, summing the counts
+pub(crate) fn analyze(grammar: &TopLevelGrammar) -> GrammarAnalysis {
+    let mut analysis = GrammarAnalysis::default();
+    for (i, g) in grammar.grammars.iter().enumerate() {
+        let prefix = label_prefix(grammar, i);
+        if let Some(lark) = &g.lark_grammar {
+            analysis.lark(lark, &prefix);
+        } else if let Some(schema) = &g.json_schema {
+            analysis.schema(schema, &prefix);
+        }
+    }
+    analysis.cost_class = if !analysis.hints.is_empty()
+        || analysis.lexer_states >= HIGH_LEXER_STATES
+    {
+        "high"
+    } else if !analysis.recursive_rules.is_empty() || analysis.lexer_states >= MEDIUM_LEXER_STATES {
+        "medium"
+    } else {
+        "low"
+    };
+    analysis
+}
+
+/// The hints about rules that parse the same text several ways
+pub(crate) fn ambiguous_rules(grammar: &TopLevelGrammar) -> Vec<String> {
+    analyze(grammar)
+        .hints
+        .into_iter()
+        .filter(|hint| hint.starts_with("Rule "))
+        .collect()
+}
+
+/// A lexeme of a Lark grammar, as a Lark terminal expression
+pub(crate) struct Lexeme {
+    /// Terminal name, or the inline literal or regex, prefixed like the analysis
+    pub label: String,
+    /// The expression with the terminals it references inlined
+    pub lark: String,
+    /// The text of a lexeme that is a single literal
+    pub literal: Option<String>,
+}
+
+/// The lexemes of each Lark grammar of `grammar`: named terminals used by a
+/// rule or `%ignore`, inline literals and regexes, and ignored expressions
+/// Lexemes that cannot be written as one expression (`%json`, references to
+/// other grammars) are left out
+pub(crate) fn lark_lexemes(grammar: &TopLevelGrammar) -> Vec<Vec<Lexeme>> {
+    let mut grammars = Vec::new();
+    for (i, g) in grammar.grammars.iter().enumerate() {
+        let Some(lark) = &g.lark_grammar else {
+            continue;
+        };
+        let prefix = label_prefix(grammar, i);
+        let (definitions, ignored) = definitions(&tokenize(lark));
+        let (terminals, rules) = split_definitions(&definitions);
+
+        let used: BTreeSet<&str> = rules
+            .values()
+            .copied()
+            .chain(&ignored)
+            .flat_map(Expr::names)
+            .collect();
+        let mut inline = BTreeMap::new();
+        for body in rules.values() {
+            body.inline_lexemes(&mut inline);
+        }
+        let named = terminals
+            .iter()
+            .filter(|(name, _)| used.contains(*name))
+            .map(|(name, expr)| (name.to_string(), *expr));
+        // `%ignore WS` is the named terminal
+        let ignored = ignored
+            .iter()
+            .enumerate()
+            .filter(|(_, expr)| !matches!(expr, Expr::Name(_)))
+            .map(|(n, expr)| (format!("%ignore{}", n), expr));
+        let lexemes = named
+            .chain(inline.iter().map(|(text, expr)| (text.clone(), *expr)))
+            .chain(ignored)
+            .filter_map(|(label, expr)| {
+                let lark = expr.to_lark(&terminals, 0)?;
+                let literal = match expr {
+                    Expr::Literal(text) => Some(text.clone()),
+                    _ => None,
+                };
+                Some(Lexeme {
+                    label: format!("{}{}", prefix, label),
+                    lark,
+                    literal,
+                })
+            })
+            .collect();
+        grammars.push(lexemes);
+    }
+    grammars
+}
+
+/// The prefix naming grammar `i` in labels, when there are several
+fn label_prefix(grammar: &TopLevelGrammar, i: usize) -> String {
+    match (grammar.grammars.len() > 1, &grammar.grammars[i].name) {
+        (false, _) => String::new(),
+        (true, Some(name)) => format!("{}/", name),
+        (true, None) => format!("{}/", i),
+    }
+}
+
+/// Lark terminals (upper-case names) and rules, by name
+fn split_definitions(
+    definitions: &[(String, Expr)],
+) -> (BTreeMap<&str, &Expr>, BTreeMap<&str, &Expr>) {
+    definitions
+        .iter()
+        .map(|(name, body)| (name.as_str(), body))
+        .partition(|(name, _)| name.starts_with(|c: char| c.is_ascii_uppercase()))
+}
+
+impl GrammarAnalysis {
+    fn hint(&mut self, hint: String) {
+        if !self.hints.contains(&hint) {
+            self.hints.push(hint);
+        }
+    }
+
+    fn lark(&mut self, lark: &str, prefix: &str) {
+        let (definitions, ignored) = definitions(&tokenize(lark));
+        let (terminals, rules) = split_definitions(&definitions);
+        self.rules += rules.len();
+        self.terminals += terminals.len();
+
+        let mut sizes = TerminalSizes {
+            terminals: &terminals,
+            sizes: BTreeMap::new(),
+            visiting: BTreeSet::new(),
+        };
+        let mut inline = BTreeMap::new();
+        for body in rules.values() {
+            body.inline_lexemes(&mut inline);
+        }
+        self.lexemes += terminals.len() + inline.len() + ignored.len();
+        for name in terminals.keys() {
+            self.lexer_states += sizes.terminal(name);
+        }
+        for expr in inline.values().copied().chain(&ignored) {
+            self.lexer_states += sizes.expr(expr);
+        }
+        for (lexeme, expr) in terminals
+            .iter()
+            .map(|(name, expr)| (name.to_string(), *expr))
+            .chain(inline.iter().map(|(text, expr)| (text.clone(), *expr)))
+        {
+            if let Some(count) = expr.dfa_blowup() {
+                self.hint(format!(
+                    "Lexeme {}{} repeats something {} times after an unbounded repetition; \
+                     its lexer DFA can grow exponentially",
+                    prefix, lexeme, count
+                ));
+            }
+        }
+
+        for (name, body) in &rules {
+            let mut reached = BTreeSet::new();
+            let mut pending: Vec<&str> = body.names();
+            while let Some(next) = pending.pop() {
+                if reached.insert(next) {
+                    if let Some(body) = rules.get(next) {
+                        pending.extend(body.names());
+                    }
+                }
+            }
+            if reached.contains(name) {
+                self.recursive_rules.push(format!("{}{}", prefix, name));
+            }
+            self.rule_hints(&format!("{}{}", prefix, name), name, body);
+        }
+
+        let mut schemas = Vec::new();
+        for body in rules.values() {
+            body.embedded_schemas(&mut schemas);
+        }
+        for (i, schema) in schemas.iter().enumerate() {
+            self.schema(schema, &format!("{}%json{}/", prefix, i));
+        }
+    }
+
+    fn rule_hints(&mut self, label: &str, name: &str, body: &Expr) {
+        let alternatives = match body {
+            Expr::Alt(alternatives) => alternatives.as_slice(),
+            body => std::slice::from_ref(body),
+        };
+        for (i, alternative) in alternatives.iter().enumerate() {
+            if alternatives[..i].contains(alternative) {
+                self.hint(format!(
+                    "Rule {} has the same alternative twice, so every match is ambiguous",
+                    label
+                ));
+            }
+            if let Expr::Seq(items) = alternative {
+                let is_self =
+                    |item: Option<&Expr>| matches!(item, Some(Expr::Name(n)) if n == name);
+                if items.len() > 2 && is_self(items.first()) && is_self(items.last()) {
+                    self.hint(format!(
+                        "Rule {} is both left and right recursive, so chains like a op b op c \
+                         parse several ways; use one rule per precedence level",
+                        label
+                    ));
+                }
+            }
+        }
+        let mut found = Vec::new();
+        body.repetition_hints(&mut found);
+        for hint in found {
+            self.hint(format!("Rule {} {}", label, hint));
+        }
+    }
+
+    fn schema(&mut self, schema: &Value, prefix: &str) {
+        let mut refs = BTreeMap::new();
+        self.subschema(schema, "#", prefix, &mut refs);
+
+        // A definition is recursive if its own references lead back to it
+        for start in refs.keys() {
+            let mut reached = BTreeSet::new();
+            let mut pending: Vec<&String> = refs[start].iter().collect();
+            while let Some(next) = pending.pop() {
+                if reached.insert(next) {
+                    if let Some(targets) = refs.get(next) {
+                        pending.extend(targets);
+                    }
+                }
+            }
+            if reached.contains(start) {
+                self.recursive_rules.push(format!("{}{}", prefix, start));
+            }
+        }
+    }
+
+    /// Count the subschema at `pointer`, recording the `$ref`s reachable from
+    /// each definition (and the root) without passing through another one
+    fn subschema(
+        &mut self,
+        schema: &Value,
+        pointer: &str,
+        prefix: &str,
+        refs: &mut BTreeMap<String, BTreeSet<String>>,
+    ) {
+        let Some(object) = schema.as_object() else {
+            return;
+        };
+        self.rules += 1;
+        let owner = if pointer == "#" || is_definition(pointer) {
+            refs.entry(pointer.to_string()).or_default();
+            pointer.to_string()
+        } else {
+            // The nearest enclosing definition, or the root
+            refs.keys()
+                .filter(|key| pointer.starts_with(&format!("{}/", key)))
+                .max_by_key(|key| key.len())
+                .cloned()
+                .unwrap_or_else(|| "#".to_string())
+        };
+        if let Some(target) = object.get("$ref").and_then(Value::as_str) {
+            refs.entry(owner).or_default().insert(target.to_string());
+        }
+
+        if let Some(pattern) = object.get("pattern").and_then(Value::as_str) {
+            let size = regex_size(pattern);
+            self.lexemes += 1;
+            self.lexer_states += size.states;
+            if let Some(count) = size.blowup {
+                self.hint(format!(
+                    "Pattern at {}{} repeats something {} times after an unbounded \
+                     repetition; its lexer DFA can grow exponentially",
+                    prefix, pointer, count
+                ));
+            }
+        } else if let Some(values) = object.get("enum").and_then(Value::as_array) {
+            self.lexemes += values.len();
+            self.lexer_states += values.iter().map(|v| v.to_string().len()).sum::<usize>();
+        } else if let Some(value) = object.get("const") {
+            self.lexemes += 1;
+            self.lexer_states += value.to_string().len();
+        } else if let Some(types) = object.get("type") {
+            let types = types
+                .as_array()
+                .map_or(std::slice::from_ref(types), Vec::as_slice);
+            let lexemes = types
+                .iter()
+                .filter(|t| t.as_str().is_some_and(|t| t != "object" && t != "array"))
+                .count();
+            self.lexemes += lexemes;
+            self.lexer_states += lexemes * JSON_LEXEME_STATES;
+        }
+
+        for key in ["properties", "patternProperties", "$defs", "definitions"] {
+            if let Some(members) = object.get(key).and_then(Value::as_object) {
+                for (name, member) in members {
+                    if key == "properties" {
+                        // The quoted property name is a lexeme of its own
+                        self.lexemes += 1;
+                        self.lexer_states += name.len() + 2;
+                    }
+                    let pointer = format!("{}/{}/{}", pointer, key, escape_pointer(name));
+                    self.subschema(member, &pointer, prefix, refs);
+                }
+            }
+        }
+        for key in [
+            "items",
+            "additionalProperties",
+            "additionalItems",
+            "contains",
+            "not",
+            "if",
+            "then",
+            "else",
+        ] {
+            match object.get(key) {
+                // Draft 4 tuple items
+                Some(Value::Array(members)) => {
+                    for (i, member) in members.iter().enumerate() {
+                        let pointer = format!("{}/{}/{}", pointer, key, i);
+                        self.subschema(member, &pointer, prefix, refs);
+                    }
+                }
+                Some(member) => {
+                    let pointer = format!("{}/{}", pointer, key);
+                    self.subschema(member, &pointer, prefix, refs);
+                }
+                None => {}
+            }
+        }
+        for key in ["anyOf", "oneOf", "allOf", "prefixItems"] {
+            if let Some(members) = object.get(key).and_then(Value::as_array) {
+                for (i, member) in members.iter().enumerate() {
+                    let pointer = format!("{}/{}/{}", pointer, key, i);
+                    self.subschema(member, &pointer, prefix, refs);
+                }
+            }
+        }
+    }
+}
+
+/// Whether `pointer` names a definition, e.g. `#/$defs/Node`
+fn is_definition(pointer: &str) -> bool {
+    ["#/$defs/", "#/definitions/"].iter().any(|defs| {
+        pointer
+            .strip_prefix(defs)
+            .is_some_and(|name| !name.contains('/'))
+    })
+}
+
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+/// Lark grammar tokens, as far as the analysis needs them
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Str(String),
+    Regex(String),
+    Number(usize),
+    Op(char),
+    /// `..` in `~ n..m` and `"a".."z"`
+    Range,
+    /// `%json {...}`, with the JSON text
+    Json(String),
+    /// A reference to another grammar or a special token, e.g. `@name`, `<|eos|>`
+    External,
+    Directive(String),
+    Newline,
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    // The index of the first `end` at or after `from`, skipping escaped characters
+    let until = |from: usize, end: char| {
+        let mut j = from;
+        while j < chars.len() && chars[j] != end {
+            j += if chars[j] == '\\' { 2 } else { 1 };
+        }
+        j.min(chars.len())
+    };
+    let word = |from: usize| {
+        let mut j = from;
+        while j < chars.len() && (chars[j].is_alphanumeric() || chars[j] == '_') {
+            j += 1;
+        }
+        j
+    };
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\n' => {
+                tokens.push(Token::Newline);
+                i += 1;
+            }
+            c if c.is_whitespace() => i += 1,
+            '/' if chars.get(i + 1) == Some(&'/') => i = until(i, '\n'),
+            '"' | '/' => {
+                let end = until(i + 1, c);
+                let body: String = chars[i + 1..end].iter().collect();
+                tokens.push(if c == '"' {
+                    Token::Str(body)
+                } else {
+                    Token::Regex(body)
+                });
+                // Flags such as "if"i or /x/s
+                i = word(end + 1);
+            }
+            '%' => {
+                let end = word(i + 1);
+                let name: String = chars[i + 1..end].iter().collect();
+                i = end;
+                while i < chars.len() && chars[i] == ' ' {
+                    i += 1;
+                }
+                if chars.get(i) == Some(&'{') {
+                    let start = i;
+                    let mut depth = 0;
+                    while i < chars.len() {
+                        match chars[i] {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            '"' => i = until(i + 1, '"'),
+                            _ => {}
+                        }
+                        i += 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    let body: String = chars[start..i.min(chars.len())].iter().collect();
+                    tokens.push(if name == "json" {
+                        Token::Json(body)
+                    } else {
+                        Token::External
+                    });
+                } else {
+                    tokens.push(Token::Directive(name));
+                }
+            }
+            '@' => {
+                tokens.push(Token::External);
+                i = word(i + 1);
+            }
+            '<' => {
+                tokens.push(Token::External);
+                i = until(i + 1, '>') + 1;
+            }
+            '.' if chars.get(i + 1) == Some(&'.') => {
+                tokens.push(Token::Range);
+                i += 2;
+            }
+            c if c.is_ascii_digit() => {
+                let end = word(i);
+                let digits: String = chars[i..end].iter().collect();
+                tokens.push(Token::Number(digits.parse().unwrap_or(usize::MAX)));
+                i = end;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let end = word(i);
+                tokens.push(Token::Name(chars[i..end].iter().collect()));
+                i = end;
+            }
+            c => {
+                tokens.push(Token::Op(c));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// The rules and terminals of a Lark grammar, and its `%ignore` expressions
+fn definitions(tokens: &[Token]) -> (Vec<(String, Expr)>, Vec<Expr>) {
+    let mut bodies: Vec<(String, Vec<Token>)> = Vec::new();
+    let mut ignored = Vec::new();
+    // Whether lines without a definition head continue the last definition
+    let mut continuing = false;
+    for line in tokens.split(|token| *token == Token::Newline) {
+        let head = match line {
+            [Token::Op('?' | '!'), rest @ ..] => rest,
+            line => line,
+        };
+        let head_len = match head {
+            [Token::Name(_), Token::Op(':'), ..] => Some(2),
+            [Token::Name(_), Token::Op('.'), Token::Number(_), Token::Op(':'), ..] => Some(4),
+            _ => None,
+        };
+        match (head, head_len) {
+            ([Token::Name(name), ..], Some(len)) => {
+                bodies.push((name.clone(), head[len..].to_vec()));
+                continuing = true;
+            }
+            ([Token::Directive(directive), rest @ ..], _) => {
+                if directive == "ignore" {
+                    ignored.push(Parser::parse(rest));
+                }
+                continuing = false;
+            }
+            _ if continuing => {
+                if let Some((_, body)) = bodies.last_mut() {
+                    body.extend_from_slice(line);
+                }
+            }
+            _ => {}
+        }
+    }
+    let definitions = bodies
+        .into_iter()
+        .map(|(name, body)| (name, Parser::parse(&body)))
+        .collect();
+    (definitions, ignored)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Alt(Vec<Expr>),
+    Seq(Vec<Expr>),
+    Repeat {
+        item: Box<Expr>,
+        min: usize,
+        max: Option<usize>,
+    },
+    Name(String),
+    Literal(String),
+    Regex(String),
+    Json(String),
+    External,
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(tokens: &'a [Token]) -> Expr {
+        let mut parser = Parser { tokens, pos: 0 };
+        let mut alternatives = vec![parser.alternatives()];
+        // Stray closing brackets; the compiler reports them
+        while parser.pos < tokens.len() {
+            parser.pos += 1;
+            alternatives.push(parser.alternatives());
+        }
+        match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Expr::Seq(alternatives),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn alternatives(&mut self) -> Expr {
+        let mut alternatives = vec![self.sequence()];
+        while self.peek() == Some(&Token::Op('|')) {
+            self.pos += 1;
+            alternatives.push(self.sequence());
+        }
+        match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Expr::Alt(alternatives),
+        }
+    }
+
+    fn sequence(&mut self) -> Expr {
+        let mut items = Vec::new();
+        while let Some(token) = self.peek() {
+            if matches!(token, Token::Op('|' | ')' | ']')) {
+                break;
+            }
+            if let Some(atom) = self.atom() {
+                items.push(self.quantified(atom));
+            }
+        }
+        match items.len() {
+            1 => items.pop().unwrap(),
+            _ => Expr::Seq(items),
+        }
+    }
+
+    fn atom(&mut self) -> Option<Expr> {
+        let token = self.peek()?;
+        self.pos += 1;
+        Some(match token {
+            Token::Op(open @ ('(' | '[')) => {
+                let inner = self.alternatives();
+                if matches!(self.peek(), Some(Token::Op(')' | ']'))) {
+                    self.pos += 1;
+                }
+                match open {
+                    '(' => inner,
+                    _ => Expr::Repeat {
+                        item: Box::new(inner),
+                        min: 0,
+                        max: Some(1),
+                    },
+                }
+            }
+            Token::Str(text) => {
+                if let [Token::Range, Token::Str(end), ..] = &self.tokens[self.pos..] {
+                    // "a".."z" is a single character class
+                    self.pos += 2;
+                    Expr::Regex(format!("[{}-{}]", text, end))
+                } else {
+                    Expr::Literal(text.clone())
+                }
+            }
+            Token::Name(name) => Expr::Name(name.clone()),
+            Token::Regex(rx) => Expr::Regex(rx.clone()),
+            Token::Json(json) => Expr::Json(json.clone()),
+            Token::External => Expr::External,
+            _ => return None,
+        })
+    }
+
+    fn quantified(&mut self, mut expr: Expr) -> Expr {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(Token::Op('*')) => (0, None),
+                Some(Token::Op('+')) => (1, None),
+                Some(Token::Op('?')) => (0, Some(1)),
+                Some(Token::Op('~')) => {
+                    let count = |token: Option<&Token>| match token {
+                        Some(Token::Number(n)) => Some(*n),
+                        _ => None,
+                    };
+                    let Some(min) = count(self.tokens.get(self.pos + 1)) else {
+                        return expr;
+                    };
+                    if self.tokens.get(self.pos + 2) == Some(&Token::Range) {
+                        let max = count(self.tokens.get(self.pos + 3)).unwrap_or(min);
+                        self.pos += 3;
+                        (min, Some(max))
+                    } else {
+                        self.pos += 1;
+                        (min, Some(min))
+                    }
+                }
+                _ => return expr,
+            };
+            self.pos += 1;
+            expr = Expr::Repeat {
+                item: Box::new(expr),
+                min,
+                max,
+            };
+        }
+    }
+}
+
+impl Expr {
+    fn children(&self) -> &[Expr] {
+        match self {
+            Expr::Alt(items) | Expr::Seq(items) => items,
+            Expr::Repeat { item, .. } => std::slice::from_ref(item),
+            _ => &[],
+        }
+    }
+
+    /// Names referenced anywhere in the expression
+    fn names(&self) -> Vec<&str> {
+        match self {
+            Expr::Name(name) => vec![name.as_str()],
+            expr => expr.children().iter().flat_map(Expr::names).collect(),
+        }
+    }
+
+    /// Literals and regexes used directly in a rule, each its own lexeme
+    fn inline_lexemes<'a>(&'a self, lexemes: &mut BTreeMap<String, &'a Expr>) {
+        match self {
+            Expr::Literal(text) => {
+                lexemes.insert(format!("{:?}", text), self);
+            }
+            Expr::Regex(rx) => {
+                lexemes.insert(format!("/{}/", rx), self);
+            }
+            expr => expr
+                .children()
+                .iter()
+                .for_each(|child| child.inline_lexemes(lexemes)),
+        }
+    }
+
+    /// Schemas of `%json {...}` in the expression; the compiler reports invalid ones
+    fn embedded_schemas(&self, schemas: &mut Vec<Value>) {
+        match self {
+            Expr::Json(json) => {
+                if let Ok(schema) = serde_json::from_str(json) {
+                    schemas.push(schema);
+                }
+            }
+            expr => expr
+                .children()
+                .iter()
+                .for_each(|child| child.embedded_schemas(schemas)),
+        }
+    }
+
+    /// Whether the expression can match the empty string, judging rules by
+    /// their name alone (as non-empty)
+    fn nullable(&self) -> bool {
+        match self {
+            Expr::Alt(items) => items.iter().any(Expr::nullable),
+            Expr::Seq(items) => items.iter().all(Expr::nullable),
+            Expr::Repeat { item, min, .. } => *min == 0 || item.nullable(),
+            Expr::Literal(text) => text.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// Repetitions that can match the same text in many ways
+    fn repetition_hints(&self, hints: &mut Vec<String>) {
+        if let Expr::Repeat {
+            item, max: None, ..
+        } = self
+        {
+            if item.nullable() || matches!(**item, Expr::Repeat { max: None, .. }) {
+                hints.push(
+                    "repeats a sub-expression that can itself repeat or be empty, so the \
+                     same text parses in many ways"
+                        .to_string(),
+                );
+                return;
+            }
+        }
+        if let Expr::Seq(items) = self {
+            for pair in items.windows(2) {
+                if let [Expr::Repeat {
+                    item: a, max: None, ..
+                }, Expr::Repeat {
+                    item: b, max: None, ..
+                }] = pair
+                {
+                    if a == b {
+                        hints.push(
+                            "has two adjacent repetitions of the same item, so the split \
+                             between them is ambiguous"
+                                .to_string(),
+                        );
+                    }
+                }
+            }
+        }
+        for child in self.children() {
+            child.repetition_hints(hints);
+        }
+    }
+
+    /// The expression in Lark syntax with referenced terminals inlined, or
+    /// None if it references a rule, a grammar or a `%json` schema
+    fn to_lark(&self, terminals: &BTreeMap<&str, &Expr>, depth: usize) -> Option<String> {
+        let join = |items: &[Expr], separator: &str| {
+            items
+                .iter()
+                .map(|item| item.to_lark(terminals, depth))
+                .collect::<Option<Vec<_>>>()
+                .map(|items| format!("({})", items.join(separator)))
+        };
+        Some(match self {
+            Expr::Alt(items) => join(items, " | ")?,
+            Expr::Seq(items) => join(items, " ")?,
+            Expr::Repeat { item, min, max } => {
+                let item = format!("({})", item.to_lark(terminals, depth)?);
+                match (min, max) {
+                    (0, None) => format!("{}*", item),
+                    (1, None) => format!("{}+", item),
+                    (min, None) => format!("({} ~ {} {}*)", item, min, item),
+                    (0, Some(1)) => format!("{}?", item),
+                    (min, Some(max)) if min == max => format!("{} ~ {}", item, min),
+                    (min, Some(max)) => format!("{} ~ {}..{}", item, min, max),
+                }
+            }
+            // A recursive terminal is an error the compiler reports
+            Expr::Name(name) if depth < MAX_INLINE_DEPTH => terminals
+                .get(name.as_str())?
+                .to_lark(terminals, depth + 1)?,
+            Expr::Literal(text) => format!("\"{}\"", text),
+            Expr::Regex(rx) => format!("/{}/", rx),
+            Expr::Name(_) | Expr::Json(_) | Expr::External => return None,
+        })
+    }
+
+    /// The largest count repeated after an unbounded repetition, if it is
+    /// large enough to blow up a DFA
+    fn dfa_blowup(&self) -> Option<usize> {
+        match self {
+            Expr::Regex(rx) => regex_size(rx).blowup,
+            expr => expr.children().iter().filter_map(Expr::dfa_blowup).max(),
+        }
+    }
+}
+
+/// Estimated lexer sizes of named terminals, memoized
+struct TerminalSizes<'a> {
+    terminals: &'a BTreeMap<&'a str, &'a Expr>,
+    sizes: BTreeMap<String, usize>,
+    visiting: BTreeSet<String>,
+}
+
+impl TerminalSizes<'_> {
+    fn terminal(&mut self, name: &str) -> usize {
+        if let Some(&size) = self.sizes.get(name) {
+            return size;
+        }
+        let Some(&body) = self.terminals.get(name) else {
+            return 0;
+        };
+        // A recursive terminal is an error the compiler reports
+        if !self.visiting.insert(name.to_string()) {
+            return 0;
+        }
+        let size = self.expr(body);
+        self.visiting.remove(name);
+        self.sizes.insert(name.to_string(), size);
+        size
+    }
+
+    fn expr(&mut self, expr: &Expr) -> usize {
+        match expr {
+            Expr::Alt(items) | Expr::Seq(items) => items.iter().map(|item| self.expr(item)).sum(),
+            Expr::Repeat { item, min, max } => {
+                self.expr(item).saturating_mul(max.unwrap_or(*min).max(1))
+            }
+            Expr::Name(name) => self.terminal(name),
+            Expr::Literal(text) => text.chars().count().max(1),
+            Expr::Regex(rx) => regex_size(rx).states,
+            Expr::Json(_) | Expr::External => 0,
+        }
+    }
+}
+
+struct RegexSize {
+    /// Estimated NFA states
+    states: usize,
+    /// Largest counted repetition after an unbounded one, if at least DFA_BLOWUP_COUNT
+    blowup: Option<usize>,
+}
+
+/// Estimate the NFA size of a regex: one state per character or class,
+/// with counted repetitions copying what they repeat
+fn regex_size(rx: &str) -> RegexSize {
+    let chars: Vec<char> = rx.chars().collect();
+    // Per open group: states so far, and states of the last atom
+    let mut groups: Vec<(usize, usize)> = vec![(0, 0)];
+    let mut unbounded = false;
+    let mut blowup: Option<usize> = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let atom = match chars[i] {
+            '\\' => {
+                i += 1;
+                if matches!(chars.get(i), Some('x' | 'u' | 'p' | 'P'))
+                    && chars.get(i + 1) == Some(&'{')
+                {
+                    while i < chars.len() && chars[i] != '}' {
+                        i += 1;
+                    }
+                }
+                Some(1)
+            }
+            '[' => {
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '\\' => i += 1,
+                        '[' => depth += 1,
+                        ']' if depth == 1 && chars[i - 1] == '[' => {}
+                        ']' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                    i += 1;
+                }
+                Some(1)
+            }
+            '(' => {
+                groups.push((0, 0));
+                if chars.get(i + 1) == Some(&'?') {
+                    while i < chars.len() && chars[i] != ':' && chars[i] != ')' {
+                        i += 1;
+                    }
+                }
+                None
+            }
+            ')' if groups.len() > 1 => groups.pop().map(|(states, _)| states),
+            '|' => {
+                groups.last_mut().unwrap().1 = 0;
+                None
+            }
+            '*' | '+' => {
+                unbounded = true;
+                None
+            }
+            '?' | '^' | '$' => None,
+            '{' => {
+                let end = chars[i..].iter().position(|&c| c == '}').map(|n| i + n);
+                let body: Option<String> = end.map(|end| chars[i + 1..end].iter().collect());
+                let bounds = body.as_deref().and_then(|body| {
+                    let (min, max) = body.split_once(',').unwrap_or((body, body));
+                    let min: usize = min.trim().parse().ok()?;
+                    let max = max.trim();
+                    match max.is_empty() {
+                        true => Some((min, None)),
+                        false => Some((min, Some(max.parse::<usize>().ok()?))),
+                    }
+                });
+                match (bounds, end) {
+                    (Some((min, max)), Some(end)) => {
+                        let count = max.unwrap_or(min).max(1);
+                        let group = groups.last_mut().unwrap();
+                        group.0 = group.0.saturating_add(group.1.saturating_mul(count - 1));
+                        if unbounded && count >= DFA_BLOWUP_COUNT {
+                            blowup = blowup.max(Some(count));
+                        }
+                        unbounded |= max.is_none();
+                        i = end;
+                        None
+                    }
+                    _ => Some(1),
+                }
+            }
+            _ => Some(1),
+        };
+        if let Some(states) = atom {
+            let group = groups.last_mut().unwrap();
+            group.0 = group.0.saturating_add(states);
+            group.1 = states;
+        }
+        i += 1;
+    }
+    RegexSize {
+        states: groups
+            .iter()
+            .map(|(states, _)| states)
+            .sum::<usize>()
+            .max(1),
+        blowup,
+    }
+}
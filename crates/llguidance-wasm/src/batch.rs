@@ -0,0 +1,142 @@
+//! Batched mask computation for parallel sequences
+//!
+//! Generating several sequences for one prompt (e.g. `num_return_sequences`)
+//! needs one mask per sequence each step. `ParserBatch` keeps one parser per
+//! sequence and returns all masks in a single buffer, so the JS/WASM call
+//! overhead is paid once per step rather than once per sequence. For beam
+//! search, `fork` branches a sequence that keeps sharing its parent's lexer
+//! caches and current mask, so only the work after the branch is repeated.
+
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+
+use crate::LLGuidanceParser;
+
+/// Independent parsers sharing one grammar, tokenizer and set of options
+#[wasm_bindgen]
+pub struct ParserBatch {
+    parsers: Vec<LLGuidanceParser>,
+}
+
+#[wasm_bindgen]
+impl ParserBatch {
+    /// Create `size` sequences, each starting at the beginning of `parser`'s grammar
+    #[wasm_bindgen(constructor)]
+    pub fn new(parser: &LLGuidanceParser, size: usize) -> Result<ParserBatch, JsValue> {
+        if size == 0 {
+            return Err(JsValue::from_str(
+                "A parser batch needs at least one sequence",
+            ));
+        }
+        Ok(ParserBatch {
+            parsers: (0..size).map(|_| parser.fresh_copy()).collect(),
+        })
+    }
+
+    /// Number of sequences in the batch
+    #[wasm_bindgen]
+    pub fn len(&self) -> usize {
+        self.parsers.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.parsers.is_empty()
+    }
+
+    /// Length of each sequence's mask
+    #[wasm_bindgen]
+    pub fn vocab_size(&self) -> usize {
+        self.parsers[0].vocab_size
+    }
+
+    /// Get the masks of all sequences, concatenated in sequence order
+    /// The mask of sequence `i` starts at `i * vocab_size()`
+    #[wasm_bindgen]
+    pub fn get_token_masks(&mut self) -> Result<Uint8Array, JsValue> {
+        let vocab_size = self.vocab_size();
+        let mut masks = Vec::with_capacity(vocab_size * self.parsers.len());
+        for parser in &mut self.parsers {
+            parser.fill_mask_buf()?;
+            masks.extend_from_slice(&parser.mask_buf);
+        }
+
+        let js_array = Uint8Array::new_with_length(masks.len() as u32);
+        js_array.copy_from(&masks);
+        Ok(js_array)
+    }
+
+    /// Advance every sequence by its sampled token, one token per sequence
+    #[wasm_bindgen]
+    pub fn advance(&mut self, token_ids: &[u32]) -> Result<(), JsValue> {
+        if token_ids.len() != self.parsers.len() {
+            return Err(JsValue::from_str(&format!(
+                "Expected {} tokens, one per sequence, but got {}",
+                self.parsers.len(),
+                token_ids.len()
+            )));
+        }
+        for (parser, &token_id) in self.parsers.iter_mut().zip(token_ids) {
+            parser.advance(token_id)?;
+        }
+        Ok(())
+    }
+
+    /// Advance a single sequence, e.g. when the others have already finished
+    #[wasm_bindgen]
+    pub fn advance_sequence(&mut self, index: usize, token_id: u32) -> Result<(), JsValue> {
+        self.sequence(index)?.advance(token_id)
+    }
+
+    /// Un-consume the last `num_tokens` tokens of a single sequence
+    #[wasm_bindgen]
+    pub fn rollback(&mut self, index: usize, num_tokens: usize) -> Result<(), JsValue> {
+        self.sequence(index)?.rollback(num_tokens)
+    }
+
+    /// Check whether a sequence is a valid complete parse
+    #[wasm_bindgen]
+    pub fn is_complete(&mut self, index: usize) -> Result<bool, JsValue> {
+        Ok(self.sequence(index)?.is_complete())
+    }
+
+    /// Branch a sequence at its current position, e.g. to split a beam
+    /// Returns the index of the new sequence, which is appended to the batch
+    #[wasm_bindgen]
+    pub fn fork(&mut self, index: usize) -> Result<usize, JsValue> {
+        let fork = self.sequence(index)?.fork_state();
+        self.parsers.push(fork);
+        Ok(self.parsers.len() - 1)
+    }
+
+    /// Drop a sequence, e.g. a pruned beam; later sequences move down one index
+    #[wasm_bindgen]
+    pub fn remove(&mut self, index: usize) -> Result<(), JsValue> {
+        self.sequence(index)?;
+        if self.parsers.len() == 1 {
+            return Err(JsValue::from_str(
+                "Cannot remove the last sequence of a parser batch",
+            ));
+        }
+        self.parsers.remove(index);
+        Ok(())
+    }
+
+    /// Restart every sequence at the beginning of the grammar
+    #[wasm_bindgen]
+    pub fn reset(&mut self) {
+        for parser in &mut self.parsers {
+            *parser = parser.fresh_copy();
+        }
+    }
+
+    fn sequence(&mut self, index: usize) -> Result<&mut LLGuidanceParser, JsValue> {
+        let len = self.parsers.len();
+        self.parsers.get_mut(index).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Sequence {} is out of range for a batch of {}",
+                index, len
+            ))
+        })
+    }
+}
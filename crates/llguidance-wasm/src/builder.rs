@@ -0,0 +1,62 @@
+//! Incremental vocabulary loading
+//!
+//! Copying hundreds of thousands of token strings across the JS/WASM boundary
+//! in one call blocks the main thread. `TokEnvBuilder` accepts the vocabulary
+//! in chunks so the caller can yield to the event loop between them.
+
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+use crate::{tokenizer, LLGuidanceParser};
+
+/// Collects token byte strings chunk by chunk, then builds a parser
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct TokEnvBuilder {
+    words: Vec<Vec<u8>>,
+}
+
+#[wasm_bindgen]
+impl TokEnvBuilder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> TokEnvBuilder {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        TokEnvBuilder::default()
+    }
+
+    /// Append the next tokens, continuing from the last token ID added
+    #[wasm_bindgen]
+    pub fn add_chunk(&mut self, tokens: Array) {
+        self.words.reserve(tokens.length() as usize);
+        self.words
+            .extend(tokens.iter().map(|token| Uint8Array::new(&token).to_vec()));
+    }
+
+    /// Number of tokens added so far
+    #[wasm_bindgen]
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Build the token trie and a parser for the grammar, consuming the builder
+    #[wasm_bindgen]
+    pub fn finish(
+        self,
+        eos_token_id: u32,
+        grammar_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        tokenizer::tok_env_from_words(self.words, eos_token_id)
+            .and_then(|tok_env| {
+                LLGuidanceParser::from_tok_env(grammar_json, tok_env, options_json.as_deref())
+            })
+            .map_err(|e| JsValue::from_str(&e))
+    }
+}
@@ -0,0 +1,93 @@
+//! Precompiled grammars
+//!
+//! Turning a grammar input into llguidance's grammar (TypeScript types, OpenAPI
+//! operations, XSDs, YAML layouts, regex translation, unions...) and checking
+//! that llguidance accepts the result is done once by `compile_grammar`; the
+//! blob it returns loads without repeating any of it, and without needing the
+//! converters' inputs at all. llguidance's lexer and parser tables depend on
+//! the tokenizer, so they are still built when a parser is created.
+//!
+//! Layout (little-endian): magic `LLGG`, format version (u32), grammar JSON
+//! length (u32) and bytes, stop sequence count (u32), then each stop sequence
+//! as its length (u32) and bytes.
+
+use llguidance::api::TopLevelGrammar;
+
+const MAGIC: &[u8; 4] = b"LLGG";
+const VERSION: u32 = 1;
+
+pub(crate) struct CompiledGrammar {
+    pub grammar: TopLevelGrammar,
+    pub stop_sequences: Vec<Vec<u8>>,
+}
+
+impl CompiledGrammar {
+    pub(crate) fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let grammar = serde_json::to_vec(&self.grammar)
+            .map_err(|e| format!("Failed to serialize grammar: {}", e))?;
+        let mut bytes = Vec::with_capacity(16 + grammar.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(grammar.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&grammar);
+        bytes.extend_from_slice(&(self.stop_sequences.len() as u32).to_le_bytes());
+        for sequence in &self.stop_sequences {
+            bytes.extend_from_slice(&(sequence.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(sequence);
+        }
+        Ok(bytes)
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes };
+        if reader.take(4).ok() != Some(MAGIC.as_slice()) {
+            return Err("Not a compiled grammar".to_string());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported compiled grammar version {} (expected {})",
+                version, VERSION
+            ));
+        }
+        let grammar = serde_json::from_slice(reader.item()?)
+            .map_err(|e| format!("Failed to read compiled grammar: {}", e))?;
+        let count = reader.u32()? as usize;
+        let stop_sequences = (0..count)
+            .map(|_| reader.item().map(<[u8]>::to_vec))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !reader.bytes.is_empty() {
+            return Err("Trailing bytes after compiled grammar".to_string());
+        }
+
+        Ok(CompiledGrammar {
+            grammar,
+            stop_sequences,
+        })
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("Compiled grammar is truncated".to_string());
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Bytes prefixed by their length
+    fn item(&mut self) -> Result<&'a [u8], String> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
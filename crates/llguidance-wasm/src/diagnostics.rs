@@ -0,0 +1,169 @@
+//! Tokenizer/grammar compatibility report
+//!
+//! Collects the facts that usually explain a surprising mask: grammar errors,
+//! an empty mask at the current position, an unusable EOS token, and raw
+//! bytes that no token in the vocabulary can produce. `MaskStats` is the
+//! cheap per-step summary of how constrained the current position is, and
+//! `GrammarValidation` the result of compiling a grammar on its own.
+
+use serde::Serialize;
+
+use llguidance::toktrie::{SimpleVob, TokTrie};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Diagnostics {
+    /// Number of tokens known to the tokenizer
+    vocab_size: usize,
+    /// Number of tokens allowed at the current position
+    allowed_tokens: usize,
+    /// Whether EOS is allowed at the current position
+    eos_allowed: bool,
+    /// Grammar or matcher error, if any
+    error: Option<String>,
+    /// Current stop reason
+    stop_reason: String,
+    /// Token IDs with no bytes (holes in the vocabulary)
+    empty_tokens: usize,
+    /// Tokens marked special (never produced by grammar text)
+    special_tokens: usize,
+    /// Byte values that no text token contains; grammars needing them cannot match
+    unreachable_bytes: Vec<u8>,
+    /// Human-readable findings, most severe first
+    warnings: Vec<String>,
+}
+
+impl Diagnostics {
+    pub(crate) fn collect(
+        trie: &TokTrie,
+        mask: Result<&SimpleVob, &str>,
+        stop_reason: String,
+    ) -> Self {
+        let vocab_size = trie.vocab_size();
+        let eos = trie.eos_token();
+
+        let mut byte_seen = [false; 256];
+        let mut empty_tokens = 0;
+        let mut special_tokens = 0;
+        for id in 0..vocab_size as u32 {
+            let token = trie.token(id);
+            match token.first() {
+                None => empty_tokens += 1,
+                Some(0xFF) => special_tokens += 1,
+                Some(_) => token.iter().for_each(|&b| byte_seen[b as usize] = true),
+            }
+        }
+        let unreachable_bytes: Vec<u8> = (0..=255u8).filter(|&b| !byte_seen[b as usize]).collect();
+
+        let mut warnings = Vec::new();
+        let (allowed_tokens, eos_allowed, error) = match mask {
+            Ok(mask) => (
+                mask.num_set(),
+                (eos as usize) < vocab_size && mask.is_allowed(eos),
+                None,
+            ),
+            Err(e) => {
+                warnings.push(format!("The grammar failed: {}", e));
+                (0, false, Some(e.to_string()))
+            }
+        };
+
+        if error.is_none() && allowed_tokens == 0 {
+            warnings.push(
+                "No token is allowed at the current position; the grammar requires text \
+                 that no token in the vocabulary can start"
+                    .to_string(),
+            );
+        }
+        if eos as usize >= vocab_size || trie.token(eos).is_empty() {
+            warnings.push(format!(
+                "EOS token {} has no bytes in the vocabulary, so generation can never end cleanly",
+                eos
+            ));
+        }
+        // Bytes 0x80..0xFF only occur inside multi-byte characters
+        let missing_ascii: Vec<String> = unreachable_bytes
+            .iter()
+            .filter(|&&b| b == b'\n' || b == b'\t' || (0x20..0x7F).contains(&b))
+            .map(|&b| format!("{:?}", b as char))
+            .collect();
+        if !missing_ascii.is_empty() {
+            warnings.push(format!(
+                "No token produces {}; grammars requiring these characters can never match",
+                missing_ascii.join(", ")
+            ));
+        }
+        if empty_tokens > 0 {
+            warnings.push(format!(
+                "{} token IDs have no bytes and are never allowed",
+                empty_tokens
+            ));
+        }
+
+        Diagnostics {
+            vocab_size,
+            allowed_tokens,
+            eos_allowed,
+            error,
+            stop_reason,
+            empty_tokens,
+            special_tokens,
+            unreachable_bytes,
+            warnings,
+        }
+    }
+}
+
+/// How constrained the model is at the current position
+#[derive(Debug, Serialize)]
+pub(crate) struct MaskStats {
+    /// Number of tokens allowed at the current position
+    allowed_tokens: usize,
+    /// Number of tokens known to the tokenizer
+    vocab_size: usize,
+    /// Fraction of the vocabulary that is allowed
+    coverage: f64,
+    /// Bits of choice left if every allowed token were equally likely
+    entropy_bits: f64,
+    /// Whether EOS is allowed at the current position
+    eos_allowed: bool,
+}
+
+impl MaskStats {
+    pub(crate) fn collect(trie: &TokTrie, mask: &SimpleVob) -> Self {
+        let vocab_size = trie.vocab_size();
+        let eos = trie.eos_token();
+        let allowed_tokens = mask.num_set();
+
+        MaskStats {
+            allowed_tokens,
+            vocab_size,
+            coverage: allowed_tokens as f64 / vocab_size as f64,
+            entropy_bits: if allowed_tokens > 1 {
+                (allowed_tokens as f64).log2()
+            } else {
+                0.0
+            },
+            eos_allowed: (eos as usize) < vocab_size && mask.is_allowed(eos),
+        }
+    }
+}
+
+/// Whether a grammar compiles, checked without the caller's tokenizer
+#[derive(Debug, Serialize)]
+pub(crate) struct GrammarValidation {
+    ok: bool,
+    /// Why the grammar cannot be used; empty if it compiled
+    errors: Vec<String>,
+    /// Problems that did not stop compilation, e.g. ignored schema keywords
+    warnings: Vec<String>,
+}
+
+impl GrammarValidation {
+    pub(crate) fn new(error: Option<String>, warnings: Vec<String>) -> Self {
+        GrammarValidation {
+            ok: error.is_none(),
+            errors: error.into_iter().collect(),
+            warnings,
+        }
+    }
+}
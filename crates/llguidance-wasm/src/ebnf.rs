@@ -0,0 +1,314 @@
+//! EBNF grammar input
+//!
+//! Specs usually publish grammars in ISO 14977 or W3C-style EBNF, which are
+//! translated to Lark like GBNF is. Rules are written `name = ... ;` or
+//! `name ::= ...`, items are concatenated with `,` or by juxtaposition, and
+//! `[...]`, `{...}` and `n * item` are optional, repeated and counted items.
+//! The first rule is the start rule. Square brackets always mean an optional
+//! item, so W3C character classes need `#xN` codes or a regex sub-grammar.
+
+use std::collections::BTreeSet;
+
+use crate::gbnf::{repeat, RuleNames};
+
+/// Translate an EBNF grammar to an equivalent Lark grammar
+pub(crate) fn ebnf_to_lark(ebnf: &str) -> Result<String, String> {
+    let mut converter = Converter {
+        chars: ebnf.chars().collect(),
+        pos: 0,
+        names: RuleNames::default(),
+        defined: BTreeSet::new(),
+        referenced: BTreeSet::new(),
+    };
+
+    let mut rules = Vec::new();
+    loop {
+        converter.skip_space()?;
+        if converter.peek().is_none() {
+            break;
+        }
+        rules.push(converter.rule(rules.is_empty())?);
+    }
+
+    if rules.is_empty() {
+        return Err("EBNF grammar has no rules".to_string());
+    }
+    if let Some(name) = converter.referenced.difference(&converter.defined).next() {
+        return Err(format!("EBNF rule {} is used but never defined", name));
+    }
+    Ok(rules.join("\n"))
+}
+
+struct Converter {
+    chars: Vec<char>,
+    pos: usize,
+    names: RuleNames,
+    defined: BTreeSet<String>,
+    referenced: BTreeSet<String>,
+}
+
+impl Converter {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("Unexpected end of grammar"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("Expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(&format!("Expected '{}' before the end", expected))),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.chars[..self.pos.min(self.chars.len())]
+            .iter()
+            .filter(|&&c| c == '\n')
+            .count()
+            + 1;
+        format!("EBNF line {}: {}", line, message)
+    }
+
+    /// Skip whitespace and `(* ... *)` or `/* ... */` comments
+    fn skip_space(&mut self) -> Result<(), String> {
+        loop {
+            if self.peek().is_some_and(char::is_whitespace) {
+                self.pos += 1;
+            } else if let Some(close) = ["(*", "/*"]
+                .iter()
+                .find(|open| self.starts_with(open))
+                .map(|open| if *open == "(*" { "*)" } else { "*/" })
+            {
+                self.pos += 2;
+                while !self.starts_with(close) {
+                    if self.peek().is_none() {
+                        return Err(self.error("Unterminated comment"));
+                    }
+                    self.pos += 1;
+                }
+                self.pos += 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Length of the `=` or `::=` at the current position, if any
+    fn definition_len(&self) -> Option<usize> {
+        if self.starts_with("::=") {
+            Some(3)
+        } else if self.starts_with("=") {
+            Some(1)
+        } else {
+            None
+        }
+    }
+
+    /// Whether a new rule starts here, which ends a W3C rule with no `;`
+    fn at_rule_start(&mut self) -> Result<bool, String> {
+        let start = self.pos;
+        let is_rule = match self.name() {
+            Ok(_) => {
+                self.skip_space()?;
+                self.definition_len().is_some()
+            }
+            Err(_) => false,
+        };
+        self.pos = start;
+        Ok(is_rule)
+    }
+
+    /// `name = alternatives ;`, where the terminator may be `;`, `.` or
+    /// omitted
+    fn rule(&mut self, first: bool) -> Result<String, String> {
+        let name = self.name()?;
+        self.skip_space()?;
+        let Some(len) = self.definition_len() else {
+            return Err(self.error(&format!("Expected '=' or '::=' after {}", name)));
+        };
+        self.pos += len;
+        if !self.defined.insert(name.clone()) {
+            return Err(self.error(&format!("Rule {} is defined more than once", name)));
+        }
+        if first {
+            self.names.reserve(&name, "start");
+        }
+        let lark_name = self.names.lark_name(&name);
+        let body = self.alternatives()?;
+        self.skip_space()?;
+        if matches!(self.peek(), Some(';' | '.')) {
+            self.pos += 1;
+        }
+        Ok(format!("{}: {}", lark_name, body))
+    }
+
+    fn alternatives(&mut self) -> Result<String, String> {
+        let mut alternatives = vec![self.sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.sequence()?);
+        }
+        Ok(alternatives.join(" | "))
+    }
+
+    /// Items up to the end of the alternative; trailing space is skipped
+    fn sequence(&mut self) -> Result<String, String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_space()?;
+            match self.peek() {
+                None | Some('|' | ')' | ']' | '}' | ';' | '.') => break,
+                Some(',') if !items.is_empty() => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some('-') => {
+                    return Err(self.error("Exceptions (a - b) are not supported"));
+                }
+                _ if self.at_rule_start()? => break,
+                _ => items.push(self.item()?),
+            }
+        }
+        if items.is_empty() {
+            return Ok("\"\"".to_string());
+        }
+        Ok(items.join(" "))
+    }
+
+    /// An atom with an optional `n *` count and W3C repetition operators
+    fn item(&mut self) -> Result<String, String> {
+        let count = if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            let count = self.number()?;
+            self.skip_space()?;
+            self.expect('*')?;
+            self.skip_space()?;
+            Some(count)
+        } else {
+            None
+        };
+
+        let (mut item, mut atomic) = self.atom()?;
+        if let Some(count) = count {
+            if !atomic {
+                item = format!("({})", item);
+            }
+            item = repeat(&item, count, Some(count));
+            atomic = true;
+        }
+        loop {
+            self.skip_space()?;
+            let Some(op @ ('*' | '+' | '?')) = self.peek() else {
+                break;
+            };
+            self.pos += 1;
+            if !atomic {
+                item = format!("({})", item);
+            }
+            item.push(op);
+            atomic = false;
+        }
+        Ok(item)
+    }
+
+    /// An atom and whether it can take an operator without parentheses
+    fn atom(&mut self) -> Result<(String, bool), String> {
+        let Some(c) = self.peek() else {
+            return Err(self.error("Unexpected end of grammar"));
+        };
+        match c {
+            '"' | '\'' => {
+                self.pos += 1;
+                let mut literal = String::new();
+                loop {
+                    match self.next()? {
+                        q if q == c => break,
+                        c => literal.push(c),
+                    }
+                }
+                Ok((serde_json::to_string(&literal).unwrap(), true))
+            }
+            '#' => {
+                self.pos += 1;
+                self.expect('x')?;
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                    self.pos += 1;
+                }
+                let digits: String = self.chars[start..self.pos].iter().collect();
+                let c = u32::from_str_radix(&digits, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error(&format!("Invalid character #x{}", digits)))?;
+                Ok((serde_json::to_string(&c.to_string()).unwrap(), true))
+            }
+            '[' | '{' | '(' => {
+                self.pos += 1;
+                let body = self.alternatives()?;
+                let (close, op) = match c {
+                    '[' => (']', "?"),
+                    '{' => ('}', "*"),
+                    _ => (')', ""),
+                };
+                self.expect(close)?;
+                Ok((format!("({}){}", body, op), op.is_empty()))
+            }
+            '?' => Err(self.error("Special sequences (? ... ?) are not supported")),
+            _ => {
+                let name = self.name()?;
+                self.referenced.insert(name.clone());
+                Ok((self.names.lark_name(&name), true))
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .map_err(|_| self.error("Expected a repetition count"))
+    }
+
+    /// A rule name, optionally in `<...>` brackets
+    fn name(&mut self) -> Result<String, String> {
+        let bracketed = self.peek() == Some('<');
+        if bracketed {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            self.pos += 1;
+        }
+        if start == self.pos || !self.chars[start].is_ascii_alphabetic() {
+            return Err(self.error("Expected a rule name"));
+        }
+        let name = self.chars[start..self.pos].iter().collect();
+        if bracketed {
+            self.expect('>')?;
+        }
+        Ok(name)
+    }
+}
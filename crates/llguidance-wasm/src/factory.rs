@@ -0,0 +1,75 @@
+//! Compile once, create many parsers
+//!
+//! The `LLGuidanceParser` constructors set up the tokenizer and compile the
+//! grammar in one call, which is too slow to repeat for every request. A
+//! server instead builds one `LLGuidanceFactory` per tokenizer, compiles each
+//! grammar it serves once, and creates a parser per request by copying the
+//! compiled grammar's start state.
+
+use wasm_bindgen::prelude::*;
+
+use crate::options::ParserOptions;
+use crate::{CachedFactory, LLGuidanceParser};
+
+/// A tokenizer, parser options and the grammars compiled against them
+#[wasm_bindgen]
+pub struct LLGuidanceFactory {
+    cached: CachedFactory,
+    options: ParserOptions,
+    /// A parser at the start of each compiled grammar, indexed by grammar reference
+    grammars: Vec<LLGuidanceParser>,
+}
+
+#[wasm_bindgen]
+impl LLGuidanceFactory {
+    /// Set up the tokenizer; the options apply to every parser created
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        tokenizer_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceFactory, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        let options =
+            ParserOptions::parse(options_json.as_deref()).map_err(|e| JsValue::from_str(&e))?;
+        let cached =
+            LLGuidanceParser::cached_factory(tokenizer_json).map_err(|e| JsValue::from_str(&e))?;
+        Ok(LLGuidanceFactory {
+            cached,
+            options,
+            grammars: Vec::new(),
+        })
+    }
+
+    /// Compile a grammar, returning the reference to pass to `create_parser`
+    #[wasm_bindgen]
+    pub fn compile_grammar(&mut self, grammar_json: &str) -> Result<u32, JsValue> {
+        let parser =
+            LLGuidanceParser::from_factory(grammar_json, self.cached.clone(), &self.options)
+                .map_err(|e| JsValue::from_str(&e))?;
+        self.grammars.push(parser);
+        Ok((self.grammars.len() - 1) as u32)
+    }
+
+    /// Number of grammars compiled so far
+    #[wasm_bindgen]
+    pub fn grammar_count(&self) -> usize {
+        self.grammars.len()
+    }
+
+    /// Create a parser at the start of a compiled grammar, without recompiling it
+    #[wasm_bindgen]
+    pub fn create_parser(&self, grammar_ref: u32) -> Result<LLGuidanceParser, JsValue> {
+        self.grammars
+            .get(grammar_ref as usize)
+            .map(LLGuidanceParser::fresh_copy)
+            .ok_or_else(|| {
+                JsValue::from_str(&format!(
+                    "Grammar reference {} is out of range for a factory of {} grammars",
+                    grammar_ref,
+                    self.grammars.len()
+                ))
+            })
+    }
+}
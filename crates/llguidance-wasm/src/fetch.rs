@@ -0,0 +1,52 @@
+//! Fetching tokenizer files through the host's global `fetch()`
+
+use js_sys::{Function, Promise, Reflect};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = fetch, catch)]
+    fn global_fetch(url: &str) -> Result<Promise, JsValue>;
+}
+
+/// Download `url` and return its body as text
+pub(crate) async fn fetch_text(url: &str) -> Result<String, String> {
+    let request = global_fetch(url).map_err(|e| fetch_error(url, &e))?;
+    let response = JsFuture::from(request)
+        .await
+        .map_err(|e| fetch_error(url, &e))?;
+
+    let ok = Reflect::get(&response, &JsValue::from_str("ok"))
+        .map(|ok| ok.is_truthy())
+        .unwrap_or(false);
+    if !ok {
+        let status = Reflect::get(&response, &JsValue::from_str("status"))
+            .ok()
+            .and_then(|status| status.as_f64())
+            .unwrap_or(0.0);
+        return Err(format!("Failed to fetch {}: HTTP {}", url, status));
+    }
+
+    let text = Reflect::get(&response, &JsValue::from_str("text"))
+        .and_then(|text| text.dyn_into::<Function>())
+        .and_then(|text| text.call0(&response))
+        .map_err(|e| fetch_error(url, &e))?;
+    JsFuture::from(Promise::from(text))
+        .await
+        .map_err(|e| fetch_error(url, &e))?
+        .as_string()
+        .ok_or_else(|| format!("Failed to fetch {}: response body is not text", url))
+}
+
+fn fetch_error(url: &str, error: &JsValue) -> String {
+    let message = error
+        .as_string()
+        .or_else(|| {
+            Reflect::get(error, &JsValue::from_str("message"))
+                .ok()
+                .and_then(|message| message.as_string())
+        })
+        .unwrap_or_else(|| format!("{:?}", error));
+    format!("Failed to fetch {}: {}", url, message)
+}
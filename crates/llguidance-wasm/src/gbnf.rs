@@ -0,0 +1,399 @@
+//! GBNF (llama.cpp grammar) input
+//!
+//! llguidance has no GBNF front end, so GBNF grammars are translated to the
+//! Lark syntax it compiles. The translation is one to one: string literals
+//! and rule references carry over, character classes and `.` become regex
+//! terminals, and the `{m,n}` repetitions llama.cpp supports are expanded.
+//! llama.cpp's `root` rule becomes Lark's `start`.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Translate a GBNF grammar to an equivalent Lark grammar
+pub(crate) fn gbnf_to_lark(gbnf: &str) -> Result<String, String> {
+    let mut converter = Converter {
+        chars: gbnf.chars().collect(),
+        pos: 0,
+        names: RuleNames::default(),
+        defined: BTreeSet::new(),
+        referenced: BTreeSet::new(),
+    };
+    converter.names.reserve("root", "start");
+
+    let mut rules = Vec::new();
+    loop {
+        converter.skip_space(true);
+        if converter.peek().is_none() {
+            break;
+        }
+        rules.push(converter.rule()?);
+    }
+
+    if !converter.defined.contains("root") {
+        return Err("GBNF grammar has no root rule".to_string());
+    }
+    if let Some(name) = converter.referenced.difference(&converter.defined).next() {
+        return Err(format!("GBNF rule {} is used but never defined", name));
+    }
+    Ok(rules.join("\n"))
+}
+
+struct Converter {
+    chars: Vec<char>,
+    pos: usize,
+    names: RuleNames,
+    defined: BTreeSet<String>,
+    referenced: BTreeSet<String>,
+}
+
+impl Converter {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self
+            .peek()
+            .ok_or_else(|| self.error("Unexpected end of grammar"))?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(c) => Err(self.error(&format!("Expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(&format!("Expected '{}' before the end", expected))),
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.chars[..self.pos.min(self.chars.len())]
+            .iter()
+            .filter(|&&c| c == '\n')
+            .count()
+            + 1;
+        format!("GBNF line {}: {}", line, message)
+    }
+
+    /// Skip blanks and `#` comments, and line breaks if `newlines`
+    fn skip_space(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => self.pos += 1,
+                '\r' | '\n' if newlines => self.pos += 1,
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// `name ::= alternatives`, ending at a line break
+    fn rule(&mut self) -> Result<String, String> {
+        let name = self.word()?;
+        self.skip_space(false);
+        for c in "::=".chars() {
+            self.expect(c)?;
+        }
+        if !self.defined.insert(name.clone()) {
+            return Err(self.error(&format!("Rule {} is defined more than once", name)));
+        }
+        let lark_name = self.names.lark_name(&name);
+        let body = self.alternatives(false)?;
+        Ok(format!("{}: {}", lark_name, body))
+    }
+
+    fn alternatives(&mut self, nested: bool) -> Result<String, String> {
+        let mut alternatives = vec![self.sequence(nested)?];
+        loop {
+            // Like llama.cpp, continue after a trailing `|`; also accept one
+            // leading the next line
+            let line_end = self.pos;
+            self.skip_space(true);
+            if self.peek() != Some('|') {
+                if !nested {
+                    self.pos = line_end;
+                }
+                break;
+            }
+            self.pos += 1;
+            self.skip_space(true);
+            alternatives.push(self.sequence(nested)?);
+        }
+        Ok(alternatives.join(" | "))
+    }
+
+    fn sequence(&mut self, nested: bool) -> Result<String, String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_space(nested);
+            match self.peek() {
+                None | Some('|' | ')' | '\r' | '\n') => break,
+                _ => items.push(self.item(nested)?),
+            }
+        }
+        if items.is_empty() {
+            return Ok("\"\"".to_string());
+        }
+        Ok(items.join(" "))
+    }
+
+    /// An atom and its repetition operators
+    fn item(&mut self, nested: bool) -> Result<String, String> {
+        let mut item = self.atom()?;
+        let mut atomic = true;
+        loop {
+            self.skip_space(nested);
+            let Some(op @ ('*' | '+' | '?' | '{')) = self.peek() else {
+                break;
+            };
+            self.pos += 1;
+            if !atomic {
+                item = format!("({})", item);
+            }
+            if op == '{' {
+                let (min, max) = self.repetition()?;
+                item = repeat(&item, min, max);
+                atomic = true;
+            } else {
+                item.push(op);
+                atomic = false;
+            }
+        }
+        Ok(item)
+    }
+
+    fn atom(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => {
+                self.pos += 1;
+                let mut literal = String::new();
+                loop {
+                    match self.next()? {
+                        '"' => break,
+                        '\\' => literal.push(self.escape()?),
+                        c => literal.push(c),
+                    }
+                }
+                Ok(serde_json::to_string(&literal).unwrap())
+            }
+            Some('[') => {
+                self.pos += 1;
+                self.char_class()
+            }
+            Some('.') => {
+                self.pos += 1;
+                Ok("/(?s:.)/".to_string())
+            }
+            Some('(') => {
+                self.pos += 1;
+                self.skip_space(true);
+                let body = self.alternatives(true)?;
+                self.skip_space(true);
+                self.expect(')')?;
+                Ok(format!("({})", body))
+            }
+            Some(c) if is_word_char(c) => {
+                let name = self.word()?;
+                self.referenced.insert(name.clone());
+                Ok(self.names.lark_name(&name))
+            }
+            Some(c) => Err(self.error(&format!("Unexpected '{}'", c))),
+            None => Err(self.error("Unexpected end of grammar")),
+        }
+    }
+
+    /// The contents of `[...]` as a regex terminal
+    fn char_class(&mut self) -> Result<String, String> {
+        let mut class = String::from("/[");
+        if self.peek() == Some('^') {
+            self.pos += 1;
+            class.push('^');
+        }
+        let mut empty = true;
+        while self.peek() != Some(']') {
+            let first = self.class_char()?;
+            class.push_str(&escape_class_char(first));
+            // A '-' right before ']' is a literal
+            if self.peek() == Some('-') && self.peek_at(1).is_some_and(|c| c != ']') {
+                self.pos += 1;
+                let last = self.class_char()?;
+                if last < first {
+                    return Err(self.error(&format!(
+                        "Character range {}-{} is out of order",
+                        first, last
+                    )));
+                }
+                class.push('-');
+                class.push_str(&escape_class_char(last));
+            }
+            empty = false;
+        }
+        self.pos += 1;
+        if empty {
+            return Err(self.error("Empty character class"));
+        }
+        class.push_str("]/");
+        Ok(class)
+    }
+
+    fn class_char(&mut self) -> Result<char, String> {
+        match self.next()? {
+            '\\' => self.escape(),
+            c => Ok(c),
+        }
+    }
+
+    /// The character after a backslash, as in llama.cpp
+    fn escape(&mut self) -> Result<char, String> {
+        let c = self.next()?;
+        let digits = match c {
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            'n' => return Ok('\n'),
+            'r' => return Ok('\r'),
+            't' => return Ok('\t'),
+            '\\' | '"' | '[' | ']' | '-' | '^' => return Ok(c),
+            _ => return Err(self.error(&format!("Unknown escape \\{}", c))),
+        };
+        let mut code = 0;
+        for _ in 0..digits {
+            let digit = self.next()?;
+            let value = digit
+                .to_digit(16)
+                .ok_or_else(|| self.error(&format!("Invalid hex digit '{}'", digit)))?;
+            code = code * 16 + value;
+        }
+        char::from_u32(code).ok_or_else(|| self.error(&format!("Invalid character U+{:X}", code)))
+    }
+
+    /// `m}`, `m,}` or `m,n}`, after the opening brace
+    fn repetition(&mut self) -> Result<(usize, Option<usize>), String> {
+        self.skip_space(true);
+        let min = self.number()?;
+        self.skip_space(true);
+        let max = if self.peek() == Some(',') {
+            self.pos += 1;
+            self.skip_space(true);
+            if self.peek() == Some('}') {
+                None
+            } else {
+                let max = self.number()?;
+                if max < min {
+                    return Err(self.error(&format!("Repetition {{{},{}}} is empty", min, max)));
+                }
+                Some(max)
+            }
+        } else {
+            Some(min)
+        };
+        self.skip_space(true);
+        self.expect('}')?;
+        Ok((min, max))
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        digits
+            .parse()
+            .map_err(|_| self.error("Expected a repetition count"))
+    }
+
+    fn word(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(is_word_char) {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(self.error("Expected a rule name"));
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+}
+
+/// Lark rule names for the rule names of another grammar format
+#[derive(Default)]
+pub(crate) struct RuleNames {
+    names: HashMap<String, String>,
+    used: HashSet<String>,
+}
+
+impl RuleNames {
+    /// Give `name` a fixed Lark name, e.g. `start` for the start rule
+    pub(crate) fn reserve(&mut self, name: &str, lark_name: &str) {
+        self.names.insert(name.to_string(), lark_name.to_string());
+        self.used.insert(lark_name.to_string());
+    }
+
+    /// The Lark name for `name`, which may contain '-' and uppercase letters
+    /// that Lark reserves for terminals
+    pub(crate) fn lark_name(&mut self, name: &str) -> String {
+        if let Some(lark_name) = self.names.get(name) {
+            return lark_name.clone();
+        }
+        let mut base: String = name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if !base.starts_with(|c: char| c.is_ascii_lowercase()) {
+            base.insert_str(0, "r_");
+        }
+        let mut lark_name = base.clone();
+        let mut suffix = 2;
+        while self.used.contains(&lark_name) {
+            lark_name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        self.used.insert(lark_name.clone());
+        self.names.insert(name.to_string(), lark_name.clone());
+        lark_name
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+/// A character inside a regex class, escaped where the regex syntax or the
+/// Lark `/.../` delimiters would read it differently
+fn escape_class_char(c: char) -> String {
+    match c {
+        '\\' | ']' | '[' | '^' | '-' | '&' | '~' | '/' => format!("\\{}", c),
+        c if c.is_control() => format!("\\x{{{:X}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+/// `item` repeated between `min` and `max` times (unbounded if `None`)
+pub(crate) fn repeat(item: &str, min: usize, max: Option<usize>) -> String {
+    let mut parts = vec![item.to_string(); min];
+    match max {
+        None => parts.push(format!("{}*", item)),
+        Some(max) => parts.extend((min..max).map(|_| format!("{}?", item))),
+    }
+    if parts.is_empty() {
+        return "\"\"".to_string();
+    }
+    format!("({})", parts.join(" "))
+}
@@ -0,0 +1,351 @@
+//! JavaScript regex input
+//!
+//! `rx` grammars are written in JavaScript regex syntax, which differs from
+//! the Rust-style syntax of llguidance lexemes: `\d`, `\w` and `\s` have ASCII
+//! (or JS whitespace) meaning rather than Unicode classes, `.` excludes every
+//! line terminator, `\u` escapes and named groups are spelled differently, and
+//! a `/` would end the Lark regex. The pattern is translated construct by
+//! construct; `^` and `$` at the ends are dropped, since a grammar always
+//! matches the whole output, and what cannot be expressed (lookaround,
+//! backreferences, word boundaries, anchors elsewhere) is rejected.
+
+/// Whitespace as JS `\s` defines it, for use inside a class
+const WHITESPACE: &str = r"\t\n\x{B}\x{C}\r \x{A0}\x{1680}\x{2000}-\x{200A}\x{2028}\x{2029}\x{202F}\x{205F}\x{3000}\x{FEFF}";
+
+/// Characters a Rust regex needs escaped to be literal
+const META: &str = r"\.+*?()|[]{}^$#&-~";
+
+/// Translate a JS regex and its flags to a llguidance regex
+pub(crate) fn translate(pattern: &str, flags: &str) -> Result<String, String> {
+    let mut dot_all = false;
+    let mut ignore_case = false;
+    for flag in flags.chars() {
+        match flag {
+            'i' => ignore_case = true,
+            's' => dot_all = true,
+            // No effect on which strings match as a whole
+            'g' | 'y' | 'd' | 'm' | 'u' | 'v' => {}
+            _ => return Err(format!("Unknown regex flag {}", flag)),
+        }
+    }
+
+    let mut translator = Translator {
+        chars: pattern.chars().collect(),
+        pos: 0,
+        depth: 0,
+        dot_all,
+        out: String::new(),
+    };
+    translator.pattern()?;
+    let out = translator.out;
+    Ok(if ignore_case {
+        format!("(?i:{})", out)
+    } else {
+        out
+    })
+}
+
+struct Translator {
+    chars: Vec<char>,
+    pos: usize,
+    /// Groups open at `pos`
+    depth: usize,
+    dot_all: bool,
+    out: String,
+}
+
+impl Translator {
+    fn error(&self, message: &str) -> String {
+        format!("Unsupported regex at position {}: {}", self.pos, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn looking_at(&self, text: &str) -> bool {
+        text.chars()
+            .enumerate()
+            .all(|(i, c)| self.chars.get(self.pos + i) == Some(&c))
+    }
+
+    /// Whether `pos` starts a top-level alternative
+    fn at_alternative_start(&self) -> bool {
+        self.depth == 0 && (self.pos == 0 || self.chars[self.pos - 1] == '|')
+    }
+
+    /// Whether `pos` is the last character of a top-level alternative
+    fn at_alternative_end(&self) -> bool {
+        self.depth == 0 && matches!(self.chars.get(self.pos + 1), None | Some('|'))
+    }
+
+    fn pattern(&mut self) -> Result<(), String> {
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => {
+                    self.pos += 1;
+                    let escape = self.escape(false)?;
+                    self.out.push_str(&escape);
+                    continue;
+                }
+                '[' => {
+                    self.class()?;
+                    continue;
+                }
+                '(' => {
+                    self.group()?;
+                    continue;
+                }
+                ')' => {
+                    if self.depth == 0 {
+                        return Err(self.error("unmatched )"));
+                    }
+                    self.depth -= 1;
+                    self.out.push(')');
+                }
+                '^' if self.at_alternative_start() => {}
+                '$' if self.at_alternative_end() => {}
+                '^' | '$' => {
+                    return Err(self
+                        .error("^ and $ are only supported at the start and end of the pattern"))
+                }
+                '.' if self.dot_all => self.out.push_str("(?s:.)"),
+                '.' => self.out.push_str(r"[^\n\r\x{2028}\x{2029}]"),
+                '*' | '+' | '?' => {
+                    self.out.push(c);
+                    self.skip_lazy();
+                }
+                '{' => match self.quantifier() {
+                    Some(quantifier) => {
+                        self.out.push_str(&quantifier);
+                        self.skip_lazy();
+                    }
+                    // JS reads a brace that starts no quantifier literally
+                    None => self.out.push_str(r"\{"),
+                },
+                '}' => self.out.push_str(r"\}"),
+                '/' => self.out.push_str(r"\/"),
+                _ => self.out.push(c),
+            }
+            self.pos += 1;
+        }
+        if self.depth > 0 {
+            return Err(self.error("unterminated group"));
+        }
+        Ok(())
+    }
+
+    /// Drop the `?` of a lazy quantifier, which matches the same strings
+    fn skip_lazy(&mut self) {
+        if self.chars.get(self.pos + 1) == Some(&'?') {
+            self.pos += 1;
+        }
+    }
+
+    /// A `{n}`, `{n,}` or `{n,m}` quantifier at `pos`; moves past it
+    fn quantifier(&mut self) -> Option<String> {
+        let rest: String = self.chars[self.pos..].iter().collect();
+        let end = rest.find('}')?;
+        let body = &rest[1..end];
+        let (min, max) = body.split_once(',').unwrap_or((body, body));
+        let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+        if !digits(min) || !(max.is_empty() || digits(max)) {
+            return None;
+        }
+        // Leave `pos` on the closing brace, like the other quantifiers
+        self.pos += body.chars().count() + 1;
+        Some(format!("{{{}}}", body))
+    }
+
+    fn group(&mut self) -> Result<(), String> {
+        if self.looking_at("(?=") || self.looking_at("(?!") {
+            return Err(self.error("lookahead is not supported"));
+        }
+        if self.looking_at("(?<=") || self.looking_at("(?<!") {
+            return Err(self.error("lookbehind is not supported"));
+        }
+        self.pos += 1;
+        if self.looking_at("?<") {
+            // A named group; names are irrelevant to matching
+            while self.peek().is_some_and(|c| c != '>') {
+                self.pos += 1;
+            }
+            self.pos += 1;
+        } else if self.looking_at("?:") {
+            self.pos += 2;
+        } else if self.peek() == Some('?') {
+            // Modifiers such as (?i:...)
+            let start = self.pos;
+            self.pos += 1;
+            while self
+                .peek()
+                .is_some_and(|c| matches!(c, 'i' | 's' | 'm' | '-'))
+            {
+                self.pos += 1;
+            }
+            if self.peek() != Some(':') {
+                return Err(self.error("unknown group syntax"));
+            }
+            let modifiers: String = self.chars[start..self.pos].iter().collect();
+            self.out.push_str(&format!("({}:", modifiers));
+            self.pos += 1;
+            self.depth += 1;
+            return Ok(());
+        }
+        self.out.push_str("(?:");
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn class(&mut self) -> Result<(), String> {
+        self.pos += 1;
+        if self.looking_at("^]") {
+            // JS for any character
+            self.pos += 2;
+            self.out.push_str("(?s:.)");
+            return Ok(());
+        }
+        if self.peek() == Some(']') {
+            return Err(self.error("[] matches nothing"));
+        }
+        self.out.push('[');
+        if self.peek() == Some('^') {
+            self.out.push('^');
+            self.pos += 1;
+        }
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unterminated character class"));
+            };
+            self.pos += 1;
+            match c {
+                ']' => break,
+                '\\' => {
+                    let escape = self.escape(true)?;
+                    self.out.push_str(&escape);
+                }
+                // Nested classes and set operations in Rust syntax only
+                '[' | '&' | '~' => self.out.push_str(&format!("\\{}", c)),
+                '-' if self.peek() == Some('-') => self.out.push_str(r"\-"),
+                '/' => self.out.push_str(r"\/"),
+                _ => self.out.push(c),
+            }
+        }
+        self.out.push(']');
+        Ok(())
+    }
+
+    /// The escape after a backslash at `pos - 1`; moves past it
+    fn escape(&mut self, in_class: bool) -> Result<String, String> {
+        let Some(c) = self.peek() else {
+            return Err(self.error("pattern ends with a backslash"));
+        };
+        self.pos += 1;
+        let class = |members: &str, negated: bool| match (in_class, negated) {
+            (true, false) => members.to_string(),
+            (_, true) => format!("[^{}]", members),
+            (false, false) => format!("[{}]", members),
+        };
+        Ok(match c {
+            'd' => class("0-9", false),
+            'D' => class("0-9", true),
+            'w' => class("A-Za-z0-9_", false),
+            'W' => class("A-Za-z0-9_", true),
+            's' => class(WHITESPACE, false),
+            'S' => class(WHITESPACE, true),
+            'b' if in_class => r"\x{8}".to_string(),
+            'b' | 'B' => {
+                self.pos -= 2;
+                return Err(self.error("word boundaries are not supported"));
+            }
+            't' | 'n' | 'r' | 'f' | 'v' => format!("\\{}", c),
+            '0' if !self.peek().is_some_and(|c| c.is_ascii_digit()) => r"\x{0}".to_string(),
+            '0'..='9' => {
+                self.pos -= 2;
+                return Err(self.error("backreferences are not supported"));
+            }
+            'k' if self.peek() == Some('<') => {
+                self.pos -= 2;
+                return Err(self.error("backreferences are not supported"));
+            }
+            'x' => match self.hex(2) {
+                Some(code) => format!("\\x{{{:X}}}", code),
+                None => "x".to_string(),
+            },
+            'u' => self.unicode_escape()?,
+            'c' => match self.peek() {
+                Some(letter) if letter.is_ascii_alphabetic() => {
+                    self.pos += 1;
+                    format!("\\x{{{:X}}}", letter as u32 % 32)
+                }
+                _ => r"\\c".to_string(),
+            },
+            'p' | 'P' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c != '}') {
+                    self.pos += 1;
+                }
+                if self.peek().is_none() || self.chars.get(start) != Some(&'{') {
+                    return Err(self.error("expected \\p{...}"));
+                }
+                self.pos += 1;
+                let property: String = self.chars[start..self.pos].iter().collect();
+                format!("\\{}{}", c, property)
+            }
+            '/' => r"\/".to_string(),
+            c if META.contains(c) => format!("\\{}", c),
+            c if c.is_ascii_alphanumeric() => {
+                self.pos -= 2;
+                return Err(self.error(&format!("unknown escape \\{}", c)));
+            }
+            c => c.to_string(),
+        })
+    }
+
+    /// `n` hex digits at `pos`; moves past them
+    fn hex(&mut self, n: usize) -> Option<u32> {
+        let digits: String = self.chars.get(self.pos..self.pos + n)?.iter().collect();
+        let code = u32::from_str_radix(&digits, 16).ok()?;
+        self.pos += n;
+        Some(code)
+    }
+
+    /// `\uXXXX`, a surrogate pair of them, or `\u{X...}`, after the `u`
+    fn unicode_escape(&mut self) -> Result<String, String> {
+        let code = if self.peek() == Some('{') {
+            let start = self.pos + 1;
+            let end = self.chars[start..]
+                .iter()
+                .position(|&c| c == '}')
+                .map(|i| start + i);
+            let digits: Option<String> = end.map(|end| self.chars[start..end].iter().collect());
+            let code = digits.and_then(|digits| u32::from_str_radix(&digits, 16).ok());
+            match (code, end) {
+                (Some(code), Some(end)) => {
+                    self.pos = end + 1;
+                    code
+                }
+                _ => return Err(self.error("invalid \\u{...} escape")),
+            }
+        } else {
+            let high = self
+                .hex(4)
+                .ok_or_else(|| self.error("invalid \\u escape"))?;
+            if (0xD800..0xDC00).contains(&high) && self.looking_at("\\u") {
+                self.pos += 2;
+                match self.hex(4) {
+                    Some(low) if (0xDC00..0xE000).contains(&low) => {
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    }
+                    _ => return Err(self.error("invalid surrogate pair")),
+                }
+            } else {
+                high
+            }
+        };
+        if char::from_u32(code).is_none() {
+            return Err(self.error("escape is not a Unicode scalar value"));
+        }
+        Ok(format!("\\x{{{:X}}}", code))
+    }
+}
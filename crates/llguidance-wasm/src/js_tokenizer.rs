@@ -0,0 +1,154 @@
+//! Tokenizer environment backed by a live JavaScript tokenizer
+//!
+//! The JS object must provide `encode(text): number[]`, `decode(ids): string`,
+//! `vocabSize` and `eosTokenId`, and may list `specialTokenIds`. Token bytes
+//! are read once by decoding each token ID on its own, and llguidance's own
+//! tokenization requests are forwarded to `encode`.
+
+use js_sys::{Array, Function, Reflect};
+use std::collections::HashSet;
+use std::sync::Arc;
+use wasm_bindgen::prelude::*;
+
+use llguidance::toktrie::{TokRxInfo, TokTrie, TokenId, TokenizerEnv};
+
+use crate::tokenizer::{ParserTokEnv, TokEnv};
+
+pub(crate) struct JsTokEnv {
+    trie: TokTrie,
+    tokenizer: JsValue,
+    encode: Function,
+    warnings: Vec<String>,
+}
+
+// SAFETY: wasm32-unknown-unknown runs this module on a single thread, so the
+// JS handles are never accessed from more than one thread.
+unsafe impl Send for JsTokEnv {}
+unsafe impl Sync for JsTokEnv {}
+
+impl TokenizerEnv for JsTokEnv {
+    fn tok_trie(&self) -> &TokTrie {
+        &self.trie
+    }
+
+    fn tokenize_bytes(&self, s: &[u8]) -> Vec<TokenId> {
+        // encode() only accepts text; partial UTF-8 falls back to the trie
+        let Ok(text) = std::str::from_utf8(s) else {
+            return self.trie.greedy_tokenize(s);
+        };
+        match self.encode.call1(&self.tokenizer, &JsValue::from_str(text)) {
+            Ok(ids) => Array::from(&ids)
+                .iter()
+                .filter_map(|id| id.as_f64())
+                .map(|id| id as TokenId)
+                .collect(),
+            Err(_) => self.trie.greedy_tokenize(s),
+        }
+    }
+}
+
+impl ParserTokEnv for JsTokEnv {
+    fn export_blob(&self) -> Result<Vec<u8>, String> {
+        Err("Tokenizers backed by a JavaScript tokenizer cannot be exported".to_string())
+    }
+
+    fn into_tok_env(self: Arc<Self>) -> Arc<dyn TokenizerEnv + Sync> {
+        self
+    }
+
+    fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+/// Create a tokenizer environment from a JavaScript tokenizer object
+pub(crate) fn create_js_tok_env(tokenizer: &JsValue) -> Result<TokEnv, String> {
+    let encode = get_function(tokenizer, "encode")?;
+    let decode = get_function(tokenizer, "decode")?;
+    let vocab_size = get_number(tokenizer, "vocabSize")? as u32;
+    let eos_token = get_number(tokenizer, "eosTokenId")? as u32;
+
+    if vocab_size == 0 {
+        return Err("Tokenizer vocabulary is empty".to_string());
+    }
+
+    let special: HashSet<u32> = match get_property(tokenizer, "specialTokenIds")? {
+        ids if ids.is_undefined() || ids.is_null() => HashSet::new(),
+        ids => Array::from(&ids)
+            .iter()
+            .filter_map(|id| id.as_f64())
+            .map(|id| id as u32)
+            .collect(),
+    };
+
+    // Build the words vector by decoding each token on its own
+    let mut words: Vec<Vec<u8>> = Vec::with_capacity(vocab_size as usize);
+    let mut lossy_tokens = 0;
+    for id in 0..vocab_size {
+        let ids = Array::of1(&JsValue::from(id));
+        let text = decode
+            .call1(tokenizer, &ids)
+            .map_err(|e| {
+                format!(
+                    "Tokenizer decode() failed for token {}: {}",
+                    id,
+                    js_error(&e)
+                )
+            })?
+            .as_string()
+            .ok_or_else(|| format!("Tokenizer decode() returned a non-string for token {}", id))?;
+
+        // Partial UTF-8 sequences come back from decode() as U+FFFD
+        if text.contains('\u{FFFD}') {
+            lossy_tokens += 1;
+        }
+
+        // Special tokens get the \xFF prefix
+        let mut bytes = Vec::with_capacity(text.len() + 1);
+        if special.contains(&id) {
+            bytes.push(0xFF);
+        }
+        bytes.extend(text.as_bytes());
+        words.push(bytes);
+    }
+
+    let mut warnings = Vec::new();
+    if lossy_tokens > 0 {
+        warnings.push(format!(
+            "{} tokens decoded to U+FFFD; their raw bytes are unknown to decode()",
+            lossy_tokens
+        ));
+    }
+
+    let info = TokRxInfo::new(vocab_size, eos_token);
+    let trie = TokTrie::from(&info, &words);
+
+    Ok(Arc::new(JsTokEnv {
+        trie,
+        tokenizer: tokenizer.clone(),
+        encode,
+        warnings,
+    }))
+}
+
+fn get_property(obj: &JsValue, name: &str) -> Result<JsValue, String> {
+    Reflect::get(obj, &JsValue::from_str(name))
+        .map_err(|e| format!("Failed to read tokenizer.{}: {}", name, js_error(&e)))
+}
+
+fn get_function(obj: &JsValue, name: &str) -> Result<Function, String> {
+    get_property(obj, name)?
+        .dyn_into::<Function>()
+        .map_err(|_| format!("Tokenizer is missing a {}() method", name))
+}
+
+fn get_number(obj: &JsValue, name: &str) -> Result<f64, String> {
+    get_property(obj, name)?
+        .as_f64()
+        .ok_or_else(|| format!("Tokenizer is missing a numeric {} property", name))
+}
+
+/// Render a thrown JS value as an error message
+fn js_error(value: &JsValue) -> String {
+    value.as_string().unwrap_or_else(|| format!("{:?}", value))
+}
@@ -0,0 +1,100 @@
+//! Output formatting for JSON schema grammars
+//!
+//! llguidance reads its JSON compile options from an `x-guidance` object at
+//! the top of the schema, so the `options` of a grammar input are written
+//! there before compiling. Key ordering has no compile option: llguidance emits
+//! properties in schema order, so sorted keys are produced by sorting the
+//! `properties` of every subschema.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct JsonFormat {
+    /// Whitespace between tokens; llguidance's default is flexible
+    whitespace: Option<Whitespace>,
+    /// Separator between array items and object members, e.g. ", "
+    item_separator: Option<String>,
+    /// Separator between an object key and its value, e.g. ": "
+    key_separator: Option<String>,
+    /// Emit object keys in sorted order rather than schema order
+    sort_keys: bool,
+}
+
+/// Syntax of the output of a JSON schema grammar
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum OutputFormat {
+    #[default]
+    Json,
+    /// Block-style YAML; see the `yaml` module
+    Yaml,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Whitespace {
+    /// Any whitespace the JSON syntax allows
+    Flexible,
+    /// Single-line JSON with no spaces, e.g. `{"a":1,"b":[1,2]}`
+    Compact,
+    /// Single-line JSON with a space after separators, e.g. `{"a": 1, "b": [1, 2]}`
+    Spaced,
+}
+
+impl JsonFormat {
+    /// Apply the options to `schema`, overriding any `x-guidance` options it has
+    pub(crate) fn apply(&self, schema: &mut Value) -> Result<(), String> {
+        if self.sort_keys {
+            sort_properties(schema);
+        }
+        let Value::Object(schema) = schema else {
+            return Err("JSON format options need an object schema".to_string());
+        };
+        let guidance = schema
+            .entry("x-guidance")
+            .or_insert_with(|| Value::Object(Map::new()));
+        let Value::Object(guidance) = guidance else {
+            return Err("x-guidance in the schema must be an object".to_string());
+        };
+
+        let (item_separator, key_separator) = match self.whitespace {
+            Some(Whitespace::Compact) => (Some(","), Some(":")),
+            Some(Whitespace::Spaced) => (Some(", "), Some(": ")),
+            Some(Whitespace::Flexible) | None => (None, None),
+        };
+        if let Some(whitespace) = self.whitespace {
+            guidance.insert(
+                "whitespace_flexible".to_string(),
+                Value::Bool(matches!(whitespace, Whitespace::Flexible)),
+            );
+        }
+        let item_separator = self.item_separator.as_deref().or(item_separator);
+        let key_separator = self.key_separator.as_deref().or(key_separator);
+        if let Some(separator) = item_separator {
+            guidance.insert("item_separator".to_string(), separator.into());
+        }
+        if let Some(separator) = key_separator {
+            guidance.insert("key_separator".to_string(), separator.into());
+        }
+        Ok(())
+    }
+}
+
+/// Sort the keys of every `properties` object in `schema`
+fn sort_properties(schema: &mut Value) {
+    match schema {
+        Value::Object(map) => {
+            if let Some(Value::Object(properties)) = map.get_mut("properties") {
+                let mut sorted: Vec<(String, Value)> =
+                    std::mem::take(properties).into_iter().collect();
+                sorted.sort_by(|a, b| a.0.cmp(&b.0));
+                properties.extend(sorted);
+            }
+            map.values_mut().for_each(sort_properties);
+        }
+        Value::Array(items) => items.iter_mut().for_each(sort_properties),
+        _ => {}
+    }
+}
@@ -0,0 +1,136 @@
+//! Lark's standard terminal library
+//!
+//! Grammars written for Lark commonly pull terminals from its bundled
+//! `common.lark` with `%import common.NUMBER`, `%import common (WS, CNAME)` or
+//! `%import common.INT -> COUNT`; llguidance has no file system to import
+//! from. The imports are replaced by definitions of the same terminals, each
+//! written as a single regex since llguidance lacks Lark's `".."` ranges and
+//! lookbehind. Only the `common` module is bundled.
+
+/// The terminals of Lark's common.lark
+const COMMON: &[(&str, &str)] = &[
+    ("DIGIT", r"/[0-9]/"),
+    ("HEXDIGIT", r"/[0-9a-fA-F]/"),
+    ("INT", r"/[0-9]+/"),
+    ("SIGNED_INT", r"/[+-]?[0-9]+/"),
+    ("DECIMAL", r"/[0-9]+\.[0-9]*|\.[0-9]+/"),
+    (
+        "FLOAT",
+        r"/[0-9]+[eE][+-]?[0-9]+|([0-9]+\.[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?/",
+    ),
+    (
+        "SIGNED_FLOAT",
+        r"/[+-]?([0-9]+[eE][+-]?[0-9]+|([0-9]+\.[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?)/",
+    ),
+    (
+        "NUMBER",
+        r"/[0-9]+([eE][+-]?[0-9]+)?|([0-9]+\.[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?/",
+    ),
+    (
+        "SIGNED_NUMBER",
+        r"/[+-]?([0-9]+([eE][+-]?[0-9]+)?|([0-9]+\.[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?)/",
+    ),
+    ("ESCAPED_STRING", r#"/"(\\[^\n]|[^"\\\n])*"/"#),
+    ("LCASE_LETTER", r"/[a-z]/"),
+    ("UCASE_LETTER", r"/[A-Z]/"),
+    ("LETTER", r"/[a-zA-Z]/"),
+    ("WORD", r"/[a-zA-Z]+/"),
+    ("CNAME", r"/[_a-zA-Z][_a-zA-Z0-9]*/"),
+    ("WS_INLINE", r"/[ \t]+/"),
+    ("WS", r"/[ \t\f\r\n]+/"),
+    ("CR", r"/\r/"),
+    ("LF", r"/\n/"),
+    ("NEWLINE", r"/(\r?\n)+/"),
+    ("SQL_COMMENT", r"/--[^\n]*/"),
+    ("C_COMMENT", r"/\/\*([^*]|\*+[^*\/])*\*+\//"),
+    ("CPP_COMMENT", r"/\/\/[^\n]*/"),
+    ("SH_COMMENT", r"/#[^\n]*/"),
+];
+
+/// Replace the `%import common...` statements of a Lark grammar with the
+/// definitions they import, appended at the end
+/// Import lines are blanked rather than removed, so later line numbers in
+/// llguidance's errors still match the original grammar
+pub(crate) fn expand_imports(lark: &str) -> Result<String, String> {
+    if !lark.contains("%import") {
+        return Ok(lark.to_string());
+    }
+
+    let mut out = String::with_capacity(lark.len());
+    let mut definitions: Vec<(String, &str)> = Vec::new();
+    let mut lines = lark.lines();
+    while let Some(line) = lines.next() {
+        let Some(statement) = line.trim_start().strip_prefix("%import") else {
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        };
+        // A parenthesized list may continue on the following lines
+        let mut statement = statement.trim().to_string();
+        let mut blank_lines = 1;
+        while statement.contains('(') && !statement.contains(')') {
+            let Some(next) = lines.next() else {
+                return Err(format!("Unterminated %import {}", statement));
+            };
+            statement.push(' ');
+            statement.push_str(next.trim());
+            blank_lines += 1;
+        }
+        for (name, alias) in imported_names(&statement)? {
+            let Some(&(_, definition)) = COMMON.iter().find(|&&(n, _)| n == name) else {
+                return Err(format!(
+                    "Unknown terminal common.{}; the bundled common library has {}",
+                    name,
+                    COMMON
+                        .iter()
+                        .map(|&(n, _)| n)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            };
+            if !definitions.iter().any(|(a, _)| *a == alias) {
+                definitions.push((alias, definition));
+            }
+        }
+        out.push_str(&"\n".repeat(blank_lines));
+    }
+
+    for (alias, definition) in definitions {
+        out.push_str(&format!("{}: {}\n", alias, definition));
+    }
+    Ok(out)
+}
+
+/// The `(name, alias)` pairs of an `%import` statement, without the keyword
+fn imported_names(statement: &str) -> Result<Vec<(String, String)>, String> {
+    let unsupported = || {
+        format!(
+            "Unsupported %import {}; only Lark's common library is bundled, e.g. \
+             %import common.NUMBER",
+            statement
+        )
+    };
+
+    if let Some(list) = statement.strip_prefix("common") {
+        let list = list.trim();
+        if let Some(list) = list.strip_prefix('(').and_then(|l| l.strip_suffix(')')) {
+            return Ok(list
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(|name| (name.to_string(), name.to_string()))
+                .collect());
+        }
+        if let Some(rest) = list.strip_prefix('.') {
+            let (name, alias) = match rest.split_once("->") {
+                Some((name, alias)) => (name.trim(), alias.trim()),
+                None => (rest.trim(), rest.trim()),
+            };
+            if name.is_empty() || alias.is_empty() {
+                return Err(unsupported());
+            }
+            return Ok(vec![(name.to_string(), alias.to_string())]);
+        }
+    }
+    Err(unsupported())
+}
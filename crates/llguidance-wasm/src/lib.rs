@@ -4,236 +4,1148 @@
 //! constrained generation library, enabling grammar-based token validation
 //! for use with transformer.js.
 
-use js_sys::Uint8Array;
+mod ambiguity;
+mod analysis;
+mod batch;
+mod builder;
+mod compiled;
+mod diagnostics;
+mod ebnf;
+mod factory;
+mod fetch;
+mod gbnf;
+mod js_regex;
+mod js_tokenizer;
+mod json_format;
+mod lark_common;
+mod openapi;
+mod options;
+mod presets;
+mod sampling;
+mod speculative;
+mod state;
+mod stop;
+mod telemetry;
+mod tokenizer;
+mod tree;
+mod typescript;
+mod xml;
+mod yaml;
+
+use js_sys::{Array, Float32Array, Uint32Array, Uint8Array};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
 use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
-use llguidance::api::TopLevelGrammar;
-use llguidance::toktrie::{ApproximateTokEnv, TokRxInfo, TokTrie};
-use llguidance::{Matcher, ParserFactory};
+use llguidance::api::{GrammarWithLexer, StopReason, TopLevelGrammar};
+use llguidance::toktrie::{SimpleVob, TokenId};
+use llguidance::{Matcher, ParserFactory, TokenParser};
+
+use json_format::{JsonFormat, OutputFormat};
+use options::{IdRemap, ParserOptions};
+use sampling::SampleOptions;
+use state::ParserState;
+use stop::StopSequences;
+use telemetry::Telemetry;
+use tokenizer::TokEnv;
 
 /// Grammar definition passed from JavaScript
 #[derive(Debug, Deserialize)]
 struct GrammarInput {
     grammars: Vec<GrammarSpec>,
+    /// Token limit for the whole generation
+    #[serde(default)]
+    max_tokens: Option<usize>,
+    /// Leave the output unconstrained until this appears; see `lazy_grammar`
+    #[serde(default)]
+    trigger: Option<Trigger>,
+    /// Strings that end generation; see `stop_regex`
+    #[serde(default)]
+    stop: Vec<String>,
 }
 
+/// Text around the parts of the output a lazy grammar constrains
 #[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum GrammarSpec {
-    JsonSchema { json_schema: serde_json::Value },
-    Regex { rx: String },
-    Lark { lark: String },
+struct Trigger {
+    start: String,
+    #[serde(default)]
+    end: Option<String>,
 }
 
-/// Tokenizer data passed from JavaScript
-/// This matches the TokenizerData interface in TypeScript
+/// One grammar of the input; the first is the root, the rest are sub-grammars
+/// a Lark grammar references as `@name`
 #[derive(Debug, Deserialize)]
-struct TokenizerInput {
-    /// Vocabulary mapping token strings to IDs
-    vocab: HashMap<String, u32>,
-    /// Optional BPE merges (not used for trie construction but kept for compatibility)
-    /// Can be either strings like "Ġ t" or arrays like ["Ġ", "t"]
-    #[serde(default, deserialize_with = "deserialize_merges")]
-    #[allow(dead_code)]
-    merges: Vec<String>,
-    /// Added tokens (special tokens)
-    #[serde(default)]
-    added_tokens: Vec<AddedToken>,
-    /// Model type (e.g., "bpe", "wordpiece")
-    /// For Transformer.js compatibility, we keep this field but it is not used.
+struct GrammarSpec {
+    #[serde(flatten)]
+    source: GrammarSource,
     #[serde(default)]
-    #[allow(dead_code)]
-    model_type: Option<String>,
-    /// Special token IDs
+    name: Option<String>,
     #[serde(default)]
-    eos_token_id: Option<u32>,
-    #[serde(default)]
-    bos_token_id: Option<u32>,
-    #[serde(default)]
-    pad_token_id: Option<u32>,
-    #[serde(default)]
-    unk_token_id: Option<u32>,
+    max_tokens: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum GrammarSource {
+    JsonSchema {
+        json_schema: serde_json::Value,
+        #[serde(default)]
+        options: Option<JsonFormat>,
+        #[serde(default)]
+        format: OutputFormat,
+    },
+    /// Any JSON object, like OpenAI's `response_format: {type: "json_object"}`
+    JsonObject {
+        json_object: bool,
+        #[serde(default)]
+        options: Option<JsonFormat>,
+    },
+    /// JSON matching a TypeScript interface or type alias
+    Typescript {
+        typescript: String,
+        /// Declaration to generate; the first one if unset
+        #[serde(default)]
+        root_type: Option<String>,
+        #[serde(default)]
+        options: Option<JsonFormat>,
+    },
+    /// JSON matching the response or request body of an OpenAPI operation
+    OpenApi {
+        openapi: serde_json::Value,
+        operation_id: String,
+        #[serde(default)]
+        target: openapi::Target,
+        /// Response status; the first 2XX response if unset
+        #[serde(default)]
+        status: Option<String>,
+        /// Media type; JSON content if unset
+        #[serde(default)]
+        media_type: Option<String>,
+        #[serde(default)]
+        options: Option<JsonFormat>,
+    },
+    /// A JavaScript regex, with its flags
+    Regex {
+        rx: String,
+        #[serde(default)]
+        flags: String,
+    },
+    Lark {
+        lark: String,
+    },
+    Gbnf {
+        gbnf: String,
+    },
+    Ebnf {
+        ebnf: String,
+    },
+    /// Well-formed XML for an element tree or an XSD schema
+    Xml {
+        xml: xml::XmlSpec,
+    },
+    /// A built-in grammar, such as read-only SQL
+    Preset {
+        preset: String,
+        #[serde(default)]
+        dialect: Option<String>,
+        #[serde(default)]
+        statements: Option<Vec<String>>,
+    },
+    /// Output matching any one of the alternatives
+    AnyOf {
+        any_of: Vec<GrammarSpec>,
+    },
+    /// Output matching each stage in turn
+    Sequence {
+        sequence: Vec<GrammarSpec>,
+    },
+    /// Free text up to and including the first occurrence of a delimiter
+    TextUntil {
+        text_until: String,
+    },
+}
+
+impl GrammarSource {
+    fn kind(&self) -> &'static str {
+        match self {
+            GrammarSource::JsonSchema { .. } => "JSON schema",
+            GrammarSource::JsonObject { .. } => "JSON object",
+            GrammarSource::Typescript { .. } => "TypeScript type",
+            GrammarSource::OpenApi { .. } => "OpenAPI operation",
+            GrammarSource::Regex { .. } => "regex",
+            GrammarSource::Lark { .. } => "Lark grammar",
+            GrammarSource::Gbnf { .. } => "GBNF grammar",
+            GrammarSource::Ebnf { .. } => "EBNF grammar",
+            GrammarSource::Xml { .. } => "XML grammar",
+            GrammarSource::Preset { .. } => "grammar preset",
+            GrammarSource::AnyOf { .. } => "grammar union",
+            GrammarSource::Sequence { .. } => "grammar sequence",
+            GrammarSource::TextUntil { .. } => "text_until grammar",
+        }
+    }
+
+    /// Compile to llguidance grammars; `prefix` keeps the names generated for
+    /// nested grammars apart
+    fn to_grammar(&self, prefix: &str) -> Result<TopLevelGrammar, String> {
+        Ok(match self {
+            GrammarSource::JsonSchema {
+                json_schema,
+                options,
+                format: OutputFormat::Yaml,
+            } => {
+                if options.is_some() {
+                    return Err("JSON format options do not apply to YAML output".to_string());
+                }
+                TopLevelGrammar::from_lark(yaml::schema_to_lark(json_schema)?)
+            }
+            GrammarSource::JsonSchema {
+                json_schema,
+                options,
+                format: OutputFormat::Json,
+            } => json_grammar(json_schema.clone(), options.as_ref())?,
+            GrammarSource::JsonObject {
+                json_object,
+                options,
+            } => {
+                if !json_object {
+                    return Err("json_object must be true".to_string());
+                }
+                json_grammar(serde_json::json!({ "type": "object" }), options.as_ref())?
+            }
+            GrammarSource::Typescript {
+                typescript,
+                root_type,
+                options,
+            } => {
+                let schema = typescript::typescript_to_schema(typescript, root_type.as_deref())?;
+                json_grammar(schema, options.as_ref())?
+            }
+            GrammarSource::OpenApi {
+                openapi,
+                operation_id,
+                target,
+                status,
+                media_type,
+                options,
+            } => {
+                let schema = openapi::operation_schema(
+                    openapi,
+                    operation_id,
+                    *target,
+                    status.as_deref(),
+                    media_type.as_deref(),
+                )?;
+                json_grammar(schema, options.as_ref())?
+            }
+            GrammarSource::Regex { rx, flags } => {
+                // Create a lark grammar that matches the regex
+                let rx = js_regex::translate(rx, flags)?;
+                TopLevelGrammar::from_lark(format!("start: /{}/", rx))
+            }
+            GrammarSource::Lark { lark } => {
+                TopLevelGrammar::from_lark(lark_common::expand_imports(lark)?)
+            }
+            GrammarSource::Gbnf { gbnf } => TopLevelGrammar::from_lark(gbnf::gbnf_to_lark(gbnf)?),
+            GrammarSource::Ebnf { ebnf } => TopLevelGrammar::from_lark(ebnf::ebnf_to_lark(ebnf)?),
+            GrammarSource::Xml { xml } => TopLevelGrammar::from_lark(xml::xml_to_lark(xml)?),
+            GrammarSource::Preset {
+                preset,
+                dialect,
+                statements,
+            } => TopLevelGrammar::from_lark(presets::preset_to_lark(
+                preset,
+                dialect.as_deref(),
+                statements.as_deref(),
+            )?),
+            GrammarSource::AnyOf { any_of } => {
+                compose(self, any_of, &format!("{}any_of_", prefix), " | ")?
+            }
+            GrammarSource::Sequence { sequence } => {
+                compose(self, sequence, &format!("{}sequence_", prefix), " ")?
+            }
+            GrammarSource::TextUntil { text_until } => {
+                TopLevelGrammar::from_lark(text_until_lark(text_until, None)?)
+            }
+        })
+    }
+
+    /// Compile to an llguidance grammar that also ends at the first stop
+    /// sequence `stop` matches, if the whole text is free up to there
+    /// Other grammars are left to `StopSequences`
+    fn to_grammar_with_stop(&self, stop: &str) -> Result<Option<TopLevelGrammar>, String> {
+        Ok(Some(match self {
+            GrammarSource::Regex { rx, flags } => {
+                // Anything the regex matches or any text ending at a stop
+                // sequence, but nothing going on past one, so that a stop in
+                // the middle of a match still ends the text
+                let rx = js_regex::translate(rx, flags)?;
+                TopLevelGrammar::from_lark(format!(
+                    "start: TEXT\nTEXT: (/{}/ | /(?s:.*){}/) & ~/(?s:.*{}.+)/",
+                    rx, stop, stop
+                ))
+            }
+            GrammarSource::TextUntil { text_until } => {
+                TopLevelGrammar::from_lark(text_until_lark(text_until, Some(stop))?)
+            }
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Compile to llguidance grammars, the first of which is called `name`
+    fn to_named_grammars(&self, name: &str) -> Result<Vec<GrammarWithLexer>, String> {
+        let mut grammars = self.to_grammar(&format!("{}_", name))?.grammars;
+        grammars[0].name = Some(name.to_string());
+        Ok(grammars)
+    }
+
+    /// Whether this grammar can reference other grammars
+    fn can_reference(&self) -> bool {
+        matches!(
+            self,
+            GrammarSource::Lark { .. }
+                | GrammarSource::AnyOf { .. }
+                | GrammarSource::Sequence { .. }
+        )
+    }
+
+    /// Whether this grammar references the grammar called `name` as `@name`
+    fn references(&self, name: &str) -> bool {
+        let lark = match self {
+            GrammarSource::Lark { lark } => lark,
+            GrammarSource::AnyOf { any_of: parts }
+            | GrammarSource::Sequence { sequence: parts } => {
+                return parts.iter().any(|spec| spec.source.references(name));
+            }
+            _ => return false,
+        };
+        let reference = format!("@{}", name);
+        lark.match_indices(&reference).any(|(start, _)| {
+            !lark[start + reference.len()..].starts_with(|c: char| c.is_alphanumeric() || c == '_')
+        })
+    }
 }
 
-/// Custom deserializer for merges that handles both string and array formats
-fn deserialize_merges<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    use serde::de::{self, SeqAccess, Visitor};
-    use std::fmt;
+/// Lark for free text up to and including the first `delimiter`, or the
+/// first stop sequence `stop` matches
+fn text_until_lark(delimiter: &str, stop: Option<&str>) -> Result<String, String> {
+    if delimiter.is_empty() {
+        return Err("text_until needs a non-empty delimiter".to_string());
+    }
+    // One lexeme, so the lexer cannot end the text early: anything
+    // ending with the delimiter and containing it nowhere else
+    let delimiter = escape_regex(delimiter);
+    Ok(match stop {
+        None => format!(
+            "start: TEXT\nTEXT: /(?s:.*){}/ & ~/(?s:.*{}.+)/",
+            delimiter, delimiter
+        ),
+        Some(stop) => format!(
+            "start: TEXT\nTEXT: (/(?s:.*){}/ | /(?s:.*){}/) & ~/(?s:.*{}.+)/ & ~/(?s:.*{}.+)/",
+            delimiter, stop, delimiter, stop
+        ),
+    })
+}
 
-    struct MergesVisitor;
+/// A regex group matching any of the stop sequences, or None without any
+fn stop_regex(stop: &[String]) -> Option<String> {
+    let sequences: Vec<String> = stop
+        .iter()
+        .filter(|s| !s.is_empty())
+        .map(|s| escape_regex(s))
+        .collect();
+    if sequences.is_empty() {
+        return None;
+    }
+    Some(format!("(?:{})", sequences.join("|")))
+}
 
-    impl<'de> Visitor<'de> for MergesVisitor {
-        type Value = Vec<String>;
+/// The grammar for a JSON schema, laid out as `options` ask
+fn json_grammar(
+    mut schema: serde_json::Value,
+    options: Option<&JsonFormat>,
+) -> Result<TopLevelGrammar, String> {
+    if let Some(options) = options {
+        options.apply(&mut schema)?;
+    }
+    Ok(TopLevelGrammar::from_json_schema(schema))
+}
 
-        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-            formatter.write_str("a sequence of strings or arrays of strings")
+/// The grammar for a union or sequence: a Lark rule joining `parts` with
+/// `separator`, each part a sub-grammar named `prefix` and its index
+fn compose(
+    composite: &GrammarSource,
+    parts: &[GrammarSpec],
+    prefix: &str,
+    separator: &str,
+) -> Result<TopLevelGrammar, String> {
+    let part = match composite {
+        GrammarSource::AnyOf { .. } => "alternative",
+        _ => "stage",
+    };
+    if parts.is_empty() {
+        return Err(format!(
+            "A {} needs at least one {}",
+            composite.kind(),
+            part
+        ));
+    }
+    let names: Vec<String> = (0..parts.len())
+        .map(|i| format!("{}{}", prefix, i))
+        .collect();
+    let references: Vec<String> = names.iter().map(|name| format!("@{}", name)).collect();
+    let mut grammar = TopLevelGrammar::from_lark(format!("start: {}", references.join(separator)));
+    for (spec, name) in parts.iter().zip(&names) {
+        if spec.max_tokens.is_some() {
+            return Err(format!(
+                "max_tokens on a {} {} is not supported; set it on the {} itself",
+                composite.kind(),
+                part,
+                composite.kind()
+            ));
         }
+        grammar
+            .grammars
+            .extend(spec.source.to_named_grammars(name)?);
+    }
+    Ok(grammar)
+}
 
-        fn visit_seq<S>(self, mut seq: S) -> Result<Vec<String>, S::Error>
-        where
-            S: SeqAccess<'de>,
-        {
-            let mut merges = Vec::new();
-
-            while let Some(value) = seq.next_element::<serde_json::Value>()? {
-                let merge_str = match value {
-                    serde_json::Value::String(s) => s,
-                    serde_json::Value::Array(arr) => {
-                        // Convert array like ["Ġ", "t"] to string "Ġ t"
-                        let parts: Vec<String> = arr
-                            .into_iter()
-                            .filter_map(|v| v.as_str().map(String::from))
-                            .collect();
-                        parts.join(" ")
-                    }
-                    _ => {
-                        return Err(de::Error::custom(
-                            "merge must be a string or array of strings",
-                        ))
-                    }
-                };
-                merges.push(merge_str);
+/// A grammar that leaves the output free until `trigger.start` appears, then
+/// enforces `grammar` (after optional whitespace) up to `trigger.end`, and is
+/// free again after it until the next trigger, like Hermes-style tool calls
+/// Without an end, generation ends with the grammar, and a stop sequence
+/// matched by `stop` in the free text ends it there
+fn lazy_grammar(
+    mut grammar: TopLevelGrammar,
+    trigger: &Trigger,
+    stop: Option<&str>,
+) -> Result<TopLevelGrammar, String> {
+    if trigger.start.is_empty() {
+        return Err("A trigger needs a non-empty start".to_string());
+    }
+    let name = grammar.grammars[0]
+        .name
+        .get_or_insert_with(|| "triggered".to_string())
+        .clone();
+
+    // Free text through the first trigger, and free text without one; single
+    // lexemes, like text_until, so the lexer cannot end the text early
+    let start = escape_regex(&trigger.start);
+    let rule = match &trigger.end {
+        Some(end) => format!(
+            "start: (UNTIL_TRIGGER WS? @{} WS? {})* FREE?",
+            name,
+            serde_json::to_string(end).unwrap()
+        ),
+        None => format!("start: FREE? | UNTIL_TRIGGER WS? @{}", name),
+    };
+    // Text with a stop sequence can only be free text, which ends there
+    let (until_stop, free_stop) = match stop {
+        Some(stop) => (
+            format!(" & ~/(?s:.*{}.*)/", stop),
+            format!(" & ~/(?s:.*{}.+)/", stop),
+        ),
+        None => Default::default(),
+    };
+    let mut lazy = TopLevelGrammar::from_lark(format!(
+        "{}\nUNTIL_TRIGGER: /(?s:.*){}/ & ~/(?s:.*{}.+)/{}\nFREE: /(?s:.+)/ & ~/(?s:.*{}.*)/{}\n\
+         WS: /[ \\t\\r\\n]+/",
+        rule, start, start, until_stop, start, free_stop
+    ));
+    lazy.grammars.append(&mut grammar.grammars);
+    Ok(lazy)
+}
+
+/// Escape `text` to match literally inside a Lark `/.../` regex
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$'
+            | '/' => {
+                escaped.push('\\');
+                escaped.push(c);
             }
+            c if c.is_control() => escaped.push_str(&format!("\\x{{{:X}}}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// What `advance` did with a token, so `rollback` can undo it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    /// The trigger token switched grammar enforcement on
+    Trigger,
+    /// Passthrough or rejected force-allowed token; the grammar never saw it
+    Skipped,
+    /// The grammar consumed the token
+    Consumed,
+    /// A grammar pushed with `push_grammar`, and since popped, consumed the token
+    Nested,
+}
+
+/// A grammar set aside by `push_grammar`, at the position it was left
+struct GrammarFrame {
+    grammar: TopLevelGrammar,
+    matcher: Matcher,
+    initial: Rc<Matcher>,
+    grammar_warnings: Vec<String>,
+    stop_sequences: StopSequences,
+    active: bool,
+    /// Length of the history when the grammar was set aside
+    history_len: usize,
+}
 
-            Ok(merges)
+impl GrammarFrame {
+    fn deep_clone(&self) -> GrammarFrame {
+        GrammarFrame {
+            grammar: self.grammar.clone(),
+            matcher: self.matcher.deep_clone(),
+            initial: self.initial.clone(),
+            grammar_warnings: self.grammar_warnings.clone(),
+            stop_sequences: self.stop_sequences.clone(),
+            active: self.active,
+            history_len: self.history_len,
         }
     }
+}
+
+/// Stable name of a stop reason, as listed in the `StopReason` type in wasm.d.ts
+fn stop_reason_name(reason: StopReason) -> &'static str {
+    match reason {
+        StopReason::NotStopped => "NotStopped",
+        StopReason::EndOfSentence => "EndOfSentence",
+        StopReason::NoExtension | StopReason::NoExtensionBias => "NoExtension",
+        StopReason::MaxTokensTotal | StopReason::MaxTokensParser => "MaxTokens",
+        // Internal errors and grammars too complex for the lexer or parser
+        _ => "ParserError",
+    }
+}
 
-    deserializer.deserialize_seq(MergesVisitor)
+/// A printable form of a token's bytes: text quoted with escapes, special
+/// tokens as-is
+fn display_token(token: &[u8]) -> String {
+    if let Some(special) = token.strip_prefix(&[0xFF]) {
+        return String::from_utf8_lossy(special).into_owned();
+    }
+    match std::str::from_utf8(token) {
+        Ok(text) => format!("{:?}", text),
+        Err(_) => format!("b\"{}\"", token.escape_ascii()),
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct AddedToken {
-    id: u32,
-    content: String,
-    #[serde(default)]
-    special: bool,
+/// A parser factory together with the tokenizer it was built from
+#[derive(Clone)]
+struct CachedFactory {
+    factory: Arc<ParserFactory>,
+    tok_env: TokEnv,
+    vocab_size: usize,
+}
+
+thread_local! {
+    /// Parser factories keyed by a hash of the tokenizer JSON they were built from,
+    /// so parsers created for the same tokenizer share one compiled token trie
+    static FACTORY_CACHE: RefCell<HashMap<u64, CachedFactory>> = RefCell::new(HashMap::new());
+
+    /// Factory over a vocabulary of single bytes, built on first use by `validate_grammar`
+    static VALIDATION_FACTORY: RefCell<Option<Arc<ParserFactory>>> = RefCell::new(None);
+
+    /// Grammars registered by name, converted once; `"@name"` stands for them
+    /// wherever grammar JSON is expected
+    static GRAMMAR_REGISTRY: RefCell<HashMap<String, (TopLevelGrammar, StopSequences)>> =
+        RefCell::new(HashMap::new());
+}
+
+fn tokenizer_cache_key(tokenizer_json: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    tokenizer_json.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Drop all cached tokenizer environments
+/// Parsers that are still alive keep their own reference and are unaffected
+#[wasm_bindgen]
+pub fn clear_tokenizer_cache() {
+    FACTORY_CACHE.with(|cache| cache.borrow_mut().clear());
+}
+
+/// Convert `grammar_json` once and keep it under `name`, so parsers can be
+/// created or reset with `"@name"` in place of the grammar JSON
+/// Registering a name again replaces the grammar for parsers created later
+#[wasm_bindgen]
+pub fn register_grammar(name: &str, grammar_json: &str) -> Result<(), JsValue> {
+    if name.is_empty() || name.starts_with('@') {
+        return Err(JsValue::from_str(&format!(
+            "Invalid grammar name \"{}\"; register it without the leading @",
+            name
+        )));
+    }
+    let grammar =
+        LLGuidanceParser::load_grammar(grammar_json).map_err(|e| JsValue::from_str(&e))?;
+    GRAMMAR_REGISTRY.with(|registry| registry.borrow_mut().insert(name.to_string(), grammar));
+    Ok(())
+}
+
+/// Remove a registered grammar; returns whether it was registered
+/// Parsers already created from it are unaffected
+#[wasm_bindgen]
+pub fn unregister_grammar(name: &str) -> bool {
+    GRAMMAR_REGISTRY.with(|registry| registry.borrow_mut().remove(name).is_some())
+}
+
+/// Size of the module's linear memory in bytes
+/// Linear memory never shrinks, so this is the peak heap size so far
+#[wasm_bindgen]
+pub fn wasm_memory_bytes() -> usize {
+    #[cfg(target_arch = "wasm32")]
+    {
+        core::arch::wasm32::memory_size(0) * 65536
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        0
+    }
+}
+
+/// Translate a JS-facing token ID to the tokenizer's own ID, if it names a token
+fn lookup_token(
+    id_remap: Option<&IdRemap>,
+    tok_vocab_size: usize,
+    token_id: u32,
+) -> Option<TokenId> {
+    match id_remap {
+        Some(remap) => remap.tokenizer_id(token_id),
+        None => Some(token_id).filter(|&id| (id as usize) < tok_vocab_size),
+    }
 }
 
 /// The main parser struct exposed to JavaScript
 #[wasm_bindgen]
 pub struct LLGuidanceParser {
     factory: Arc<ParserFactory>,
+    tok_env: TokEnv,
+    /// The grammar the matcher was last created from
+    grammar: TopLevelGrammar,
     matcher: Matcher,
+    /// Matcher at the start of the grammar, copied instead of recompiling it
+    initial: Rc<Matcher>,
+    /// Length of the token mask, at least the tokenizer vocabulary size
     vocab_size: usize,
+    /// Number of tokens known to the tokenizer
+    tok_vocab_size: usize,
+    /// Tokens removed from every mask regardless of the grammar
+    banned_tokens: Vec<TokenId>,
+    /// Translation to the runtime's token IDs, if they differ from the tokenizer's
+    id_remap: Option<IdRemap>,
+    /// Tokens that bypass the grammar entirely
+    passthrough_tokens: Vec<TokenId>,
+    /// Token that switches grammar enforcement on
+    trigger_token: Option<TokenId>,
+    /// Whether the grammar is being enforced, i.e. the trigger token has been seen
+    active: bool,
+    /// Tokens banned from every mask, set from JavaScript at any time
+    deny_list: Vec<TokenId>,
+    /// Tokens added to every mask, winning over the deny list
+    force_allow_list: Vec<TokenId>,
+    /// Logit biases for allowed and disallowed tokens
+    allowed_bias: f32,
+    disallowed_bias: f32,
+    /// Most tokens a single `rollback` may undo, if limited
+    max_rollback_tokens: Option<usize>,
+    /// Mask for the current position, until the parser next moves
+    cached_mask: Option<Rc<SimpleVob>>,
+    /// Scratch buffer for byte masks, reused across steps
+    mask_buf: Vec<u8>,
+    /// Mask last reported by `get_token_mask_diff`
+    diff_base: Vec<u8>,
+    /// Counters for the current generation
+    telemetry: Telemetry,
+    /// Prompt token rolled back by `enable_token_healing`, if any
+    healing_token: Option<TokenId>,
+    /// Tokens accepted since the start of the grammar, for `save_state` and `rollback`
+    history: Vec<(TokenId, Step)>,
+    /// Warnings from compiling the grammar (e.g. unsupported schema keywords)
+    grammar_warnings: Vec<String>,
+    /// Stop sequences from the grammar input and whether one has appeared
+    stop_sequences: StopSequences,
+    /// Grammars set aside by `push_grammar`, innermost last
+    grammar_stack: Vec<GrammarFrame>,
+    /// A parser replayed up to `history[..n]`, caught up by `get_temperature`
+    shadow: Option<(TokenParser, usize)>,
 }
 
 #[wasm_bindgen]
 impl LLGuidanceParser {
     /// Create a new parser with the given grammar and tokenizer configuration
     #[wasm_bindgen(constructor)]
-    pub fn new(grammar_json: &str, tokenizer_json: &str) -> Result<LLGuidanceParser, JsValue> {
+    pub fn new(
+        grammar_json: &str,
+        tokenizer_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
         // Set up panic hook for better error messages
         #[cfg(feature = "console_error_panic_hook")]
         console_error_panic_hook::set_once();
 
-        Self::new_inner(grammar_json, tokenizer_json).map_err(|e| JsValue::from_str(&e))
+        Self::new_inner(grammar_json, tokenizer_json, options_json.as_deref())
+            .map_err(|e| JsValue::from_str(&e))
     }
 
-    fn new_inner(grammar_json: &str, tokenizer_json: &str) -> Result<LLGuidanceParser, String> {
-        // Parse the grammar
-        let grammar = Self::parse_grammar(grammar_json)?;
+    /// Create a new parser from a tokenizer.json fetched from `tokenizer_url`
+    /// The file is downloaded and parsed inside the WASM module, never as a JS object
+    #[wasm_bindgen]
+    pub async fn from_url(
+        grammar_json: String,
+        tokenizer_url: String,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        let tokenizer_json = fetch::fetch_text(&tokenizer_url)
+            .await
+            .map_err(|e| JsValue::from_str(&e))?;
+        Self::new_inner(&grammar_json, &tokenizer_json, options_json.as_deref())
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Create a new parser from token byte strings indexed by token ID,
+    /// bypassing tokenizer.json parsing
+    #[wasm_bindgen]
+    pub fn from_vocab(
+        tokens: Array,
+        eos_token_id: u32,
+        grammar_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        let words = tokens
+            .iter()
+            .map(|token| Uint8Array::new(&token).to_vec())
+            .collect();
+
+        tokenizer::tok_env_from_words(words, eos_token_id)
+            .and_then(|tok_env| Self::from_tok_env(grammar_json, tok_env, options_json.as_deref()))
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Create a new parser backed by a live JavaScript tokenizer object
+    /// implementing `{ encode(text), decode(ids), vocabSize, eosTokenId }`
+    #[wasm_bindgen]
+    pub fn from_tokenizer(
+        tokenizer: JsValue,
+        grammar_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        js_tokenizer::create_js_tok_env(&tokenizer)
+            .and_then(|tok_env| Self::from_tok_env(grammar_json, tok_env, options_json.as_deref()))
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Create a new parser from a tiktoken BPE rank file (`.tiktoken`)
+    /// and a JSON map of special token contents to IDs
+    #[wasm_bindgen]
+    pub fn from_tiktoken(
+        bpe_ranks: &str,
+        special_tokens_json: &str,
+        grammar_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        tokenizer::create_tiktoken_env(bpe_ranks, special_tokens_json)
+            .and_then(|tok_env| Self::from_tok_env(grammar_json, tok_env, options_json.as_deref()))
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Create a new parser from GGUF tokenizer metadata
+    /// (`tokenizer.ggml.tokens`, `scores`, `token_type`, ... as JSON)
+    #[wasm_bindgen]
+    pub fn from_gguf(
+        metadata_json: &str,
+        grammar_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        tokenizer::create_gguf_tok_env(metadata_json)
+            .and_then(|tok_env| Self::from_tok_env(grammar_json, tok_env, options_json.as_deref()))
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Create a new parser from a tokenizer environment exported with
+    /// `export_tok_env()`, skipping tokenizer parsing entirely
+    #[wasm_bindgen]
+    pub fn from_tok_env_blob(
+        blob: &[u8],
+        grammar_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        tokenizer::tok_env_from_blob(blob)
+            .and_then(|tok_env| Self::from_tok_env(grammar_json, tok_env, options_json.as_deref()))
+            .map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Compile a grammar without a tokenizer and report `{ ok, errors, warnings }`
+    /// as JSON, e.g. to lint grammars ahead of time; no parser is kept
+    #[wasm_bindgen]
+    pub fn validate_grammar(grammar_json: &str) -> Result<String, JsValue> {
+        let compiled = Self::validation_factory().and_then(|factory| {
+            let (grammar, _) = Self::load_grammar(grammar_json)?;
+            let (matcher, warnings) = Self::create_matcher(&factory, &grammar);
+            Ok((matcher.get_error(), warnings))
+        });
+        let (error, warnings) = compiled.unwrap_or_else(|e| (Some(e), Vec::new()));
+        let report = diagnostics::GrammarValidation::new(error, warnings);
+        serde_json::to_string(&report)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize validation: {}", e)))
+    }
+
+    /// Convert and check a grammar once, returning a blob that
+    /// `from_compiled_grammar` loads without converting it again
+    #[wasm_bindgen]
+    pub fn compile_grammar(grammar_json: &str) -> Result<Vec<u8>, JsValue> {
+        Self::compile_inner(grammar_json).map_err(|e| JsValue::from_str(&e))
+    }
+
+    fn compile_inner(grammar_json: &str) -> Result<Vec<u8>, String> {
+        let (grammar, stop_sequences) = Self::load_grammar(grammar_json)?;
+        let (matcher, _) = Self::create_matcher(&*Self::validation_factory()?, &grammar);
+        if let Some(e) = matcher.get_error() {
+            return Err(e);
+        }
+        compiled::CompiledGrammar {
+            grammar,
+            stop_sequences: stop_sequences.sequences().to_vec(),
+        }
+        .to_bytes()
+    }
+
+    /// Create a new parser from a grammar returned by `compile_grammar` and
+    /// a tokenizer configuration
+    #[wasm_bindgen]
+    pub fn from_compiled_grammar(
+        compiled: &[u8],
+        tokenizer_json: &str,
+        options_json: Option<String>,
+    ) -> Result<LLGuidanceParser, JsValue> {
+        #[cfg(feature = "console_error_panic_hook")]
+        console_error_panic_hook::set_once();
+
+        let load = || {
+            let compiled = compiled::CompiledGrammar::from_bytes(compiled)?;
+            let options = ParserOptions::parse(options_json.as_deref())?;
+            let cached = Self::cached_factory(tokenizer_json)?;
+            let stop_sequences = StopSequences::from_sequences(compiled.stop_sequences);
+            Self::from_grammar(compiled.grammar, stop_sequences, cached, &options)
+        };
+        load().map_err(|e| JsValue::from_str(&e))
+    }
+
+    /// Estimate how expensive a grammar is to enforce, as JSON: rule and lexeme
+    /// counts, lexer size, recursive rules, ambiguity hints and a cost class
+    #[wasm_bindgen]
+    pub fn analyze_grammar(grammar_json: &str) -> Result<String, JsValue> {
+        let (grammar, _) = Self::load_grammar(grammar_json).map_err(|e| JsValue::from_str(&e))?;
+        serde_json::to_string(&analysis::analyze(&grammar))
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize analysis: {}", e)))
+    }
+
+    /// The factory `validate_grammar` compiles with, whose tokens are the
+    /// single bytes; grammars compile the same for any tokenizer
+    fn validation_factory() -> Result<Arc<ParserFactory>, String> {
+        if let Some(factory) = VALIDATION_FACTORY.with(|cell| cell.borrow().clone()) {
+            return Ok(factory);
+        }
+        let mut words: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).collect();
+        // Special tokens start with 0xFF, like EOS in a real vocabulary
+        words.push(b"\xFF</s>".to_vec());
+        let eos_token = words.len() as u32 - 1;
+        let tok_env = tokenizer::tok_env_from_words(words, eos_token)?;
+        let factory = Self::create_factory(&tok_env)?.factory;
+        VALIDATION_FACTORY.with(|cell| *cell.borrow_mut() = Some(factory.clone()));
+        Ok(factory)
+    }
+
+    fn new_inner(
+        grammar_json: &str,
+        tokenizer_json: &str,
+        options_json: Option<&str>,
+    ) -> Result<LLGuidanceParser, String> {
+        let options = ParserOptions::parse(options_json)?;
+        let cached = Self::cached_factory(tokenizer_json)?;
+        Self::from_factory(grammar_json, cached, &options)
+    }
+
+    /// The factory for `tokenizer_json`, shared with any parser built from an
+    /// identical tokenizer before
+    fn cached_factory(tokenizer_json: &str) -> Result<CachedFactory, String> {
+        let key = tokenizer_cache_key(tokenizer_json);
+        if let Some(cached) = FACTORY_CACHE.with(|cache| cache.borrow().get(&key).cloned()) {
+            return Ok(cached);
+        }
 
         // Create tokenizer environment
-        let tok_env = Self::create_tok_env(tokenizer_json)?;
-        let vocab_size = tok_env.tok_trie().vocab_size();
+        let tok_env = tokenizer::create_tok_env(tokenizer_json)?;
+        let cached = Self::create_factory(&tok_env)?;
+        FACTORY_CACHE.with(|cache| cache.borrow_mut().insert(key, cached.clone()));
+        Ok(cached)
+    }
+
+    fn from_tok_env(
+        grammar_json: &str,
+        tok_env: TokEnv,
+        options_json: Option<&str>,
+    ) -> Result<LLGuidanceParser, String> {
+        let options = ParserOptions::parse(options_json)?;
+        let cached = Self::create_factory(&tok_env)?;
+        Self::from_factory(grammar_json, cached, &options)
+    }
+
+    fn create_factory(tok_env: &TokEnv) -> Result<CachedFactory, String> {
+        let vocab_size = tok_env.model_vocab_size();
 
         // Create parser factory
-        let mut factory = ParserFactory::new_simple(&tok_env)
+        let mut factory = ParserFactory::new_simple(&tok_env.clone().into_tok_env())
             .map_err(|e| format!("Failed to create parser factory: {}", e))?;
 
-        // Minimal logging
+        // Nothing on stderr; warnings are buffered and returned by `grammar_warnings`
         factory.set_stderr_log_level(0);
+        factory.set_buffer_log_level(1);
+
+        Ok(CachedFactory {
+            factory: Arc::new(factory),
+            tok_env: tok_env.clone(),
+            vocab_size,
+        })
+    }
+
+    fn from_factory(
+        grammar_json: &str,
+        cached: CachedFactory,
+        options: &ParserOptions,
+    ) -> Result<LLGuidanceParser, String> {
+        let (grammar, stop_sequences) = Self::load_grammar(grammar_json)?;
+        Self::from_grammar(grammar, stop_sequences, cached, options)
+    }
 
-        let factory = Arc::new(factory);
+    fn from_grammar(
+        grammar: TopLevelGrammar,
+        stop_sequences: StopSequences,
+        cached: CachedFactory,
+        options: &ParserOptions,
+    ) -> Result<LLGuidanceParser, String> {
+        let CachedFactory {
+            factory,
+            tok_env,
+            vocab_size: tok_vocab_size,
+        } = cached;
+        let id_remap = options.id_remap(tok_vocab_size)?;
+        let vocab_size = options.mask_len(
+            id_remap
+                .as_ref()
+                .map_or(tok_vocab_size, IdRemap::runtime_len),
+        )?;
+        let option_token = |token_id: u32| {
+            lookup_token(id_remap.as_ref(), tok_vocab_size, token_id).ok_or_else(|| {
+                format!(
+                    "Token ID {} in parser options does not belong to a token in the vocabulary",
+                    token_id
+                )
+            })
+        };
+        let passthrough_tokens = options
+            .passthrough_tokens
+            .iter()
+            .map(|&token_id| option_token(token_id))
+            .collect::<Result<Vec<_>, _>>()?;
+        let trigger_token = options.trigger_token.map(option_token).transpose()?;
+        let banned_tokens = if options.exclude_special_tokens {
+            tokenizer::special_token_ids(tok_env.tok_trie())
+        } else {
+            Vec::new()
+        };
 
         // Create the parser and matcher
-        let parser = factory.create_parser(grammar);
-        let matcher = Matcher::new(parser);
+        let (matcher, grammar_warnings) = Self::create_matcher(&factory, &grammar);
+        let initial = Rc::new(matcher.deep_clone());
 
         Ok(LLGuidanceParser {
             factory,
+            tok_env,
+            grammar,
             matcher,
+            initial,
             vocab_size,
+            tok_vocab_size,
+            banned_tokens,
+            id_remap,
+            passthrough_tokens,
+            trigger_token,
+            active: trigger_token.is_none(),
+            deny_list: Vec::new(),
+            force_allow_list: Vec::new(),
+            allowed_bias: options.allowed_bias,
+            disallowed_bias: options.disallowed_bias.unwrap_or(f32::NEG_INFINITY),
+            max_rollback_tokens: options.max_rollback_tokens,
+            cached_mask: None,
+            mask_buf: Vec::new(),
+            diff_base: Vec::new(),
+            telemetry: Telemetry::default(),
+            healing_token: None,
+            history: Vec::new(),
+            grammar_warnings,
+            stop_sequences,
+            grammar_stack: Vec::new(),
+            shadow: None,
         })
     }
 
-    /// Create a tokenizer environment from the JSON configuration
-    fn create_tok_env(
-        tokenizer_json: &str,
-    ) -> Result<Arc<dyn llguidance::toktrie::TokenizerEnv + Sync>, String> {
-        // Try to parse as TokenizerInput
-        let input: TokenizerInput = serde_json::from_str(tokenizer_json)
-            .map_err(|e| format!("Failed to parse tokenizer JSON: {}", e))?;
-
-        // Check if we have a valid vocabulary
-        if input.vocab.is_empty() {
-            return Err("Tokenizer vocabulary is empty".to_string());
-        }
-
-        // Find the maximum token ID to determine vocab size
-        let max_id = input.vocab.values().copied().max().unwrap_or(0);
-        let vocab_size = (max_id + 1) as usize;
-
-        // Build the words vector (token bytes indexed by token ID)
-        // Each entry is the byte representation of the token
-        let mut words: Vec<Vec<u8>> = vec![Vec::new(); vocab_size];
-
-        for (token_str, id) in &input.vocab {
-            if (*id as usize) < vocab_size {
-                // Handle special token encoding
-                // llguidance uses \xFF prefix for special tokens
-                let bytes = if input.added_tokens.iter().any(|t| t.id == *id && t.special) {
-                    // Special tokens get the \xFF prefix
-                    let mut special_bytes = vec![0xFF];
-                    special_bytes.extend(token_str.as_bytes());
-                    special_bytes
-                } else {
-                    // Regular tokens: decode the token string
-                    // GPT-2 style tokenizers use 'Ġ' (U+0120) to represent space
-                    // and other Unicode characters for byte encoding
-                    decode_token_bytes(token_str)
-                };
-                words[*id as usize] = bytes;
+    /// A matcher at the start of `grammar`, with the warnings from compiling it
+    fn create_matcher(
+        factory: &ParserFactory,
+        grammar: &TopLevelGrammar,
+    ) -> (Matcher, Vec<String>) {
+        match factory.create_parser(grammar.clone()) {
+            Ok(mut parser) => {
+                let warnings = parser
+                    .logger
+                    .get_and_clear_logs()
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .filter(|line| !line.is_empty())
+                    .collect();
+                (Matcher::new(Ok(parser)), warnings)
             }
+            Err(e) => (Matcher::new(Err(e)), Vec::new()),
         }
+    }
 
-        // Determine EOS token
-        // Priority: explicit eos_token_id > added token named </s> or <|endoftext|> > last token
-        let eos_token = input.eos_token_id.unwrap_or_else(|| {
-            // Look for common EOS tokens in added_tokens
-            for token in &input.added_tokens {
-                if token.content == "</s>"
-                    || token.content == "<|endoftext|>"
-                    || token.content == "<eos>"
-                    || token.content == "<|eos|>"
-                {
-                    return token.id;
-                }
-            }
-            // Fallback to last token
-            (vocab_size - 1) as u32
-        });
+    /// A parser with the same grammar and options, at the start of the grammar
+    fn fresh_copy(&self) -> LLGuidanceParser {
+        let mut copy = self.with_matcher(self.initial.deep_clone());
+        if let Some(base) = self.grammar_stack.first() {
+            copy.swap_grammar(&mut base.deep_clone());
+            copy.matcher = copy.initial.deep_clone();
+            copy.stop_sequences.restart(&[]);
+        }
+        copy.active = self.trigger_token.is_none();
+        copy
+    }
+
+    /// A parser with the same grammar, options and position, whose state
+    /// evolves independently from here
+    fn fork_state(&self) -> LLGuidanceParser {
+        let mut fork = self.with_matcher(self.matcher.deep_clone());
+        // Same position, same mask, same generation so far
+        fork.cached_mask = self.cached_mask.clone();
+        fork.telemetry = self.telemetry.clone();
+        fork.healing_token = self.healing_token;
+        fork.history = self.history.clone();
+        fork.stop_sequences = self.stop_sequences.clone();
+        fork.grammar_stack = self
+            .grammar_stack
+            .iter()
+            .map(GrammarFrame::deep_clone)
+            .collect();
+        fork
+    }
+
+    /// Exchange the grammar in effect, and the position in it, with `frame`'s
+    fn swap_grammar(&mut self, frame: &mut GrammarFrame) {
+        std::mem::swap(&mut self.grammar, &mut frame.grammar);
+        std::mem::swap(&mut self.matcher, &mut frame.matcher);
+        std::mem::swap(&mut self.initial, &mut frame.initial);
+        std::mem::swap(&mut self.grammar_warnings, &mut frame.grammar_warnings);
+        std::mem::swap(&mut self.stop_sequences, &mut frame.stop_sequences);
+        std::mem::swap(&mut self.active, &mut frame.active);
+    }
+
+    /// Drop the grammars pushed with `push_grammar`, going back to the one
+    /// the parser was created or last reset with
+    fn restore_base_grammar(&mut self) {
+        if let Some(mut base) = std::mem::take(&mut self.grammar_stack).into_iter().next() {
+            self.swap_grammar(&mut base);
+        }
+    }
 
-        // Create TokRxInfo
-        let mut info = TokRxInfo::new(vocab_size as u32, eos_token);
-        info.tok_bos = input.bos_token_id;
-        info.tok_pad = input.pad_token_id;
-        info.tok_unk = input.unk_token_id;
+    /// Index in the history of the first token the current grammar saw
+    fn grammar_start(&self) -> usize {
+        self.grammar_stack
+            .last()
+            .map_or(0, |frame| frame.history_len)
+    }
 
-        // Create the trie
-        let trie = TokTrie::from(&info, &words);
+    /// The grammar the parser was created or last reset with
+    fn base_grammar(&self) -> &TopLevelGrammar {
+        self.grammar_stack
+            .first()
+            .map_or(&self.grammar, |frame| &frame.grammar)
+    }
 
-        // Wrap in ApproximateTokEnv
-        let tok_env = ApproximateTokEnv::new(trie);
+    fn with_matcher(&self, matcher: Matcher) -> LLGuidanceParser {
+        LLGuidanceParser {
+            factory: self.factory.clone(),
+            tok_env: self.tok_env.clone(),
+            grammar: self.grammar.clone(),
+            matcher,
+            initial: self.initial.clone(),
+            vocab_size: self.vocab_size,
+            tok_vocab_size: self.tok_vocab_size,
+            banned_tokens: self.banned_tokens.clone(),
+            id_remap: self.id_remap.clone(),
+            passthrough_tokens: self.passthrough_tokens.clone(),
+            trigger_token: self.trigger_token,
+            active: self.active,
+            deny_list: self.deny_list.clone(),
+            force_allow_list: self.force_allow_list.clone(),
+            allowed_bias: self.allowed_bias,
+            disallowed_bias: self.disallowed_bias,
+            max_rollback_tokens: self.max_rollback_tokens,
+            cached_mask: None,
+            mask_buf: Vec::new(),
+            diff_base: Vec::new(),
+            telemetry: Telemetry::default(),
+            healing_token: None,
+            history: Vec::new(),
+            grammar_warnings: self.grammar_warnings.clone(),
+            stop_sequences: {
+                let mut stop_sequences = self.stop_sequences.clone();
+                stop_sequences.restart(&[]);
+                stop_sequences
+            },
+            grammar_stack: Vec::new(),
+            shadow: None,
+        }
+    }
 
-        Ok(Arc::new(tok_env))
+    /// The grammar and stop sequences of `grammar_json`, or of the grammar
+    /// registered as `name` if it is `"@name"`
+    fn load_grammar(grammar_json: &str) -> Result<(TopLevelGrammar, StopSequences), String> {
+        if let Some(name) = grammar_json.strip_prefix('@') {
+            return GRAMMAR_REGISTRY
+                .with(|registry| registry.borrow().get(name).cloned())
+                .ok_or_else(|| format!("No grammar is registered as \"{}\"", name));
+        }
+        let grammar = Self::parse_grammar(grammar_json)?;
+        Ok((grammar, StopSequences::from_grammar_json(grammar_json)))
     }
 
     fn parse_grammar(grammar_json: &str) -> Result<TopLevelGrammar, String> {
@@ -250,131 +1162,1447 @@ impl LLGuidanceParser {
     }
 
     fn convert_grammar(input: &GrammarInput) -> Result<TopLevelGrammar, String> {
-        if input.grammars.is_empty() {
+        let Some((root, subgrammars)) = input.grammars.split_first() else {
             return Err("No grammars provided".to_string());
+        };
+        if !subgrammars.is_empty() && !root.source.can_reference() {
+            return Err(format!(
+                "Only a Lark grammar, union or sequence can reference other grammars, but \
+                 the root grammar is a {}",
+                root.source.kind()
+            ));
         }
 
-        // For now, handle the first grammar only
-        let spec = &input.grammars[0];
+        // A root grammar that is free text has the stop sequences compiled in;
+        // with a trigger, the free text is the lazy grammar's
+        let stop = stop_regex(&input.stop);
+        let with_stop = match (&stop, &input.trigger) {
+            (Some(stop), None) => root.source.to_grammar_with_stop(stop)?,
+            _ => None,
+        };
 
-        match spec {
-            GrammarSpec::JsonSchema { json_schema } => {
-                // Use TopLevelGrammar::from_json_schema
-                Ok(TopLevelGrammar::from_json_schema(json_schema.clone()))
+        // llguidance starts from the first grammar and reaches the others by name
+        let mut grammar = match with_stop {
+            Some(grammar) => grammar,
+            None => root.source.to_grammar("")?,
+        };
+        grammar.grammars[0].name = root.name.clone();
+        for (i, spec) in subgrammars.iter().enumerate() {
+            let Some(name) = spec.name.as_deref() else {
+                return Err(format!(
+                    "Grammar {} needs a name for the root grammar to reference it as @name",
+                    i + 1
+                ));
+            };
+            if !input.grammars.iter().any(|g| g.source.references(name)) {
+                return Err(format!(
+                    "Grammar \"{}\" is never referenced; use @{} in a Lark grammar",
+                    name, name
+                ));
             }
-            GrammarSpec::Regex { rx } => {
-                // Create a lark grammar that matches the regex
-                let lark_grammar = format!("start: /{}/", rx);
-                Ok(TopLevelGrammar::from_lark(lark_grammar))
+            if spec.max_tokens.is_some() {
+                return Err(format!(
+                    "max_tokens on grammar \"{}\" is not supported; only the root grammar \
+                     and the whole input can have a token limit",
+                    name
+                ));
+            }
+
+            grammar
+                .grammars
+                .extend(spec.source.to_named_grammars(name)?);
+        }
+
+        if let Some(trigger) = &input.trigger {
+            grammar = lazy_grammar(grammar, trigger, stop.as_deref())?;
+        }
+
+        // Names generated for unions must not clash with the given ones either
+        let mut names = BTreeSet::new();
+        for name in grammar.grammars.iter().filter_map(|g| g.name.as_deref()) {
+            if !names.insert(name) {
+                return Err(format!("More than one grammar is named \"{}\"", name));
             }
-            GrammarSpec::Lark { lark } => Ok(TopLevelGrammar::from_lark(lark.clone())),
         }
+
+        // The root grammar's limit and the overall one both apply
+        grammar.max_tokens = match (root.max_tokens, input.max_tokens) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Ok(grammar)
     }
 
     /// Check if a specific token is allowed at the current position
     #[wasm_bindgen]
     pub fn is_token_allowed(&mut self, token_id: u32) -> Result<bool, JsValue> {
-        let mask = self
-            .matcher
-            .compute_mask()
-            .map_err(|e| JsValue::from_str(&format!("Failed to compute mask: {}", e)))?;
+        // Padding positions and IDs without a token are never allowed
+        let Ok(token) = self.tokenizer_id(token_id) else {
+            return Ok(false);
+        };
+        if let Some(mask) = &self.cached_mask {
+            return Ok(mask.is_allowed(token));
+        }
 
-        Ok(mask.is_allowed(token_id))
+        // Without a mask for this step, check just this token's bytes
+        // against the grammar instead of computing the whole mask
+        if self.force_allow_list.contains(&token) {
+            return Ok(true);
+        }
+        if self.deny_list.contains(&token) {
+            return Ok(false);
+        }
+        if self.passthrough_tokens.contains(&token) {
+            return Ok(true);
+        }
+        if self.stop_sequences.hit() {
+            // Generation ended at a stop sequence
+            return Ok(token == self.tok_env.tok_trie().eos_token());
+        }
+        if !self.active {
+            return Ok(Some(token) == self.trigger_token);
+        }
+        if self.banned_tokens.contains(&token) {
+            return Ok(false);
+        }
+        let valid = self
+            .matcher
+            .validate_tokens(&[self.grammar_token(token)])
+            .map_err(|e| JsValue::from_str(&format!("Failed to validate token: {}", e)))?;
+        Ok(valid == 1)
     }
 
-    /// Get the full token mask for the current position
+    /// Keep only the candidate token IDs the grammar allows, in their original order
+    /// Only the candidates are checked, so this is much cheaper than a full
+    /// mask when the sampler already has its top-k
     #[wasm_bindgen]
-    pub fn get_token_mask(&mut self) -> Result<Uint8Array, JsValue> {
-        let mask = self
-            .matcher
-            .compute_mask()
-            .map_err(|e| JsValue::from_str(&format!("Failed to compute mask: {}", e)))?;
+    pub fn filter_candidates(&mut self, token_ids: &[u32]) -> Result<Vec<u32>, JsValue> {
+        let mut allowed = Vec::with_capacity(token_ids.len());
+        for &token_id in token_ids {
+            if self.is_token_allowed(token_id)? {
+                allowed.push(token_id);
+            }
+        }
+        Ok(allowed)
+    }
+
+    /// Mask for the current position, computed once per position
+    fn compute_mask(&mut self) -> Result<Rc<SimpleVob>, JsValue> {
+        if let Some(mask) = &self.cached_mask {
+            return Ok(mask.clone());
+        }
 
-        let mut mask_vec = vec![0u8; self.vocab_size];
-        for (i, item) in mask_vec.iter_mut().enumerate().take(self.vocab_size) {
-            if mask.is_allowed(i as u32) {
-                *item = 1;
+        let start = telemetry::now();
+        let mut mask = if self.stop_sequences.hit() {
+            // Generation ended at a stop sequence
+            let mut mask = SimpleVob::alloc(self.tok_vocab_size);
+            mask.allow_token(self.tok_env.tok_trie().eos_token());
+            mask
+        } else if self.active {
+            let mask = self
+                .matcher
+                .compute_mask()
+                .map_err(|e| JsValue::from_str(&format!("Failed to compute mask: {}", e)))?;
+            let mut mask = self.model_mask(mask);
+            for &token in &self.banned_tokens {
+                mask.disallow_token(token);
+            }
+            mask
+        } else {
+            let mut mask = SimpleVob::alloc(self.tok_vocab_size);
+            if let Some(trigger) = self.trigger_token {
+                mask.allow_token(trigger);
             }
+            mask
+        };
+        for &token in &self.passthrough_tokens {
+            mask.allow_token(token);
         }
+        for &token in &self.deny_list {
+            mask.disallow_token(token);
+        }
+        for &token in &self.force_allow_list {
+            mask.allow_token(token);
+        }
+        self.telemetry
+            .record_mask(mask.num_set(), telemetry::now() - start);
+        let mask = Rc::new(mask);
+        self.cached_mask = Some(mask.clone());
+        Ok(mask)
+    }
 
-        let js_array = Uint8Array::new_with_length(mask_vec.len() as u32);
-        js_array.copy_from(&mask_vec);
-        Ok(js_array)
+    /// Project a mask over the trie onto the model's tokens, each allowed if
+    /// the variant the grammar sees at this position is
+    fn model_mask(&self, mask: SimpleVob) -> SimpleVob {
+        if self.tok_env.tok_trie().vocab_size() == self.tok_vocab_size {
+            return mask;
+        }
+        let spaced = self.spaced_next();
+        let mut model_mask = SimpleVob::alloc(self.tok_vocab_size);
+        for token in 0..self.tok_vocab_size as TokenId {
+            let grammar_token = match self.tok_env.spaced_variant(token) {
+                Some(variant) if spaced => variant,
+                _ => token,
+            };
+            if mask.is_allowed(grammar_token) {
+                model_mask.allow_token(token);
+            }
+        }
+        model_mask
     }
 
-    /// Advance the parser state after a token has been selected
-    #[wasm_bindgen]
-    pub fn advance(&mut self, token_id: u32) -> Result<(), JsValue> {
-        self.matcher
-            .consume_token(token_id)
-            .map_err(|e| JsValue::from_str(&format!("Failed to consume token: {}", e)))?;
-        Ok(())
+    /// The trie token the grammar sees for `token` at this position: a
+    /// WordPiece word-initial token takes a space after the text before it,
+    /// and none at the start or after an opening bracket or quote
+    fn grammar_token(&self, token: TokenId) -> TokenId {
+        match self.tok_env.spaced_variant(token) {
+            Some(spaced) if self.spaced_next() => spaced,
+            _ => token,
+        }
     }
 
-    /// Check if the current state represents a valid complete parse
-    #[wasm_bindgen]
-    pub fn is_complete(&self) -> bool {
-        let reason = format!("{:?}", self.matcher.stop_reason());
-        reason.contains("EndOfSentence")
-            || reason.contains("NoExtension")
-            || reason.contains("MaxTokensTotal")
-            || reason.contains("NoExtensionBias")
+    /// Whether a WordPiece word-initial token takes a space after the text
+    /// consumed so far
+    fn spaced_next(&self) -> bool {
+        let trie = self.tok_env.tok_trie();
+        self.history
+            .iter()
+            .rev()
+            .filter(|(_, step)| matches!(step, Step::Consumed | Step::Nested))
+            .map(|&(token, _)| trie.token(token))
+            .find(|bytes| !bytes.is_empty())
+            .is_some_and(tokenizer::spaced_after)
     }
 
-    /// Reset the parser to its initial state
+    /// Whether `mask` allows exactly one token besides the ones allowed everywhere
+    fn single_choice(&self, mask: &SimpleVob) -> bool {
+        // Passthrough and force-allowed tokens are allowed everywhere, so they do not count
+        let mut choices = (0..self.tok_vocab_size as TokenId).filter(|&token| {
+            mask.is_allowed(token)
+                && !self.passthrough_tokens.contains(&token)
+                && !self.force_allow_list.contains(&token)
+        });
+        choices.next().is_some() && choices.next().is_none()
+    }
+
+    /// Ban these tokens at every position, whatever the grammar allows
+    /// Replaces the previous deny list; pass an empty list to clear it
     #[wasm_bindgen]
-    pub fn reset(&mut self, grammar_json: &str) -> Result<(), JsValue> {
-        let grammar = Self::parse_grammar(grammar_json).map_err(|e| JsValue::from_str(&e))?;
-        let parser = self.factory.create_parser(grammar);
-        self.matcher = Matcher::new(parser);
+    pub fn set_token_deny_list(&mut self, token_ids: &[u32]) -> Result<(), JsValue> {
+        self.deny_list = self.tokenizer_ids(token_ids)?;
+        self.cached_mask = None;
         Ok(())
     }
 
-    /// Get the vocabulary size
+    /// Allow these tokens at every position, even if the grammar or the deny
+    /// list rejects them (e.g. a cancellation token)
+    /// Replaces the previous list; pass an empty list to clear it
+    #[wasm_bindgen]
+    pub fn set_token_force_allow_list(&mut self, token_ids: &[u32]) -> Result<(), JsValue> {
+        self.force_allow_list = self.tokenizer_ids(token_ids)?;
+        self.cached_mask = None;
+        Ok(())
+    }
+
+    fn tokenizer_ids(&self, token_ids: &[u32]) -> Result<Vec<TokenId>, JsValue> {
+        token_ids
+            .iter()
+            .map(|&token_id| self.tokenizer_id(token_id))
+            .collect()
+    }
+
+    /// Get the full token mask for the current position
+    /// With `invert`, 1 marks the disallowed tokens instead
+    #[wasm_bindgen]
+    pub fn get_token_mask(&mut self, invert: Option<bool>) -> Result<Uint8Array, JsValue> {
+        self.fill_mask_buf()?;
+        if invert.unwrap_or(false) {
+            self.invert_mask_buf();
+        }
+
+        let js_array = Uint8Array::new_with_length(self.mask_buf.len() as u32);
+        js_array.copy_from(&self.mask_buf);
+        Ok(js_array)
+    }
+
+    /// Write the token mask for the current position into `dest`, which must
+    /// hold at least `vocab_size()` bytes; bytes past the mask are left as-is
+    /// With `invert`, 1 marks the disallowed tokens instead
+    #[wasm_bindgen]
+    pub fn fill_token_mask(
+        &mut self,
+        dest: &Uint8Array,
+        invert: Option<bool>,
+    ) -> Result<(), JsValue> {
+        if (dest.length() as usize) < self.vocab_size {
+            return Err(JsValue::from_str(&format!(
+                "Mask buffer holds {} bytes, but the mask has {} entries",
+                dest.length(),
+                self.vocab_size
+            )));
+        }
+        self.fill_mask_buf()?;
+        if invert.unwrap_or(false) {
+            self.invert_mask_buf();
+        }
+
+        dest.subarray(0, self.vocab_size as u32)
+            .copy_from(&self.mask_buf);
+        Ok(())
+    }
+
+    /// Set the logits of disallowed tokens to -Infinity in place
+    /// Logits past the end of the mask (padding) are disallowed too
+    #[wasm_bindgen]
+    pub fn apply_mask(&mut self, logits: &mut [f32]) -> Result<(), JsValue> {
+        self.fill_mask_buf()?;
+
+        for (i, logit) in logits.iter_mut().enumerate() {
+            if self.mask_buf.get(i) != Some(&1) {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sample a token from the logits among those the grammar allows
+    /// This does not advance the parser; pass the result to `advance`
+    #[wasm_bindgen]
+    pub fn sample_token(
+        &mut self,
+        logits: &[f32],
+        options_json: Option<String>,
+    ) -> Result<u32, JsValue> {
+        let options =
+            SampleOptions::parse(options_json.as_deref()).map_err(|e| JsValue::from_str(&e))?;
+        self.fill_mask_buf()?;
+
+        sampling::sample(logits, &self.mask_buf, &options, js_sys::Math::random())
+            .ok_or_else(|| JsValue::from_str("No token allowed by the grammar has a finite logit"))
+    }
+
+    /// Compute the byte mask into the reusable buffer
+    fn fill_mask_buf(&mut self) -> Result<(), JsValue> {
+        let mask = self.compute_mask()?;
+
+        // Padding positions and runtime IDs without a token stay 0
+        let mut mask_buf = std::mem::take(&mut self.mask_buf);
+        mask_buf.clear();
+        mask_buf.resize(self.vocab_size, 0);
+        for token in 0..self.tok_vocab_size as TokenId {
+            if mask.is_allowed(token) {
+                mask_buf[self.runtime_id(token) as usize] = 1;
+            }
+        }
+        self.mask_buf = mask_buf;
+        Ok(())
+    }
+
+    /// Get the changes to the mask since the last call, as
+    /// `[newly_allowed, newly_disallowed]` ID arrays in ascending order
+    /// The first call reports every allowed token as newly allowed
+    #[wasm_bindgen]
+    pub fn get_token_mask_diff(&mut self) -> Result<Array, JsValue> {
+        self.fill_mask_buf()?;
+        self.diff_base.resize(self.vocab_size, 0);
+
+        let mut allowed = Vec::new();
+        let mut disallowed = Vec::new();
+        for (id, (&new, old)) in self.mask_buf.iter().zip(&mut self.diff_base).enumerate() {
+            if new != *old {
+                if new == 1 {
+                    allowed.push(id as u32);
+                } else {
+                    disallowed.push(id as u32);
+                }
+                *old = new;
+            }
+        }
+
+        Ok(Array::of2(
+            &Uint32Array::from(&allowed[..]),
+            &Uint32Array::from(&disallowed[..]),
+        ))
+    }
+
+    /// Get whichever of the allowed and disallowed ID lists is shorter, as
+    /// `["allowed" | "disallowed", ids]` with the IDs in ascending order
+    #[wasm_bindgen]
+    pub fn get_compact_mask(&mut self) -> Result<Array, JsValue> {
+        self.fill_mask_buf()?;
+
+        let allowed_count = self
+            .mask_buf
+            .iter()
+            .filter(|&&allowed| allowed == 1)
+            .count();
+        let (representation, wanted) = if allowed_count * 2 <= self.mask_buf.len() {
+            ("allowed", 1)
+        } else {
+            ("disallowed", 0)
+        };
+        let ids: Vec<u32> = self
+            .mask_buf
+            .iter()
+            .enumerate()
+            .filter(|&(_, &allowed)| allowed == wanted)
+            .map(|(id, _)| id as u32)
+            .collect();
+
+        Ok(Array::of2(
+            &JsValue::from_str(representation),
+            &Uint32Array::from(&ids[..]),
+        ))
+    }
+
+    fn invert_mask_buf(&mut self) {
+        for allowed in &mut self.mask_buf {
+            *allowed ^= 1;
+        }
+    }
+
+    /// Get the IDs of the tokens disallowed at the current position, in ascending
+    /// order, including padding positions and runtime IDs without a token
+    #[wasm_bindgen]
+    pub fn get_disallowed_tokens(&mut self) -> Result<Vec<u32>, JsValue> {
+        self.fill_mask_buf()?;
+
+        Ok(self
+            .mask_buf
+            .iter()
+            .enumerate()
+            .filter(|&(_, &allowed)| allowed == 0)
+            .map(|(id, _)| id as u32)
+            .collect())
+    }
+
+    /// Get the IDs of the tokens allowed at the current position, in ascending order
+    #[wasm_bindgen]
+    pub fn get_allowed_tokens(&mut self) -> Result<Vec<u32>, JsValue> {
+        let mask = self.compute_mask()?;
+
+        let mut allowed: Vec<u32> = (0..self.tok_vocab_size as TokenId)
+            .filter(|&token| mask.is_allowed(token))
+            .map(|token| self.runtime_id(token))
+            .collect();
+        if self.id_remap.is_some() {
+            allowed.sort_unstable();
+        }
+        Ok(allowed)
+    }
+
+    /// Get the token mask for the current position packed one bit per token
+    /// Token `i` is bit `i % 32` of word `i / 32`
+    #[wasm_bindgen]
+    pub fn get_token_bitmask(&mut self) -> Result<Uint32Array, JsValue> {
+        let mask = self.compute_mask()?;
+
+        let mut words = vec![0u32; self.vocab_size.div_ceil(32)];
+        for token in 0..self.tok_vocab_size as TokenId {
+            if mask.is_allowed(token) {
+                let id = self.runtime_id(token) as usize;
+                words[id / 32] |= 1 << (id % 32);
+            }
+        }
+
+        let js_array = Uint32Array::new_with_length(words.len() as u32);
+        js_array.copy_from(&words);
+        Ok(js_array)
+    }
+
+    /// Get an additive logit bias for the current position
+    /// Allowed tokens get the `allowed_bias` option (default 0.0), everything
+    /// else `disallowed_value`, falling back to the `disallowed_bias` option
+    /// and then -Infinity
+    #[wasm_bindgen]
+    pub fn compute_logit_bias(
+        &mut self,
+        disallowed_value: Option<f32>,
+    ) -> Result<Float32Array, JsValue> {
+        let mask = self.compute_mask()?;
+
+        let mut bias = vec![disallowed_value.unwrap_or(self.disallowed_bias); self.vocab_size];
+        for token in 0..self.tok_vocab_size as TokenId {
+            if mask.is_allowed(token) {
+                bias[self.runtime_id(token) as usize] = self.allowed_bias;
+            }
+        }
+
+        let js_array = Float32Array::new_with_length(bias.len() as u32);
+        js_array.copy_from(&bias);
+        Ok(js_array)
+    }
+
+    /// Advance the parser state after a token has been selected
+    #[wasm_bindgen]
+    pub fn advance(&mut self, token_id: u32) -> Result<(), JsValue> {
+        let token = self.tokenizer_id(token_id)?;
+        let forced = self
+            .cached_mask
+            .take()
+            .is_some_and(|mask| self.single_choice(&mask));
+        let start = telemetry::now();
+        let step = self.advance_token(token, token_id)?;
+        self.telemetry.record_step(forced, telemetry::now() - start);
+        // The history keeps the variant the grammar consumed, so its bytes
+        // include any space between words
+        let token = match step {
+            Step::Consumed => self.grammar_token(token),
+            _ => token,
+        };
+        self.history.push((token, step));
+        if step == Step::Consumed {
+            let bytes = self.tok_env.tok_trie().token(token);
+            if bytes.first() != Some(&0xFF) {
+                self.stop_sequences.push(bytes);
+            }
+        }
+        Ok(())
+    }
+
+    fn advance_token(&mut self, token: TokenId, token_id: u32) -> Result<Step, JsValue> {
+        if !self.active && Some(token) == self.trigger_token {
+            self.active = true;
+            return Ok(Step::Trigger);
+        }
+        if self.passthrough_tokens.contains(&token) {
+            return Ok(Step::Skipped);
+        }
+        if self.stop_sequences.hit() {
+            // The grammar may not accept EOS here; the stop sequence ended it anyway
+            if token == self.tok_env.tok_trie().eos_token() {
+                return Ok(Step::Skipped);
+            }
+            return Err(JsValue::from_str(&format!(
+                "Token ID {} is not allowed after a stop sequence",
+                token_id
+            )));
+        }
+        let grammar_token = self.grammar_token(token);
+        // Force-allowed tokens the grammar rejects leave the grammar untouched
+        if self.force_allow_list.contains(&token)
+            && (!self.active || self.matcher.validate_tokens(&[grammar_token]).ok() != Some(1))
+        {
+            return Ok(Step::Skipped);
+        }
+        if !self.active {
+            return Err(JsValue::from_str(&format!(
+                "Token ID {} is not allowed before the trigger token",
+                token_id
+            )));
+        }
+        self.matcher
+            .consume_token(grammar_token)
+            .map_err(|e| JsValue::from_str(&format!("Failed to consume token: {}", e)))?;
+        Ok(Step::Consumed)
+    }
+
+    /// Advance by every token in `token_ids`, in one call, e.g. for prefill
+    /// Stops at the first rejected token; the tokens before it stay consumed
+    #[wasm_bindgen]
+    pub fn advance_many(&mut self, token_ids: &[u32]) -> Result<(), JsValue> {
+        for &token_id in token_ids {
+            self.advance(token_id)?;
+        }
+        Ok(())
+    }
+
+    /// Advance by `token_ids` up to the first token that is rejected, and
+    /// return its index, or -1 if every token was accepted
+    /// The parser is left after the accepted prefix, e.g. for auditing output
+    /// that was generated without the mask
+    #[wasm_bindgen]
+    pub fn validate_tokens(&mut self, token_ids: &[u32]) -> i32 {
+        for (index, &token_id) in token_ids.iter().enumerate() {
+            if !self.grammar_accepts(token_id) || self.advance(token_id).is_err() {
+                return index as i32;
+            }
+        }
+        -1
+    }
+
+    /// Check a token the grammar itself would consume, without consuming it,
+    /// so a rejected token leaves the matcher usable
+    fn grammar_accepts(&mut self, token_id: u32) -> bool {
+        let token = match lookup_token(self.id_remap.as_ref(), self.tok_vocab_size, token_id) {
+            Some(token) => token,
+            None => return false,
+        };
+        if !self.active
+            || self.stop_sequences.hit()
+            || self.passthrough_tokens.contains(&token)
+            || self.force_allow_list.contains(&token)
+        {
+            // Decided by `advance_token` without consuming anything
+            return true;
+        }
+        self.matcher
+            .validate_tokens(&[self.grammar_token(token)])
+            .ok()
+            == Some(1)
+    }
+
+    /// Advance past `bytes`, tokenized with the parser's own tokenizer
+    /// Returns the tokens consumed, which need not match the model's tokenization
+    #[wasm_bindgen]
+    pub fn consume_bytes(&mut self, bytes: &[u8]) -> Result<Vec<u32>, JsValue> {
+        let token_ids: Vec<u32> = self
+            .tok_env
+            .tokenize_bytes(bytes)
+            .into_iter()
+            .map(|token| self.runtime_id(token))
+            .collect();
+        self.advance_many(&token_ids)?;
+        Ok(token_ids)
+    }
+
+    /// Advance past `text`; see `consume_bytes`
+    #[wasm_bindgen]
+    pub fn consume_text(&mut self, text: &str) -> Result<Vec<u32>, JsValue> {
+        self.consume_bytes(text.as_bytes())
+    }
+
+    /// Un-consume the last `num_tokens` tokens passed to `advance`
+    /// At most `max_rollback_tokens` at a time, when that option is set
+    #[wasm_bindgen]
+    pub fn rollback(&mut self, num_tokens: usize) -> Result<(), JsValue> {
+        if let Some(limit) = self.max_rollback_tokens.filter(|&limit| num_tokens > limit) {
+            return Err(JsValue::from_str(&format!(
+                "Cannot roll back {} tokens, max_rollback_tokens is {}",
+                num_tokens, limit
+            )));
+        }
+        if num_tokens > self.history.len() {
+            return Err(JsValue::from_str(&format!(
+                "Cannot roll back {} tokens, only {} have been consumed",
+                num_tokens,
+                self.history.len()
+            )));
+        }
+
+        let start = self.history.len() - num_tokens;
+        if start < self.grammar_start() {
+            return Err(JsValue::from_str(
+                "Cannot roll back past push_grammar; pop the grammar first",
+            ));
+        }
+        if self.history[start..]
+            .iter()
+            .any(|&(_, step)| step == Step::Nested)
+        {
+            return Err(JsValue::from_str(
+                "Cannot roll back past pop_grammar into the popped grammar",
+            ));
+        }
+
+        let undone = self.history.split_off(start);
+        self.shadow = None;
+        let consumed = undone
+            .iter()
+            .filter(|&&(_, step)| step == Step::Consumed)
+            .count();
+        if undone.iter().any(|&(_, step)| step == Step::Trigger) {
+            self.active = false;
+        }
+        self.cached_mask = None;
+        self.matcher
+            .rollback(consumed)
+            .map_err(|e| JsValue::from_str(&format!("Failed to roll back: {}", e)))?;
+        let output = self.history_bytes(self.grammar_start(), |step| step == Step::Consumed);
+        self.stop_sequences.restart(&output);
+        Ok(())
+    }
+
+    /// Switch to another grammar mid-generation, e.g. a tool call schema once
+    /// the model opens a tool call, until `pop_grammar` switches back
+    ///
+    /// The new grammar starts from scratch and is enforced at once, even before
+    /// a trigger token; the current grammar is set aside where it is and does
+    /// not see the tokens consumed in between. The history carries on across
+    /// switches, so `consumed_bytes` holds the output of every grammar.
+    #[wasm_bindgen]
+    pub fn push_grammar(&mut self, grammar_json: &str) -> Result<(), JsValue> {
+        let (grammar, stop_sequences) =
+            Self::load_grammar(grammar_json).map_err(|e| JsValue::from_str(&e))?;
+        let (matcher, grammar_warnings) = Self::create_matcher(&self.factory, &grammar);
+        let mut frame = GrammarFrame {
+            grammar,
+            initial: Rc::new(matcher.deep_clone()),
+            matcher,
+            grammar_warnings,
+            stop_sequences,
+            active: true,
+            history_len: self.history.len(),
+        };
+        self.swap_grammar(&mut frame);
+        self.grammar_stack.push(frame);
+        self.cached_mask = None;
+        self.shadow = None;
+        Ok(())
+    }
+
+    /// Go back to the grammar set aside by the last `push_grammar`, at the
+    /// position it was left; the tokens consumed since stay in the output
+    #[wasm_bindgen]
+    pub fn pop_grammar(&mut self) -> Result<(), JsValue> {
+        let mut frame = self
+            .grammar_stack
+            .pop()
+            .ok_or_else(|| JsValue::from_str("No grammar has been pushed"))?;
+        self.swap_grammar(&mut frame);
+        for (_, step) in &mut self.history[frame.history_len..] {
+            if *step == Step::Consumed {
+                *step = Step::Nested;
+            }
+        }
+        self.cached_mask = None;
+        self.shadow = None;
+        Ok(())
+    }
+
+    /// Number of grammars pushed with `push_grammar` and not yet popped
+    #[wasm_bindgen]
+    pub fn grammar_depth(&self) -> usize {
+        self.grammar_stack.len()
+    }
+
+    /// Copy this parser at its current position, e.g. to branch a beam
+    /// The copy shares the compiled grammar, the lexer caches and the mask
+    /// already computed for this position, so only work after the branch
+    /// point is repeated
+    #[wasm_bindgen]
+    pub fn fork(&self) -> LLGuidanceParser {
+        self.fork_state()
+    }
+
+    /// Check whether `advance(token_id)` would succeed, without moving the parser
+    /// Unlike `is_token_allowed`, this runs the full advance on a copy, so it
+    /// also covers the trigger, passthrough and force-allow rules
+    #[wasm_bindgen]
+    pub fn try_advance(&self, token_id: u32) -> bool {
+        self.fork_state().advance(token_id).is_ok()
+    }
+
+    /// Advance by `token_id` and return the mask for the next position,
+    /// in one call instead of `advance` followed by `get_token_mask`
+    #[wasm_bindgen]
+    pub fn step(&mut self, token_id: u32) -> Result<Uint8Array, JsValue> {
+        self.advance(token_id)?;
+        self.get_token_mask(None)
+    }
+
+    /// Get the tokens the grammar forces next, without advancing
+    /// They can be appended and passed to `advance` without running the model
+    #[wasm_bindgen]
+    pub fn get_forced_tokens(&mut self) -> Vec<u32> {
+        // After a stop sequence nothing but EOS may follow
+        if !self.active || self.stop_sequences.hit() {
+            return Vec::new();
+        }
+        self.matcher
+            .compute_ff_tokens()
+            .into_iter()
+            .map(|token| self.runtime_id(token))
+            .collect()
+    }
+
+    /// Get the bytes the grammar forces next, without advancing
+    /// Unlike `get_forced_tokens`, this includes text that does not yet line
+    /// up with a token boundary, e.g. the start of a fixed JSON key
+    #[wasm_bindgen]
+    pub fn forced_bytes(&mut self) -> Vec<u8> {
+        if !self.active || self.stop_sequences.hit() {
+            return Vec::new();
+        }
+        self.matcher.compute_ff_bytes()
+    }
+
+    /// Get what the grammar can accept next, for autocomplete-style hints
+    ///
+    /// The lexer's terminals are not exposed, so this is derived from the
+    /// mask: the forced text when there is one, otherwise the distinct first
+    /// characters of the allowed tokens, in code point order. EOS is left out.
+    #[wasm_bindgen]
+    pub fn expected(&mut self) -> Result<Array, JsValue> {
+        let forced = self.forced_bytes();
+        if !forced.is_empty() {
+            return Ok(Array::of1(&JsValue::from_str(&String::from_utf8_lossy(
+                &forced,
+            ))));
+        }
+
+        let mask = self.compute_mask()?;
+        let trie = self.tok_env.tok_trie();
+        let eos = trie.eos_token();
+        let mut starts = BTreeSet::new();
+        for token in 0..self.tok_vocab_size as TokenId {
+            let bytes = trie.token(self.grammar_token(token));
+            // Special tokens and tokens starting mid-character have no first character
+            if token == eos || !mask.is_allowed(token) || bytes.first() == Some(&0xFF) {
+                continue;
+            }
+            match String::from_utf8_lossy(bytes).chars().next() {
+                Some(c) if c != char::REPLACEMENT_CHARACTER => {
+                    starts.insert(c);
+                }
+                _ => {}
+            }
+        }
+        Ok(starts
+            .into_iter()
+            .map(|c| JsValue::from_str(&c.to_string()))
+            .collect())
+    }
+
+    /// Check whether exactly one token is allowed now, so the model has no choice
+    #[wasm_bindgen]
+    pub fn is_forced(&mut self) -> Result<bool, JsValue> {
+        if !self.active || self.matcher.is_stopped() {
+            return Ok(false);
+        }
+        if self.stop_sequences.hit() {
+            // Generation ended at a stop sequence, so EOS is the one choice
+            return Ok(true);
+        }
+
+        let mask = self.compute_mask()?;
+        Ok(self.single_choice(&mask))
+    }
+
+    /// Check whether EOS is the only token the grammar allows now
+    /// Unlike `is_complete`, this is false while the grammar could still continue
+    #[wasm_bindgen]
+    pub fn must_stop(&mut self) -> Result<bool, JsValue> {
+        if !self.active {
+            return Ok(false);
+        }
+        if self.matcher.is_stopped() {
+            return Ok(true);
+        }
+
+        let mask = self.compute_mask()?;
+        let eos = self.tok_env.tok_trie().eos_token();
+        // Passthrough and force-allowed tokens are allowed everywhere, so they do not count
+        Ok(mask.is_allowed(eos)
+            && (0..self.tok_vocab_size as TokenId).all(|token| {
+                token == eos
+                    || !mask.is_allowed(token)
+                    || self.passthrough_tokens.contains(&token)
+                    || self.force_allow_list.contains(&token)
+            }))
+    }
+
+    /// Check whether any token can extend the output, i.e. the grammar has not
+    /// reached a dead end; false once the parser has stopped or failed
+    #[wasm_bindgen]
+    pub fn can_advance(&mut self) -> bool {
+        if !self.active {
+            return true;
+        }
+        if self.matcher.is_stopped() {
+            return false;
+        }
+
+        let mask = match self.compute_mask() {
+            Ok(mask) => mask,
+            Err(_) => return false,
+        };
+        // Passthrough and force-allowed tokens are allowed everywhere, so they do not count
+        if self.passthrough_tokens.is_empty() && self.force_allow_list.is_empty() {
+            return !mask.is_zero();
+        }
+        (0..self.tok_vocab_size as TokenId).any(|token| {
+            mask.is_allowed(token)
+                && !self.passthrough_tokens.contains(&token)
+                && !self.force_allow_list.contains(&token)
+        })
+    }
+
+    /// Check whether a finished string matches the grammar from its start
+    ///
+    /// Runs on a separate matcher for the grammar the parser was created or
+    /// last reset with, so the parser's own position and any pushed grammar
+    /// are untouched. As with `advance`, the text ends at a stop sequence, and
+    /// a lazy grammar checks only the text after its trigger token.
+    #[wasm_bindgen]
+    pub fn validate_text(&self, text: &str) -> bool {
+        let trie = self.tok_env.tok_trie();
+        let mut text = text.as_bytes();
+        if let Some(trigger) = self.trigger_token {
+            let marker = trie.token(trigger);
+            let marker = marker.strip_prefix(&[0xFF]).unwrap_or(marker);
+            match text.windows(marker.len()).position(|w| w == marker) {
+                Some(at) => text = &text[at + marker.len()..],
+                None => return false,
+            }
+        }
+
+        let tokens = self.tok_env.tokenize_bytes(text);
+        let mut stop_sequences = self
+            .grammar_stack
+            .first()
+            .map_or(&self.stop_sequences, |frame| &frame.stop_sequences)
+            .clone();
+        stop_sequences.restart(&[]);
+        // Text going on past a stop sequence is rejected, as `advance` rejects it
+        if let Some(end) = tokens
+            .iter()
+            .position(|&t| stop_sequences.push(trie.token(t)))
+        {
+            if end + 1 < tokens.len() {
+                return false;
+            }
+        }
+
+        let mut matcher = Matcher::new(self.factory.create_parser(self.base_grammar().clone()));
+        if matcher.validate_tokens(&tokens).ok() != Some(tokens.len())
+            || matcher.consume_tokens(&tokens).is_err()
+        {
+            return false;
+        }
+        let eos = trie.eos_token();
+        stop_sequences.hit()
+            || matcher.is_stopped()
+            || matcher
+                .compute_mask()
+                .is_ok_and(|mask| mask.is_allowed(eos))
+    }
+
+    /// Check if the current state represents a valid complete parse
+    /// True both once generation has stopped cleanly and while the grammar is
+    /// in an accepting state that could still be extended
+    #[wasm_bindgen]
+    pub fn is_complete(&mut self) -> bool {
+        if !self.active {
+            return false;
+        }
+        if self.stop_sequences.hit() {
+            return true;
+        }
+        if self.matcher.is_stopped() {
+            return matches!(
+                self.matcher.stop_reason(),
+                StopReason::EndOfSentence
+                    | StopReason::NoExtension
+                    | StopReason::NoExtensionBias
+                    | StopReason::MaxTokensTotal
+            );
+        }
+        self.matcher.is_accepting().unwrap_or(false)
+    }
+
+    /// Check whether the parser has stopped and accepts no further tokens,
+    /// whether cleanly or because of an error; see `stop_reason`
+    #[wasm_bindgen]
+    pub fn is_stopped(&self) -> bool {
+        self.stop_sequences.hit() || self.matcher.is_stopped()
+    }
+
+    /// Reset the parser to its initial state
+    ///
+    /// Without `grammar_json` the parser restarts the grammar it already has,
+    /// reusing the compiled grammar; with it, the new grammar is compiled.
+    /// Grammars pushed with `push_grammar` are dropped either way.
+    #[wasm_bindgen]
+    pub fn reset(&mut self, grammar_json: Option<String>) -> Result<(), JsValue> {
+        match grammar_json {
+            Some(grammar_json) => {
+                let (grammar, stop_sequences) =
+                    Self::load_grammar(&grammar_json).map_err(|e| JsValue::from_str(&e))?;
+                let (matcher, grammar_warnings) = Self::create_matcher(&self.factory, &grammar);
+                self.initial = Rc::new(matcher.deep_clone());
+                self.matcher = matcher;
+                self.grammar = grammar;
+                self.grammar_warnings = grammar_warnings;
+                self.stop_sequences = stop_sequences;
+                self.grammar_stack.clear();
+            }
+            None => {
+                self.restore_base_grammar();
+                self.matcher = self.initial.deep_clone();
+                self.stop_sequences.restart(&[]);
+            }
+        }
+        self.active = self.trigger_token.is_none();
+        self.cached_mask = None;
+        self.telemetry = Telemetry::default();
+        self.healing_token = None;
+        self.history.clear();
+        self.shadow = None;
+        Ok(())
+    }
+
+    /// Enable token healing for a prompt ending in `last_prompt_token`
+    ///
+    /// Restarts the parser so that the token's bytes are re-generated as the
+    /// start of the output, ahead of the grammar. Returns true when the token
+    /// was rolled back, in which case it must be dropped from the prompt.
+    #[wasm_bindgen]
+    pub fn enable_token_healing(&mut self, last_prompt_token: u32) -> Result<bool, JsValue> {
+        let token = self.tokenizer_id(last_prompt_token)?;
+        self.restore_base_grammar();
+        let (matcher, healed) = self.healing_matcher(token)?;
+        self.matcher = matcher;
+        self.cached_mask = None;
+        self.telemetry = Telemetry::default();
+        self.healing_token = Some(token);
+        self.history.clear();
+        self.stop_sequences.restart(&[]);
+        self.shadow = None;
+        Ok(healed)
+    }
+
+    /// A matcher at the start of the grammar, healing `token` at the prompt boundary
+    fn healing_matcher(&self, token: TokenId) -> Result<(Matcher, bool), JsValue> {
+        let mut parser = self
+            .factory
+            .create_parser(self.grammar.clone())
+            .map_err(|e| JsValue::from_str(&format!("Failed to create parser: {}", e)))?;
+        let prompt = parser.process_prompt(vec![token]);
+        Ok((Matcher::new(Ok(parser)), prompt.is_empty()))
+    }
+
+    /// Snapshot the parser's position as bytes, e.g. to persist it across page
+    /// reloads or hand it to another thread; restore it with `load_state` on a
+    /// parser built from the same grammar and tokenizer
+    #[wasm_bindgen]
+    pub fn save_state(&self) -> Result<Vec<u8>, JsValue> {
+        if !self.grammar_stack.is_empty()
+            || self.history.iter().any(|&(_, step)| step == Step::Nested)
+        {
+            return Err(JsValue::from_str(
+                "Cannot save the state of a parser whose grammar was switched with push_grammar",
+            ));
+        }
+        Ok(ParserState {
+            grammar_fingerprint: self.grammar_fingerprint()?,
+            tok_vocab_size: self.tok_vocab_size as u32,
+            healing_token: self.healing_token,
+            tokens: self.history.iter().map(|&(token, _)| token).collect(),
+        }
+        .to_bytes())
+    }
+
+    /// Restore a position saved by `save_state`, replacing the current one,
+    /// including any grammars pushed since; the deny and force-allow lists in
+    /// effect must match the saved parser's
+    #[wasm_bindgen]
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let state = ParserState::from_bytes(bytes).map_err(|e| JsValue::from_str(&e))?;
+        if state.grammar_fingerprint != self.grammar_fingerprint()? {
+            return Err(JsValue::from_str(
+                "Parser state was saved for a different grammar",
+            ));
+        }
+        if state.tok_vocab_size as usize != self.tok_vocab_size {
+            return Err(JsValue::from_str(&format!(
+                "Parser state was saved for a tokenizer with {} tokens, but this one has {}",
+                state.tok_vocab_size, self.tok_vocab_size
+            )));
+        }
+
+        // Replay into a copy so a failed load leaves this parser untouched;
+        // snapshots cover only the base grammar, so any pushed ones are dropped
+        let mut loaded = self.fresh_copy();
+        if let Some(token) = state.healing_token {
+            loaded.matcher = loaded.healing_matcher(token)?.0;
+            loaded.healing_token = Some(token);
+        }
+        for token in state.tokens {
+            let token_id = loaded.runtime_id(token);
+            loaded.advance(token_id)?;
+        }
+        loaded.telemetry = Telemetry::default();
+        *self = loaded;
+        Ok(())
+    }
+
+    fn grammar_fingerprint(&self) -> Result<u64, JsValue> {
+        let grammar = serde_json::to_vec(self.base_grammar())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize grammar: {}", e)))?;
+        Ok(state::fingerprint(&grammar))
+    }
+
+    /// Get the vocabulary size (the token mask length, including any padding)
     #[wasm_bindgen]
     pub fn vocab_size(&self) -> usize {
         self.vocab_size
     }
 
-    /// Get the current stop reason
+    /// Export the compiled tokenizer environment as a binary blob
+    /// Pass it to `from_tok_env_blob()` to skip tokenizer parsing next time
     #[wasm_bindgen]
-    pub fn stop_reason(&self) -> String {
-        format!("{:?}", self.matcher.stop_reason())
+    pub fn export_tok_env(&self) -> Result<Vec<u8>, JsValue> {
+        self.tok_env
+            .export_blob()
+            .map_err(|e| JsValue::from_str(&e))
     }
-}
 
-/// Decode a token string to its byte representation
-/// Handles GPT-2/BPE style encoding where special Unicode characters represent bytes
-fn decode_token_bytes(token: &str) -> Vec<u8> {
-    let mut result = Vec::new();
+    /// Get the warnings llguidance reported while compiling the grammar, such
+    /// as unsupported JSON schema keywords; empty if it compiled cleanly
+    #[wasm_bindgen]
+    pub fn grammar_warnings(&self) -> Array {
+        self.grammar_warnings
+            .iter()
+            .map(|w| JsValue::from_str(w))
+            .collect()
+    }
 
-    for c in token.chars() {
-        match c {
-            // GPT-2 style: 'Ġ' (U+0120) represents space (0x20)
-            'Ġ' => result.push(b' '),
-            // GPT-2 style: 'Ċ' (U+010A) represents newline (0x0A)
-            'Ċ' => result.push(b'\n'),
-            // GPT-2 style: 'ċ' (U+010B) sometimes used for tab
-            'ċ' => result.push(b'\t'),
-            // GPT-2 uses Unicode range U+0100-U+01FF to encode bytes 0x00-0xFF
-            // The mapping is: byte = unicode_codepoint - 0x100 (for 0x00-0x20, 0x7F-0xA0, 0xAD)
-            // But for printable ASCII, the character is used directly
-            c if c as u32 >= 0x100 && c as u32 <= 0x1FF => {
-                // This is a GPT-2 byte encoding
-                let byte = (c as u32 - 0x100) as u8;
-                result.push(byte);
-            }
-            // Regular ASCII/UTF-8 characters
-            c => {
-                // Encode the character as UTF-8 bytes
-                let mut buf = [0u8; 4];
-                let s = c.encode_utf8(&mut buf);
-                result.extend(s.as_bytes());
-            }
-        }
-    }
-
-    result
+    /// Get the problems found while reading the tokenizer that did not stop
+    /// construction, such as lossy conversions of vocabulary entries
+    #[wasm_bindgen]
+    pub fn tokenizer_warnings(&self) -> Array {
+        self.tok_env
+            .warnings()
+            .iter()
+            .map(|w| JsValue::from_str(w))
+            .collect()
+    }
+
+    /// Tokenize text with the parser's tokenizer
+    #[wasm_bindgen]
+    pub fn tokenize(&self, text: &str) -> Vec<u32> {
+        self.tok_env
+            .tokenize_bytes(text.as_bytes())
+            .into_iter()
+            .map(|token| self.runtime_id(token))
+            .collect()
+    }
+
+    /// Decode token IDs back to text
+    /// Special tokens are rendered as their content, invalid UTF-8 as U+FFFD
+    #[wasm_bindgen]
+    pub fn detokenize(&self, ids: &[u32]) -> Result<String, JsValue> {
+        let trie = self.tok_env.tok_trie();
+        let mut bytes = Vec::new();
+        let mut previous: &[u8] = &[];
+        for &id in ids {
+            let mut token = self.tokenizer_id(id)?;
+            // A WordPiece word is separated by a space from the text before it
+            if let Some(spaced) = self.tok_env.spaced_variant(token) {
+                if tokenizer::spaced_after(previous) {
+                    token = spaced;
+                }
+            }
+            let token = trie.token(token);
+            // Drop the \xFF marker of special tokens
+            bytes.extend_from_slice(token.strip_prefix(&[0xFF]).unwrap_or(token));
+            if !token.is_empty() {
+                previous = token;
+            }
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Get the bytes the grammar has consumed since its start, including those
+    /// consumed by grammars pushed with `push_grammar`
+    /// Passthrough tokens, the trigger token and EOS are not part of the output
+    #[wasm_bindgen]
+    pub fn consumed_bytes(&self) -> Vec<u8> {
+        self.history_bytes(0, |step| matches!(step, Step::Consumed | Step::Nested))
+    }
+
+    /// Bytes of the tokens from `history[from..]` whose step passes `include`, without EOS
+    fn history_bytes(&self, from: usize, include: impl Fn(Step) -> bool) -> Vec<u8> {
+        let trie = self.tok_env.tok_trie();
+        let eos = trie.eos_token();
+        let mut bytes = Vec::new();
+        for &(token, step) in &self.history[from..] {
+            if include(step) && token != eos {
+                let token = trie.token(token);
+                bytes.extend_from_slice(token.strip_prefix(&[0xFF]).unwrap_or(token));
+            }
+        }
+        bytes
+    }
+
+    /// Get the text the grammar has consumed since its start, invalid UTF-8 as U+FFFD
+    #[wasm_bindgen]
+    pub fn consumed_text(&self) -> String {
+        String::from_utf8_lossy(&self.consumed_bytes()).into_owned()
+    }
+
+    /// Get the named captures matched so far, as `[name, text]` pairs in
+    /// capture order; a name captured twice appears twice
+    ///
+    /// The matcher does not expose its parser, so the consumed tokens are
+    /// replayed on a fresh one; the cost grows with the length of the output.
+    #[wasm_bindgen]
+    pub fn get_captures(&self) -> Result<Array, JsValue> {
+        let mut parser = self.start_parser()?;
+        self.replay(&mut parser, self.grammar_start())?;
+
+        Ok(parser
+            .parser
+            .captures()
+            .iter()
+            .map(|(name, bytes)| {
+                Array::of2(
+                    &JsValue::from_str(name),
+                    &JsValue::from_str(&String::from_utf8_lossy(bytes)),
+                )
+            })
+            .collect())
+    }
+
+    /// Get the derivation tree of the text the current Lark grammar consumed,
+    /// as JSON: nested `{ rule, start, end, text, children }` nodes, with
+    /// byte offsets, under the `start` rule
+    ///
+    /// Like `get_captures` this replays the consumed tokens, here on a copy of
+    /// the grammar that captures every rule; see `tree`. A rule that has a
+    /// capture of its own appears under the capture's name.
+    #[wasm_bindgen]
+    pub fn get_parse_tree(&mut self) -> Result<String, JsValue> {
+        if !self
+            .grammar
+            .grammars
+            .iter()
+            .any(|g| g.lark_grammar.is_some())
+        {
+            return Err(JsValue::from_str("A parse tree needs a Lark grammar"));
+        }
+        let mut parser = self
+            .factory
+            .create_parser(tree::with_rule_captures(&self.grammar))
+            .map_err(|e| JsValue::from_str(&format!("Failed to create parser: {}", e)))?;
+        if let Some(token) = self.healing_token.filter(|_| self.grammar_stack.is_empty()) {
+            parser.process_prompt(vec![token]);
+        }
+        let complete = self.is_complete();
+
+        let trie = self.tok_env.tok_trie();
+        let eos = trie.eos_token();
+        let mut builder = tree::TreeBuilder::default();
+        let mut ended = false;
+        for &(token, step) in &self.history[self.grammar_start()..] {
+            if step == Step::Consumed {
+                parser
+                    .consume_token(token)
+                    .map_err(|e| JsValue::from_str(&format!("Failed to replay token: {}", e)))?;
+                let bytes = if token == eos {
+                    &[][..]
+                } else {
+                    let bytes = trie.token(token);
+                    bytes.strip_prefix(&[0xFF]).unwrap_or(bytes)
+                };
+                builder.consume(bytes, &parser.parser.captures()[..]);
+                ended |= token == eos;
+            }
+        }
+        // The last lexeme, and the rules it completes, only end at EOS
+        if complete && !ended && parser.consume_token(eos).is_ok() {
+            builder.consume(&[], &parser.parser.captures()[..]);
+        }
+
+        serde_json::to_string(&builder.finish("start"))
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize parse tree: {}", e)))
+    }
+
+    /// Get the temperature the grammar suggests for the next token, from Lark
+    /// `[temperature=...]` rule annotations; `None` where no annotation applies
+    ///
+    /// llguidance reports a temperature of 0 as no annotation. Like
+    /// `get_captures` this needs a parser of its own, which is kept and only
+    /// caught up from one call to the next, so calling it every step is cheap.
+    #[wasm_bindgen]
+    pub fn get_temperature(&mut self) -> Result<Option<f32>, JsValue> {
+        if !self.active || self.stop_sequences.hit() {
+            return Ok(None);
+        }
+        let (mut parser, from) = match self.shadow.take() {
+            Some(shadow) => shadow,
+            None => (self.start_parser()?, self.grammar_start()),
+        };
+        self.replay(&mut parser, from)?;
+        let temperature = parser.parser.temperature();
+        self.shadow = Some((parser, self.history.len()));
+        Ok(temperature)
+    }
+
+    /// A parser at the start of the current grammar, past any healed prompt token
+    fn start_parser(&self) -> Result<TokenParser, JsValue> {
+        let mut parser = self
+            .factory
+            .create_parser(self.grammar.clone())
+            .map_err(|e| JsValue::from_str(&format!("Failed to create parser: {}", e)))?;
+        // Only the base grammar follows the prompt; pushed grammars start fresh
+        if let Some(token) = self.healing_token.filter(|_| self.grammar_stack.is_empty()) {
+            parser.process_prompt(vec![token]);
+        }
+        Ok(parser)
+    }
+
+    /// Feed `parser` the tokens the current grammar consumed from `history[from..]`
+    fn replay(&self, parser: &mut TokenParser, from: usize) -> Result<(), JsValue> {
+        for &(token, step) in &self.history[from..] {
+            if step == Step::Consumed {
+                parser
+                    .consume_token(token)
+                    .map_err(|e| JsValue::from_str(&format!("Failed to replay token: {}", e)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the raw bytes of a token as seen by the grammar engine
+    /// Special tokens start with a \xFF marker byte
+    #[wasm_bindgen]
+    pub fn token_bytes(&self, token_id: u32) -> Result<Vec<u8>, JsValue> {
+        Ok(self.token(token_id)?.to_vec())
+    }
+
+    /// Get a printable representation of a token for debugging
+    /// Text tokens are quoted with escapes, special tokens are shown as-is
+    #[wasm_bindgen]
+    pub fn token_display(&self, token_id: u32) -> Result<String, JsValue> {
+        Ok(display_token(self.token(token_id)?))
+    }
+
+    fn token(&self, token_id: u32) -> Result<&[u8], JsValue> {
+        let token = self.tokenizer_id(token_id)?;
+        Ok(self.tok_env.tok_trie().token(token))
+    }
+
+    /// Translate a JS-facing token ID to the tokenizer's own ID
+    fn tokenizer_id(&self, token_id: u32) -> Result<TokenId, JsValue> {
+        lookup_token(self.id_remap.as_ref(), self.tok_vocab_size, token_id).ok_or_else(|| {
+            JsValue::from_str(&format!(
+                "Token ID {} does not belong to a token in the vocabulary",
+                token_id
+            ))
+        })
+    }
+
+    /// Translate a tokenizer token ID to the JS-facing ID
+    /// A spacing variant has the ID of the model token it stands for
+    fn runtime_id(&self, token: TokenId) -> u32 {
+        let token = self.tok_env.model_token(token);
+        match &self.id_remap {
+            Some(remap) => remap.runtime_id(token),
+            None => token,
+        }
+    }
+
+    /// Report tokenizer/grammar mismatches at the current position, as JSON
+    #[wasm_bindgen]
+    pub fn diagnose(&mut self) -> Result<String, JsValue> {
+        let mask = self
+            .compute_mask()
+            .map_err(|e| e.as_string().unwrap_or_else(|| format!("{:?}", e)));
+        let report = diagnostics::Diagnostics::collect(
+            self.tok_env.tok_trie(),
+            mask.as_deref().map_err(String::as_str),
+            self.stop_reason(),
+        );
+        serde_json::to_string(&report)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize diagnostics: {}", e)))
+    }
+
+    /// Report what makes the current Lark grammar ambiguous, as JSON: rules
+    /// that parse text several ways, pairs of lexemes that match the same
+    /// text, and the tokens such lexemes compete for
+    /// Compiles a grammar per pair of lexemes, so it is meant for development
+    #[wasm_bindgen]
+    pub fn diagnose_ambiguity(&self) -> Result<String, JsValue> {
+        let report = ambiguity::AmbiguityReport::collect(
+            &self.factory,
+            self.tok_env.tok_trie(),
+            &self.grammar,
+        );
+        serde_json::to_string(&report)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize ambiguity report: {}", e)))
+    }
+
+    /// Summarize how constrained the current position is, as JSON
+    #[wasm_bindgen]
+    pub fn mask_stats(&mut self) -> Result<String, JsValue> {
+        let mask = self.compute_mask()?;
+        let stats = diagnostics::MaskStats::collect(self.tok_env.tok_trie(), &mask);
+        serde_json::to_string(&stats)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize mask stats: {}", e)))
+    }
+
+    /// Get the constraint telemetry for the generation so far, as JSON
+    /// Counts restart at `reset`; a fork inherits its parent's counts
+    #[wasm_bindgen]
+    pub fn get_telemetry(&self) -> Result<String, JsValue> {
+        serde_json::to_string(&self.telemetry.report())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize telemetry: {}", e)))
+    }
+
+    /// Get the timings of the latest mask computation and advance, with
+    /// llguidance's Earley and lexer counters for them, as JSON
+    ///
+    /// The matcher does not expose its parser's counters, so the step is
+    /// repeated on the parser `get_temperature` keeps, which only needs to
+    /// catch up with the tokens consumed since it was last used.
+    #[wasm_bindgen]
+    pub fn last_step_stats(&mut self) -> Result<String, JsValue> {
+        let counters = if self.active && !self.stop_sequences.hit() {
+            self.parser_counters()?
+        } else {
+            telemetry::ParserCounters::default()
+        };
+        serde_json::to_string(&self.telemetry.last_step().with_parser(counters))
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize step stats: {}", e)))
+    }
+
+    /// Counters of consuming the last token the current grammar saw, and of
+    /// computing the mask after it
+    fn parser_counters(&mut self) -> Result<telemetry::ParserCounters, JsValue> {
+        let start = self.grammar_start();
+        let last = self.history[start..]
+            .iter()
+            .rposition(|&(_, step)| step == Step::Consumed)
+            .map(|i| start + i);
+
+        // Catch up to just before the last token, which needs a fresh parser
+        // if the kept one is already past it
+        let (mut parser, from) = match self.shadow.take() {
+            Some((parser, from)) if last.is_none_or(|last| from <= last) => (parser, from),
+            _ => (self.start_parser()?, start),
+        };
+        let until = last.unwrap_or(self.history.len());
+        for &(token, step) in &self.history[from..until] {
+            if step == Step::Consumed {
+                parser
+                    .consume_token(token)
+                    .map_err(|e| JsValue::from_str(&format!("Failed to replay token: {}", e)))?;
+            }
+        }
+
+        let before = parser.parser.stats().clone();
+        if let Some(last) = last {
+            parser
+                .consume_token(self.history[last].0)
+                .map_err(|e| JsValue::from_str(&format!("Failed to replay token: {}", e)))?;
+        }
+        parser
+            .compute_mask()
+            .map_err(|e| JsValue::from_str(&format!("Failed to compute mask: {}", e)))?;
+        let after = parser.parser.stats().clone();
+        let counters = telemetry::ParserCounters {
+            earley_rows: (after.rows - before.rows) as u64,
+            earley_items: (after.all_items - before.all_items) as u64,
+            lexer_states: parser.parser.lexer_stats().num_states as u64,
+            slices_applied: (after.slices_applied - before.slices_applied) as u64,
+            trie_nodes_walked: (after.trie_nodes_walked - before.trie_nodes_walked) as u64,
+            lexer_cost: (after.lexer_cost - before.lexer_cost) as u64,
+        };
+        self.shadow = Some((parser, self.history.len()));
+        Ok(counters)
+    }
+
+    /// Get the current stop reason: NotStopped, EndOfSentence, NoExtension,
+    /// MaxTokens or ParserError
+    #[wasm_bindgen]
+    pub fn stop_reason(&self) -> String {
+        if self.stop_sequences.hit() {
+            return "EndOfSentence".to_string();
+        }
+        stop_reason_name(self.matcher.stop_reason()).to_string()
+    }
 }
 
 /// Initialize the WASM module
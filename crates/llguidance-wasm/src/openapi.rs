@@ -0,0 +1,268 @@
+//! OpenAPI operation input
+//!
+//! An operation is looked up by its `operationId`, and the JSON schema of its
+//! response (or request body) is what the output has to match. The document's
+//! `components.schemas` become `$defs` of that schema, so the
+//! `#/components/schemas/...` references OpenAPI documents use keep working.
+//! OpenAPI 3.0 `nullable` is translated to a `null` type, and properties are
+//! dropped where the spec says they are absent: `readOnly` ones from request
+//! bodies and `writeOnly` ones from responses.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Keywords describing the API rather than the JSON, removed before compiling
+const ANNOTATIONS: [&str; 5] = [
+    "discriminator",
+    "xml",
+    "externalDocs",
+    "example",
+    "nullable",
+];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Target {
+    /// A response of the operation, as the API would return it
+    #[default]
+    Response,
+    /// The request body of the operation, as a client would send it
+    RequestBody,
+}
+
+/// The JSON schema of one operation's response or request body
+///
+/// `status` defaults to the first 2XX response (or `default`), and
+/// `media_type` to `application/json` or another JSON media type.
+pub(crate) fn operation_schema(
+    document: &Value,
+    operation_id: &str,
+    target: Target,
+    status: Option<&str>,
+    media_type: Option<&str>,
+) -> Result<Value, String> {
+    match document.get("openapi").and_then(Value::as_str) {
+        Some(version) if version.starts_with("3.") => {}
+        _ => return Err("Only OpenAPI 3 documents are supported".to_string()),
+    }
+    let operation = find_operation(document, operation_id)?;
+
+    let body = match target {
+        Target::RequestBody => operation
+            .get("requestBody")
+            .ok_or_else(|| format!("OpenAPI operation {} has no request body", operation_id))?,
+        Target::Response => {
+            let responses = operation
+                .get("responses")
+                .and_then(Value::as_object)
+                .ok_or_else(|| format!("OpenAPI operation {} has no responses", operation_id))?;
+            match status {
+                Some(status) => responses.get(status).ok_or_else(|| {
+                    format!(
+                        "OpenAPI operation {} has no {} response",
+                        operation_id, status
+                    )
+                })?,
+                None => responses
+                    .iter()
+                    .find(|(status, _)| status.starts_with('2'))
+                    .map(|(_, response)| response)
+                    .or_else(|| responses.get("default"))
+                    .ok_or_else(|| {
+                        format!("OpenAPI operation {} has no success response", operation_id)
+                    })?,
+            }
+        }
+    };
+    let body = resolve(document, body)?;
+
+    let what = match target {
+        Target::RequestBody => "request body",
+        Target::Response => "response",
+    };
+    let content = body
+        .get("content")
+        .and_then(Value::as_object)
+        .ok_or_else(|| format!("OpenAPI operation {} {} has no content", operation_id, what))?;
+    let media = match media_type {
+        Some(media_type) => content.get(media_type).ok_or_else(|| {
+            format!(
+                "OpenAPI operation {} {} has no {} content",
+                operation_id, what, media_type
+            )
+        })?,
+        None => content
+            .get("application/json")
+            .or_else(|| {
+                content
+                    .iter()
+                    .find(|(media_type, _)| is_json(media_type))
+                    .map(|(_, media)| media)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "OpenAPI operation {} {} has no JSON content",
+                    operation_id, what
+                )
+            })?,
+    };
+    let schema = media
+        .get("schema")
+        .ok_or_else(|| format!("OpenAPI operation {} {} has no schema", operation_id, what))?;
+
+    let mut schema = convert(schema, target)?;
+    if let Some(Value::Object(schemas)) = document.pointer("/components/schemas") {
+        let mut defs = Map::new();
+        for (name, schema) in schemas {
+            defs.insert(name.clone(), convert(schema, target)?);
+        }
+        if let Value::Object(map) = &mut schema {
+            map.insert("$defs".to_string(), Value::Object(defs));
+        }
+    }
+    Ok(schema)
+}
+
+fn find_operation<'a>(document: &'a Value, operation_id: &str) -> Result<&'a Value, String> {
+    let paths = document
+        .get("paths")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|paths| paths.values());
+    for path in paths {
+        for method in METHODS {
+            if let Some(operation) = path.get(method) {
+                if operation.get("operationId").and_then(Value::as_str) == Some(operation_id) {
+                    return Ok(operation);
+                }
+            }
+        }
+    }
+    Err(format!("OpenAPI operation {} not found", operation_id))
+}
+
+/// Follow the `$ref`s of a response or request body within the document
+fn resolve<'a>(document: &'a Value, mut value: &'a Value) -> Result<&'a Value, String> {
+    // Bounded, so references that loop end with an error
+    for _ in 0..32 {
+        let Some(reference) = value.get("$ref").and_then(Value::as_str) else {
+            return Ok(value);
+        };
+        value = reference
+            .strip_prefix('#')
+            .and_then(|pointer| document.pointer(pointer))
+            .ok_or_else(|| format!("Cannot resolve OpenAPI $ref {}", reference))?;
+    }
+    Err("OpenAPI $refs form a cycle".to_string())
+}
+
+fn is_json(media_type: &str) -> bool {
+    let essence = media_type.split(';').next().unwrap_or("").trim();
+    essence == "application/json" || essence.ends_with("+json")
+}
+
+/// Translate an OpenAPI schema object to the JSON schema llguidance compiles
+fn convert(schema: &Value, target: Target) -> Result<Value, String> {
+    match schema {
+        Value::Object(map) => {
+            let mut result = Map::new();
+            for (key, value) in map {
+                if ANNOTATIONS.contains(&key.as_str()) {
+                    continue;
+                }
+                let value = match (key.as_str(), value) {
+                    ("$ref", Value::String(reference)) => Value::String(convert_ref(reference)?),
+                    ("properties", Value::Object(properties)) => {
+                        let mut converted = Map::new();
+                        for (name, property) in properties {
+                            if !is_absent(property, target) {
+                                converted.insert(name.clone(), convert(property, target)?);
+                            }
+                        }
+                        Value::Object(converted)
+                    }
+                    _ => convert(value, target)?,
+                };
+                result.insert(key.clone(), value);
+            }
+
+            // Properties absent from this side of the API cannot be required
+            if let (Some(Value::Array(required)), Some(Value::Object(properties))) =
+                (result.get("required"), map.get("properties"))
+            {
+                let required: Vec<Value> = required
+                    .iter()
+                    .filter(|name| {
+                        name.as_str()
+                            .and_then(|name| properties.get(name))
+                            .is_none_or(|property| !is_absent(property, target))
+                    })
+                    .cloned()
+                    .collect();
+                result.insert("required".to_string(), Value::Array(required));
+            }
+
+            if map.get("nullable") == Some(&Value::Bool(true)) {
+                return Ok(nullable(result));
+            }
+            Ok(Value::Object(result))
+        }
+        Value::Array(items) => Ok(Value::Array(
+            items
+                .iter()
+                .map(|item| convert(item, target))
+                .collect::<Result<_, _>>()?,
+        )),
+        _ => Ok(schema.clone()),
+    }
+}
+
+fn convert_ref(reference: &str) -> Result<String, String> {
+    reference
+        .strip_prefix("#/components/schemas/")
+        .map(|name| format!("#/$defs/{}", name))
+        .ok_or_else(|| {
+            format!(
+                "Unsupported $ref {} in an OpenAPI schema; only #/components/schemas references are supported",
+                reference
+            )
+        })
+}
+
+/// Whether a property is left out of `target` by `readOnly` or `writeOnly`
+fn is_absent(property: &Value, target: Target) -> bool {
+    let flag = match target {
+        Target::Response => "writeOnly",
+        Target::RequestBody => "readOnly",
+    };
+    property.get(flag) == Some(&Value::Bool(true))
+}
+
+/// Allow `null` in a schema marked `nullable: true`
+fn nullable(mut schema: Map<String, Value>) -> Value {
+    let has_enum = if let Some(Value::Array(values)) = schema.get_mut("enum") {
+        values.push(Value::Null);
+        true
+    } else {
+        false
+    };
+    match schema.remove("type") {
+        Some(Value::String(ty)) => {
+            schema.insert(
+                "type".to_string(),
+                Value::Array(vec![Value::String(ty), "null".into()]),
+            );
+            Value::Object(schema)
+        }
+        Some(ty) => {
+            schema.insert("type".to_string(), ty);
+            Value::Object(schema)
+        }
+        None if has_enum => Value::Object(schema),
+        None => serde_json::json!({ "anyOf": [schema, { "type": "null" }] }),
+    }
+}
@@ -0,0 +1,129 @@
+//! Per-parser options passed from JavaScript
+//!
+//! Options only affect the individual parser, never the tokenizer, so parsers
+//! with different options can still share one cached tokenizer environment.
+
+use serde::Deserialize;
+
+use llguidance::toktrie::TokenId;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub(crate) struct ParserOptions {
+    /// Length of the model's logits when they are padded beyond the tokenizer
+    /// vocabulary; the padding positions are always masked out
+    pub vocab_size_override: Option<usize>,
+    /// Mask out every special token except EOS, whatever the grammar allows
+    pub exclude_special_tokens: bool,
+    /// Runtime ID of each tokenizer token, when the runtime orders logits differently
+    pub id_remap: Option<Vec<u32>>,
+    /// Tokens the grammar never sees (e.g. chat-template role markers); they are
+    /// always allowed and advancing over them leaves the grammar untouched
+    pub passthrough_tokens: Vec<u32>,
+    /// Token that starts grammar enforcement; until it is produced, only it and
+    /// the passthrough tokens are allowed
+    pub trigger_token: Option<u32>,
+    /// Logit bias for allowed tokens in `compute_logit_bias`
+    pub allowed_bias: f32,
+    /// Logit bias for disallowed tokens in `compute_logit_bias`; -Infinity if unset
+    pub disallowed_bias: Option<f32>,
+    /// Most tokens a single `rollback` call may undo; unlimited if unset
+    pub max_rollback_tokens: Option<usize>,
+}
+
+impl ParserOptions {
+    /// Parse the options JSON, treating a missing value as all defaults
+    pub(crate) fn parse(options_json: Option<&str>) -> Result<Self, String> {
+        match options_json {
+            Some(json) if !json.trim().is_empty() => serde_json::from_str(json)
+                .map_err(|e| format!("Failed to parse parser options: {}", e)),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    /// Length of the token mask, which must cover the `min_len` IDs in use
+    pub(crate) fn mask_len(&self, min_len: usize) -> Result<usize, String> {
+        match self.vocab_size_override {
+            Some(size) if size < min_len => Err(format!(
+                "vocab_size_override ({}) is smaller than the {} token IDs in use",
+                size, min_len
+            )),
+            Some(size) => Ok(size),
+            None => Ok(min_len),
+        }
+    }
+
+    /// Build the ID remapping table for a tokenizer with `tok_vocab_size` tokens
+    pub(crate) fn id_remap(&self, tok_vocab_size: usize) -> Result<Option<IdRemap>, String> {
+        self.id_remap
+            .as_ref()
+            .map(|remap| IdRemap::new(remap.clone(), tok_vocab_size))
+            .transpose()
+    }
+}
+
+/// Bound on runtime IDs, far above any vocabulary; masks span the largest ID
+const MAX_RUNTIME_ID: u32 = 1 << 24;
+
+/// Translation between tokenizer token IDs and the runtime's logit indices
+#[derive(Clone)]
+pub(crate) struct IdRemap {
+    /// Runtime ID of each tokenizer token
+    runtime_ids: Vec<u32>,
+    /// Tokenizer token at each runtime ID, `None` where the runtime has no token
+    tokenizer_ids: Vec<Option<TokenId>>,
+}
+
+impl IdRemap {
+    fn new(runtime_ids: Vec<u32>, tok_vocab_size: usize) -> Result<Self, String> {
+        if runtime_ids.len() != tok_vocab_size {
+            return Err(format!(
+                "id_remap has {} entries, but the tokenizer has {} tokens",
+                runtime_ids.len(),
+                tok_vocab_size
+            ));
+        }
+
+        if let Some((token, &runtime)) = runtime_ids
+            .iter()
+            .enumerate()
+            .find(|&(_, &runtime)| runtime >= MAX_RUNTIME_ID)
+        {
+            return Err(format!(
+                "id_remap maps token {} to ID {}, but runtime IDs must be below {}",
+                token, runtime, MAX_RUNTIME_ID
+            ));
+        }
+
+        let len = runtime_ids.iter().max().map_or(0, |&max| max as usize + 1);
+        let mut tokenizer_ids = vec![None; len];
+        for (token, &runtime) in runtime_ids.iter().enumerate() {
+            let slot = &mut tokenizer_ids[runtime as usize];
+            if let Some(other) = slot {
+                return Err(format!(
+                    "id_remap maps both token {} and token {} to ID {}",
+                    other, token, runtime
+                ));
+            }
+            *slot = Some(token as TokenId);
+        }
+
+        Ok(IdRemap {
+            runtime_ids,
+            tokenizer_ids,
+        })
+    }
+
+    /// Number of runtime IDs the mapping spans
+    pub(crate) fn runtime_len(&self) -> usize {
+        self.tokenizer_ids.len()
+    }
+
+    pub(crate) fn runtime_id(&self, token: TokenId) -> u32 {
+        self.runtime_ids[token as usize]
+    }
+
+    pub(crate) fn tokenizer_id(&self, runtime: u32) -> Option<TokenId> {
+        self.tokenizer_ids.get(runtime as usize).copied().flatten()
+    }
+}
@@ -0,0 +1,156 @@
+//! Built-in grammar presets
+//!
+//! `{"preset": "sql", "dialect": "sqlite", "statements": ["select"]}` expands
+//! to a curated Lark grammar for read-only SQL: queries with joins, subqueries,
+//! common table expressions, compound selects, grouping, ordering and limits,
+//! and optionally EXPLAIN. Statements that modify data are not part of any
+//! preset. Keywords are case-insensitive and whitespace between tokens is free.
+
+/// The SQL shared by every dialect; `{name}` placeholders are filled per dialect
+const SQL: &str = r#"
+query: with_clause? select_core (compound_op select_core)* order_by? limit_clause?
+with_clause: "with"i "recursive"i? cte ("," cte)*
+cte: name ("(" name ("," name)* ")")? "as"i "(" query ")"
+compound_op: "union"i "all"i? | "intersect"i | "except"i
+select_core: "select"i ("distinct"i | "all"i)? result_column ("," result_column)* from_clause? where_clause? group_by? having?
+result_column: "*" | name "." "*" | expr ("as"i? name)?
+from_clause: "from"i table_or_subquery join*
+table_or_subquery: qualified_name ("as"i? name)? | "(" query ")" ("as"i? name)?
+join: ("," | join_op) table_or_subquery join_constraint?
+join_op: "natural"i? (("left"i | "right"i | "full"i) "outer"i? | "inner"i | "cross"i)? "join"i
+join_constraint: "on"i expr | "using"i "(" name ("," name)* ")"
+where_clause: "where"i expr
+group_by: "group"i "by"i expr ("," expr)*
+having: "having"i expr
+order_by: "order"i "by"i ordering_term ("," ordering_term)*
+ordering_term: expr ("asc"i | "desc"i)? ("nulls"i ("first"i | "last"i))?
+limit_clause: {limit}
+expr: and_expr ("or"i and_expr)*
+and_expr: not_expr ("and"i not_expr)*
+not_expr: "not"i not_expr | predicate
+predicate: sum (comp_op sum)*
+    | sum "is"i "not"i? sum
+    | sum "not"i? "between"i sum "and"i sum
+    | sum "not"i? "in"i "(" (query | expr ("," expr)*) ")"
+    | sum "not"i? like_op sum ("escape"i sum)?
+comp_op: "=" | "==" | "!=" | "<>" | "<" | "<=" | ">" | ">="
+like_op: {like}
+sum: product (("+" | "-" | "||") product)*
+product: unary (("*" | "/" | "%") unary)*
+unary: ("-" | "+") unary | {unary}
+primary: literal
+    | name ("." name)?
+    | name "(" ("*" | "distinct"i? expr ("," expr)*)? ")"
+    | "(" expr ")"
+    | "(" query ")"
+    | "exists"i "(" query ")"
+    | "case"i expr? ("when"i expr "then"i expr)+ ("else"i expr)? "end"i
+    | "cast"i "(" expr "as"i type_name ")"
+literal: NUMBER | STRING | "null"i | "true"i | "false"i
+    | "current_date"i | "current_time"i | "current_timestamp"i
+qualified_name: name ("." name)?
+type_name: IDENT+ ("(" NUMBER ("," NUMBER)? ")")?
+name: IDENT | QUOTED_IDENT
+STRING: /'([^']|'')*'/
+NUMBER: /[0-9]+(\.[0-9]*)?([eE][+-]?[0-9]+)?|\.[0-9]+([eE][+-]?[0-9]+)?/
+IDENT: /[A-Za-z_][A-Za-z0-9_$]*/
+QUOTED_IDENT: {quoted_ident}
+%ignore /[ \t\r\n]+/
+"#;
+
+struct Dialect {
+    /// The body of `limit_clause`, followed by any rules it needs
+    limit: &'static str,
+    like: &'static str,
+    /// The last alternative of `unary`, e.g. with casts
+    unary: &'static str,
+    quoted_ident: &'static str,
+    explain: &'static str,
+}
+
+fn dialect(name: &str) -> Result<Dialect, String> {
+    let standard_limit = r#""limit"i expr ("offset"i expr)?"#;
+    Ok(match name {
+        "ansi" => Dialect {
+            limit: r#""offset"i expr ("row"i | "rows"i) fetch? | fetch
+fetch: "fetch"i ("first"i | "next"i) expr ("row"i | "rows"i) "only"i"#,
+            like: r#""like"i"#,
+            unary: "primary",
+            quoted_ident: r#"/"([^"]|"")+"/"#,
+            explain: r#""explain"i"#,
+        },
+        "sqlite" => Dialect {
+            limit: standard_limit,
+            like: r#""like"i | "glob"i"#,
+            unary: "primary",
+            quoted_ident: r#"/"([^"]|"")+"/ | /`([^`]|``)+`/ | /\[[^\]]+\]/"#,
+            explain: r#""explain"i ("query"i "plan"i)?"#,
+        },
+        "postgres" => Dialect {
+            limit: standard_limit,
+            like: r#""like"i | "ilike"i | "similar"i "to"i"#,
+            unary: r#"primary ("::" type_name)*"#,
+            quoted_ident: r#"/"([^"]|"")+"/"#,
+            explain: r#""explain"i"#,
+        },
+        "mysql" => Dialect {
+            limit: r#""limit"i expr (("offset"i | ",") expr)?"#,
+            like: r#""like"i | "regexp"i | "rlike"i"#,
+            unary: "primary",
+            quoted_ident: r#"/`([^`]|``)+`/"#,
+            explain: r#""explain"i"#,
+        },
+        _ => {
+            return Err(format!(
+                "Unknown SQL dialect {}; expected ansi, sqlite, postgres or mysql",
+                name
+            ))
+        }
+    })
+}
+
+/// Expand a preset to its Lark grammar
+pub(crate) fn preset_to_lark(
+    preset: &str,
+    dialect: Option<&str>,
+    statements: Option<&[String]>,
+) -> Result<String, String> {
+    match preset {
+        "sql" => sql(dialect.unwrap_or("ansi"), statements.unwrap_or(&[])),
+        _ => Err(format!("Unknown grammar preset {}; expected sql", preset)),
+    }
+}
+
+fn sql(name: &str, statements: &[String]) -> Result<String, String> {
+    let dialect = dialect(name)?;
+    let mut alternatives = Vec::new();
+    for statement in statements {
+        let alternative = match statement.as_str() {
+            "select" => "query".to_string(),
+            "explain" => format!("{} query", dialect.explain),
+            _ => {
+                return Err(format!(
+                    "Unknown SQL statement {}; the read-only presets support select and explain",
+                    statement
+                ))
+            }
+        };
+        if !alternatives.contains(&alternative) {
+            alternatives.push(alternative);
+        }
+    }
+    if alternatives.is_empty() {
+        alternatives.push("query".to_string());
+    }
+
+    let grammar = SQL
+        .replace("{limit}", dialect.limit)
+        .replace("{like}", dialect.like)
+        .replace("{unary}", dialect.unary)
+        .replace("{quoted_ident}", dialect.quoted_ident);
+    Ok(format!(
+        "start: statement \";\"?\nstatement: {}{}",
+        alternatives.join(" | "),
+        grammar
+    ))
+}
@@ -0,0 +1,98 @@
+//! Constrained sampling over masked logits
+//!
+//! Mirrors the usual transformers sampling pipeline (temperature, top-k,
+//! top-p) but only ever considers tokens the grammar allows.
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub(crate) struct SampleOptions {
+    /// Softmax temperature; 0 or below picks the most likely token
+    pub temperature: f32,
+    /// Keep only the `top_k` most likely tokens; 0 keeps all of them
+    pub top_k: usize,
+    /// Keep the smallest set of tokens whose probability reaches `top_p`
+    pub top_p: f32,
+}
+
+impl Default for SampleOptions {
+    fn default() -> Self {
+        SampleOptions {
+            temperature: 1.0,
+            top_k: 0,
+            top_p: 1.0,
+        }
+    }
+}
+
+impl SampleOptions {
+    /// Parse the options JSON, treating a missing value as all defaults
+    pub(crate) fn parse(options_json: Option<&str>) -> Result<Self, String> {
+        match options_json {
+            Some(json) if !json.trim().is_empty() => serde_json::from_str(json)
+                .map_err(|e| format!("Failed to parse sampling options: {}", e)),
+            _ => Ok(Self::default()),
+        }
+    }
+}
+
+/// Pick a token from `logits`, restricted to positions where `mask` is 1
+///
+/// `random` is a uniform sample from [0, 1). Returns `None` when no allowed
+/// token has a finite logit.
+pub(crate) fn sample(
+    logits: &[f32],
+    mask: &[u8],
+    options: &SampleOptions,
+    random: f64,
+) -> Option<u32> {
+    let mut candidates: Vec<(u32, f32)> = logits
+        .iter()
+        .zip(mask)
+        .enumerate()
+        .filter(|&(_, (logit, &allowed))| allowed == 1 && logit.is_finite())
+        .map(|(id, (&logit, _))| (id as u32, logit))
+        .collect();
+
+    if options.temperature <= 0.0 || candidates.len() == 1 {
+        return candidates
+            .into_iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| id);
+    }
+
+    candidates.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+    if options.top_k > 0 {
+        candidates.truncate(options.top_k);
+    }
+    let (_, max_logit) = *candidates.first()?;
+
+    let mut weights: Vec<f64> = candidates
+        .iter()
+        .map(|&(_, logit)| (((logit - max_logit) / options.temperature) as f64).exp())
+        .collect();
+    if options.top_p < 1.0 {
+        let total: f64 = weights.iter().sum();
+        let mut cumulative = 0.0;
+        let keep = weights
+            .iter()
+            .position(|&w| {
+                cumulative += w / total;
+                cumulative >= options.top_p as f64
+            })
+            .map_or(weights.len(), |i| i + 1);
+        weights.truncate(keep);
+    }
+
+    let total: f64 = weights.iter().sum();
+    let mut target = random * total;
+    for (&(id, _), &w) in candidates.iter().zip(&weights) {
+        if target < w {
+            return Some(id);
+        }
+        target -= w;
+    }
+    // Rounding can leave a sliver past the last weight
+    candidates.get(weights.len() - 1).map(|&(id, _)| id)
+}
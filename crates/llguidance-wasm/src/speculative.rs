@@ -0,0 +1,131 @@
+//! Grammar constraints for speculative decoding across two tokenizers
+//!
+//! When the draft model and the target model use different tokenizers, a
+//! single token mask cannot constrain both. `SpeculativeParser` pairs a
+//! parser per vocabulary, built from the same grammar: the draft parser masks
+//! the draft model's proposals, the proposed text is re-tokenized and checked
+//! against the target vocabulary, and both parsers are moved to whatever text
+//! the target model accepts.
+
+use js_sys::Uint8Array;
+use wasm_bindgen::prelude::*;
+
+use crate::LLGuidanceParser;
+
+/// A target-vocabulary parser and a draft-vocabulary parser kept on the same text
+#[wasm_bindgen]
+pub struct SpeculativeParser {
+    target: LLGuidanceParser,
+    /// Draft parser at the committed text
+    committed_draft: LLGuidanceParser,
+    /// Draft parser at the committed text plus the current proposal
+    draft: LLGuidanceParser,
+    /// Bytes of the current proposal
+    draft_bytes: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl SpeculativeParser {
+    /// Pair `target` and `draft`, which must use the same grammar and be at the
+    /// same text; both are copied at their current position
+    #[wasm_bindgen(constructor)]
+    pub fn new(target: &LLGuidanceParser, draft: &LLGuidanceParser) -> SpeculativeParser {
+        SpeculativeParser {
+            target: target.fork_state(),
+            committed_draft: draft.fork_state(),
+            draft: draft.fork_state(),
+            draft_bytes: Vec::new(),
+        }
+    }
+
+    /// Length of the target model's mask
+    #[wasm_bindgen]
+    pub fn target_vocab_size(&self) -> usize {
+        self.target.vocab_size
+    }
+
+    /// Length of the draft model's mask
+    #[wasm_bindgen]
+    pub fn draft_vocab_size(&self) -> usize {
+        self.draft.vocab_size
+    }
+
+    /// Get the target-vocabulary mask at the committed text
+    #[wasm_bindgen]
+    pub fn target_mask(&mut self) -> Result<Uint8Array, JsValue> {
+        self.target.get_token_mask(None)
+    }
+
+    /// Get the draft-vocabulary mask after the proposal so far
+    #[wasm_bindgen]
+    pub fn draft_mask(&mut self) -> Result<Uint8Array, JsValue> {
+        self.draft.get_token_mask(None)
+    }
+
+    /// Extend the proposal by a token sampled from the draft model
+    #[wasm_bindgen]
+    pub fn advance_draft(&mut self, draft_token_id: u32) -> Result<(), JsValue> {
+        self.draft.advance(draft_token_id)?;
+        let token = self.draft.token(draft_token_id)?;
+        // Special tokens (e.g. EOS) have no text to hand over to the target
+        if token.first() != Some(&0xFF) {
+            self.draft_bytes.extend_from_slice(token);
+        }
+        Ok(())
+    }
+
+    /// Re-tokenize the proposal with the target tokenizer, truncated to the
+    /// longest prefix the grammar accepts, ready for the target model to verify
+    #[wasm_bindgen]
+    pub fn draft_as_target_tokens(&mut self) -> Result<Vec<u32>, JsValue> {
+        let tokens = self.target.tok_env.tokenize_bytes(&self.draft_bytes);
+        let valid = self
+            .target
+            .matcher
+            .validate_tokens(&tokens)
+            .map_err(|e| JsValue::from_str(&format!("Failed to validate draft: {}", e)))?;
+        Ok(tokens[..valid]
+            .iter()
+            .map(|&token| self.target.runtime_id(token))
+            .collect())
+    }
+
+    /// Advance both parsers by the target tokens the target model accepted,
+    /// discarding the rest of the proposal
+    #[wasm_bindgen]
+    pub fn commit(&mut self, target_token_ids: &[u32]) -> Result<(), JsValue> {
+        let mut bytes = Vec::new();
+        for &token_id in target_token_ids {
+            self.target.advance(token_id)?;
+            let token = self.target.token(token_id)?;
+            if token.first() != Some(&0xFF) {
+                bytes.extend_from_slice(token);
+            }
+        }
+
+        let draft_tokens = self.committed_draft.tok_env.tokenize_bytes(&bytes);
+        for token in draft_tokens {
+            let token_id = self.committed_draft.runtime_id(token);
+            self.committed_draft.advance(token_id)?;
+        }
+        self.restart_draft();
+        Ok(())
+    }
+
+    /// Discard the proposal, e.g. when every drafted token was rejected
+    #[wasm_bindgen]
+    pub fn reject_draft(&mut self) {
+        self.restart_draft();
+    }
+
+    /// Check whether the committed text is a valid complete parse
+    #[wasm_bindgen]
+    pub fn is_complete(&mut self) -> bool {
+        self.target.is_complete()
+    }
+
+    fn restart_draft(&mut self) {
+        self.draft = self.committed_draft.fork_state();
+        self.draft_bytes.clear();
+    }
+}
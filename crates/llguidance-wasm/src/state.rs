@@ -0,0 +1,101 @@
+//! Portable parser snapshots
+//!
+//! The Earley and lexer state inside the matcher cannot be serialized, so a
+//! snapshot records how the parser got where it is instead: the token healing
+//! prefix and every token accepted since the start of the grammar. Loading a
+//! snapshot replays those tokens, which is far cheaper than re-running the
+//! model and rebuilds exactly the same state.
+//!
+//! Layout (little-endian): magic `LLGS`, format version (u32), grammar
+//! fingerprint (u64), tokenizer vocabulary size (u32), healing token
+//! (u32, `u32::MAX` if none), token count (u32), then the tokens (u32 each).
+
+use llguidance::toktrie::TokenId;
+
+const MAGIC: &[u8; 4] = b"LLGS";
+const VERSION: u32 = 1;
+const NO_TOKEN: u32 = u32::MAX;
+
+#[derive(Debug)]
+pub(crate) struct ParserState {
+    /// Fingerprint of the grammar the tokens were accepted by
+    pub grammar_fingerprint: u64,
+    pub tok_vocab_size: u32,
+    /// Prompt token rolled back by `enable_token_healing`, if any
+    pub healing_token: Option<TokenId>,
+    /// Tokenizer IDs of the tokens accepted so far, in order
+    pub tokens: Vec<TokenId>,
+}
+
+impl ParserState {
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(28 + 4 * self.tokens.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.grammar_fingerprint.to_le_bytes());
+        bytes.extend_from_slice(&self.tok_vocab_size.to_le_bytes());
+        bytes.extend_from_slice(&self.healing_token.unwrap_or(NO_TOKEN).to_le_bytes());
+        bytes.extend_from_slice(&(self.tokens.len() as u32).to_le_bytes());
+        for &token in &self.tokens {
+            bytes.extend_from_slice(&token.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err("Not a parser state snapshot".to_string());
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(format!(
+                "Unsupported parser state version {} (expected {})",
+                version, VERSION
+            ));
+        }
+        let grammar_fingerprint = u64::from_le_bytes(reader.take(8)?.try_into().unwrap());
+        let tok_vocab_size = reader.u32()?;
+        let healing_token = Some(reader.u32()?).filter(|&token| token != NO_TOKEN);
+        let count = reader.u32()? as usize;
+        let tokens = (0..count)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        if !reader.bytes.is_empty() {
+            return Err("Trailing bytes after parser state snapshot".to_string());
+        }
+
+        Ok(ParserState {
+            grammar_fingerprint,
+            tok_vocab_size,
+            healing_token,
+            tokens,
+        })
+    }
+}
+
+/// FNV-1a hash, stable across builds so snapshots survive upgrades
+pub(crate) fn fingerprint(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() < len {
+            return Err("Parser state snapshot is truncated".to_string());
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,83 @@
+//! Stop sequences from the grammar input
+//!
+//! A stop sequence ends generation wherever it appears in the output, even
+//! inside free text the grammar otherwise leaves unconstrained. When the whole
+//! output is free text up to the sequence, as with a regex, `text_until` or
+//! the text around a lazy grammar's trigger, the sequences are compiled into
+//! the grammar's lexemes, which then end at the first one. Elsewhere they
+//! would cut a grammar short in the middle, which llguidance cannot express,
+//! so the parser also watches the bytes it consumes and allows only EOS once
+//! one of the sequences has appeared.
+
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+struct StopInput {
+    #[serde(default)]
+    stop: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct StopSequences {
+    sequences: Vec<Vec<u8>>,
+    /// The last consumed bytes, long enough to hold any sequence but the last byte
+    tail: Vec<u8>,
+    /// Whether a sequence has appeared in the output
+    hit: bool,
+}
+
+impl StopSequences {
+    /// Read the `stop` field of a grammar input; other formats have none
+    pub(crate) fn from_grammar_json(grammar_json: &str) -> Self {
+        let input: StopInput = serde_json::from_str(grammar_json).unwrap_or_default();
+        StopSequences {
+            sequences: input
+                .stop
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .map(String::into_bytes)
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Watch for `sequences`, e.g. as stored in a compiled grammar
+    pub(crate) fn from_sequences(sequences: Vec<Vec<u8>>) -> Self {
+        StopSequences {
+            sequences,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn sequences(&self) -> &[Vec<u8>] {
+        &self.sequences
+    }
+
+    pub(crate) fn hit(&self) -> bool {
+        self.hit
+    }
+
+    /// Record newly consumed bytes; returns whether a sequence has now appeared
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> bool {
+        if self.sequences.is_empty() || self.hit {
+            return self.hit;
+        }
+        self.tail.extend_from_slice(bytes);
+        self.hit = self
+            .sequences
+            .iter()
+            .any(|s| self.tail.windows(s.len()).any(|w| w == s.as_slice()));
+
+        let keep = self.sequences.iter().map(Vec::len).max().unwrap_or(1) - 1;
+        let excess = self.tail.len().saturating_sub(keep);
+        self.tail.drain(..excess);
+        self.hit
+    }
+
+    /// Start over from an output of `consumed`, e.g. after a rollback
+    pub(crate) fn restart(&mut self, consumed: &[u8]) {
+        self.tail.clear();
+        self.hit = false;
+        self.push(consumed);
+    }
+}
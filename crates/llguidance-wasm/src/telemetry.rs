@@ -0,0 +1,125 @@
+//! Per-generation constraint telemetry
+//!
+//! Cheap counters updated as the parser computes masks and advances, so
+//! grammars can be compared in production by how much they constrain the
+//! model and how much time the constraint costs. The measurements of the
+//! latest step are kept separately, to find the steps that are slow, and
+//! come with llguidance's own Earley and lexer counters for that step.
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = performance, js_name = now)]
+    fn performance_now() -> f64;
+}
+
+/// Milliseconds since an arbitrary origin, with sub-millisecond resolution
+pub(crate) fn now() -> f64 {
+    performance_now()
+}
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Telemetry {
+    steps: u64,
+    forced_steps: u64,
+    mask_computations: u64,
+    allowed_tokens_total: u64,
+    mask_time_ms: f64,
+    last_step: StepStats,
+}
+
+impl Telemetry {
+    /// Record a mask computed in `elapsed_ms` that allows `allowed_tokens` tokens
+    pub(crate) fn record_mask(&mut self, allowed_tokens: usize, elapsed_ms: f64) {
+        self.mask_computations += 1;
+        self.allowed_tokens_total += allowed_tokens as u64;
+        self.mask_time_ms += elapsed_ms;
+        self.last_step.mask_time_us = elapsed_ms * 1000.0;
+        self.last_step.allowed_tokens = allowed_tokens;
+    }
+
+    /// Record a token accepted in `elapsed_ms`; `forced` if its position's
+    /// mask left no choice
+    pub(crate) fn record_step(&mut self, forced: bool, elapsed_ms: f64) {
+        self.steps += 1;
+        if forced {
+            self.forced_steps += 1;
+        }
+        self.last_step.advance_time_us = elapsed_ms * 1000.0;
+    }
+
+    pub(crate) fn last_step(&self) -> &StepStats {
+        &self.last_step
+    }
+
+    pub(crate) fn report(&self) -> TelemetryReport {
+        TelemetryReport {
+            steps: self.steps,
+            forced_steps: self.forced_steps,
+            mask_computations: self.mask_computations,
+            average_allowed_tokens: if self.mask_computations == 0 {
+                0.0
+            } else {
+                self.allowed_tokens_total as f64 / self.mask_computations as f64
+            },
+            mask_time_ms: self.mask_time_ms,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TelemetryReport {
+    /// Tokens accepted by `advance`
+    steps: u64,
+    /// Steps whose mask was computed and allowed exactly one token
+    forced_steps: u64,
+    /// Masks computed; repeated requests at one position are served from cache
+    mask_computations: u64,
+    /// Mean number of allowed tokens over the computed masks
+    average_allowed_tokens: f64,
+    /// Total time spent computing masks, in milliseconds
+    mask_time_ms: f64,
+}
+
+/// Measurements of the latest mask computation and advance
+#[derive(Debug, Default, Clone, Serialize)]
+pub(crate) struct StepStats {
+    /// Time the last computed mask took, in microseconds
+    mask_time_us: f64,
+    /// Tokens the last computed mask allowed
+    allowed_tokens: usize,
+    /// Time the last `advance` took to consume its token, in microseconds
+    advance_time_us: f64,
+    #[serde(flatten)]
+    parser: ParserCounters,
+}
+
+impl StepStats {
+    /// These measurements with the parser's counters for the same step
+    pub(crate) fn with_parser(&self, parser: ParserCounters) -> StepStats {
+        StepStats {
+            parser,
+            ..self.clone()
+        }
+    }
+}
+
+/// llguidance's counters for consuming the latest token and computing the
+/// mask after it; all zero while the grammar is not enforced
+#[derive(Debug, Default, Clone, Serialize)]
+pub(crate) struct ParserCounters {
+    /// Earley rows added
+    pub earley_rows: u64,
+    /// Earley items in those rows
+    pub earley_items: u64,
+    /// Lexer states built so far, over the whole generation
+    pub lexer_states: u64,
+    /// Precomputed mask slices applied
+    pub slices_applied: u64,
+    /// Token trie nodes walked
+    pub trie_nodes_walked: u64,
+    /// Lexer work, in llguidance's units
+    pub lexer_cost: u64,
+}
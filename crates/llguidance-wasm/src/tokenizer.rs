@@ -0,0 +1,1342 @@
+//! Tokenizer environment construction
+//!
+//! Builds an llguidance tokenizer environment from either the flattened
+//! `TokenizerData` produced by the TypeScript bridge or a raw Hugging Face
+//! `tokenizer.json` file, and exports compiled environments as binary blobs
+//! that can be cached and loaded again without re-parsing the tokenizer.
+
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use llguidance::toktrie::{TokRxInfo, TokTrie, TokenId, TokenizerEnv};
+
+/// A tokenizer environment that can also be exported
+pub(crate) trait ParserTokEnv: TokenizerEnv + Sync {
+    /// Serialize the environment to a blob accepted by `tok_env_from_blob`
+    fn export_blob(&self) -> Result<Vec<u8>, String>;
+
+    /// Convert to the environment type expected by the parser factory
+    fn into_tok_env(self: Arc<Self>) -> Arc<dyn TokenizerEnv + Sync>;
+
+    /// Problems found while building the environment that did not stop it
+    fn warnings(&self) -> &[String] {
+        &[]
+    }
+
+    /// Number of the model's own tokens; spacing variants follow them in the trie
+    fn model_vocab_size(&self) -> usize {
+        self.tok_trie().vocab_size()
+    }
+
+    /// The variant of a WordPiece word-initial token with the space that
+    /// separates it from a preceding word, if it has one
+    fn spaced_variant(&self, _token: TokenId) -> Option<TokenId> {
+        None
+    }
+
+    /// The model token a trie token stands for
+    fn model_token(&self, token: TokenId) -> TokenId {
+        token
+    }
+}
+
+/// Shared tokenizer environment handed to the parser factory
+pub(crate) type TokEnv = Arc<dyn ParserTokEnv>;
+
+/// Leading bytes of an exported tokenizer environment
+const BLOB_MAGIC: &[u8; 4] = b"LLGT";
+/// Version of the exported blob layout
+/// Version 2 added the WordPiece spacing variants
+const BLOB_VERSION: u32 = 2;
+/// Stored in place of an unset optional token ID
+const BLOB_NO_TOKEN: u32 = u32::MAX;
+
+/// Tokenizer data passed from JavaScript
+/// This matches the TokenizerData interface in TypeScript, and also accepts
+/// a raw Hugging Face tokenizer.json (with a nested `model` section)
+#[derive(Debug, Default, Deserialize)]
+struct TokenizerInput {
+    /// Vocabulary mapping token strings to IDs
+    #[serde(default)]
+    vocab: HashMap<String, u32>,
+    /// Optional BPE merges, used to tokenize text the same way the model does
+    /// Can be either strings like "Ġ t" or arrays like ["Ġ", "t"]
+    #[serde(default, deserialize_with = "deserialize_merges")]
+    merges: Vec<String>,
+    /// Added tokens (special tokens)
+    #[serde(default)]
+    added_tokens: Vec<AddedToken>,
+    /// Additional token contents to treat as special (e.g. tool-call sentinels)
+    #[serde(default)]
+    special_tokens: Vec<String>,
+    /// Unigram log-probabilities indexed by token ID (SentencePiece models)
+    #[serde(default)]
+    scores: Vec<f64>,
+    /// Model type (e.g., "bpe", "unigram", "wordpiece")
+    #[serde(default)]
+    model_type: Option<String>,
+    /// Prefix marking word continuations in WordPiece vocabularies
+    #[serde(default)]
+    continuing_subword_prefix: Option<String>,
+    /// Model section of a raw tokenizer.json
+    #[serde(default)]
+    model: Option<ModelSection>,
+    /// Decoder section of a raw tokenizer.json
+    #[serde(default)]
+    decoder: Option<DecoderSection>,
+    /// Contents of the model's tokenizer_config.json
+    #[serde(default)]
+    tokenizer_config: Option<TokenizerConfig>,
+    /// Special token IDs
+    #[serde(default)]
+    eos_token_id: Option<u32>,
+    #[serde(default)]
+    bos_token_id: Option<u32>,
+    #[serde(default)]
+    pad_token_id: Option<u32>,
+    #[serde(default)]
+    unk_token_id: Option<u32>,
+}
+
+/// The `model` section of a Hugging Face tokenizer.json
+#[derive(Debug, Deserialize)]
+struct ModelSection {
+    #[serde(rename = "type", default)]
+    model_type: Option<String>,
+    #[serde(default)]
+    vocab: ModelVocab,
+    #[serde(default, deserialize_with = "deserialize_merges")]
+    merges: Vec<String>,
+    /// Unknown token ID (Unigram models)
+    #[serde(default)]
+    unk_id: Option<u32>,
+    /// Prefix marking word continuations (WordPiece models)
+    #[serde(default)]
+    continuing_subword_prefix: Option<String>,
+}
+
+/// The parts of a Hugging Face tokenizer_config.json that name special tokens
+#[derive(Debug, Deserialize)]
+struct TokenizerConfig {
+    #[serde(default)]
+    eos_token: Option<SpecialTokenSpec>,
+    #[serde(default)]
+    bos_token: Option<SpecialTokenSpec>,
+    #[serde(default)]
+    pad_token: Option<SpecialTokenSpec>,
+    #[serde(default)]
+    unk_token: Option<SpecialTokenSpec>,
+    /// Added tokens keyed by their ID as a string
+    #[serde(default)]
+    added_tokens_decoder: HashMap<String, AddedTokenEntry>,
+}
+
+/// A special token given either as its content or as an added token object
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SpecialTokenSpec {
+    Content(String),
+    Token { content: String },
+}
+
+impl SpecialTokenSpec {
+    fn content(&self) -> &str {
+        match self {
+            SpecialTokenSpec::Content(content) => content,
+            SpecialTokenSpec::Token { content } => content,
+        }
+    }
+}
+
+/// An entry of `added_tokens_decoder`
+#[derive(Debug, Deserialize)]
+struct AddedTokenEntry {
+    content: String,
+    #[serde(default)]
+    special: bool,
+}
+
+/// The `decoder` section of a Hugging Face tokenizer.json
+#[derive(Debug, Deserialize)]
+struct DecoderSection {
+    #[serde(rename = "type", default)]
+    decoder_type: Option<String>,
+}
+
+/// Vocabulary layouts used by tokenizer.json models
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ModelVocab {
+    /// BPE and WordPiece: token string to ID
+    Map(HashMap<String, u32>),
+    /// Unigram: `[piece, score]` pairs, where the ID is the array index
+    Scored(Vec<(String, f64)>),
+}
+
+impl Default for ModelVocab {
+    fn default() -> Self {
+        ModelVocab::Map(HashMap::new())
+    }
+}
+
+impl TokenizerInput {
+    /// Fold the nested tokenizer.json `model` section into the flat fields
+    fn normalize(mut self) -> Self {
+        if let Some(model) = self.model.take() {
+            if self.vocab.is_empty() {
+                match model.vocab {
+                    ModelVocab::Map(vocab) => self.vocab = vocab,
+                    ModelVocab::Scored(pieces) => {
+                        for (id, (piece, score)) in pieces.into_iter().enumerate() {
+                            self.vocab.insert(piece, id as u32);
+                            self.scores.push(score);
+                        }
+                    }
+                }
+            }
+            if self.unk_token_id.is_none() {
+                self.unk_token_id = model.unk_id;
+            }
+            if self.merges.is_empty() {
+                self.merges = model.merges;
+            }
+            if self.model_type.is_none() {
+                self.model_type = model.model_type;
+            }
+            if self.continuing_subword_prefix.is_none() {
+                self.continuing_subword_prefix = model.continuing_subword_prefix;
+            }
+        }
+        if let Some(config) = self.tokenizer_config.take() {
+            self.apply_config(config);
+        }
+        self
+    }
+
+    /// Merge tokenizer_config.json added tokens and resolve its special tokens
+    /// Explicit token IDs take priority over the config
+    fn apply_config(&mut self, config: TokenizerConfig) {
+        for (id, entry) in config.added_tokens_decoder {
+            let Ok(id) = id.parse::<u32>() else {
+                continue;
+            };
+            if !self.added_tokens.iter().any(|t| t.id == id) {
+                self.added_tokens.push(AddedToken {
+                    id,
+                    content: entry.content,
+                    special: entry.special,
+                });
+            }
+        }
+
+        let resolve = |spec: &Option<SpecialTokenSpec>| {
+            spec.as_ref().and_then(|spec| self.token_id(spec.content()))
+        };
+        let eos = resolve(&config.eos_token);
+        let bos = resolve(&config.bos_token);
+        let pad = resolve(&config.pad_token);
+        let unk = resolve(&config.unk_token);
+
+        self.eos_token_id = self.eos_token_id.or(eos);
+        self.bos_token_id = self.bos_token_id.or(bos);
+        self.pad_token_id = self.pad_token_id.or(pad);
+        self.unk_token_id = self.unk_token_id.or(unk);
+    }
+
+    /// Look up a token ID by its content, checking added tokens first
+    fn token_id(&self, content: &str) -> Option<u32> {
+        self.added_tokens
+            .iter()
+            .find(|t| t.content == content)
+            .map(|t| t.id)
+            .or_else(|| self.vocab.get(content).copied())
+    }
+
+    /// Detect how token strings encode their bytes
+    fn encoding(&self) -> VocabEncoding {
+        let model_type = self.model_type.as_deref().unwrap_or("");
+        if model_type.eq_ignore_ascii_case("wordpiece") {
+            let prefix = self.continuing_subword_prefix.as_deref().unwrap_or("##");
+            VocabEncoding::WordPiece(prefix.to_string())
+        } else if model_type.eq_ignore_ascii_case("unigram")
+            || self.vocab.keys().any(|k| k.starts_with('▁'))
+        {
+            // SentencePiece vocabularies mark spaces with '▁' instead of 'Ġ'
+            VocabEncoding::SentencePiece
+        } else if self
+            .decoder
+            .as_ref()
+            .and_then(|d| d.decoder_type.as_deref())
+            == Some("ByteLevel")
+            || self.vocab.keys().any(|k| k.contains(['Ġ', 'Ċ']))
+        {
+            VocabEncoding::ByteLevel(byte_level_decoder())
+        } else {
+            VocabEncoding::Plain
+        }
+    }
+}
+
+/// Conventions used to spell token bytes in vocabulary strings
+enum VocabEncoding {
+    /// GPT-2 style byte-to-unicode mapping ('Ġ' for space), with its inverse
+    ByteLevel(HashMap<char, u8>),
+    /// '▁' marks a leading space
+    SentencePiece,
+    /// Word-initial tokens, with continuations marked by the given prefix
+    WordPiece(String),
+    /// Token strings are the UTF-8 text of the token
+    Plain,
+}
+
+/// Custom deserializer for merges that handles both string and array formats
+fn deserialize_merges<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::{self, SeqAccess, Visitor};
+    use std::fmt;
+
+    struct MergesVisitor;
+
+    impl<'de> Visitor<'de> for MergesVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a sequence of strings or arrays of strings")
+        }
+
+        fn visit_seq<S>(self, mut seq: S) -> Result<Vec<String>, S::Error>
+        where
+            S: SeqAccess<'de>,
+        {
+            let mut merges = Vec::new();
+
+            while let Some(value) = seq.next_element::<serde_json::Value>()? {
+                let merge_str = match value {
+                    serde_json::Value::String(s) => s,
+                    serde_json::Value::Array(arr) => {
+                        // Convert array like ["Ġ", "t"] to string "Ġ t"
+                        let parts: Vec<String> = arr
+                            .into_iter()
+                            .filter_map(|v| v.as_str().map(String::from))
+                            .collect();
+                        parts.join(" ")
+                    }
+                    _ => {
+                        return Err(de::Error::custom(
+                            "merge must be a string or array of strings",
+                        ))
+                    }
+                };
+                merges.push(merge_str);
+            }
+
+            Ok(merges)
+        }
+    }
+
+    deserializer.deserialize_seq(MergesVisitor)
+}
+
+#[derive(Debug, Deserialize)]
+struct AddedToken {
+    id: u32,
+    content: String,
+    #[serde(default)]
+    special: bool,
+}
+
+/// How text is split into tokens
+enum TokenizerModel {
+    /// Merge pairs to (rank, merged token)
+    Bpe(HashMap<(TokenId, TokenId), (usize, TokenId)>),
+    /// Unigram log-probabilities indexed by token ID
+    Unigram(Vec<f64>),
+    /// tiktoken-style BPE, where a token's ID is its merge rank
+    RankedBpe,
+    /// Longest-match tokenization over the trie
+    Greedy,
+}
+
+/// Tokenizer environment backed by the model's real vocabulary
+///
+/// Token IDs in the trie are the model's own IDs, and text is tokenized with
+/// the model's own algorithm (BPE merges or Unigram scores) when available.
+pub(crate) struct HfTokEnv {
+    trie: TokTrie,
+    /// Token byte strings to token IDs
+    token_ids: HashMap<Vec<u8>, TokenId>,
+    /// Length in bytes of the longest token
+    max_token_len: usize,
+    model: TokenizerModel,
+    /// SentencePiece byte-fallback tokens (`<0x0A>` etc.) by the byte they stand for
+    byte_tokens: HashMap<u8, TokenId>,
+    /// The model token each spacing variant stands for; the variants follow
+    /// the model's tokens in the trie
+    spaced: Vec<TokenId>,
+    /// Spacing variant of each model token that has one
+    spaced_ids: HashMap<TokenId, TokenId>,
+    /// Lossy conversions made while reading the vocabulary
+    warnings: Vec<String>,
+}
+
+impl HfTokEnv {
+    fn new(
+        info: &TokRxInfo,
+        words: Vec<Vec<u8>>,
+        model: TokenizerModel,
+        byte_tokens: HashMap<u8, TokenId>,
+    ) -> Self {
+        // Prefer real pieces over byte-fallback tokens with the same bytes
+        let fallback_ids: HashSet<TokenId> = byte_tokens.values().copied().collect();
+        let mut token_ids = HashMap::new();
+        for (id, bytes) in words.iter().enumerate() {
+            if !bytes.is_empty() && !fallback_ids.contains(&(id as TokenId)) {
+                token_ids.entry(bytes.clone()).or_insert(id as TokenId);
+            }
+        }
+        for (&byte, &id) in &byte_tokens {
+            token_ids.entry(vec![byte]).or_insert(id);
+        }
+
+        let max_token_len = token_ids.keys().map(|k| k.len()).max().unwrap_or(1);
+
+        // Create the trie
+        let trie = TokTrie::from(info, &words);
+
+        HfTokEnv {
+            trie,
+            token_ids,
+            max_token_len,
+            model,
+            byte_tokens,
+            spaced: Vec::new(),
+            spaced_ids: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Record that the last `spaced.len()` trie tokens are spacing variants
+    /// of these model tokens
+    fn set_spaced(&mut self, spaced: Vec<TokenId>) {
+        let first = (self.trie.vocab_size() - spaced.len()) as TokenId;
+        self.spaced_ids = spaced
+            .iter()
+            .enumerate()
+            .map(|(i, &token)| (token, first + i as TokenId))
+            .collect();
+        self.spaced = spaced;
+    }
+
+    /// Split text into initial BPE pieces
+    ///
+    /// Byte-level vocabularies start from single bytes. With byte fallback
+    /// the pieces are whole characters, and only characters missing from the
+    /// vocabulary are spelled out as byte tokens.
+    fn initial_pieces(&self, s: &[u8]) -> Option<Vec<TokenId>> {
+        if self.byte_tokens.is_empty() {
+            return s
+                .iter()
+                .map(|b| self.token_ids.get(std::slice::from_ref(b)).copied())
+                .collect();
+        }
+
+        let mut ids = Vec::with_capacity(s.len());
+        for chunk in s.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                let mut buf = [0u8; 4];
+                let bytes = c.encode_utf8(&mut buf).as_bytes();
+                match self.token_ids.get(bytes) {
+                    Some(&id) => ids.push(id),
+                    None => {
+                        for b in bytes {
+                            ids.push(*self.byte_tokens.get(b)?);
+                        }
+                    }
+                }
+            }
+            for b in chunk.invalid() {
+                ids.push(*self.byte_tokens.get(b)?);
+            }
+        }
+        Some(ids)
+    }
+
+    /// Tokenize by repeatedly applying the lowest-ranked merge
+    fn bpe_tokenize(
+        &self,
+        merges: &HashMap<(TokenId, TokenId), (usize, TokenId)>,
+        s: &[u8],
+    ) -> Option<Vec<TokenId>> {
+        let mut ids = self.initial_pieces(s)?;
+
+        loop {
+            let best = ids
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    merges
+                        .get(&(pair[0], pair[1]))
+                        .map(|&(rank, merged)| (rank, i, merged))
+                })
+                .min();
+
+            match best {
+                Some((_, i, merged)) => {
+                    ids[i] = merged;
+                    ids.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+
+        Some(ids)
+    }
+
+    /// Tokenize by repeatedly merging the adjacent pair whose concatenation
+    /// has the lowest-ranked (lowest ID) token
+    fn ranked_bpe_tokenize(&self, s: &[u8]) -> Option<Vec<TokenId>> {
+        // Byte ranges of the current pieces
+        let mut pieces: Vec<(usize, usize)> = (0..s.len()).map(|i| (i, i + 1)).collect();
+
+        loop {
+            let best = pieces
+                .windows(2)
+                .enumerate()
+                .filter_map(|(i, pair)| {
+                    self.token_ids
+                        .get(&s[pair[0].0..pair[1].1])
+                        .map(|&rank| (rank, i))
+                })
+                .min();
+
+            match best {
+                Some((_, i)) => {
+                    pieces[i].1 = pieces[i + 1].1;
+                    pieces.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+
+        pieces
+            .into_iter()
+            .map(|(start, end)| self.token_ids.get(&s[start..end]).copied())
+            .collect()
+    }
+
+    /// Tokenize by picking the segmentation with the highest total score
+    fn unigram_tokenize(&self, scores: &[f64], s: &[u8]) -> Option<Vec<TokenId>> {
+        // best[i] holds (score, last token, start of last token) for s[..i]
+        let mut best: Vec<Option<(f64, TokenId, usize)>> = vec![None; s.len() + 1];
+        best[0] = Some((0.0, 0, 0));
+
+        for start in 0..s.len() {
+            let Some((base, _, _)) = best[start] else {
+                continue;
+            };
+            let max_end = s.len().min(start + self.max_token_len);
+            for end in start + 1..=max_end {
+                let Some(&id) = self.token_ids.get(&s[start..end]) else {
+                    continue;
+                };
+                let Some(&score) = scores.get(id as usize) else {
+                    continue;
+                };
+                let total = base + score;
+                if best[end].is_none_or(|(prev, _, _)| total > prev) {
+                    best[end] = Some((total, id, start));
+                }
+            }
+        }
+
+        let mut ids = Vec::new();
+        let mut pos = s.len();
+        while pos > 0 {
+            let (_, id, start) = best[pos]?;
+            ids.push(id);
+            pos = start;
+        }
+        ids.reverse();
+        Some(ids)
+    }
+}
+
+impl TokenizerEnv for HfTokEnv {
+    fn tok_trie(&self) -> &TokTrie {
+        &self.trie
+    }
+
+    fn tokenize_bytes(&self, s: &[u8]) -> Vec<TokenId> {
+        let ids = match &self.model {
+            TokenizerModel::Bpe(merges) => self.bpe_tokenize(merges, s),
+            TokenizerModel::Unigram(scores) => self.unigram_tokenize(scores, s),
+            TokenizerModel::RankedBpe => self.ranked_bpe_tokenize(s),
+            TokenizerModel::Greedy => None,
+        };
+        // Fall back when some bytes have no token of their own
+        ids.unwrap_or_else(|| self.trie.greedy_tokenize(s))
+    }
+}
+
+impl ParserTokEnv for HfTokEnv {
+    /// Layout (little-endian): magic, version, vocab size, EOS/BOS/PAD/UNK
+    /// token IDs, the length-prefixed bytes of every token, then the
+    /// tokenization model tag and its data, the byte-fallback tokens, and the
+    /// model tokens of the spacing variants
+    fn export_blob(&self) -> Result<Vec<u8>, String> {
+        let info = self.trie.info();
+        let mut out = Vec::new();
+        out.extend_from_slice(BLOB_MAGIC);
+        for value in [
+            BLOB_VERSION,
+            info.vocab_size,
+            info.tok_eos,
+            info.tok_bos.unwrap_or(BLOB_NO_TOKEN),
+            info.tok_pad.unwrap_or(BLOB_NO_TOKEN),
+            info.tok_unk.unwrap_or(BLOB_NO_TOKEN),
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for id in 0..info.vocab_size {
+            let token = self.trie.token(id);
+            out.extend_from_slice(&(token.len() as u32).to_le_bytes());
+            out.extend_from_slice(token);
+        }
+
+        match &self.model {
+            TokenizerModel::Bpe(merges) => {
+                out.push(1);
+                out.extend_from_slice(&(merges.len() as u32).to_le_bytes());
+                for (&(left, right), &(rank, merged)) in merges {
+                    for value in [left, right, rank as u32, merged] {
+                        out.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+            }
+            TokenizerModel::Unigram(scores) => {
+                out.push(2);
+                out.extend_from_slice(&(scores.len() as u32).to_le_bytes());
+                for score in scores {
+                    out.extend_from_slice(&score.to_le_bytes());
+                }
+            }
+            TokenizerModel::RankedBpe => out.push(3),
+            TokenizerModel::Greedy => out.push(0),
+        }
+
+        out.extend_from_slice(&(self.byte_tokens.len() as u32).to_le_bytes());
+        for (&byte, &id) in &self.byte_tokens {
+            out.push(byte);
+            out.extend_from_slice(&id.to_le_bytes());
+        }
+
+        out.extend_from_slice(&(self.spaced.len() as u32).to_le_bytes());
+        for &token in &self.spaced {
+            out.extend_from_slice(&token.to_le_bytes());
+        }
+
+        Ok(out)
+    }
+
+    fn into_tok_env(self: Arc<Self>) -> Arc<dyn TokenizerEnv + Sync> {
+        self
+    }
+
+    fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    fn model_vocab_size(&self) -> usize {
+        self.trie.vocab_size() - self.spaced.len()
+    }
+
+    fn spaced_variant(&self, token: TokenId) -> Option<TokenId> {
+        self.spaced_ids.get(&token).copied()
+    }
+
+    fn model_token(&self, token: TokenId) -> TokenId {
+        let first = self.model_vocab_size() as TokenId;
+        match token.checked_sub(first) {
+            Some(i) => self.spaced[i as usize],
+            None => token,
+        }
+    }
+}
+
+/// Sequential reader over an exported tokenizer environment
+struct BlobReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> BlobReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.data.len() < len {
+            return Err("Tokenizer blob is truncated".to_string());
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn token_id(&mut self) -> Result<Option<TokenId>, String> {
+        Ok(Some(self.u32()?).filter(|&id| id != BLOB_NO_TOKEN))
+    }
+
+    /// Fail if the rest of the blob is too short for `count` items of at
+    /// least `item_len` bytes each, so a corrupted count cannot request a
+    /// huge allocation
+    fn check_room(&self, count: usize, item_len: usize) -> Result<(), String> {
+        if count.saturating_mul(item_len) > self.data.len() {
+            return Err("Tokenizer blob is truncated".to_string());
+        }
+        Ok(())
+    }
+
+    /// A count of items of at least `item_len` bytes each; see `check_room`
+    fn count(&mut self, item_len: usize) -> Result<usize, String> {
+        let count = self.u32()? as usize;
+        self.check_room(count, item_len)?;
+        Ok(count)
+    }
+}
+
+/// Create a tokenizer environment from the JSON configuration
+pub(crate) fn create_tok_env(tokenizer_json: &str) -> Result<TokEnv, String> {
+    let mut warnings = Vec::new();
+    let tokenizer_json = replace_lone_surrogates(tokenizer_json, &mut warnings);
+
+    // Try to parse as TokenizerInput
+    let input: TokenizerInput = serde_json::from_str(&tokenizer_json)
+        .map_err(|e| format!("Failed to parse tokenizer JSON: {}", e))?;
+    build_tok_env(input.normalize(), warnings)
+}
+
+/// Replace `\uD800`-style escapes of unpaired UTF-16 surrogates, which some
+/// tokenizer.json files contain but JSON parsers reject, with U+FFFD
+fn replace_lone_surrogates<'a>(json: &'a str, warnings: &mut Vec<String>) -> Cow<'a, str> {
+    fn surrogate_at(bytes: &[u8], i: usize) -> Option<u16> {
+        let escape = bytes.get(i..i + 6)?;
+        if !escape.starts_with(b"\\u") {
+            return None;
+        }
+        let unit = u16::from_str_radix(std::str::from_utf8(&escape[2..]).ok()?, 16).ok()?;
+        (0xD800..0xE000).contains(&unit).then_some(unit)
+    }
+
+    let bytes = json.as_bytes();
+    let mut out: Option<String> = None;
+    let mut replaced = 0;
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            i += 1;
+            continue;
+        }
+        match surrogate_at(bytes, i) {
+            // A high surrogate followed by a low one is a valid pair
+            Some(0xD800..=0xDBFF)
+                if surrogate_at(bytes, i + 6).is_some_and(|low| low >= 0xDC00) =>
+            {
+                i += 12;
+            }
+            Some(_) => {
+                let out = out.get_or_insert_with(|| String::with_capacity(json.len()));
+                out.push_str(&json[copied..i]);
+                out.push_str("\\uFFFD");
+                replaced += 1;
+                i += 6;
+                copied = i;
+            }
+            // Skip the escaped character, so `\\uD800` is left alone
+            None => i += 2,
+        }
+    }
+
+    match out {
+        Some(mut out) => {
+            out.push_str(&json[copied..]);
+            warnings.push(format!(
+                "Replaced {} unpaired UTF-16 surrogate escapes with U+FFFD",
+                replaced
+            ));
+            Cow::Owned(out)
+        }
+        None => Cow::Borrowed(json),
+    }
+}
+
+/// Load a tokenizer environment exported with `ParserTokEnv::export_blob`
+pub(crate) fn tok_env_from_blob(blob: &[u8]) -> Result<TokEnv, String> {
+    let mut reader = BlobReader { data: blob };
+    if reader.bytes(BLOB_MAGIC.len()).ok() != Some(BLOB_MAGIC.as_slice()) {
+        return Err("Not an exported tokenizer environment".to_string());
+    }
+    let version = reader.u32()?;
+    if !(1..=BLOB_VERSION).contains(&version) {
+        return Err(format!(
+            "Unsupported tokenizer blob version {} (expected {})",
+            version, BLOB_VERSION
+        ));
+    }
+
+    let vocab_size = reader.u32()?;
+    let eos_token = reader.u32()?;
+    if eos_token >= vocab_size {
+        return Err(format!(
+            "EOS token {} is outside the vocabulary of {} tokens",
+            eos_token, vocab_size
+        ));
+    }
+    let mut info = TokRxInfo::new(vocab_size, eos_token);
+    info.tok_bos = reader.token_id()?;
+    info.tok_pad = reader.token_id()?;
+    info.tok_unk = reader.token_id()?;
+
+    // Each token is at least its length prefix
+    reader.check_room(vocab_size as usize, 4)?;
+    let mut words = Vec::with_capacity(vocab_size as usize);
+    for _ in 0..vocab_size {
+        let len = reader.u32()? as usize;
+        words.push(reader.bytes(len)?.to_vec());
+    }
+
+    let model = match reader.u8()? {
+        0 => TokenizerModel::Greedy,
+        1 => {
+            let count = reader.count(16)?;
+            let mut merges = HashMap::with_capacity(count);
+            for _ in 0..count {
+                let (left, right) = (reader.u32()?, reader.u32()?);
+                let (rank, merged) = (reader.u32()? as usize, reader.u32()?);
+                merges.insert((left, right), (rank, merged));
+            }
+            TokenizerModel::Bpe(merges)
+        }
+        2 => {
+            let count = reader.count(8)?;
+            let scores = (0..count).map(|_| reader.f64()).collect::<Result<_, _>>()?;
+            TokenizerModel::Unigram(scores)
+        }
+        3 => TokenizerModel::RankedBpe,
+        tag => return Err(format!("Unknown tokenizer model {} in blob", tag)),
+    };
+
+    let mut byte_tokens = HashMap::new();
+    for _ in 0..reader.count(5)? {
+        byte_tokens.insert(reader.u8()?, reader.u32()?);
+    }
+
+    let spaced = match version {
+        1 => Vec::new(),
+        _ => (0..reader.count(4)?)
+            .map(|_| reader.u32())
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    let model_vocab_size = words.len().saturating_sub(spaced.len());
+    if spaced
+        .iter()
+        .any(|&token| token as usize >= model_vocab_size)
+    {
+        return Err("Tokenizer blob has invalid spacing variants".to_string());
+    }
+
+    let mut env = HfTokEnv::new(&info, words, model, byte_tokens);
+    env.set_spaced(spaced);
+    Ok(Arc::new(env))
+}
+
+/// Create a tokenizer environment from GGUF tokenizer metadata
+///
+/// Accepts the `tokenizer.ggml.*` keys, with or without the prefix.
+pub(crate) fn create_gguf_tok_env(metadata_json: &str) -> Result<TokEnv, String> {
+    let gguf: GgufTokenizer = serde_json::from_str(metadata_json)
+        .map_err(|e| format!("Failed to parse GGUF tokenizer metadata: {}", e))?;
+    build_tok_env(gguf.into_input(), Vec::new())
+}
+
+/// Tokenizer metadata stored in a GGUF header
+#[derive(Debug, Deserialize)]
+struct GgufTokenizer {
+    /// Tokenizer family: "llama" (SentencePiece), "gpt2" (byte-level BPE), "bert", "t5"
+    #[serde(alias = "tokenizer.ggml.model")]
+    model: String,
+    #[serde(alias = "tokenizer.ggml.tokens")]
+    tokens: Vec<String>,
+    #[serde(default, alias = "tokenizer.ggml.scores")]
+    scores: Vec<f64>,
+    #[serde(default, alias = "tokenizer.ggml.token_type")]
+    token_type: Vec<i32>,
+    #[serde(default, alias = "tokenizer.ggml.merges")]
+    merges: Vec<String>,
+    #[serde(default, alias = "tokenizer.ggml.eos_token_id")]
+    eos_token_id: Option<u32>,
+    #[serde(default, alias = "tokenizer.ggml.bos_token_id")]
+    bos_token_id: Option<u32>,
+    #[serde(default, alias = "tokenizer.ggml.padding_token_id")]
+    padding_token_id: Option<u32>,
+    #[serde(default, alias = "tokenizer.ggml.unknown_token_id")]
+    unknown_token_id: Option<u32>,
+}
+
+/// GGUF token type for the unknown token
+const GGUF_TOKEN_UNKNOWN: i32 = 2;
+/// GGUF token type for control (special) tokens
+const GGUF_TOKEN_CONTROL: i32 = 3;
+/// GGUF token type for user-defined tokens
+const GGUF_TOKEN_USER_DEFINED: i32 = 4;
+
+impl GgufTokenizer {
+    fn into_input(self) -> TokenizerInput {
+        let model_type = match self.model.as_str() {
+            "llama" | "t5" => "unigram",
+            "bert" => "wordpiece",
+            _ => "bpe",
+        };
+
+        // Only Unigram-style models tokenize by score
+        let scores = if model_type == "unigram" {
+            self.scores
+        } else {
+            Vec::new()
+        };
+
+        let mut added_tokens = Vec::new();
+        for (id, (token, token_type)) in self.tokens.iter().zip(&self.token_type).enumerate() {
+            let special = matches!(*token_type, GGUF_TOKEN_UNKNOWN | GGUF_TOKEN_CONTROL);
+            if special || *token_type == GGUF_TOKEN_USER_DEFINED {
+                added_tokens.push(AddedToken {
+                    id: id as u32,
+                    content: token.clone(),
+                    special,
+                });
+            }
+        }
+
+        let vocab = self
+            .tokens
+            .into_iter()
+            .enumerate()
+            .map(|(id, token)| (token, id as u32))
+            .collect();
+
+        TokenizerInput {
+            vocab,
+            merges: self.merges,
+            added_tokens,
+            scores,
+            model_type: Some(model_type.to_string()),
+            eos_token_id: self.eos_token_id,
+            bos_token_id: self.bos_token_id,
+            pad_token_id: self.padding_token_id,
+            unk_token_id: self.unknown_token_id,
+            ..Default::default()
+        }
+    }
+}
+
+fn build_tok_env(input: TokenizerInput, mut warnings: Vec<String>) -> Result<TokEnv, String> {
+    // Check if we have a valid vocabulary
+    if input.vocab.is_empty() {
+        return Err("Tokenizer vocabulary is empty".to_string());
+    }
+
+    // Find the maximum token ID to determine vocab size
+    // Added tokens may live outside the base vocabulary
+    let max_id = input
+        .vocab
+        .values()
+        .chain(input.added_tokens.iter().map(|t| &t.id))
+        .copied()
+        .max()
+        .unwrap_or(0);
+    let vocab_size = (max_id + 1) as usize;
+
+    let encoding = input.encoding();
+
+    // Tokens flagged special by the tokenizer or named special by the caller
+    let special_ids: HashSet<u32> = input
+        .added_tokens
+        .iter()
+        .filter(|t| t.special)
+        .map(|t| t.id)
+        .chain(
+            input
+                .special_tokens
+                .iter()
+                .filter_map(|c| input.token_id(c)),
+        )
+        .collect();
+
+    // Build the words vector (token bytes indexed by token ID)
+    // Each entry is the byte representation of the token
+    let mut words: Vec<Vec<u8>> = vec![Vec::new(); vocab_size];
+    let mut unmapped_tokens = 0;
+    // WordPiece tokens that take a space after another word
+    let mut spaced = Vec::new();
+
+    for (token_str, id) in &input.vocab {
+        // Handle special token encoding
+        // llguidance uses \xFF prefix for special tokens
+        let bytes = if special_ids.contains(id) {
+            // Special tokens get the \xFF prefix
+            let mut special_bytes = vec![0xFF];
+            special_bytes.extend(token_str.as_bytes());
+            special_bytes
+        } else {
+            match &encoding {
+                VocabEncoding::SentencePiece => decode_sentencepiece_bytes(token_str),
+                VocabEncoding::WordPiece(prefix) => {
+                    let (bytes, word_initial) = decode_wordpiece_bytes(token_str, prefix);
+                    if word_initial {
+                        spaced.push(*id);
+                    }
+                    bytes
+                }
+                // Regular tokens: decode the token string
+                // GPT-2 style tokenizers use 'Ġ' (U+0120) to represent space
+                // and other Unicode characters for byte encoding
+                VocabEncoding::ByteLevel(decoder) => {
+                    if token_str.chars().any(|c| !decoder.contains_key(&c)) {
+                        unmapped_tokens += 1;
+                    }
+                    decode_token_bytes(token_str, decoder)
+                }
+                VocabEncoding::Plain => token_str.as_bytes().to_vec(),
+            }
+        };
+        words[*id as usize] = bytes;
+    }
+    if unmapped_tokens > 0 {
+        warnings.push(format!(
+            "{} byte-level tokens contain characters outside the byte alphabet; \
+             those characters were kept as UTF-8",
+            unmapped_tokens
+        ));
+    }
+
+    // Added tokens are matched by their exact content, not the vocab encoding
+    for token in &input.added_tokens {
+        let mut bytes = Vec::with_capacity(token.content.len() + 1);
+        if special_ids.contains(&token.id) {
+            bytes.push(0xFF);
+        }
+        bytes.extend(token.content.as_bytes());
+        words[token.id as usize] = bytes;
+    }
+
+    // The space between words depends on the previous token, so word-initial
+    // WordPiece tokens get a second, spaced variant past the model's tokens;
+    // the parser picks one by what precedes them
+    spaced.retain(|id| !input.added_tokens.iter().any(|t| t.id == *id));
+    spaced.sort_unstable();
+    for &id in &spaced {
+        words.push([b" ".as_slice(), words[id as usize].as_slice()].concat());
+    }
+
+    // Determine EOS token
+    // Priority: explicit eos_token_id > added token named </s> or <|endoftext|> > last token
+    let eos_token = input.eos_token_id.unwrap_or_else(|| {
+        // Look for common EOS tokens in added_tokens
+        for token in &input.added_tokens {
+            if token.content == "</s>"
+                || token.content == "<|endoftext|>"
+                || token.content == "<eos>"
+                || token.content == "<|eos|>"
+            {
+                return token.id;
+            }
+        }
+        // Fallback to last token
+        (vocab_size - 1) as u32
+    });
+
+    // Create TokRxInfo
+    let mut info = TokRxInfo::new(words.len() as u32, eos_token);
+    info.tok_bos = input.bos_token_id;
+    info.tok_pad = input.pad_token_id;
+    info.tok_unk = input.unk_token_id;
+
+    // Byte-fallback tokens spell out characters missing from the vocabulary
+    let byte_tokens: HashMap<u8, TokenId> = match encoding {
+        VocabEncoding::SentencePiece => input
+            .vocab
+            .iter()
+            .filter(|(_, id)| !special_ids.contains(id))
+            .filter_map(|(token, &id)| Some((parse_byte_token(token)?, id)))
+            .collect(),
+        _ => HashMap::new(),
+    };
+
+    let model = if !input.scores.is_empty() {
+        let mut scores = input.scores.clone();
+        // Like SentencePiece, only fall back to bytes when no piece matches
+        if !byte_tokens.is_empty() {
+            let min_score = scores.iter().copied().fold(f64::INFINITY, f64::min);
+            for &id in byte_tokens.values() {
+                if let Some(score) = scores.get_mut(id as usize) {
+                    *score = min_score - 10.0;
+                }
+            }
+        }
+        TokenizerModel::Unigram(scores)
+    } else if !input.merges.is_empty() {
+        // Resolve merges like "Ġ t" into token ID pairs, keeping their rank
+        let mut merges = HashMap::new();
+        for (rank, merge) in input.merges.iter().enumerate() {
+            let Some((left, right)) = merge.split_once(' ') else {
+                continue;
+            };
+            let (Some(&l), Some(&r), Some(&merged)) = (
+                input.vocab.get(left),
+                input.vocab.get(right),
+                input.vocab.get(&format!("{}{}", left, right)),
+            ) else {
+                continue;
+            };
+            merges.entry((l, r)).or_insert((rank, merged));
+        }
+        TokenizerModel::Bpe(merges)
+    } else {
+        TokenizerModel::Greedy
+    };
+
+    let mut env = HfTokEnv::new(&info, words, model, byte_tokens);
+    env.set_spaced(spaced);
+    env.warnings = warnings;
+    Ok(Arc::new(env))
+}
+
+/// Create a tokenizer environment from token byte strings indexed by token ID
+/// Text is tokenized by longest match, since no merges or scores are known
+pub(crate) fn tok_env_from_words(words: Vec<Vec<u8>>, eos_token: u32) -> Result<TokEnv, String> {
+    if words.is_empty() {
+        return Err("Tokenizer vocabulary is empty".to_string());
+    }
+    if eos_token as usize >= words.len() {
+        return Err(format!(
+            "EOS token {} is outside the vocabulary of {} tokens",
+            eos_token,
+            words.len()
+        ));
+    }
+
+    let info = TokRxInfo::new(words.len() as u32, eos_token);
+    Ok(Arc::new(HfTokEnv::new(
+        &info,
+        words,
+        TokenizerModel::Greedy,
+        HashMap::new(),
+    )))
+}
+
+/// IDs of all special tokens (marked with a leading \xFF byte) other than EOS
+pub(crate) fn special_token_ids(trie: &TokTrie) -> Vec<TokenId> {
+    let eos = trie.eos_token();
+    (0..trie.vocab_size() as TokenId)
+        .filter(|&id| id != eos && trie.token(id).first() == Some(&0xFF))
+        .collect()
+}
+
+/// Create a tokenizer environment from a tiktoken BPE rank file
+///
+/// Each line of `bpe_ranks` is a base64-encoded token followed by its rank,
+/// which is also its token ID. `special_tokens_json` maps special token
+/// contents to their IDs, and must include the EOS token.
+pub(crate) fn create_tiktoken_env(
+    bpe_ranks: &str,
+    special_tokens_json: &str,
+) -> Result<TokEnv, String> {
+    let special_tokens: HashMap<String, u32> = serde_json::from_str(special_tokens_json)
+        .map_err(|e| format!("Failed to parse special tokens JSON: {}", e))?;
+
+    let mut ranks: Vec<(Vec<u8>, u32)> = Vec::new();
+    for (line_no, line) in bpe_ranks.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = line.split_once(' ').and_then(|(token, rank)| {
+            Some((decode_base64(token)?, rank.trim().parse::<u32>().ok()?))
+        });
+        match parsed {
+            Some(entry) => ranks.push(entry),
+            None => return Err(format!("Invalid tiktoken rank on line {}", line_no + 1)),
+        }
+    }
+
+    if ranks.is_empty() {
+        return Err("Tokenizer vocabulary is empty".to_string());
+    }
+
+    let max_id = ranks
+        .iter()
+        .map(|(_, rank)| rank)
+        .chain(special_tokens.values())
+        .copied()
+        .max()
+        .unwrap_or(0);
+    let mut words: Vec<Vec<u8>> = vec![Vec::new(); (max_id + 1) as usize];
+
+    for (bytes, rank) in ranks {
+        words[rank as usize] = bytes;
+    }
+    // Special tokens get the \xFF prefix
+    for (content, id) in &special_tokens {
+        let mut bytes = vec![0xFF];
+        bytes.extend(content.as_bytes());
+        words[*id as usize] = bytes;
+    }
+
+    let eos_token = [
+        "<|endoftext|>",
+        "<|end_of_text|>",
+        "</s>",
+        "<eos>",
+        "<|eos|>",
+    ]
+    .iter()
+    .find_map(|name| special_tokens.get(*name).copied())
+    .ok_or("Unable to determine the EOS token; include it in the special tokens")?;
+
+    let info = TokRxInfo::new(words.len() as u32, eos_token);
+    Ok(Arc::new(HfTokEnv::new(
+        &info,
+        words,
+        TokenizerModel::RankedBpe,
+        HashMap::new(),
+    )))
+}
+
+/// Decode standard (padded or unpadded) base64
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut result = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for c in input.bytes().take_while(|&c| c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(result)
+}
+
+/// Build the inverse of GPT-2's `bytes_to_unicode` table
+///
+/// Byte-level BPE spells every byte with a printable character: printable
+/// Latin-1 bytes stand for themselves, and the remaining bytes are assigned
+/// U+0100, U+0101, ... in byte order (so space is 'Ġ' and newline is 'Ċ').
+fn byte_level_decoder() -> HashMap<char, u8> {
+    let is_printable = |b: u8| matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+    let mut decoder = HashMap::with_capacity(256);
+    let mut next_shifted = 0x100u32;
+    for b in 0..=255u8 {
+        let c = if is_printable(b) {
+            char::from(b)
+        } else {
+            let c = char::from_u32(next_shifted).unwrap_or(char::REPLACEMENT_CHARACTER);
+            next_shifted += 1;
+            c
+        };
+        decoder.insert(c, b);
+    }
+    decoder
+}
+
+/// Decode a byte-level BPE token string to its byte representation
+fn decode_token_bytes(token: &str, decoder: &HashMap<char, u8>) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    for c in token.chars() {
+        match decoder.get(&c) {
+            Some(&byte) => result.push(byte),
+            // Characters outside the byte alphabet are kept as UTF-8
+            None => {
+                let mut buf = [0u8; 4];
+                result.extend(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+
+    result
+}
+
+/// Decode a SentencePiece token string to its byte representation
+/// SentencePiece uses '▁' (U+2581) in place of the space character, and
+/// byte-fallback tokens like `<0x0A>` stand for a single raw byte
+fn decode_sentencepiece_bytes(token: &str) -> Vec<u8> {
+    match parse_byte_token(token) {
+        Some(byte) => vec![byte],
+        None => token.replace('▁', " ").into_bytes(),
+    }
+}
+
+/// Parse a SentencePiece byte-fallback token such as `<0x0A>`
+fn parse_byte_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+/// Decode a WordPiece token string to its byte representation, and whether
+/// it starts a word
+/// Continuation pieces attach directly to the previous token. BERT's decode
+/// joins the other pieces with spaces and then drops the space before
+/// punctuation, so only a piece starting with a letter or digit takes one;
+/// the bytes here are without the space.
+fn decode_wordpiece_bytes(token: &str, prefix: &str) -> (Vec<u8>, bool) {
+    match token.strip_prefix(prefix) {
+        Some(rest) if !rest.is_empty() => (rest.as_bytes().to_vec(), false),
+        _ => (
+            token.as_bytes().to_vec(),
+            token.starts_with(|c: char| c.is_alphanumeric()),
+        ),
+    }
+}
+
+/// Whether a word-initial WordPiece token after `previous` takes a space
+/// None is taken at the start of the text, after a special token or
+/// whitespace, or after an opening bracket or quote, so quoted text such as
+/// a JSON string reads as written.
+pub(crate) fn spaced_after(previous: &[u8]) -> bool {
+    previous.first() != Some(&0xFF)
+        && String::from_utf8_lossy(previous)
+            .chars()
+            .next_back()
+            .is_some_and(|c| !c.is_whitespace() && !"([{\"'".contains(c))
+}
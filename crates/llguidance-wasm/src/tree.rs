@@ -0,0 +1,155 @@
+//! Parse trees of Lark grammars
+//!
+//! llguidance's Earley parser recognizes the output without building a
+//! derivation tree, but it does record the text of captured rules as they
+//! complete. The tree is rebuilt from those: the grammar is copied with
+//! every rule captured under its own name, the consumed tokens are replayed
+//! on it one at a time, and each capture is placed in the text where the
+//! token that completed it ended its last lexeme. Captures are nested by
+//! their spans; of two with the same span, the later completed is the outer.
+
+use std::cmp::Reverse;
+
+use serde::Serialize;
+
+use llguidance::api::TopLevelGrammar;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Node {
+    rule: String,
+    /// Byte offsets of the rule's text in the text the grammar consumed
+    start: usize,
+    end: usize,
+    text: String,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+struct Span {
+    rule: String,
+    start: usize,
+    end: usize,
+}
+
+/// A copy of `grammar` whose Lark rules are all captured; rules with a
+/// capture of their own keep it
+pub(crate) fn with_rule_captures(grammar: &TopLevelGrammar) -> TopLevelGrammar {
+    let mut grammar = grammar.clone();
+    for g in &mut grammar.grammars {
+        if let Some(lark) = &g.lark_grammar {
+            let lines: Vec<String> = lark.lines().map(capture_rule).collect();
+            g.lark_grammar = Some(lines.join("\n"));
+        }
+    }
+    grammar
+}
+
+/// `line` with a capture added if it starts a rule definition
+fn capture_rule(line: &str) -> String {
+    let body = line.trim_start();
+    let rest = &body[usize::from(body.starts_with(['?', '!']))..];
+    let name_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let name = &rest[..name_len];
+    // Terminals are upper case; priorities and other lines are left alone
+    if !name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_') {
+        return line.to_string();
+    }
+    let head = line.len() - rest.len() + name_len;
+    let after = &line[head..];
+    if after.trim_start().starts_with(':') {
+        return format!("{}[capture=\"{}\"]{}", &line[..head], name, after);
+    }
+    if let Some(attributes) = after.strip_prefix('[') {
+        let closed = attributes
+            .find(']')
+            .is_some_and(|end| attributes[end + 1..].trim_start().starts_with(':'));
+        if closed && !attributes.contains("capture") {
+            return format!("{}[capture=\"{}\", {}", &line[..head], name, attributes);
+        }
+    }
+    line.to_string()
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TreeBuilder {
+    /// The text consumed so far
+    text: Vec<u8>,
+    /// Captures already placed, or skipped
+    seen: usize,
+    spans: Vec<Span>,
+}
+
+impl TreeBuilder {
+    /// Record the bytes of a consumed token, and place the captures the
+    /// parser has recorded since the last token
+    pub(crate) fn consume(&mut self, bytes: &[u8], captures: &[(String, Vec<u8>)]) {
+        let mut earliest = self.text.len();
+        self.text.extend_from_slice(bytes);
+        for (rule, capture) in captures.iter().skip(self.seen) {
+            if let Some(end) = self.place(capture, earliest) {
+                self.spans.push(Span {
+                    rule: rule.clone(),
+                    start: end - capture.len(),
+                    end,
+                });
+                earliest = end;
+            }
+        }
+        self.seen = captures.len();
+    }
+
+    /// Where `capture` ends: the first fit at or after `earliest`, since the
+    /// token that completed the rule also ended its last lexeme, or else the
+    /// last fit before it
+    fn place(&self, capture: &[u8], earliest: usize) -> Option<usize> {
+        if capture.is_empty() {
+            return None;
+        }
+        let fits = |end: &usize| self.text[..*end].ends_with(capture);
+        (earliest.max(capture.len())..=self.text.len())
+            .find(fits)
+            .or_else(|| (capture.len()..earliest).rev().find(fits))
+    }
+
+    /// The tree of the placed captures under a root `rule` spanning all the text
+    pub(crate) fn finish(mut self, rule: &str) -> Node {
+        let len = self.text.len();
+        let mut spans: Vec<(usize, Span)> = std::mem::take(&mut self.spans)
+            .into_iter()
+            .enumerate()
+            .collect();
+        // Outer spans first
+        spans.sort_by_key(|(i, span)| (span.start, Reverse(span.end), Reverse(*i)));
+        spans.dedup_by(|(_, a), (_, b)| a.rule == b.rule && a.start == b.start && a.end == b.end);
+
+        let mut stack = vec![self.node(rule.to_string(), 0, len)];
+        for (_, span) in spans {
+            // The root covers the whole text already
+            if span.rule == rule && span.start == 0 && span.end == len {
+                continue;
+            }
+            while stack.len() > 1 && stack.last().is_some_and(|top| top.end < span.end) {
+                let child = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(child);
+            }
+            stack.push(self.node(span.rule, span.start, span.end));
+        }
+        while stack.len() > 1 {
+            let child = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(child);
+        }
+        stack.pop().unwrap()
+    }
+
+    fn node(&self, rule: String, start: usize, end: usize) -> Node {
+        Node {
+            rule,
+            start,
+            end,
+            text: String::from_utf8_lossy(&self.text[start..end]).into_owned(),
+            children: Vec::new(),
+        }
+    }
+}
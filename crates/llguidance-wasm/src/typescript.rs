@@ -0,0 +1,556 @@
+//! TypeScript type input
+//!
+//! Front-end code describes shapes as TypeScript interfaces and type aliases,
+//! so those are translated to the JSON schema llguidance compiles. Each
+//! declaration becomes an entry of `$defs`, and the first one (or the one named
+//! by `root_type`) is the schema of the output. The supported types are the
+//! ones that describe JSON: primitives, literals, object types, arrays,
+//! tuples, unions, `Array<T>`, `Record<string, T>`, `extends` and references
+//! to other declarations.
+
+use serde_json::{json, Map, Value};
+
+/// Translate TypeScript declarations to a JSON schema for `root_type`, or for
+/// the first declaration if it is `None`
+pub(crate) fn typescript_to_schema(source: &str, root_type: Option<&str>) -> Result<Value, String> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+    };
+    let mut decls = Vec::new();
+    while parser.peek().is_some() {
+        decls.push(parser.declaration()?);
+    }
+
+    let root = match root_type {
+        Some(name) => decls
+            .iter()
+            .find(|decl| decl.name == name)
+            .ok_or_else(|| format!("TypeScript type {} is not declared", name))?,
+        None => decls
+            .first()
+            .ok_or_else(|| "TypeScript source has no interface or type declarations".to_string())?,
+    };
+
+    let mut defs = Map::new();
+    for decl in &decls {
+        if defs.contains_key(&decl.name) {
+            return Err(format!(
+                "TypeScript type {} is declared more than once",
+                decl.name
+            ));
+        }
+        defs.insert(
+            decl.name.clone(),
+            decl_schema(decl, &decls, &mut Vec::new())?,
+        );
+    }
+    let mut schema = defs[&root.name].clone();
+    schema["$defs"] = Value::Object(defs);
+    Ok(schema)
+}
+
+struct Decl {
+    name: String,
+    extends: Vec<String>,
+    ty: Type,
+}
+
+enum Type {
+    Primitive(&'static str),
+    Any,
+    Literal(Value),
+    Array(Box<Type>),
+    Tuple(Vec<Type>),
+    Union(Vec<Type>),
+    Object {
+        properties: Vec<Property>,
+        index: Option<Box<Type>>,
+    },
+    Ref(String),
+}
+
+struct Property {
+    name: String,
+    optional: bool,
+    ty: Type,
+}
+
+fn decl_schema(decl: &Decl, decls: &[Decl], seen: &mut Vec<String>) -> Result<Value, String> {
+    if decl.extends.is_empty() {
+        return type_schema(&decl.ty, decls);
+    }
+    if seen.contains(&decl.name) {
+        return Err(format!("TypeScript interface {} extends itself", decl.name));
+    }
+    seen.push(decl.name.clone());
+    // Inherited members come first, as they would in the object
+    let mut schema = json!({ "type": "object", "properties": {}, "required": [] });
+    for name in &decl.extends {
+        let base = decls
+            .iter()
+            .find(|d| &d.name == name)
+            .ok_or_else(|| format!("TypeScript type {} is not declared", name))?;
+        if !matches!(base.ty, Type::Object { .. }) {
+            return Err(format!(
+                "{} extends {}, which is not an object type",
+                decl.name, name
+            ));
+        }
+        merge_object(&mut schema, decl_schema(base, decls, seen)?);
+    }
+    merge_object(&mut schema, type_schema(&decl.ty, decls)?);
+    seen.pop();
+    Ok(schema)
+}
+
+/// Add the members of the object schema `other` to `schema`
+fn merge_object(schema: &mut Value, other: Value) {
+    if let (Some(properties), Some(Value::Object(other_properties))) = (
+        schema["properties"].as_object_mut(),
+        other.get("properties"),
+    ) {
+        properties.extend(other_properties.clone());
+    }
+    if let (Some(required), Some(Value::Array(other_required))) =
+        (schema["required"].as_array_mut(), other.get("required"))
+    {
+        for name in other_required {
+            if !required.contains(name) {
+                required.push(name.clone());
+            }
+        }
+    }
+    schema["additionalProperties"] = other["additionalProperties"].clone();
+}
+
+fn type_schema(ty: &Type, decls: &[Decl]) -> Result<Value, String> {
+    Ok(match ty {
+        Type::Primitive(name) => json!({ "type": name }),
+        Type::Any => json!({}),
+        Type::Literal(value) => json!({ "const": value }),
+        Type::Array(item) => json!({ "type": "array", "items": type_schema(item, decls)? }),
+        Type::Tuple(items) => {
+            let items = items
+                .iter()
+                .map(|item| type_schema(item, decls))
+                .collect::<Result<Vec<_>, _>>()?;
+            let len = items.len();
+            json!({
+                "type": "array",
+                "prefixItems": items,
+                "minItems": len,
+                "maxItems": len,
+            })
+        }
+        Type::Union(types) => {
+            // A union of literals is an enum, which llguidance compiles more simply
+            if let Some(values) = types
+                .iter()
+                .map(|ty| match ty {
+                    Type::Literal(value) => Some(value.clone()),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()
+            {
+                json!({ "enum": values })
+            } else {
+                let types = types
+                    .iter()
+                    .map(|ty| type_schema(ty, decls))
+                    .collect::<Result<Vec<_>, _>>()?;
+                json!({ "anyOf": types })
+            }
+        }
+        Type::Object { properties, index } => {
+            let mut schema_properties = Map::new();
+            let mut required = Vec::new();
+            for property in properties {
+                schema_properties.insert(property.name.clone(), type_schema(&property.ty, decls)?);
+                if !property.optional {
+                    required.push(Value::String(property.name.clone()));
+                }
+            }
+            let additional = match index {
+                Some(ty) => type_schema(ty, decls)?,
+                None => Value::Bool(false),
+            };
+            json!({
+                "type": "object",
+                "properties": schema_properties,
+                "required": required,
+                "additionalProperties": additional,
+            })
+        }
+        Type::Ref(name) => {
+            if !decls.iter().any(|decl| &decl.name == name) {
+                return Err(format!("TypeScript type {} is not declared", name));
+            }
+            json!({ "$ref": format!("#/$defs/{}", name) })
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Number(String),
+    Punct(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, String> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\n' {
+            line += 1;
+            i += 1;
+        } else if c.is_whitespace() {
+            i += 1;
+        } else if c == '/' && chars.get(i + 1) == Some(&'/') {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+        } else if c == '/' && chars.get(i + 1) == Some(&'*') {
+            i += 2;
+            while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                if chars[i] == '\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            if i >= chars.len() {
+                return Err(format!("TypeScript line {}: Unterminated comment", line));
+            }
+            i += 2;
+        } else if c == '"' || c == '\'' {
+            let mut text = String::new();
+            i += 1;
+            loop {
+                match chars.get(i).copied() {
+                    None | Some('\n') => {
+                        return Err(format!("TypeScript line {}: Unterminated string", line))
+                    }
+                    Some(q) if q == c => break,
+                    Some('\\') => {
+                        let escaped = chars.get(i + 1).copied().unwrap_or('\\');
+                        text.push(match escaped {
+                            'n' => '\n',
+                            't' => '\t',
+                            'r' => '\r',
+                            other => other,
+                        });
+                        i += 2;
+                        continue;
+                    }
+                    Some(other) => text.push(other),
+                }
+                i += 1;
+            }
+            i += 1;
+            tokens.push((Token::Str(text), line));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                i += 1;
+            }
+            tokens.push((Token::Number(chars[start..i].iter().collect()), line));
+        } else if c.is_alphabetic() || c == '_' || c == '$' {
+            let start = i;
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '$')
+            {
+                i += 1;
+            }
+            tokens.push((Token::Ident(chars[start..i].iter().collect()), line));
+        } else if "{}[]()<>:;,|&?=-".contains(c) {
+            tokens.push((Token::Punct(c), line));
+            i += 1;
+        } else {
+            return Err(format!("TypeScript line {}: Unexpected '{}'", line, c));
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self
+            .tokens
+            .get(self.pos)
+            .or(self.tokens.last())
+            .map_or(1, |(_, line)| *line);
+        format!("TypeScript line {}: {}", line, message)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| self.error("Unexpected end of source"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", c)))
+        }
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(ident)) if ident == keyword) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            _ => {
+                self.pos -= 1;
+                Err(self.error("Expected a name"))
+            }
+        }
+    }
+
+    /// `interface Name extends A, B { ... }` or `type Name = ...;`
+    fn declaration(&mut self) -> Result<Decl, String> {
+        self.eat_keyword("export");
+        if self.eat_keyword("interface") {
+            let name = self.ident()?;
+            self.no_type_parameters()?;
+            let mut extends = Vec::new();
+            if self.eat_keyword("extends") {
+                loop {
+                    extends.push(self.ident()?);
+                    if !self.eat(',') {
+                        break;
+                    }
+                }
+            }
+            self.expect('{')?;
+            let ty = self.object_body()?;
+            self.eat(';');
+            Ok(Decl { name, extends, ty })
+        } else if self.eat_keyword("type") {
+            let name = self.ident()?;
+            self.no_type_parameters()?;
+            self.expect('=')?;
+            let ty = self.union()?;
+            self.eat(';');
+            Ok(Decl {
+                name,
+                extends: Vec::new(),
+                ty,
+            })
+        } else {
+            Err(self.error("Expected an interface or type declaration"))
+        }
+    }
+
+    fn no_type_parameters(&self) -> Result<(), String> {
+        if self.peek() == Some(&Token::Punct('<')) {
+            return Err(self.error("Generic declarations are not supported"));
+        }
+        Ok(())
+    }
+
+    fn union(&mut self) -> Result<Type, String> {
+        self.eat('|');
+        let mut types = vec![self.postfix()?];
+        while self.eat('|') {
+            types.push(self.postfix()?);
+        }
+        Ok(if types.len() == 1 {
+            types.remove(0)
+        } else {
+            Type::Union(types)
+        })
+    }
+
+    /// A primary type followed by any number of `[]`
+    fn postfix(&mut self) -> Result<Type, String> {
+        let mut ty = self.primary()?;
+        while self.peek() == Some(&Token::Punct('['))
+            && self.tokens.get(self.pos + 1).map(|(t, _)| t) == Some(&Token::Punct(']'))
+        {
+            self.pos += 2;
+            ty = Type::Array(Box::new(ty));
+        }
+        if self.peek() == Some(&Token::Punct('&')) {
+            return Err(self.error("Intersection types are not supported"));
+        }
+        Ok(ty)
+    }
+
+    fn primary(&mut self) -> Result<Type, String> {
+        match self.next()? {
+            Token::Punct('(') => {
+                let ty = self.union()?;
+                self.expect(')')?;
+                Ok(ty)
+            }
+            Token::Punct('{') => self.object_body(),
+            Token::Punct('[') => {
+                let mut items = Vec::new();
+                while !self.eat(']') {
+                    items.push(self.union()?);
+                    if !self.eat(',') {
+                        self.expect(']')?;
+                        break;
+                    }
+                }
+                Ok(Type::Tuple(items))
+            }
+            Token::Str(text) => Ok(Type::Literal(Value::String(text))),
+            Token::Number(text) => self.number(&text, false),
+            Token::Punct('-') => match self.next()? {
+                Token::Number(text) => self.number(&text, true),
+                _ => Err(self.error("Expected a number after '-'")),
+            },
+            Token::Ident(ident) => self.named(ident),
+            Token::Punct(c) => {
+                self.pos -= 1;
+                Err(self.error(&format!("Unexpected '{}'", c)))
+            }
+        }
+    }
+
+    fn number(&self, text: &str, negative: bool) -> Result<Type, String> {
+        let text = if negative {
+            format!("-{}", text)
+        } else {
+            text.to_string()
+        };
+        let value: serde_json::Number = text
+            .parse()
+            .map_err(|_| self.error(&format!("Invalid number literal {}", text)))?;
+        Ok(Type::Literal(Value::Number(value)))
+    }
+
+    fn named(&mut self, ident: String) -> Result<Type, String> {
+        Ok(match ident.as_str() {
+            "string" => Type::Primitive("string"),
+            "number" => Type::Primitive("number"),
+            "boolean" => Type::Primitive("boolean"),
+            "null" => Type::Primitive("null"),
+            "true" => Type::Literal(Value::Bool(true)),
+            "false" => Type::Literal(Value::Bool(false)),
+            "any" | "unknown" => Type::Any,
+            "object" => Type::Object {
+                properties: Vec::new(),
+                index: Some(Box::new(Type::Any)),
+            },
+            "undefined" | "void" | "never" | "bigint" | "symbol" => {
+                self.pos -= 1;
+                return Err(self.error(&format!("{} has no JSON equivalent", ident)));
+            }
+            "Array" | "ReadonlyArray" => {
+                let [item] = self.type_arguments::<1>(&ident)?;
+                Type::Array(Box::new(item))
+            }
+            "Record" => {
+                let [key, value] = self.type_arguments::<2>(&ident)?;
+                if !matches!(key, Type::Primitive("string" | "number")) {
+                    return Err(self.error("Record keys must be string or number"));
+                }
+                Type::Object {
+                    properties: Vec::new(),
+                    index: Some(Box::new(value)),
+                }
+            }
+            _ => {
+                if self.peek() == Some(&Token::Punct('<')) {
+                    return Err(self.error(&format!("Generic type {} is not supported", ident)));
+                }
+                Type::Ref(ident)
+            }
+        })
+    }
+
+    fn type_arguments<const N: usize>(&mut self, name: &str) -> Result<[Type; N], String> {
+        self.expect('<')?;
+        let mut args = Vec::new();
+        loop {
+            args.push(self.union()?);
+            if !self.eat(',') {
+                break;
+            }
+        }
+        self.expect('>')?;
+        args.try_into()
+            .map_err(|_| self.error(&format!("{} takes {} type argument(s)", name, N)))
+    }
+
+    /// Members of an object type, after the opening brace
+    fn object_body(&mut self) -> Result<Type, String> {
+        let mut properties = Vec::new();
+        let mut index = None;
+        while !self.eat('}') {
+            // `readonly` is a modifier unless it is the property's name
+            let next = self.tokens.get(self.pos + 1).map(|(token, _)| token);
+            if !matches!(next, Some(Token::Punct(':' | '?'))) {
+                self.eat_keyword("readonly");
+            }
+            if self.eat('[') {
+                // Index signature, `[key: string]: T`
+                self.ident()?;
+                self.expect(':')?;
+                let key = self.ident()?;
+                if key != "string" && key != "number" {
+                    return Err(self.error("Index signature keys must be string or number"));
+                }
+                self.expect(']')?;
+                self.expect(':')?;
+                index = Some(Box::new(self.union()?));
+            } else {
+                let name = match self.next()? {
+                    Token::Ident(name) | Token::Str(name) | Token::Number(name) => name,
+                    _ => {
+                        self.pos -= 1;
+                        return Err(self.error("Expected a property name"));
+                    }
+                };
+                let optional = self.eat('?');
+                if self.peek() == Some(&Token::Punct('(')) {
+                    return Err(self.error("Methods have no JSON equivalent"));
+                }
+                self.expect(':')?;
+                let ty = self.union()?;
+                properties.push(Property { name, optional, ty });
+            }
+            if !self.eat(';') && !self.eat(',') && self.peek() != Some(&Token::Punct('}')) {
+                return Err(self.error("Expected ';' or '}' after a property"));
+            }
+        }
+        Ok(Type::Object { properties, index })
+    }
+}
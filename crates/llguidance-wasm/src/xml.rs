@@ -0,0 +1,888 @@
+//! XML output
+//!
+//! The XML is described either as an element tree (`{"name": ..., "attributes":
+//! [...], "children": [...]}`) or by an XSD schema, and both are translated to
+//! a Lark grammar for one element. Text and attribute values only admit
+//! characters that need no escaping, plus entity and character references, so
+//! the output is always well-formed. Whitespace is allowed around child
+//! elements, and attributes are written in declaration order after one space.
+
+use std::collections::{BTreeSet, HashMap};
+
+use serde::Deserialize;
+
+use crate::gbnf::repeat;
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum XmlSpec {
+    Xsd {
+        xsd: String,
+        /// Global element to generate; the first one if unset
+        #[serde(default)]
+        root_element: Option<String>,
+    },
+    Element(ElementSpec),
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ElementSpec {
+    name: String,
+    #[serde(default)]
+    attributes: Vec<AttributeSpec>,
+    /// Type of the text content; an element without text or children is empty
+    #[serde(default)]
+    text: Option<SimpleType>,
+    #[serde(default)]
+    children: Vec<ChildSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChildSpec {
+    #[serde(flatten)]
+    element: ElementSpec,
+    #[serde(default = "one")]
+    min_occurs: usize,
+    #[serde(default)]
+    max_occurs: Option<MaxOccurs>,
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+enum MaxOccurs {
+    Count(usize),
+    Unbounded(Unbounded),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Unbounded {
+    Unbounded,
+}
+
+#[derive(Debug, Deserialize)]
+struct AttributeSpec {
+    name: String,
+    #[serde(default, rename = "type")]
+    ty: Option<SimpleType>,
+    #[serde(default)]
+    required: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum SimpleType {
+    Builtin(Builtin),
+    Enum {
+        #[serde(rename = "enum")]
+        values: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Builtin {
+    String,
+    Integer,
+    Number,
+    Boolean,
+}
+
+/// Attributes and content shared by the elements of one type
+struct ComplexType {
+    attributes: Vec<Attribute>,
+    content: Content,
+}
+
+struct Attribute {
+    name: String,
+    ty: SimpleType,
+    required: bool,
+}
+
+enum Content {
+    Empty,
+    Text(SimpleType),
+    Children(Particle),
+}
+
+struct Particle {
+    term: Term,
+    min: usize,
+    max: Option<usize>,
+}
+
+enum Term {
+    /// An element with its name and the index of its type
+    Element(String, usize),
+    Sequence(Vec<Particle>),
+    Choice(Vec<Particle>),
+}
+
+/// Lark terminals, written out only when used
+const TERMINALS: [(&str, &str); 6] = [
+    (
+        "TEXT",
+        r"/([^<&]|&(lt|gt|amp|quot|apos|#[0-9]+|#x[0-9a-fA-F]+);)+/",
+    ),
+    (
+        "ATTRIBUTE_TEXT",
+        r#"/([^<&"]|&(lt|gt|amp|quot|apos|#[0-9]+|#x[0-9a-fA-F]+);)+/"#,
+    ),
+    ("INTEGER", r"/[+-]?[0-9]+/"),
+    (
+        "NUMBER",
+        r"/[+-]?([0-9]+(\.[0-9]*)?|\.[0-9]+)([eE][+-]?[0-9]+)?/",
+    ),
+    ("BOOLEAN", r"/true|false|1|0/"),
+    ("WS", r"/[ \t\r\n]+/"),
+];
+
+/// Translate an XML description to a Lark grammar for its root element
+pub(crate) fn xml_to_lark(spec: &XmlSpec) -> Result<String, String> {
+    let mut types = Vec::new();
+    let (name, ty) = match spec {
+        XmlSpec::Xsd { xsd, root_element } => {
+            let document = parse_document(xsd)?;
+            let mut schema = Xsd::new(&document)?;
+            let root = match root_element {
+                Some(name) => schema.global_element(name)?,
+                None => {
+                    let first = schema
+                        .first_element
+                        .ok_or_else(|| "XSD schema declares no elements".to_string())?;
+                    schema.global_element(first)?
+                }
+            };
+            types = schema.types;
+            root
+        }
+        XmlSpec::Element(element) => from_spec(element, &mut types)?,
+    };
+
+    let mut writer = Writer {
+        types: &types,
+        rules: HashMap::new(),
+        pending: Vec::new(),
+        terminals: BTreeSet::new(),
+    };
+    let mut lark = format!("start: {}\n", writer.element_rule(&name, ty));
+    while let Some((name, ty, rule)) = writer.pending.pop() {
+        let body = writer.element_body(&name, ty);
+        lark.push_str(&format!("{}: {}\n", rule, body));
+    }
+    for (terminal, regex) in TERMINALS {
+        if writer.terminals.contains(terminal) {
+            lark.push_str(&format!("{}: {}\n", terminal, regex));
+        }
+    }
+    Ok(lark)
+}
+
+fn from_spec(
+    element: &ElementSpec,
+    types: &mut Vec<ComplexType>,
+) -> Result<(String, usize), String> {
+    check_name(&element.name)?;
+    let content = match (&element.text, element.children.is_empty()) {
+        (Some(_), false) => {
+            return Err(format!(
+                "XML element {} cannot have both text and children",
+                element.name
+            ))
+        }
+        (Some(text), true) => Content::Text(check_simple(text.clone())?),
+        (None, true) => Content::Empty,
+        (None, false) => {
+            let mut particles = Vec::new();
+            for child in &element.children {
+                let (name, ty) = from_spec(&child.element, types)?;
+                let max = match child.max_occurs {
+                    None => Some(1),
+                    Some(MaxOccurs::Count(max)) => Some(max),
+                    Some(MaxOccurs::Unbounded(Unbounded::Unbounded)) => None,
+                };
+                particles.push(particle(Term::Element(name, ty), child.min_occurs, max)?);
+            }
+            Content::Children(particle(Term::Sequence(particles), 1, Some(1))?)
+        }
+    };
+
+    let mut attributes: Vec<Attribute> = Vec::new();
+    for attribute in &element.attributes {
+        check_name(&attribute.name)?;
+        if attributes.iter().any(|a| a.name == attribute.name) {
+            return Err(format!(
+                "XML element {} has more than one {} attribute",
+                element.name, attribute.name
+            ));
+        }
+        attributes.push(Attribute {
+            name: attribute.name.clone(),
+            ty: check_simple(
+                attribute
+                    .ty
+                    .clone()
+                    .unwrap_or(SimpleType::Builtin(Builtin::String)),
+            )?,
+            required: attribute.required,
+        });
+    }
+    types.push(ComplexType {
+        attributes,
+        content,
+    });
+    Ok((element.name.clone(), types.len() - 1))
+}
+
+fn particle(term: Term, min: usize, max: Option<usize>) -> Result<Particle, String> {
+    if max.is_some_and(|max| max < min) {
+        return Err(format!(
+            "maxOccurs {} is less than minOccurs {}",
+            max.unwrap_or_default(),
+            min
+        ));
+    }
+    Ok(Particle { term, min, max })
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_alphabetic() || c == '_' || c == ':')
+        && chars.all(|c| c.is_alphanumeric() || matches!(c, '_' | ':' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{:?} is not a valid XML name", name))
+    }
+}
+
+fn check_simple(ty: SimpleType) -> Result<SimpleType, String> {
+    match &ty {
+        SimpleType::Enum { values } if values.is_empty() => {
+            Err("An XML enum needs at least one value".to_string())
+        }
+        _ => Ok(ty),
+    }
+}
+
+struct Writer<'a> {
+    types: &'a [ComplexType],
+    /// Rule of each element name and type seen so far
+    rules: HashMap<(String, usize), String>,
+    /// Rules named but not yet written
+    pending: Vec<(String, usize, String)>,
+    terminals: BTreeSet<&'static str>,
+}
+
+impl Writer<'_> {
+    fn element_rule(&mut self, name: &str, ty: usize) -> String {
+        let key = (name.to_string(), ty);
+        if let Some(rule) = self.rules.get(&key) {
+            return rule.clone();
+        }
+        let rule = format!("element_{}", self.rules.len());
+        self.rules.insert(key, rule.clone());
+        self.pending.push((name.to_string(), ty, rule.clone()));
+        rule
+    }
+
+    fn element_body(&mut self, name: &str, ty: usize) -> String {
+        let types = self.types;
+        let ty = &types[ty];
+        let mut parts = vec![literal(&format!("<{}", name))];
+        for attribute in &ty.attributes {
+            let value = format!(
+                "{} {} {}",
+                literal(&format!(" {}=\"", attribute.name)),
+                self.value(&attribute.ty, true),
+                literal("\"")
+            );
+            parts.push(if attribute.required {
+                value
+            } else {
+                format!("({})?", value)
+            });
+        }
+        let close = literal(&format!("</{}>", name));
+        match &ty.content {
+            Content::Empty => parts.push(literal("/>")),
+            Content::Text(text) => {
+                parts.push(literal(">"));
+                parts.push(self.value(text, false));
+                parts.push(close);
+            }
+            Content::Children(children) => {
+                parts.push(literal(">"));
+                parts.push(self.particle(children));
+                parts.push(self.terminal("WS", "?"));
+                parts.push(close);
+            }
+        }
+        parts.join(" ")
+    }
+
+    fn particle(&mut self, particle: &Particle) -> String {
+        let term = match &particle.term {
+            Term::Element(name, ty) => {
+                let rule = self.element_rule(name, *ty);
+                format!("{} {}", self.terminal("WS", "?"), rule)
+            }
+            Term::Sequence(particles) if particles.is_empty() => "\"\"".to_string(),
+            Term::Sequence(particles) => particles
+                .iter()
+                .map(|p| self.particle(p))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Term::Choice(particles) => format!(
+                "({})",
+                particles
+                    .iter()
+                    .map(|p| self.particle(p))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            ),
+        };
+        if particle.min == 1 && particle.max == Some(1) {
+            term
+        } else {
+            repeat(&format!("({})", term), particle.min, particle.max)
+        }
+    }
+
+    /// The value of a text node or (if `in_attribute`) a quoted attribute
+    fn value(&mut self, ty: &SimpleType, in_attribute: bool) -> String {
+        match ty {
+            SimpleType::Builtin(Builtin::String) if in_attribute => {
+                self.terminal("ATTRIBUTE_TEXT", "?")
+            }
+            SimpleType::Builtin(Builtin::String) => self.terminal("TEXT", "?"),
+            SimpleType::Builtin(Builtin::Integer) => self.terminal("INTEGER", ""),
+            SimpleType::Builtin(Builtin::Number) => self.terminal("NUMBER", ""),
+            SimpleType::Builtin(Builtin::Boolean) => self.terminal("BOOLEAN", ""),
+            SimpleType::Enum { values } => format!(
+                "({})",
+                values
+                    .iter()
+                    .map(|value| literal(&escape(value, in_attribute)))
+                    .collect::<Vec<_>>()
+                    .join(" | ")
+            ),
+        }
+    }
+
+    fn terminal(&mut self, name: &'static str, suffix: &str) -> String {
+        self.terminals.insert(name);
+        format!("{}{}", name, suffix)
+    }
+}
+
+fn literal(text: &str) -> String {
+    serde_json::to_string(text).unwrap()
+}
+
+/// Escape the characters that cannot appear literally in text or attributes
+fn escape(text: &str, in_attribute: bool) -> String {
+    let text = text.replace('&', "&amp;").replace('<', "&lt;");
+    if in_attribute {
+        text.replace('"', "&quot;")
+    } else {
+        text
+    }
+}
+
+/// The components declared by an XSD schema, translated on first use
+struct Xsd<'a> {
+    elements: HashMap<&'a str, &'a Node>,
+    complex_types: HashMap<&'a str, &'a Node>,
+    simple_types: HashMap<&'a str, &'a Node>,
+    first_element: Option<&'a str>,
+    types: Vec<ComplexType>,
+    /// Type index of each global element and named type translated so far
+    element_types: HashMap<String, usize>,
+    named_types: HashMap<String, usize>,
+}
+
+impl<'a> Xsd<'a> {
+    fn new(schema: &'a Node) -> Result<Self, String> {
+        if schema.local_name() != "schema" {
+            return Err("XSD root element must be xs:schema".to_string());
+        }
+        let mut xsd = Xsd {
+            elements: HashMap::new(),
+            complex_types: HashMap::new(),
+            simple_types: HashMap::new(),
+            first_element: None,
+            types: Vec::new(),
+            element_types: HashMap::new(),
+            named_types: HashMap::new(),
+        };
+        for child in &schema.children {
+            let components = match child.local_name() {
+                "element" => &mut xsd.elements,
+                "complexType" => &mut xsd.complex_types,
+                "simpleType" => &mut xsd.simple_types,
+                _ => continue,
+            };
+            let name = child.required_attribute("name")?;
+            if components.insert(name, child).is_some() {
+                return Err(format!(
+                    "XSD {} {} is declared more than once",
+                    child.local_name(),
+                    name
+                ));
+            }
+            if child.local_name() == "element" && xsd.first_element.is_none() {
+                xsd.first_element = Some(name);
+            }
+        }
+        Ok(xsd)
+    }
+
+    /// Reserve a type index, so recursive types can refer to it while it is
+    /// being translated
+    fn reserve(&mut self) -> usize {
+        self.types.push(ComplexType {
+            attributes: Vec::new(),
+            content: Content::Empty,
+        });
+        self.types.len() - 1
+    }
+
+    fn global_element(&mut self, name: &str) -> Result<(String, usize), String> {
+        let name = local(name);
+        if let Some(&ty) = self.element_types.get(name) {
+            return Ok((name.to_string(), ty));
+        }
+        let node = *self
+            .elements
+            .get(name)
+            .ok_or_else(|| format!("XSD element {} is not declared", name))?;
+        let ty = match node.attribute("type") {
+            Some(ty) => self.named_type(ty)?,
+            None => {
+                let ty = self.reserve();
+                self.element_types.insert(name.to_string(), ty);
+                self.types[ty] = self.anonymous_type(node)?;
+                ty
+            }
+        };
+        self.element_types.insert(name.to_string(), ty);
+        Ok((name.to_string(), ty))
+    }
+
+    fn element(&mut self, node: &Node) -> Result<Particle, String> {
+        let (name, ty) = match node.attribute("ref") {
+            Some(reference) => self.global_element(reference)?,
+            None => {
+                let name = node.required_attribute("name")?;
+                check_name(name)?;
+                let ty = match node.attribute("type") {
+                    Some(ty) => self.named_type(ty)?,
+                    None => {
+                        let ty = self.anonymous_type(node)?;
+                        self.types.push(ty);
+                        self.types.len() - 1
+                    }
+                };
+                (name.to_string(), ty)
+            }
+        };
+        let (min, max) = occurs(node)?;
+        particle(Term::Element(name, ty), min, max)
+    }
+
+    /// The type declared inside an element without a `type` attribute
+    fn anonymous_type(&mut self, node: &Node) -> Result<ComplexType, String> {
+        for child in &node.children {
+            match child.local_name() {
+                "complexType" => return self.complex_type(child),
+                "simpleType" => return Ok(text_type(self.simple_type(child)?)),
+                _ => {}
+            }
+        }
+        Ok(text_type(SimpleType::Builtin(Builtin::String)))
+    }
+
+    fn named_type(&mut self, name: &str) -> Result<usize, String> {
+        let name = local(name);
+        if let Some(&ty) = self.named_types.get(name) {
+            return Ok(ty);
+        }
+        let ty = self.reserve();
+        self.named_types.insert(name.to_string(), ty);
+        self.types[ty] = match self.complex_types.get(name).copied() {
+            Some(node) => self.complex_type(node)?,
+            None => text_type(self.named_simple_type(name)?),
+        };
+        Ok(ty)
+    }
+
+    fn named_simple_type(&mut self, name: &str) -> Result<SimpleType, String> {
+        let name = local(name);
+        if let Some(node) = self.simple_types.get(name).copied() {
+            return self.simple_type(node);
+        }
+        let builtin = match name {
+            "string" | "normalizedString" | "token" | "anyURI" | "ID" | "IDREF" | "Name"
+            | "NCName" | "NMTOKEN" | "language" => Builtin::String,
+            "integer" | "int" | "long" | "short" | "byte" | "nonNegativeInteger"
+            | "positiveInteger" | "nonPositiveInteger" | "negativeInteger" | "unsignedLong"
+            | "unsignedInt" | "unsignedShort" | "unsignedByte" => Builtin::Integer,
+            "decimal" | "float" | "double" => Builtin::Number,
+            "boolean" => Builtin::Boolean,
+            _ => return Err(format!("XSD type {} is not supported", name)),
+        };
+        Ok(SimpleType::Builtin(builtin))
+    }
+
+    fn simple_type(&mut self, node: &Node) -> Result<SimpleType, String> {
+        let restriction = node
+            .children
+            .iter()
+            .find(|child| child.local_name() == "restriction")
+            .ok_or_else(|| "Only restrictions are supported in an XSD simpleType".to_string())?;
+        let values: Vec<String> = restriction
+            .children
+            .iter()
+            .filter(|child| child.local_name() == "enumeration")
+            .map(|child| child.required_attribute("value").map(str::to_string))
+            .collect::<Result<_, _>>()?;
+        if !values.is_empty() {
+            return Ok(SimpleType::Enum { values });
+        }
+        // Facets other than enumerations are not enforced
+        self.named_simple_type(restriction.required_attribute("base")?)
+    }
+
+    fn complex_type(&mut self, node: &Node) -> Result<ComplexType, String> {
+        if node.attribute("mixed") == Some("true") {
+            return Err("XSD mixed content is not supported".to_string());
+        }
+        let mut ty = ComplexType {
+            attributes: Vec::new(),
+            content: Content::Empty,
+        };
+        for child in &node.children {
+            match child.local_name() {
+                "sequence" | "choice" | "all" => {
+                    ty.content = Content::Children(self.group(child)?);
+                }
+                "simpleContent" => {
+                    let extension = child
+                        .children
+                        .iter()
+                        .find(|c| matches!(c.local_name(), "extension" | "restriction"))
+                        .ok_or_else(|| "XSD simpleContent needs an extension".to_string())?;
+                    ty.content = Content::Text(
+                        self.named_simple_type(extension.required_attribute("base")?)?,
+                    );
+                    for attribute in &extension.children {
+                        if attribute.local_name() == "attribute" {
+                            self.attribute(attribute, &mut ty.attributes)?;
+                        }
+                    }
+                }
+                "attribute" => self.attribute(child, &mut ty.attributes)?,
+                "annotation" | "anyAttribute" => {}
+                other => return Err(format!("XSD {} is not supported", other)),
+            }
+        }
+        Ok(ty)
+    }
+
+    /// A sequence, choice or all group; the elements of `all` keep their order
+    fn group(&mut self, node: &Node) -> Result<Particle, String> {
+        let mut particles = Vec::new();
+        for child in &node.children {
+            match child.local_name() {
+                "element" => particles.push(self.element(child)?),
+                "sequence" | "choice" => particles.push(self.group(child)?),
+                "annotation" => {}
+                other => return Err(format!("XSD {} is not supported", other)),
+            }
+        }
+        let term = match node.local_name() {
+            "choice" if particles.is_empty() => {
+                return Err("An XSD choice needs at least one element".to_string())
+            }
+            "choice" => Term::Choice(particles),
+            _ => Term::Sequence(particles),
+        };
+        let (min, max) = occurs(node)?;
+        particle(term, min, max)
+    }
+
+    fn attribute(&mut self, node: &Node, attributes: &mut Vec<Attribute>) -> Result<(), String> {
+        if node.attribute("ref").is_some() {
+            return Err("XSD attribute references are not supported".to_string());
+        }
+        let name = node.required_attribute("name")?;
+        check_name(name)?;
+        let required = match node.attribute("use") {
+            Some("prohibited") => return Ok(()),
+            Some(used) => used == "required",
+            None => false,
+        };
+        let ty = match node.attribute("type") {
+            Some(ty) => self.named_simple_type(ty)?,
+            None => match node
+                .children
+                .iter()
+                .find(|c| c.local_name() == "simpleType")
+            {
+                Some(simple_type) => self.simple_type(simple_type)?,
+                None => SimpleType::Builtin(Builtin::String),
+            },
+        };
+        attributes.push(Attribute {
+            name: name.to_string(),
+            ty,
+            required,
+        });
+        Ok(())
+    }
+}
+
+fn text_type(ty: SimpleType) -> ComplexType {
+    ComplexType {
+        attributes: Vec::new(),
+        content: Content::Text(ty),
+    }
+}
+
+fn occurs(node: &Node) -> Result<(usize, Option<usize>), String> {
+    let min = match node.attribute("minOccurs") {
+        Some(min) => min
+            .parse()
+            .map_err(|_| format!("Invalid XSD minOccurs {:?}", min))?,
+        None => 1,
+    };
+    let max = match node.attribute("maxOccurs") {
+        Some("unbounded") => None,
+        Some(max) => Some(
+            max.parse()
+                .map_err(|_| format!("Invalid XSD maxOccurs {:?}", max))?,
+        ),
+        None => Some(1),
+    };
+    Ok((min, max))
+}
+
+/// A name without its namespace prefix
+fn local(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// An element of a parsed XML document; text content is dropped, which is
+/// all an XSD schema needs
+struct Node {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn local_name(&self) -> &str {
+        local(&self.name)
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn required_attribute(&self, name: &str) -> Result<&str, String> {
+        self.attribute(name)
+            .ok_or_else(|| format!("XSD {} needs a {} attribute", self.local_name(), name))
+    }
+}
+
+fn parse_document(source: &str) -> Result<Node, String> {
+    let mut parser = XmlParser { source, pos: 0 };
+    parser.misc()?;
+    let root = parser.element()?;
+    parser.misc()?;
+    if parser.pos < source.len() {
+        return Err(parser.error("Unexpected content after the root element"));
+    }
+    Ok(root)
+}
+
+struct XmlParser<'a> {
+    source: &'a str,
+    pos: usize,
+}
+
+impl XmlParser<'_> {
+    fn error(&self, message: &str) -> String {
+        let line = self.source[..self.pos].matches('\n').count() + 1;
+        format!("XSD line {}: {}", line, message)
+    }
+
+    fn rest(&self) -> &str {
+        &self.source[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// Skip past `end`, which must follow
+    fn skip_past(&mut self, end: &str) -> Result<(), String> {
+        match self.rest().find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("Expected '{}'", end))),
+        }
+    }
+
+    /// Skip whitespace, comments, processing instructions and DOCTYPEs
+    fn misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '=' | '<'))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return Err(self.error("Expected a name"));
+        }
+        let name = rest[..end].to_string();
+        self.pos += end;
+        Ok(name)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), String> {
+        if self.rest().starts_with(text) {
+            self.pos += text.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", text)))
+        }
+    }
+
+    fn element(&mut self) -> Result<Node, String> {
+        self.expect("<")?;
+        let mut node = Node {
+            name: self.name()?,
+            attributes: Vec::new(),
+            children: Vec::new(),
+        };
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.pos += 2;
+                return Ok(node);
+            }
+            if self.rest().starts_with('>') {
+                self.pos += 1;
+                break;
+            }
+            let name = self.name()?;
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(self.error("Expected a quoted attribute value")),
+            };
+            self.pos += 1;
+            let end = self
+                .rest()
+                .find(quote)
+                .ok_or_else(|| self.error("Unterminated attribute value"))?;
+            let value = unescape(&self.rest()[..end]);
+            self.pos += end + 1;
+            node.attributes.push((name, value));
+        }
+
+        loop {
+            let rest = self.rest();
+            if rest.is_empty() {
+                return Err(self.error(&format!("Unclosed element {}", node.name)));
+            } else if rest.starts_with("</") {
+                self.pos += 2;
+                let name = self.name()?;
+                if name != node.name {
+                    return Err(self.error(&format!("Expected </{}>", node.name)));
+                }
+                self.skip_whitespace();
+                self.expect(">")?;
+                return Ok(node);
+            } else if rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if rest.starts_with("<![CDATA[") {
+                self.skip_past("]]>")?;
+            } else if rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if rest.starts_with('<') {
+                node.children.push(self.element()?);
+            } else {
+                self.pos += rest.find('<').unwrap_or(rest.len());
+            }
+        }
+    }
+}
+
+/// Replace the entity and character references of an attribute value
+fn unescape(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let reference = &rest[1..end];
+        let c = match reference {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => reference
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16).ok())
+                .unwrap_or_else(|| reference.strip_prefix('#').and_then(|n| n.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
@@ -0,0 +1,422 @@
+//! YAML output for JSON schemas
+//!
+//! A JSON schema is translated to a Lark grammar for block-style YAML:
+//! objects as `key: value` lines, arrays as `- item` lines, two spaces of
+//! indentation per level, and every line ending in a newline. Since YAML
+//! structure depends on indentation, each subschema gets a rule per
+//! indentation level and position (top level, after a key, after a dash).
+//! Strings are always double-quoted, so no string can be read back as a
+//! number or boolean. Values without a type, and values nested deeper than
+//! `MAX_DEPTH` (as in recursive schemas), are written as flow-style JSON,
+//! which YAML also accepts.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use crate::gbnf::repeat;
+
+/// Nesting levels written in block style
+const MAX_DEPTH: usize = 16;
+
+/// Lark terminals, written out only when used
+const TERMINALS: [(&str, &str); 4] = [
+    (
+        "STRING",
+        r#"/"([^"\\\x00-\x1F]|\\(["\\\/bfnrt]|u[0-9a-fA-F]{4}))*"/"#,
+    ),
+    ("INTEGER", r"/-?(0|[1-9][0-9]*)/"),
+    (
+        "NUMBER",
+        r"/-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?/",
+    ),
+    ("KEY", r"/[A-Za-z_][A-Za-z0-9_.-]*/"),
+];
+
+/// Where a value starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Position {
+    /// The whole document
+    Top,
+    /// After `key:` on the line of the key
+    Key,
+    /// After `-` on the line of the dash
+    Dash,
+}
+
+/// Translate a JSON schema to a Lark grammar for YAML matching it
+pub(crate) fn schema_to_lark(schema: &Value) -> Result<String, String> {
+    let mut writer = Writer {
+        root: schema,
+        rules: HashMap::new(),
+        lark: String::new(),
+    };
+    let start = writer.value_rule("", 0, Position::Top)?;
+    let mut lark = format!("start: {}\n{}", start, writer.lark);
+    for (terminal, regex) in TERMINALS {
+        if writer.lark.contains(terminal) {
+            lark.push_str(&format!("{}: {}\n", terminal, regex));
+        }
+    }
+    Ok(lark)
+}
+
+struct Writer<'a> {
+    root: &'a Value,
+    /// Rule of each subschema (by JSON pointer), indentation and position
+    rules: HashMap<(String, usize, Position), String>,
+    lark: String,
+}
+
+impl<'a> Writer<'a> {
+    fn value_rule(
+        &mut self,
+        pointer: &str,
+        indent: usize,
+        position: Position,
+    ) -> Result<String, String> {
+        let key = (pointer.to_string(), indent, position);
+        if let Some(rule) = self.rules.get(&key) {
+            return Ok(rule.clone());
+        }
+        let rule = format!("value_{}", self.rules.len());
+        // Named before the body is written, so recursive schemas terminate
+        self.rules.insert(key, rule.clone());
+        let body = self.value(pointer, indent, position)?;
+        self.lark.push_str(&format!("{}: {}\n", rule, body));
+        Ok(rule)
+    }
+
+    /// The subschema at `pointer`, which may be a `$ref` to follow
+    fn schema(&self, pointer: &str) -> Result<(String, &'a Value), String> {
+        let mut pointer = pointer.to_string();
+        // Bounded, so references that loop end with an error
+        for _ in 0..32 {
+            let schema = self
+                .root
+                .pointer(&pointer)
+                .ok_or_else(|| format!("Cannot resolve $ref #{}", pointer))?;
+            match schema.get("$ref").and_then(Value::as_str) {
+                Some(reference) => {
+                    pointer = reference
+                        .strip_prefix('#')
+                        .ok_or_else(|| {
+                            format!(
+                                "Only local $refs are supported in YAML output, not {}",
+                                reference
+                            )
+                        })?
+                        .to_string();
+                }
+                None => return Ok((pointer, schema)),
+            }
+        }
+        Err("$refs in the schema form a cycle".to_string())
+    }
+
+    fn value(
+        &mut self,
+        pointer: &str,
+        indent: usize,
+        position: Position,
+    ) -> Result<String, String> {
+        let (pointer, schema) = self.schema(pointer)?;
+        let schema = match schema {
+            Value::Bool(true) => return Ok(self.flow(&pointer, position)),
+            Value::Bool(false) => return Err("A false schema matches nothing".to_string()),
+            Value::Object(schema) => schema,
+            _ => return Err(format!("Invalid schema at #{}", pointer)),
+        };
+        if indent / 2 >= MAX_DEPTH {
+            return Ok(self.flow(&pointer, position));
+        }
+        if schema.contains_key("allOf") {
+            return Err("allOf is not supported in YAML output".to_string());
+        }
+
+        if let Some(value) = schema.get("const") {
+            return Ok(scalar(&literal(&flow_value(value)), position));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let values: Vec<String> = values.iter().map(|v| literal(&flow_value(v))).collect();
+            return Ok(scalar(&format!("({})", values.join(" | ")), position));
+        }
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(Value::Array(alternatives)) = schema.get(keyword) {
+                let alternatives = (0..alternatives.len())
+                    .map(|i| {
+                        self.value_rule(&format!("{}/{}/{}", pointer, keyword, i), indent, position)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(format!("({})", alternatives.join(" | ")));
+            }
+        }
+
+        match schema.get("type") {
+            Some(Value::String(ty)) => self.typed(&pointer, schema, ty, indent, position),
+            Some(Value::Array(types)) => {
+                let alternatives = types
+                    .iter()
+                    .map(|ty| {
+                        let ty = ty
+                            .as_str()
+                            .ok_or_else(|| "Schema types must be strings".to_string())?;
+                        self.typed(&pointer, schema, ty, indent, position)
+                    })
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok(format!("({})", alternatives.join(" | ")))
+            }
+            Some(_) => Err(format!("Invalid type at #{}", pointer)),
+            None if schema.contains_key("properties") => {
+                self.typed(&pointer, schema, "object", indent, position)
+            }
+            None if schema.contains_key("items") => {
+                self.typed(&pointer, schema, "array", indent, position)
+            }
+            None => Ok(self.flow(&pointer, position)),
+        }
+    }
+
+    fn typed(
+        &mut self,
+        pointer: &str,
+        schema: &Map<String, Value>,
+        ty: &str,
+        indent: usize,
+        position: Position,
+    ) -> Result<String, String> {
+        Ok(match ty {
+            "string" => scalar("STRING", position),
+            "integer" => scalar("INTEGER", position),
+            "number" => scalar("NUMBER", position),
+            "boolean" => scalar("(\"true\" | \"false\")", position),
+            "null" => scalar("\"null\"", position),
+            "object" => self.object(pointer, schema, indent, position)?,
+            "array" => self.array(pointer, schema, indent, position)?,
+            _ => return Err(format!("Unknown type {} at #{}", ty, pointer)),
+        })
+    }
+
+    fn object(
+        &mut self,
+        pointer: &str,
+        schema: &Map<String, Value>,
+        indent: usize,
+        position: Position,
+    ) -> Result<String, String> {
+        let child = match position {
+            Position::Top => 0,
+            Position::Key | Position::Dash => indent + 2,
+        };
+        let prefix = match position {
+            Position::Top => "",
+            Position::Key | Position::Dash => "\"\\n\" ",
+        };
+        let empty = scalar("\"{}\"", position);
+
+        let properties = match schema.get("properties") {
+            Some(Value::Object(properties)) if !properties.is_empty() => properties,
+            _ => {
+                // A map: any keys, with values matching additionalProperties
+                let value = match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => return Ok(empty),
+                    Some(Value::Object(_)) => self.value_rule(
+                        &format!("{}/additionalProperties", pointer),
+                        child,
+                        Position::Key,
+                    )?,
+                    _ => self.flow(&format!("{}/additionalProperties", pointer), Position::Key),
+                };
+                let entry = format!(
+                    "{} (KEY | STRING) \":\" {}",
+                    literal(&" ".repeat(child)),
+                    value
+                );
+                return Ok(format!("({}({})+ | {})", prefix, entry, empty));
+            }
+        };
+
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut entries = Vec::new();
+        for name in properties.keys() {
+            let value = self.value_rule(
+                &format!("{}/properties/{}", pointer, escape_pointer(name)),
+                child,
+                Position::Key,
+            )?;
+            entries.push((key(name), value, required.contains(&name.as_str())));
+        }
+        let line = |(key, value, _): &(String, String, bool), indent: usize| {
+            format!(
+                "{} {}",
+                literal(&format!("{}{}:", " ".repeat(indent), key)),
+                value
+            )
+        };
+
+        // A first required property can share the line of the dash
+        if position == Position::Dash && entries[0].2 {
+            let mut lines = vec![format!("{} {}", literal(" "), line(&entries[0], 0))];
+            lines.extend(
+                entries[1..]
+                    .iter()
+                    .map(|entry| optional(line(entry, child), entry.2)),
+            );
+            return Ok(lines.join(" "));
+        }
+
+        let lines = if entries.iter().any(|entry| entry.2) {
+            entries
+                .iter()
+                .map(|entry| optional(line(entry, child), entry.2))
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            // All optional, but at least one present; no lines is `{}`
+            let alternatives: Vec<String> = (0..entries.len())
+                .map(|first| {
+                    let mut lines = vec![line(&entries[first], child)];
+                    lines.extend(
+                        entries[first + 1..]
+                            .iter()
+                            .map(|entry| optional(line(entry, child), false)),
+                    );
+                    lines.join(" ")
+                })
+                .collect();
+            return Ok(format!(
+                "({}({}) | {})",
+                prefix,
+                alternatives.join(" | "),
+                empty
+            ));
+        };
+        Ok(format!("{}{}", prefix, lines))
+    }
+
+    fn array(
+        &mut self,
+        pointer: &str,
+        schema: &Map<String, Value>,
+        indent: usize,
+        position: Position,
+    ) -> Result<String, String> {
+        if schema.contains_key("prefixItems") {
+            return Err("prefixItems is not supported in YAML output".to_string());
+        }
+        let child = match position {
+            Position::Top => 0,
+            Position::Key | Position::Dash => indent + 2,
+        };
+        let prefix = match position {
+            Position::Top => "",
+            Position::Key | Position::Dash => "\"\\n\" ",
+        };
+        let item = match schema.get("items") {
+            Some(Value::Object(_) | Value::Bool(true)) => {
+                self.value_rule(&format!("{}/items", pointer), child, Position::Dash)?
+            }
+            _ => self.flow(&format!("{}/items", pointer), Position::Dash),
+        };
+        let item = format!("({} {})", literal(&format!("{}-", " ".repeat(child))), item);
+
+        let count = |keyword: &str| {
+            schema
+                .get(keyword)
+                .and_then(Value::as_u64)
+                .map(|n| n as usize)
+        };
+        let min = count("minItems").unwrap_or(0);
+        let max = count("maxItems");
+        let items = format!("{}{}", prefix, repeat(&item, min.max(1), max));
+        Ok(match (min, max) {
+            (_, Some(0)) => scalar("\"[]\"", position),
+            (0, _) => format!("({} | {})", items, scalar("\"[]\"", position)),
+            _ => items,
+        })
+    }
+
+    /// The subschema at `pointer` as flow-style JSON, e.g. for values of any type
+    fn flow(&self, pointer: &str, position: Position) -> String {
+        let mut schema = self
+            .root
+            .pointer(pointer)
+            .cloned()
+            .unwrap_or(Value::Bool(true));
+        if schema == Value::Bool(true) {
+            schema = Value::Object(Map::new());
+        }
+        if let (Value::Object(schema), Value::Object(root)) = (&mut schema, self.root) {
+            // Keep the definitions that local $refs point at
+            for defs in ["$defs", "definitions"] {
+                if let Some(definitions) = root.get(defs) {
+                    schema.insert(defs.to_string(), definitions.clone());
+                }
+            }
+            // Single-line JSON, so it cannot break the indentation
+            schema.insert(
+                "x-guidance".to_string(),
+                serde_json::json!({
+                    "whitespace_flexible": false,
+                    "item_separator": ", ",
+                    "key_separator": ": "
+                }),
+            );
+        }
+        scalar(
+            &format!("%json {}", serde_json::to_string(&schema).unwrap()),
+            position,
+        )
+    }
+}
+
+/// A scalar value followed by the end of its line
+fn scalar(value: &str, position: Position) -> String {
+    match position {
+        Position::Top => format!("{} \"\\n\"", value),
+        Position::Key | Position::Dash => format!("\" \" {} \"\\n\"", value),
+    }
+}
+
+fn optional(line: String, required: bool) -> String {
+    if required {
+        line
+    } else {
+        format!("({})?", line)
+    }
+}
+
+fn literal(text: &str) -> String {
+    serde_json::to_string(text).unwrap()
+}
+
+/// A constant as YAML; JSON values are valid YAML flow values
+fn flow_value(value: &Value) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// A mapping key, quoted unless YAML reads it back as the same plain string
+fn key(name: &str) -> String {
+    let mut chars = name.chars();
+    let plain = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        && !matches!(
+            name.to_ascii_lowercase().as_str(),
+            "true" | "false" | "null" | "yes" | "no" | "on" | "off" | "y" | "n"
+        );
+    if plain {
+        name.to_string()
+    } else {
+        serde_json::to_string(name).unwrap()
+    }
+}
+
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
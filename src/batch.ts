@@ -0,0 +1,133 @@
+interface WasmParserBatch {
+  len(): number;
+  vocab_size(): number;
+  get_token_masks(): Uint8Array;
+  advance(tokenIds: Uint32Array): void;
+  advance_sequence(index: number, tokenId: number): void;
+  rollback(index: number, numTokens: number): void;
+  is_complete(index: number): boolean;
+  fork(index: number): number;
+  remove(index: number): void;
+  reset(): void;
+  free(): void;
+}
+
+/**
+ * GuidanceBatch tracks several sequences generated in parallel against one
+ * grammar, and computes all of their masks in a single WASM call.
+ * Create one with GuidanceParser.createBatch().
+ */
+export class GuidanceBatch {
+  private _wasmBatch: WasmParserBatch | null;
+
+  constructor(wasmBatch: unknown) {
+    // Use GuidanceParser.createBatch() rather than calling this directly
+    this._wasmBatch = wasmBatch as WasmParserBatch;
+  }
+
+  private get wasmBatch(): WasmParserBatch {
+    if (!this._wasmBatch) {
+      throw new Error('GuidanceBatch used after free()');
+    }
+    return this._wasmBatch;
+  }
+
+  /**
+   * Number of sequences in the batch
+   */
+  get size(): number {
+    return this.wasmBatch.len();
+  }
+
+  /**
+   * Length of each sequence's mask
+   */
+  get vocabSize(): number {
+    return this.wasmBatch.vocab_size();
+  }
+
+  /**
+   * Get the masks of all sequences in one buffer
+   * @returns size * vocabSize bytes; sequence i's mask starts at i * vocabSize
+   */
+  getTokenMasks(): Uint8Array {
+    return this.wasmBatch.get_token_masks();
+  }
+
+  /**
+   * Get the mask of one sequence out of a buffer returned by getTokenMasks()
+   * @param masks The concatenated masks
+   * @param index The sequence index
+   */
+  sequenceMask(masks: Uint8Array, index: number): Uint8Array {
+    const vocabSize = this.vocabSize;
+    return masks.subarray(index * vocabSize, (index + 1) * vocabSize);
+  }
+
+  /**
+   * Advance every sequence by its sampled token
+   * @param tokenIds One token per sequence, in sequence order
+   */
+  advance(tokenIds: ArrayLike<number>): void {
+    this.wasmBatch.advance(Uint32Array.from(tokenIds));
+  }
+
+  /**
+   * Advance a single sequence, e.g. when the others have already finished
+   * @param index The sequence index
+   * @param tokenId The token that was selected
+   */
+  advanceSequence(index: number, tokenId: number): void {
+    this.wasmBatch.advance_sequence(index, tokenId);
+  }
+
+  /**
+   * Undo the last tokens of a single sequence, e.g. when a sampler backtracks
+   * @param index The sequence index
+   * @param nTokens Number of advance calls to undo
+   */
+  rollback(index: number, nTokens: number): void {
+    this.wasmBatch.rollback(index, nTokens);
+  }
+
+  /**
+   * Check whether a sequence is a valid complete parse
+   * @param index The sequence index
+   */
+  isComplete(index: number): boolean {
+    return this.wasmBatch.is_complete(index);
+  }
+
+  /**
+   * Branch a sequence at its current position, e.g. to split a beam
+   * The branch shares the work already done for the shared prefix
+   * @param index The sequence to branch
+   * @returns The index of the new sequence, appended at the end
+   */
+  fork(index: number): number {
+    return this.wasmBatch.fork(index);
+  }
+
+  /**
+   * Drop a sequence, e.g. a pruned beam; later sequences move down one index
+   * @param index The sequence to drop
+   */
+  remove(index: number): void {
+    this.wasmBatch.remove(index);
+  }
+
+  /**
+   * Restart every sequence at the beginning of the grammar
+   */
+  reset(): void {
+    this.wasmBatch.reset();
+  }
+
+  /**
+   * Release the batch's WASM memory now; safe to call more than once
+   */
+  free(): void {
+    this._wasmBatch?.free();
+    this._wasmBatch = null;
+  }
+}
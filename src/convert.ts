@@ -0,0 +1,251 @@
+import type {
+  AnyOfGrammar,
+  Grammar,
+  GrammarName,
+  JsonFormatOptions,
+  ParserOptions,
+  SequenceGrammar,
+  XmlChildElement,
+  XmlElement,
+} from './types';
+
+type GrammarEntry = Record<string, unknown>;
+
+/**
+ * Serialize parser options to the JSON the WASM module expects
+ */
+export function convertOptions(options?: ParserOptions): string | undefined {
+  if (!options) {
+    return undefined;
+  }
+  return JSON.stringify({
+    vocab_size_override: options.vocabSizeOverride,
+    exclude_special_tokens: options.excludeSpecialTokens,
+    id_remap: options.idRemap ? Array.from(options.idRemap) : undefined,
+    passthrough_tokens: options.passthroughTokens,
+    trigger_token: options.triggerToken,
+    allowed_bias: options.allowedBias,
+    disallowed_bias: options.disallowedBias,
+    max_rollback_tokens: options.maxRollbackTokens,
+  });
+}
+
+/**
+ * Serialize a grammar for WASM; a registered grammar's name is passed as is
+ */
+export function grammarToJson(grammar: Grammar | GrammarName): string {
+  return typeof grammar === 'string' ? grammar : JSON.stringify(convertGrammar(grammar));
+}
+
+/**
+ * Convert a grammar to the llguidance grammar input format
+ */
+export function convertGrammar(grammar: Grammar): Record<string, unknown> {
+  switch (grammar.type) {
+    case 'json_schema':
+      return {
+        grammars: [
+          {
+            json_schema: grammar.schema,
+            options: convertJsonFormat(grammar.format),
+            format: grammar.output,
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'json_object':
+      return {
+        grammars: [
+          {
+            json_object: true,
+            options: convertJsonFormat(grammar.format),
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'typescript':
+      return {
+        grammars: [
+          {
+            typescript: grammar.source,
+            root_type: grammar.rootType,
+            options: convertJsonFormat(grammar.format),
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'openapi':
+      return {
+        grammars: [
+          {
+            openapi: grammar.document,
+            operation_id: grammar.operationId,
+            target: grammar.target === 'requestBody' ? 'request_body' : grammar.target,
+            status: grammar.status,
+            media_type: grammar.mediaType,
+            options: convertJsonFormat(grammar.format),
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'regex': {
+      const { pattern } = grammar;
+      const rx = typeof pattern === 'string' ? pattern : pattern.source;
+      const flags = typeof pattern === 'string' ? undefined : pattern.flags;
+      return {
+        grammars: [
+          {
+            rx,
+            flags: (grammar.flags ?? flags) || undefined,
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    }
+    case 'lark':
+      return {
+        grammars: [
+          {
+            lark: grammar.grammar,
+            start: grammar.startSymbol ?? 'start',
+            max_tokens: grammar.maxTokens,
+          },
+          ...Object.entries(grammar.subgrammars ?? {}).flatMap(([name, subgrammar]) => {
+            const [first, ...rest] = convertGrammar(nested(subgrammar)).grammars as GrammarEntry[];
+            return [{ ...first, name }, ...rest];
+          }),
+        ],
+        stop: grammar.stop,
+      };
+    case 'gbnf':
+      return {
+        grammars: [
+          {
+            gbnf: grammar.grammar,
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'ebnf':
+      return {
+        grammars: [
+          {
+            ebnf: grammar.grammar,
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'xml':
+      return {
+        grammars: [
+          {
+            xml:
+              grammar.xsd !== undefined
+                ? { xsd: grammar.xsd, root_element: grammar.rootElement }
+                : grammar.element && convertXmlElement(grammar.element),
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'preset':
+      return {
+        grammars: [
+          {
+            preset: grammar.preset,
+            dialect: grammar.dialect,
+            statements: grammar.statements,
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'any_of':
+      return convertComposite('any_of', grammar.anyOf, grammar);
+    case 'sequence':
+      return convertComposite('sequence', grammar.sequence, grammar);
+    case 'text_until':
+      return {
+        grammars: [
+          {
+            text_until: grammar.delimiter,
+            max_tokens: grammar.maxTokens,
+          },
+        ],
+        stop: grammar.stop,
+      };
+    case 'lazy':
+      return {
+        ...convertGrammar(nested(grammar.grammar)),
+        trigger: { start: grammar.trigger, end: grammar.end },
+      };
+  }
+}
+
+/**
+ * A grammar nested in another; the trigger of a lazy grammar only works at the root
+ */
+function nested(grammar: Grammar): Grammar {
+  if (grammar.type === 'lazy') {
+    throw new Error('A lazy grammar cannot be nested in another grammar');
+  }
+  return grammar;
+}
+
+/**
+ * Convert JSON format options to the grammar input's `options`
+ */
+function convertJsonFormat(format?: JsonFormatOptions): Record<string, unknown> | undefined {
+  return (
+    format && {
+      whitespace: format.whitespace,
+      item_separator: format.itemSeparator,
+      key_separator: format.keySeparator,
+      sort_keys: format.sortKeys,
+    }
+  );
+}
+
+/**
+ * Convert an XML element tree to the grammar input's snake_case fields
+ */
+function convertXmlElement(element: XmlElement | XmlChildElement): Record<string, unknown> {
+  const child = element as XmlChildElement;
+  return {
+    name: element.name,
+    attributes: element.attributes,
+    text: element.text,
+    children: element.children?.map(convertXmlElement),
+    min_occurs: child.minOccurs,
+    max_occurs: child.maxOccurs,
+  };
+}
+
+/**
+ * Convert a union or sequence of grammars
+ * Sub-grammars of the parts sit next to the composite, like any others
+ */
+function convertComposite(
+  key: 'any_of' | 'sequence',
+  parts: Grammar[],
+  grammar: AnyOfGrammar | SequenceGrammar,
+): Record<string, unknown> {
+  const converted = parts.map((part) => convertGrammar(nested(part)).grammars as GrammarEntry[]);
+  return {
+    grammars: [
+      {
+        [key]: converted.map(([first]) => first),
+        max_tokens: grammar.maxTokens,
+      },
+      ...converted.flatMap(([, ...nested]) => nested),
+    ],
+    stop: grammar.stop,
+  };
+}
@@ -0,0 +1,107 @@
+import type { CsvColumn, CsvOptions, LarkGrammar } from './types';
+
+/**
+ * Build a grammar for delimited data (CSV, TSV, ...) with the given columns
+ * String fields follow RFC 4180: a field holding the delimiter, a quote or a
+ * line break must be quoted, with quotes inside doubled. Typed and pattern
+ * columns are written unquoted. Without quoting (the default for tabs),
+ * string fields cannot contain the delimiter or line breaks at all
+ * @param columns The columns of each row, in order
+ * @param options Delimiter, header, quoting and number of rows
+ */
+export function csvGrammar(columns: CsvColumn[], options: CsvOptions = {}): LarkGrammar {
+  if (columns.length === 0) {
+    throw new Error('csvGrammar() needs at least one column');
+  }
+  const delimiter = options.delimiter ?? ',';
+  if ([...delimiter].length !== 1 || '"\r\n'.includes(delimiter)) {
+    throw new Error(`Invalid CSV delimiter ${JSON.stringify(delimiter)}`);
+  }
+  const quote = options.quote ?? delimiter !== '\t';
+  const newline = options.lineTerminator ?? '\n';
+  const minRows = options.minRows ?? options.rows ?? 1;
+  const maxRows = options.maxRows ?? options.rows;
+  if (maxRows !== undefined && maxRows < minRows) {
+    throw new Error(`maxRows ${maxRows} is less than minRows ${minRows}`);
+  }
+
+  const excluded = `${escapeClass(delimiter)}\\r\\n${quote ? '"' : ''}`;
+  const terminals = new Map<string, string>();
+  const fields = columns.map((column, i) => {
+    if (column.pattern !== undefined) {
+      terminals.set(`COLUMN_${i}`, `/${column.pattern.replace(/\\?\//g, '\\/')}/`);
+      return `COLUMN_${i}`;
+    }
+    switch (column.type ?? 'string') {
+      case 'string':
+        terminals.set('TEXT', `/[^${excluded}]+/`);
+        if (!quote) {
+          return 'TEXT?';
+        }
+        terminals.set('QUOTED', '/"([^"]|"")*"/');
+        return '(TEXT? | QUOTED)';
+      case 'integer':
+        terminals.set('INTEGER', '/-?[0-9]+/');
+        return 'INTEGER';
+      case 'number':
+        terminals.set('NUMBER', '/-?([0-9]+(\\.[0-9]*)?|\\.[0-9]+)([eE][+-]?[0-9]+)?/');
+        return 'NUMBER';
+      case 'boolean':
+        return '("true" | "false")';
+    }
+  });
+
+  const separator = ` ${JSON.stringify(delimiter)} `;
+  const end = ` ${JSON.stringify(newline)}`;
+  const rules = [
+    `start: ${options.header === false ? '' : 'header '}${repeat('row', minRows, maxRows)}`,
+    `row: ${fields.join(separator)}${end}`,
+  ];
+  if (options.header !== false) {
+    const names = columns.map((column) =>
+      JSON.stringify(headerField(column.name, delimiter, quote)),
+    );
+    rules.push(`header: ${names.join(separator)}${end}`);
+  }
+  for (const [name, regex] of terminals) {
+    rules.push(`${name}: ${regex}`);
+  }
+  return {
+    type: 'lark',
+    grammar: rules.join('\n'),
+    maxTokens: options.maxTokens,
+    stop: options.stop,
+  };
+}
+
+/** A column name as written in the header row */
+function headerField(name: string, delimiter: string, quote: boolean): string {
+  const special = name.includes(delimiter) || /["\r\n]/.test(name);
+  if (!special) {
+    return name;
+  }
+  if (!quote) {
+    throw new Error(`Column name ${JSON.stringify(name)} cannot be written without quoting`);
+  }
+  return `"${name.replace(/"/g, '""')}"`;
+}
+
+/** `item` repeated between `min` and `max` times (unbounded if undefined) */
+function repeat(item: string, min: number, max?: number): string {
+  const parts: string[] = Array(min).fill(item);
+  if (max === undefined) {
+    parts.push(`${item}*`);
+  } else {
+    for (let i = min; i < max; i++) {
+      parts.push(`${item}?`);
+    }
+  }
+  return parts.length === 0 ? '""' : parts.join(' ');
+}
+
+function escapeClass(char: string): string {
+  if (char === '\t') {
+    return '\\t';
+  }
+  return /[\\\]^\-[/]/.test(char) ? `\\${char}` : char;
+}
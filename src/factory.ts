@@ -0,0 +1,82 @@
+import type { Grammar, GrammarName, ParserOptions, TokenizerData } from './types';
+import { convertOptions, grammarToJson } from './convert';
+import { labelErrors } from './label';
+import { GuidanceParser } from './parser';
+
+interface WasmFactory {
+  compile_grammar(grammarJson: string): number;
+  grammar_count(): number;
+  create_parser(grammarRef: number): unknown;
+  free(): void;
+}
+
+/**
+ * GuidanceFactory holds a tokenizer and the grammars compiled against it,
+ * so a server can compile each grammar once and create a cheap parser per
+ * request with createParser().
+ */
+export class GuidanceFactory {
+  private _wasmFactory: WasmFactory | null;
+  private label?: string;
+
+  private constructor(wasmFactory: unknown, label?: string) {
+    // Private constructor - use static create() method
+    this._wasmFactory = labelErrors(wasmFactory as WasmFactory, label);
+    this.label = label;
+  }
+
+  private get wasmFactory(): WasmFactory {
+    if (!this._wasmFactory) {
+      throw new Error('GuidanceFactory used after free()');
+    }
+    return this._wasmFactory;
+  }
+
+  /**
+   * Create a factory for a tokenizer
+   * The token trie is shared with any parser built from identical TokenizerData
+   * @param tokenizer The tokenizer data from transformer.js
+   * @param options Parser options, applied to every parser created
+   */
+  static async create(tokenizer: TokenizerData, options?: ParserOptions): Promise<GuidanceFactory> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    return new GuidanceFactory(
+      new wasm.LLGuidanceFactory(JSON.stringify(tokenizer), convertOptions(options)),
+      options?.label,
+    );
+  }
+
+  /**
+   * Compile a grammar for later use with createParser()
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF),
+   * or a registered grammar's name
+   * @returns A reference to the compiled grammar
+   */
+  compileGrammar(grammar: Grammar | GrammarName): number {
+    return this.wasmFactory.compile_grammar(grammarToJson(grammar));
+  }
+
+  /**
+   * Number of grammars compiled so far
+   */
+  get grammarCount(): number {
+    return this.wasmFactory.grammar_count();
+  }
+
+  /**
+   * Create a parser at the start of a compiled grammar, without recompiling it
+   * @param grammarRef A reference returned by compileGrammar()
+   */
+  createParser(grammarRef: number): GuidanceParser {
+    return GuidanceParser.fromWasm(this.wasmFactory.create_parser(grammarRef), this.label);
+  }
+
+  /**
+   * Release the compiled grammars' WASM memory now; safe to call more than once
+   * Parsers already created keep working and must be freed separately
+   */
+  free(): void {
+    this._wasmFactory?.free();
+    this._wasmFactory = null;
+  }
+}
@@ -1,16 +1,67 @@
 export { GuidanceParser } from './parser';
+export { GuidanceFactory } from './factory';
+export { GuidanceBatch } from './batch';
+export { SpeculativeGuidance } from './speculative';
+export { GuidanceState, type GuidanceStep } from './state';
 export { GuidanceLogitsProcessor } from './processor';
+export { resolveGrammarRefs, resolveSchemaRefs } from './refs';
+export { toolCallGrammar } from './tools';
+export { csvGrammar } from './csv';
 export {
+  createSharedMaskBuffer,
+  sharedMaskGeneration,
+  sharedMaskView,
+  SHARED_MASK_HEADER_BYTES,
+} from './shared-mask';
+export {
+  createCallbackTokenizer,
   extractTokenizerData,
   loadTokenizerData,
   type TransformersTokenizer,
 } from './tokenizer-bridge';
 export type {
   Grammar,
+  GrammarName,
   JsonSchemaGrammar,
+  JsonObjectGrammar,
+  TypeScriptGrammar,
+  OpenApiGrammar,
+  JsonFormatOptions,
   RegexGrammar,
   LarkGrammar,
+  GbnfGrammar,
+  EbnfGrammar,
+  XmlGrammar,
+  XmlElement,
+  XmlChildElement,
+  XmlAttribute,
+  XmlValueType,
+  PresetGrammar,
+  AnyOfGrammar,
+  SequenceGrammar,
+  TextUntilGrammar,
+  LazyGrammar,
+  Tool,
+  ToolCallOptions,
+  CsvColumn,
+  CsvOptions,
+  ParserOptions,
+  RefResolver,
   ProcessorOptions,
+  SamplingOptions,
   TokenizerData,
+  TokenizerConfig,
+  CallbackTokenizer,
+  CompactMask,
+  Diagnostics,
+  AmbiguityReport,
+  GrammarAnalysis,
+  GrammarValidation,
+  MaskStats,
+  ParserTelemetry,
+  ParseTreeNode,
+  StepStats,
+  StopReason,
+  GgufTokenizerData,
 } from './types';
 
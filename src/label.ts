@@ -0,0 +1,43 @@
+/**
+ * Prefix a message with an instance label, so the output of parsers running
+ * side by side can be told apart
+ */
+export function labelMessage(message: string, label?: string): string {
+  return label === undefined ? message : `[${label}] ${message}`;
+}
+
+/**
+ * Wrap a WASM object so that every error its methods throw carries the label
+ * Returns the object itself when there is no label
+ */
+export function labelErrors<T extends object>(target: T, label?: string): T {
+  if (label === undefined) {
+    return target;
+  }
+  return new Proxy(target, {
+    get(obj, prop) {
+      const value: unknown = Reflect.get(obj, prop, obj);
+      if (typeof value !== 'function') {
+        return value;
+      }
+      return (...args: unknown[]) => {
+        try {
+          return value.apply(obj, args);
+        } catch (e) {
+          throw labelError(e, label);
+        }
+      };
+    },
+  });
+}
+
+function labelError(error: unknown, label: string): unknown {
+  // wasm-bindgen throws the Rust error strings as-is
+  if (typeof error === 'string') {
+    return labelMessage(error, label);
+  }
+  if (error instanceof Error) {
+    error.message = labelMessage(error.message, label);
+  }
+  return error;
+}
@@ -1,4 +1,28 @@
-import type { Grammar, TokenizerData } from './types';
+import type {
+  AmbiguityReport,
+  CallbackTokenizer,
+  CompactMask,
+  Diagnostics,
+  GgufTokenizerData,
+  Grammar,
+  GrammarAnalysis,
+  GrammarName,
+  GrammarValidation,
+  MaskStats,
+  ParserOptions,
+  ParserTelemetry,
+  ParseTreeNode,
+  SamplingOptions,
+  StepStats,
+  StopReason,
+  TokenizerData,
+} from './types';
+import { GuidanceBatch } from './batch';
+import { convertOptions, grammarToJson } from './convert';
+import { labelErrors, labelMessage } from './label';
+import { resolveGrammarRefs } from './refs';
+import { SpeculativeGuidance } from './speculative';
+import { publishSharedMask, sharedMaskView } from './shared-mask';
 
 /**
  * GuidanceParser wraps the llguidance WASM module and provides
@@ -7,6 +31,8 @@ import type { Grammar, TokenizerData } from './types';
 export class GuidanceParser {
   private wasmParser: unknown;
   private _isInitialized: boolean = false;
+  private _isFreed: boolean = false;
+  private label?: string;
 
   private constructor() {
     // Private constructor - use static create() method
@@ -14,62 +40,402 @@ export class GuidanceParser {
 
   /**
    * Create a new GuidanceParser instance
-   * @param grammar The grammar definition (JSON Schema, Regex, or Lark)
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
    * @param tokenizer The tokenizer data from transformer.js
+   * @param options Parser options
    */
   static async create(
-    grammar: Grammar,
+    grammar: Grammar | GrammarName,
     tokenizer: TokenizerData,
+    options?: ParserOptions,
   ): Promise<GuidanceParser> {
     const parser = new GuidanceParser();
-    await parser.initialize(grammar, tokenizer);
+    await parser.initialize(grammar, tokenizer, options);
     return parser;
   }
 
-  private async initialize(
+  /**
+   * Create a new GuidanceParser from a tokenizer.json URL
+   * The file is fetched and parsed inside the WASM module, so it is never
+   * materialized as a JS object; a URL already fetched by transformer.js is
+   * usually served from the HTTP cache
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param tokenizerUrl URL of a Hugging Face tokenizer.json
+   * @param options Parser options
+   */
+  static async fromUrl(
+    grammar: Grammar | GrammarName,
+    tokenizerUrl: string,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = await wasm.LLGuidanceParser.from_url(
+      grammarJson,
+      tokenizerUrl,
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Create a new GuidanceParser from a precomputed vocabulary
+   * This bypasses tokenizer.json parsing, for custom tokenizers
+   * @param tokens The byte string of each token, indexed by token ID
+   * @param eosTokenId The end-of-sequence token ID
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param options Parser options
+   */
+  static async fromVocab(
+    tokens: Uint8Array[],
+    eosTokenId: number,
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = wasm.LLGuidanceParser.from_vocab(
+      tokens,
+      eosTokenId,
+      grammarJson,
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Create a new GuidanceParser from a precomputed vocabulary without blocking
+   * The tokens are handed to WASM in chunks, yielding to the event loop between
+   * them, so large vocabularies do not freeze the page while loading
+   * @param tokens The byte string of each token, indexed by token ID
+   * @param eosTokenId The end-of-sequence token ID
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param options Parser options
+   * @param chunkSize Number of tokens passed to WASM per chunk
+   */
+  static async fromVocabChunked(
+    tokens: Uint8Array[],
+    eosTokenId: number,
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+    chunkSize: number = 16384,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const builder = new wasm.TokEnvBuilder();
+    for (let start = 0; start < tokens.length; start += chunkSize) {
+      builder.add_chunk(tokens.slice(start, start + chunkSize));
+      await new Promise((resolve) => setTimeout(resolve, 0));
+    }
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = builder.finish(eosTokenId, grammarJson, convertOptions(options));
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Create a new GuidanceParser backed by a live tokenizer
+   * The WASM module calls back into the tokenizer instead of parsing tokenizer.json
+   * @param tokenizer The callback tokenizer (see createCallbackTokenizer())
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param options Parser options
+   */
+  static async fromTokenizer(
+    tokenizer: CallbackTokenizer,
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = wasm.LLGuidanceParser.from_tokenizer(
+      tokenizer,
+      grammarJson,
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Create a new GuidanceParser from an OpenAI tiktoken vocabulary
+   * @param bpeRanks Contents of the .tiktoken file (base64 token and rank per line)
+   * @param specialTokens Special token contents mapped to their IDs, including EOS
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param options Parser options
+   */
+  static async fromTiktoken(
+    bpeRanks: string,
+    specialTokens: Record<string, number>,
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = wasm.LLGuidanceParser.from_tiktoken(
+      bpeRanks,
+      JSON.stringify(specialTokens),
+      grammarJson,
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Create a new GuidanceParser from the tokenizer metadata of a GGUF file
+   * Useful for llama.cpp-in-WASM runtimes such as wllama
+   * @param metadata The tokenizer.ggml.* values from the GGUF header
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param options Parser options
+   */
+  static async fromGguf(
+    metadata: GgufTokenizerData,
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = wasm.LLGuidanceParser.from_gguf(
+      JSON.stringify(metadata),
+      grammarJson,
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Create a new GuidanceParser from a blob returned by exportTokEnv()
+   * This skips tokenizer parsing, e.g. when the blob is cached in IndexedDB
+   * @param blob The exported tokenizer environment
+   * @param grammar The grammar definition (JSON Schema, Regex, Lark, GBNF, or EBNF)
+   * @param options Parser options
+   */
+  static async fromTokEnvBlob(
+    blob: Uint8Array,
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    parser.wasmParser = wasm.LLGuidanceParser.from_tok_env_blob(
+      blob,
+      grammarJson,
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Convert and check a grammar once, e.g. at build time in Node
+   * The returned blob loads with fromCompiledGrammar() without converting the
+   * grammar again or resolving its $refs; it does not depend on the tokenizer
+   * @param grammar The grammar definition
+   * @param options Only resolveRef is used, to inline external $refs first
+   * @returns The compiled grammar
+   */
+  static async compileGrammar(
+    grammar: Grammar | GrammarName,
+    options?: Pick<ParserOptions, 'resolveRef'>,
+  ): Promise<Uint8Array> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    return wasm.LLGuidanceParser.compile_grammar(grammarJson);
+  }
+
+  /**
+   * Create a new GuidanceParser from a grammar returned by compileGrammar()
+   * @param compiled The compiled grammar
+   * @param tokenizer The tokenizer data from transformer.js
+   * @param options Parser options; resolveRef is not needed
+   */
+  static async fromCompiledGrammar(
+    compiled: Uint8Array,
+    tokenizer: TokenizerData,
+    options?: ParserOptions,
+  ): Promise<GuidanceParser> {
+    const parser = new GuidanceParser();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+
+    parser.wasmParser = wasm.LLGuidanceParser.from_compiled_grammar(
+      compiled,
+      JSON.stringify(tokenizer),
+      convertOptions(options),
+    );
+    parser.finishInit(options?.label);
+    return parser;
+  }
+
+  /**
+   * Wrap a WASM parser created elsewhere in the library, e.g. by GuidanceFactory
+   * @internal
+   */
+  static fromWasm(wasmParser: unknown, label?: string): GuidanceParser {
+    const parser = new GuidanceParser();
+    parser.wasmParser = wasmParser;
+    parser.finishInit(label);
+    return parser;
+  }
+
+  /**
+   * Drop the cached tokenizer environments shared between parsers
+   * Parsers created from identical TokenizerData reuse one compiled token trie;
+   * call this to release that memory once those tokenizers are no longer needed
+   */
+  static async clearTokenizerCache(): Promise<void> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    wasm.clear_tokenizer_cache();
+  }
+
+  /**
+   * Convert a grammar once and keep it in the WASM module under a name
+   * Parsers can then be created or reset with "@name" instead of the grammar,
+   * e.g. so a worker is sent the name rather than the whole schema each time.
+   * The registry belongs to this module instance, so register in the worker
+   * @param name The name, without the leading @
+   * @param grammar The grammar definition
+   * @param options Only resolveRef is used, to inline external $refs first
+   */
+  static async registerGrammar(
+    name: string,
     grammar: Grammar,
+    options?: Pick<ParserOptions, 'resolveRef'>,
+  ): Promise<void> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    wasm.register_grammar(name, await GuidanceParser.grammarJson(grammar, options));
+  }
+
+  /**
+   * Forget a registered grammar; parsers already created from it keep working
+   * @param name The name it was registered under, without the leading @
+   * @returns Whether a grammar was registered under that name
+   */
+  static async unregisterGrammar(name: string): Promise<boolean> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    return wasm.unregister_grammar(name);
+  }
+
+  /**
+   * Get the size of the WASM module's linear memory, shared by all parsers
+   * WASM memory only grows, so this is the high-water mark of the heap
+   * @returns The memory size in bytes
+   */
+  static async wasmMemoryBytes(): Promise<number> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    return wasm.wasm_memory_bytes();
+  }
+
+  /**
+   * Check that a grammar compiles, without a tokenizer or a parser
+   * Meant for linting grammars ahead of time, e.g. in CI; a grammar that
+   * cannot be converted is reported in errors rather than thrown
+   * @param grammar The grammar definition
+   * @param options Only resolveRef is used, to inline external $refs first
+   * @returns Whether the grammar compiled, with its errors and warnings
+   */
+  static async validateGrammar(
+    grammar: Grammar | GrammarName,
+    options?: Pick<ParserOptions, 'resolveRef'>,
+  ): Promise<GrammarValidation> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    let grammarJson: string;
+    try {
+      grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    } catch (error) {
+      const message = error instanceof Error ? error.message : String(error);
+      return { ok: false, errors: [message], warnings: [] };
+    }
+    return JSON.parse(wasm.LLGuidanceParser.validate_grammar(grammarJson)) as GrammarValidation;
+  }
+
+  /**
+   * Estimate how expensive a grammar is to enforce, without compiling it
+   * Use it to turn away user-submitted grammars that would be too slow;
+   * pair it with validateGrammar() to also catch grammars that do not compile
+   * @param grammar The grammar definition
+   * @param options Only resolveRef is used, to inline external $refs first
+   * @returns Rule and lexeme counts, lexer size, hints and a cost class
+   */
+  static async analyzeGrammar(
+    grammar: Grammar | GrammarName,
+    options?: Pick<ParserOptions, 'resolveRef'>,
+  ): Promise<GrammarAnalysis> {
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
+    const report = JSON.parse(
+      wasm.LLGuidanceParser.analyze_grammar(grammarJson),
+    ) as Record<string, unknown>;
+    return {
+      rules: report.rules as number,
+      terminals: report.terminals as number,
+      lexemes: report.lexemes as number,
+      lexerStates: report.lexer_states as number,
+      recursiveRules: report.recursive_rules as string[],
+      hints: report.hints as string[],
+      costClass: report.cost_class as GrammarAnalysis['costClass'],
+    };
+  }
+
+  private async initialize(
+    grammar: Grammar | GrammarName,
     tokenizer: TokenizerData,
+    options?: ParserOptions,
   ): Promise<void> {
     // Dynamic import of WASM module (bundler target auto-initializes via top-level await)
     const wasm = await import('../pkg/llguidance_wasm.js');
 
     // Convert grammar to the format expected by llguidance
-    const grammarJson = JSON.stringify(this.convertGrammar(grammar));
+    const grammarJson = await GuidanceParser.grammarJson(grammar, options);
     const tokenizerJson = JSON.stringify(tokenizer);
 
     // Initialize the WASM parser
-    this.wasmParser = new wasm.LLGuidanceParser(grammarJson, tokenizerJson);
-    this._isInitialized = true;
+    this.wasmParser = new wasm.LLGuidanceParser(
+      grammarJson,
+      tokenizerJson,
+      convertOptions(options),
+    );
+    this.finishInit(options?.label);
   }
 
-  private convertGrammar(grammar: Grammar): Record<string, unknown> {
-    switch (grammar.type) {
-      case 'json_schema':
-        return {
-          grammars: [
-            {
-              json_schema: grammar.schema,
-            },
-          ],
-        };
-      case 'regex':
-        return {
-          grammars: [
-            {
-              rx: grammar.pattern,
-            },
-          ],
-        };
-      case 'lark':
-        return {
-          grammars: [
-            {
-              lark: grammar.grammar,
-              start: grammar.startSymbol ?? 'start',
-            },
-          ],
-        };
-    }
+  /**
+   * Serialize a grammar for WASM, first inlining external $refs when
+   * options.resolveRef is set
+   */
+  private static async grammarJson(
+    grammar: Grammar | GrammarName,
+    options?: ParserOptions,
+  ): Promise<string> {
+    const resolved =
+      options?.resolveRef && typeof grammar !== 'string'
+        ? await resolveGrammarRefs(grammar, options.resolveRef)
+        : grammar;
+    return grammarToJson(resolved);
+  }
+
+  /**
+   * Mark the parser ready once wasmParser is set, labelling its errors
+   */
+  private finishInit(label?: string): void {
+    this.label = label;
+    this.wasmParser = labelErrors(this.wasmParser as object, label);
+    this._isInitialized = true;
   }
 
   /**
@@ -83,14 +449,193 @@ export class GuidanceParser {
     return (this.wasmParser as { is_token_allowed: (id: number) => boolean }).is_token_allowed(tokenId);
   }
 
+  /**
+   * Ban tokens at every position, whatever the grammar allows
+   * (e.g. profanity or raw special tokens). Replaces any previous deny list
+   * @param tokenIds The tokens to ban; pass [] to clear the list
+   */
+  setTokenDenyList(tokenIds: ArrayLike<number>): void {
+    this.ensureInitialized();
+    (
+      this.wasmParser as { set_token_deny_list: (ids: Uint32Array) => void }
+    ).set_token_deny_list(Uint32Array.from(tokenIds));
+  }
+
+  /**
+   * Allow tokens at every position, even where the grammar or the deny list
+   * bans them (e.g. a cancellation token). Advancing over one the grammar
+   * rejects leaves the grammar untouched. Replaces any previous list
+   * @param tokenIds The tokens to allow; pass [] to clear the list
+   */
+  setTokenForceAllowList(tokenIds: ArrayLike<number>): void {
+    this.ensureInitialized();
+    (
+      this.wasmParser as { set_token_force_allow_list: (ids: Uint32Array) => void }
+    ).set_token_force_allow_list(Uint32Array.from(tokenIds));
+  }
+
+  /**
+   * Keep only the candidate tokens the grammar allows
+   * Checks just the given IDs, which is far cheaper than a full-vocabulary mask
+   * @param tokenIds Candidate token IDs, e.g. the model's top-k
+   * @returns The allowed candidates, in their original order
+   */
+  filterCandidates(tokenIds: ArrayLike<number>): Uint32Array {
+    this.ensureInitialized();
+    return (
+      this.wasmParser as { filter_candidates: (ids: Uint32Array) => Uint32Array }
+    ).filter_candidates(Uint32Array.from(tokenIds));
+  }
+
   /**
    * Get the full token mask for the current position
    * This is the slow path used when speculation fails
-   * @returns A Uint8Array where 1 = allowed, 0 = banned
+   * @param invert Mark the banned tokens with 1 instead, for bad_words-style APIs
+   * @returns A Uint8Array where 1 = allowed, 0 = banned (reversed with invert)
+   */
+  getTokenMask(invert?: boolean): Uint8Array {
+    this.ensureInitialized();
+    return (
+      this.wasmParser as { get_token_mask: (invert?: boolean) => Uint8Array }
+    ).get_token_mask(invert);
+  }
+
+  /**
+   * Get the full token mask for the current position once pending work has run
+   * llguidance computes a mask in one uninterrupted pass, so this yields to the
+   * event loop before the computation rather than during it, and the pass
+   * itself still blocks. For grammars that are slow on every step, run the
+   * parser in a Web Worker instead
+   * @returns A Promise for a Uint8Array where 1 = allowed, 0 = banned
+   */
+  async getTokenMaskAsync(): Promise<Uint8Array> {
+    this.ensureInitialized();
+    await new Promise((resolve) => setTimeout(resolve, 0));
+    return this.getTokenMask();
+  }
+
+  /**
+   * Write the token mask for the current position into a caller-owned buffer
+   * Avoids allocating a new mask every decoding step
+   * @param dest Buffer of at least vocabSize bytes; receives 1 = allowed, 0 = banned
+   * @param invert Mark the banned tokens with 1 instead
+   */
+  fillTokenMask(dest: Uint8Array, invert?: boolean): void {
+    this.ensureInitialized();
+    (
+      this.wasmParser as { fill_token_mask: (dest: Uint8Array, invert?: boolean) => void }
+    ).fill_token_mask(dest, invert);
+  }
+
+  /**
+   * Get the mask as whichever ID list is shorter: the allowed tokens when few
+   * are allowed, the disallowed tokens when almost everything is allowed
+   * @returns The ID list tagged with its representation
    */
-  getTokenMask(): Uint8Array {
+  getCompactMask(): CompactMask {
     this.ensureInitialized();
-    return (this.wasmParser as { get_token_mask: () => Uint8Array }).get_token_mask();
+    const [representation, tokenIds] = (
+      this.wasmParser as { get_compact_mask: () => [CompactMask['representation'], Uint32Array] }
+    ).get_compact_mask();
+    return { representation, tokenIds };
+  }
+
+  /**
+   * Get how the mask changed since the previous call
+   * Lets a persistent bias tensor (e.g. on the GPU) be patched instead of
+   * re-uploaded; the first call reports every allowed token as newly allowed
+   * @returns Token IDs that became allowed and that became banned, ascending
+   */
+  getTokenMaskDiff(): { allowed: Uint32Array; disallowed: Uint32Array } {
+    this.ensureInitialized();
+    const [allowed, disallowed] = (
+      this.wasmParser as { get_token_mask_diff: () => [Uint32Array, Uint32Array] }
+    ).get_token_mask_diff();
+    return { allowed, disallowed };
+  }
+
+  /**
+   * Get the IDs of the tokens banned at the current position
+   * Includes padding positions; for runtimes that take a bad_words list
+   * @returns Banned token IDs in ascending order
+   */
+  getDisallowedTokens(): Uint32Array {
+    this.ensureInitialized();
+    return (
+      this.wasmParser as { get_disallowed_tokens: () => Uint32Array }
+    ).get_disallowed_tokens();
+  }
+
+  /**
+   * Write the token mask for the current position into a shared mask slot
+   * and bump its generation counter, for reading from another thread
+   * @param buffer A slot from createSharedMaskBuffer(vocabSize)
+   * @returns The new generation number
+   */
+  writeSharedMask(buffer: SharedArrayBuffer): number {
+    this.fillTokenMask(sharedMaskView(buffer));
+    return publishSharedMask(buffer);
+  }
+
+  /**
+   * Set the logits of disallowed tokens to -Infinity, inside WASM
+   * Logits past the end of the mask (padding) are disallowed too
+   * @param logits The logits for the current position, modified in place
+   */
+  applyMask(logits: Float32Array): void {
+    this.ensureInitialized();
+    (this.wasmParser as { apply_mask: (logits: Float32Array) => void }).apply_mask(logits);
+  }
+
+  /**
+   * Sample a token from the logits among those the grammar allows
+   * Masking, renormalization and sampling all happen in one WASM call.
+   * The parser is not advanced; call advance() with the result
+   * @param logits The logits for the current position
+   * @param options Temperature, top-k and top-p settings
+   * @returns The sampled token ID
+   */
+  sampleToken(logits: Float32Array, options?: SamplingOptions): number {
+    this.ensureInitialized();
+    const optionsJson = options
+      ? JSON.stringify({ temperature: options.temperature, top_k: options.topK, top_p: options.topP })
+      : undefined;
+    return (
+      this.wasmParser as { sample_token: (logits: Float32Array, options?: string) => number }
+    ).sample_token(logits, optionsJson);
+  }
+
+  /**
+   * Get the IDs of the tokens allowed at the current position
+   * Cheaper than a dense mask when the grammar only allows a few tokens
+   * @returns Allowed token IDs in ascending order
+   */
+  getAllowedTokens(): Uint32Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { get_allowed_tokens: () => Uint32Array }).get_allowed_tokens();
+  }
+
+  /**
+   * Get the token mask for the current position packed one bit per token
+   * Moves 8x less data than getTokenMask(), e.g. for uploading to the GPU
+   * @returns A Uint32Array where token i is allowed if bit (i % 32) of word (i / 32) is set
+   */
+  getTokenBitmask(): Uint32Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { get_token_bitmask: () => Uint32Array }).get_token_bitmask();
+  }
+
+  /**
+   * Get an additive logit bias for the current position, shaped like the logits
+   * @param disallowedValue Bias for banned tokens, overriding the disallowedBias option
+   * @returns A Float32Array with the allowed bias for allowed tokens and the
+   *   disallowed bias otherwise (0 and -Infinity unless configured)
+   */
+  computeLogitBias(disallowedValue?: number): Float32Array {
+    this.ensureInitialized();
+    return (
+      this.wasmParser as { compute_logit_bias: (value?: number) => Float32Array }
+    ).compute_logit_bias(disallowedValue);
   }
 
   /**
@@ -102,8 +647,238 @@ export class GuidanceParser {
     (this.wasmParser as { advance: (id: number) => void }).advance(tokenId);
   }
 
+  /**
+   * Check whether advance(tokenId) would succeed, without committing
+   * Lets a sampler probe a few candidates before choosing; to inspect the
+   * state a candidate leads to, advance a fork() instead
+   * @param tokenId The candidate token
+   */
+  tryAdvance(tokenId: number): boolean {
+    this.ensureInitialized();
+    return (this.wasmParser as { try_advance: (id: number) => boolean }).try_advance(tokenId);
+  }
+
+  /**
+   * Advance past a whole sequence of tokens in one WASM call
+   * Use for prefill or externally generated chunks instead of calling advance() per token
+   * Stops at the first rejected token; the tokens before it stay consumed
+   * @param tokenIds The tokens, in order
+   */
+  advanceMany(tokenIds: ArrayLike<number>): void {
+    this.ensureInitialized();
+    (this.wasmParser as { advance_many: (ids: Uint32Array) => void }).advance_many(
+      Uint32Array.from(tokenIds),
+    );
+  }
+
+  /**
+   * Advance through a token sequence up to the first token the grammar rejects,
+   * e.g. to audit output generated without the mask
+   * The parser is left after the accepted prefix
+   * @param tokenIds The tokens, in order
+   * @returns The index of the first rejected token, or -1 if all were accepted
+   */
+  validateTokens(tokenIds: ArrayLike<number>): number {
+    this.ensureInitialized();
+    return (this.wasmParser as { validate_tokens: (ids: Uint32Array) => number }).validate_tokens(
+      Uint32Array.from(tokenIds),
+    );
+  }
+
+  /**
+   * Advance past text, e.g. prompt content the grammar is anchored after,
+   * without tokenizing it exactly as the model would
+   * @param text The text to consume
+   * @returns The tokens the text was split into, one advance() each
+   */
+  consumeText(text: string): Uint32Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { consume_text: (text: string) => Uint32Array }).consume_text(text);
+  }
+
+  /**
+   * Advance past raw bytes; see consumeText()
+   * @param bytes The bytes to consume
+   * @returns The tokens the bytes were split into, one advance() each
+   */
+  consumeBytes(bytes: Uint8Array): Uint32Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { consume_bytes: (bytes: Uint8Array) => Uint32Array }).consume_bytes(
+      bytes,
+    );
+  }
+
+  /**
+   * Un-consume the last tokens, e.g. for rejection sampling or regenerating
+   * the last sentence, without a reset and replay
+   * @param nTokens How many of the most recent advance() calls to undo
+   */
+  rollback(nTokens: number): void {
+    this.ensureInitialized();
+    (this.wasmParser as { rollback: (n: number) => void }).rollback(nTokens);
+  }
+
+  /**
+   * Advance past the selected token and get the mask for the next position
+   * Crosses the WASM boundary once instead of twice (advance + getTokenMask)
+   * @param tokenId The token that was selected
+   * @returns A Uint8Array where 1 = allowed, 0 = banned
+   */
+  step(tokenId: number): Uint8Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { step: (id: number) => Uint8Array }).step(tokenId);
+  }
+
+  /**
+   * Get the tokens the grammar fully determines next (e.g. JSON scaffolding)
+   * The parser is not advanced: append them to the sequence, call advance()
+   * for each, and skip running the model for those positions
+   * @returns The forced token IDs, empty if the next token is a real choice
+   */
+  getForcedTokens(): Uint32Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { get_forced_tokens: () => Uint32Array }).get_forced_tokens();
+  }
+
+  /**
+   * Get the text the grammar fully determines next (quotes, braces, fixed keys)
+   * It can be rendered immediately, before the model "generates" it; unlike
+   * getForcedTokens(), it may end in the middle of a token
+   * @returns The forced UTF-8 bytes, empty if the next byte is a real choice
+   */
+  forcedBytes(): Uint8Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { forced_bytes: () => Uint8Array }).forced_bytes();
+  }
+
+  /**
+   * Get the text the grammar has consumed so far, i.e. what the parser
+   * believes has been generated; passthrough tokens and EOS are left out
+   */
+  consumedText(): string {
+    this.ensureInitialized();
+    return (this.wasmParser as { consumed_text: () => string }).consumed_text();
+  }
+
+  /**
+   * Get the raw bytes the grammar has consumed so far; see consumedText()
+   */
+  consumedBytes(): Uint8Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { consumed_bytes: () => Uint8Array }).consumed_bytes();
+  }
+
+  /**
+   * Get the named captures of the grammar matched so far
+   * Works on completion and mid-generation, so structured fields can be read
+   * without re-parsing the output; a name captured twice keeps its last value
+   * @returns Capture name to captured text
+   */
+  getCaptures(): Map<string, string> {
+    this.ensureInitialized();
+    return new Map(
+      (this.wasmParser as { get_captures: () => [string, string][] }).get_captures(),
+    );
+  }
+
+  /**
+   * Get the derivation tree of the text a Lark grammar has consumed: rule
+   * names and spans, so a generated DSL need not be parsed again
+   * The tokens are replayed on a copy of the grammar, so the cost grows with
+   * the length of the output; call it once generation is complete
+   * @returns The tree under the start rule
+   */
+  getParseTree(): ParseTreeNode {
+    this.ensureInitialized();
+    return JSON.parse(
+      (this.wasmParser as { get_parse_tree: () => string }).get_parse_tree(),
+    ) as ParseTreeNode;
+  }
+
+  /**
+   * Get the temperature the grammar suggests for the next token, from Lark
+   * rule annotations such as `value[temperature=0.8]: ...`
+   * llguidance reports an annotation of 0 as none, so annotate near-greedy
+   * regions with a small value such as 0.01. Cheap to call every step.
+   * @returns The temperature, or undefined where no annotation applies
+   */
+  getTemperature(): number | undefined {
+    this.ensureInitialized();
+    return (this.wasmParser as { get_temperature: () => number | undefined }).get_temperature();
+  }
+
+  /**
+   * Check whether a finished string matches the grammar, e.g. to validate
+   * cached responses with the same grammar used for generation
+   * The parser's own position is not affected. The text is checked against the
+   * grammar the parser was created with, not one pushed with pushGrammar, and
+   * ends at a stop sequence as generation does
+   * @param text The complete string
+   */
+  validateText(text: string): boolean {
+    this.ensureInitialized();
+    return (this.wasmParser as { validate_text: (text: string) => boolean }).validate_text(text);
+  }
+
+  /**
+   * Get what the grammar can accept next, e.g. for editor autocomplete hints
+   * Derived from the mask: the forced text if the grammar fixes it (e.g. a
+   * JSON key), otherwise the distinct characters the allowed tokens start with
+   * @returns Expected literals, e.g. ['"', ',', '}']
+   */
+  expected(): string[] {
+    this.ensureInitialized();
+    return (this.wasmParser as { expected: () => string[] }).expected();
+  }
+
+  /**
+   * Check whether the grammar allows exactly one token now
+   * When true, the model's choice does not matter and the step can be skipped
+   */
+  isForced(): boolean {
+    this.ensureInitialized();
+    return (this.wasmParser as { is_forced: () => boolean }).is_forced();
+  }
+
+  /**
+   * Check whether the grammar now allows only EOS
+   * When true, emit EOS whatever the model's top logit is
+   */
+  mustStop(): boolean {
+    this.ensureInitialized();
+    return (this.wasmParser as { must_stop: () => boolean }).must_stop();
+  }
+
+  /**
+   * Check whether any token is allowed now
+   * When false, the grammar has reached a dead end (or stopped): abort or back
+   * off with rollback() instead of sampling from an all-zero mask
+   */
+  canAdvance(): boolean {
+    this.ensureInitialized();
+    return (this.wasmParser as { can_advance: () => boolean }).can_advance();
+  }
+
+  /**
+   * Check whether the parser has stopped and accepts no further tokens,
+   * either cleanly or after an error; see stopReason()
+   */
+  isStopped(): boolean {
+    this.ensureInitialized();
+    return (this.wasmParser as { is_stopped: () => boolean }).is_stopped();
+  }
+
+  /**
+   * Get why the parser stopped, or 'NotStopped' while generation can continue
+   */
+  stopReason(): StopReason {
+    this.ensureInitialized();
+    return (this.wasmParser as { stop_reason: () => StopReason }).stop_reason();
+  }
+
   /**
    * Check if the current state represents a valid complete parse
+   * Also true while the grammar accepts here but could still continue
    * @returns true if generation can terminate here
    */
   isComplete(): boolean {
@@ -112,19 +887,240 @@ export class GuidanceParser {
   }
 
   /**
-   * Reset the parser to its initial state with a new grammar
-   * Useful for reusing the parser for a new generation
-   * @param grammar Optional new grammar to reset to
+   * Reset the parser to its initial state, optionally with a new grammar
+   * Useful for reusing the parser for a new generation. Without a grammar the
+   * current one is restarted without being compiled again.
+   * @param grammar Optional new grammar to reset to, or a registered grammar's name
    */
-  reset(grammar?: Grammar): void {
+  reset(grammar?: Grammar | GrammarName): void {
     this.ensureInitialized();
-    if (grammar) {
-      const grammarJson = JSON.stringify(this.convertGrammar(grammar));
-      (this.wasmParser as { reset: (json: string) => void }).reset(grammarJson);
-    } else {
-      // Reset with empty string uses the original grammar
-      (this.wasmParser as { reset: (json: string) => void }).reset('');
-    }
+    const grammarJson = grammar ? grammarToJson(grammar) : undefined;
+    (this.wasmParser as { reset: (json?: string) => void }).reset(grammarJson);
+  }
+
+  /**
+   * Switch to another grammar mid-generation until popGrammar(), e.g. a tool
+   * call schema once the model has emitted the tool call opener
+   * The new grammar is enforced from its start; the current one is set aside
+   * where it is. Token positions and consumedText() carry on across switches.
+   * @param grammar The grammar to enforce next, or a registered grammar's name
+   */
+  pushGrammar(grammar: Grammar | GrammarName): void {
+    this.ensureInitialized();
+    (this.wasmParser as { push_grammar: (json: string) => void }).push_grammar(
+      grammarToJson(grammar),
+    );
+  }
+
+  /**
+   * Go back to the grammar set aside by the last pushGrammar(), where it left off
+   * Rolling back past a switch is not supported
+   */
+  popGrammar(): void {
+    this.ensureInitialized();
+    (this.wasmParser as { pop_grammar: () => void }).pop_grammar();
+  }
+
+  /**
+   * Number of grammars pushed with pushGrammar() and not yet popped
+   */
+  get grammarDepth(): number {
+    this.ensureInitialized();
+    return (this.wasmParser as { grammar_depth: () => number }).grammar_depth();
+  }
+
+  /**
+   * Copy this parser at its current position, e.g. to branch a beam
+   * The copy shares the compiled grammar, lexer caches and the mask already
+   * computed here; from now on the two advance independently
+   */
+  fork(): GuidanceParser {
+    this.ensureInitialized();
+    const fork = new GuidanceParser();
+    fork.wasmParser = (this.wasmParser as { fork: () => unknown }).fork();
+    fork.finishInit(this.label);
+    return fork;
+  }
+
+  /**
+   * Duplicate the full parser state, e.g. to keep K beams or explore branches
+   * without replaying the token history; same as fork()
+   */
+  clone(): GuidanceParser {
+    return this.fork();
+  }
+
+  /**
+   * Create a batch of sequences for parallel generation (e.g. num_return_sequences)
+   * Each sequence starts at the beginning of this parser's grammar, with the same options
+   * @param size Number of sequences
+   */
+  async createBatch(size: number): Promise<GuidanceBatch> {
+    this.ensureInitialized();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    return new GuidanceBatch(labelErrors(new wasm.ParserBatch(this.wasmParser, size), this.label));
+  }
+
+  /**
+   * Pair this parser with a draft-model parser for speculative decoding
+   * when the two models use different tokenizers
+   * Both parsers must use the same grammar and be at the same position;
+   * they are copied, so later calls on either do not affect the pair
+   * @param draft A parser for the same grammar, built with the draft tokenizer
+   */
+  async createSpeculative(draft: GuidanceParser): Promise<SpeculativeGuidance> {
+    this.ensureInitialized();
+    draft.ensureInitialized();
+    const wasm = await import('../pkg/llguidance_wasm.js');
+    return new SpeculativeGuidance(
+      labelErrors(new wasm.SpeculativeParser(this.wasmParser, draft.wasmParser), this.label),
+    );
+  }
+
+  /**
+   * Enable token healing at the prompt boundary
+   * When the prompt ends mid-token, the last prompt token is rolled back to
+   * bytes that the first generated token must reproduce, before the grammar
+   * applies. Call before generation, right after create() or reset()
+   * @param lastPromptTokenId The last token of the prompt
+   * @returns true if the token was rolled back and must be removed from the prompt
+   */
+  enableTokenHealing(lastPromptTokenId: number): boolean {
+    this.ensureInitialized();
+    return (
+      this.wasmParser as { enable_token_healing: (id: number) => boolean }
+    ).enable_token_healing(lastPromptTokenId);
+  }
+
+  /**
+   * Snapshot the parser's position, e.g. to persist it across page reloads or
+   * move it between a worker and the main thread
+   * The snapshot records the accepted tokens, so restoring replays them
+   * through the grammar instead of re-running the model
+   * @returns A compact binary snapshot
+   */
+  saveState(): Uint8Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { save_state: () => Uint8Array }).save_state();
+  }
+
+  /**
+   * Restore a position from saveState(), replacing the current one
+   * The parser must use the same grammar, tokenizer, deny and force-allow lists;
+   * grammars pushed with pushGrammar() are dropped
+   * @param state The snapshot
+   */
+  loadState(state: Uint8Array): void {
+    this.ensureInitialized();
+    (this.wasmParser as { load_state: (state: Uint8Array) => void }).load_state(state);
+  }
+
+  /**
+   * Report tokenizer/grammar mismatches at the current position
+   * Use this when a mask unexpectedly comes back all zeros
+   * @returns The diagnostics report
+   */
+  diagnose(): Diagnostics {
+    this.ensureInitialized();
+    const report = JSON.parse(
+      (this.wasmParser as { diagnose: () => string }).diagnose(),
+    ) as Record<string, unknown>;
+    return {
+      vocabSize: report.vocab_size as number,
+      allowedTokens: report.allowed_tokens as number,
+      eosAllowed: report.eos_allowed as boolean,
+      error: report.error as string | null,
+      stopReason: report.stop_reason as StopReason,
+      emptyTokens: report.empty_tokens as number,
+      specialTokens: report.special_tokens as number,
+      unreachableBytes: report.unreachable_bytes as number[],
+      warnings: report.warnings as string[],
+    };
+  }
+
+  /**
+   * Report what makes the current Lark grammar ambiguous: rules that parse
+   * the same text several ways, terminals that match the same text, and the
+   * tokens where such terminals compete
+   * Compiles a small grammar per pair of terminals, so use it while writing
+   * a grammar rather than during generation
+   * @returns The ambiguity report
+   */
+  diagnoseAmbiguity(): AmbiguityReport {
+    this.ensureInitialized();
+    const report = JSON.parse(
+      (this.wasmParser as { diagnose_ambiguity: () => string }).diagnose_ambiguity(),
+    ) as Record<string, unknown>;
+    return {
+      ambiguousRules: report.ambiguous_rules as string[],
+      overlappingTerminals: report.overlapping_terminals as AmbiguityReport['overlappingTerminals'],
+      competingTokens: report.competing_tokens as AmbiguityReport['competingTokens'],
+      truncated: report.truncated as boolean,
+    };
+  }
+
+  /**
+   * Summarize how constrained the current position is
+   * Useful for debugging UIs and for spotting near-dead-ends early
+   * @returns Allowed-token count, coverage and entropy of the current mask
+   */
+  maskStats(): MaskStats {
+    this.ensureInitialized();
+    const stats = JSON.parse(
+      (this.wasmParser as { mask_stats: () => string }).mask_stats(),
+    ) as Record<string, unknown>;
+    return {
+      allowedTokens: stats.allowed_tokens as number,
+      vocabSize: stats.vocab_size as number,
+      coverage: stats.coverage as number,
+      entropyBits: stats.entropy_bits as number,
+      eosAllowed: stats.eos_allowed as boolean,
+    };
+  }
+
+  /**
+   * Get constraint telemetry for the generation so far, e.g. to compare grammars
+   * Counts restart at reset(); a forked parser inherits its parent's counts
+   * @returns Step, forced-step and mask-computation counts, average allowed-set
+   * size, and total mask time
+   */
+  getTelemetry(): ParserTelemetry {
+    this.ensureInitialized();
+    const telemetry = JSON.parse(
+      (this.wasmParser as { get_telemetry: () => string }).get_telemetry(),
+    ) as Record<string, unknown>;
+    return {
+      steps: telemetry.steps as number,
+      forcedSteps: telemetry.forced_steps as number,
+      maskComputations: telemetry.mask_computations as number,
+      averageAllowedTokens: telemetry.average_allowed_tokens as number,
+      maskTimeMs: telemetry.mask_time_ms as number,
+    };
+  }
+
+  /**
+   * Get the timings of the latest mask computation and advance, with
+   * llguidance's Earley and lexer counters for them, to find the steps where
+   * a grammar is slow and why
+   * The counters come from repeating the step on a parser of its own, which
+   * only catches up with the tokens since the last call
+   */
+  lastStepStats(): StepStats {
+    this.ensureInitialized();
+    const stats = JSON.parse(
+      (this.wasmParser as { last_step_stats: () => string }).last_step_stats(),
+    ) as Record<string, unknown>;
+    return {
+      maskTimeUs: stats.mask_time_us as number,
+      allowedTokens: stats.allowed_tokens as number,
+      advanceTimeUs: stats.advance_time_us as number,
+      earleyRows: stats.earley_rows as number,
+      earleyItems: stats.earley_items as number,
+      lexerStates: stats.lexer_states as number,
+      slicesApplied: stats.slices_applied as number,
+      trieNodesWalked: stats.trie_nodes_walked as number,
+      lexerCost: stats.lexer_cost as number,
+    };
   }
 
   /**
@@ -135,7 +1131,110 @@ export class GuidanceParser {
     return (this.wasmParser as { vocab_size: () => number }).vocab_size();
   }
 
+  /**
+   * Export the compiled tokenizer environment as a binary blob
+   * Store it (e.g. in IndexedDB) and pass it to fromTokEnvBlob() on later loads.
+   * Not supported for parsers created with fromTokenizer()
+   * @returns The serialized tokenizer environment
+   */
+  exportTokEnv(): Uint8Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { export_tok_env: () => Uint8Array }).export_tok_env();
+  }
+
+  /**
+   * Warnings llguidance reported while compiling the grammar, such as
+   * unsupported JSON schema keywords or suspicious regexes
+   * Empty if the grammar compiled cleanly; updated by reset()
+   */
+  get grammarWarnings(): string[] {
+    this.ensureInitialized();
+    return (this.wasmParser as { grammar_warnings: () => string[] })
+      .grammar_warnings()
+      .map((warning) => labelMessage(warning, this.label));
+  }
+
+  /**
+   * Get the problems found while reading the tokenizer that did not stop
+   * construction, such as vocabulary entries that could only be converted lossily
+   * @returns The warning messages, empty if the vocabulary was read cleanly
+   */
+  tokenizerWarnings(): string[] {
+    this.ensureInitialized();
+    return (this.wasmParser as { tokenizer_warnings: () => string[] })
+      .tokenizer_warnings()
+      .map((warning) => labelMessage(warning, this.label));
+  }
+
+  /**
+   * Tokenize text with the parser's tokenizer
+   * Useful for pre-filling prompts without a second tokenizer implementation
+   * @param text The text to tokenize
+   * @returns The token IDs
+   */
+  tokenize(text: string): Uint32Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { tokenize: (text: string) => Uint32Array }).tokenize(text);
+  }
+
+  /**
+   * Decode token IDs back to text
+   * Special tokens are rendered as their content
+   * @param ids The token IDs to decode
+   * @returns The decoded text
+   */
+  detokenize(ids: ArrayLike<number>): string {
+    this.ensureInitialized();
+    return (this.wasmParser as { detokenize: (ids: Uint32Array) => string }).detokenize(
+      Uint32Array.from(ids),
+    );
+  }
+
+  /**
+   * Get the raw bytes of a token as seen by the grammar engine
+   * Special tokens start with a 0xFF marker byte
+   * @param tokenId The token ID
+   */
+  tokenBytes(tokenId: number): Uint8Array {
+    this.ensureInitialized();
+    return (this.wasmParser as { token_bytes: (id: number) => Uint8Array }).token_bytes(tokenId);
+  }
+
+  /**
+   * Get a printable representation of a token, for debugging masks
+   * Text tokens are quoted with escapes, special tokens are shown as-is
+   * @param tokenId The token ID
+   */
+  tokenDisplay(tokenId: number): string {
+    this.ensureInitialized();
+    return (this.wasmParser as { token_display: (id: number) => string }).token_display(tokenId);
+  }
+
+  /**
+   * Release the parser's WASM memory now rather than whenever it is garbage
+   * collected. Safe to call more than once; any other method throws afterwards
+   */
+  free(): void {
+    if (this._isFreed || !this._isInitialized) {
+      return;
+    }
+    (this.wasmParser as { free: () => void }).free();
+    this.wasmParser = undefined;
+    this._isInitialized = false;
+    this._isFreed = true;
+  }
+
+  /**
+   * Same as free()
+   */
+  dispose(): void {
+    this.free();
+  }
+
   private ensureInitialized(): void {
+    if (this._isFreed) {
+      throw new Error(labelMessage('GuidanceParser used after free()', this.label));
+    }
     if (!this._isInitialized) {
       throw new Error(
         'GuidanceParser not initialized. Use GuidanceParser.create() to create an instance.',
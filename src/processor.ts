@@ -93,12 +93,23 @@ export class GuidanceLogitsProcessor {
     logits: Float32Array,
     k: number,
   ): Array<{ tokenId: number; logit: number }> {
-    const indexed: Array<{ tokenId: number; logit: number }> = [];
+    // Partial selection: sorting every logit is too slow for 256k vocabularies
+    const top: Array<{ tokenId: number; logit: number }> = [];
     for (let i = 0; i < logits.length; i++) {
-      indexed.push({ tokenId: i, logit: logits[i] });
+      const logit = logits[i];
+      if (top.length === k && !(logit > top[k - 1].logit)) {
+        continue;
+      }
+      let pos = top.length;
+      while (pos > 0 && top[pos - 1].logit < logit) {
+        pos--;
+      }
+      top.splice(pos, 0, { tokenId: i, logit });
+      if (top.length > k) {
+        top.pop();
+      }
     }
-    indexed.sort((a, b) => b.logit - a.logit);
-    return indexed.slice(0, k);
+    return top;
   }
 
   private maskAllExcept(logits: Float32Array, allowedToken: number): Float32Array {
@@ -108,8 +119,9 @@ export class GuidanceLogitsProcessor {
   }
 
   private applyBitmask(logits: Float32Array, mask: Uint8Array): Float32Array {
+    // Positions past the end of the mask (padded logits) are banned too
     for (let i = 0; i < logits.length; i++) {
-      if (mask[i] === 0) {
+      if (!mask[i]) {
         logits[i] = -Infinity;
       }
     }
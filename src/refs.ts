@@ -0,0 +1,134 @@
+import type { Grammar, RefResolver } from './types';
+
+type Schema = Record<string, unknown>;
+
+/**
+ * Inline the external $refs of every JSON schema in a grammar
+ * Each referenced document is fetched once with resolveRef() and copied into
+ * the root schema's $defs, so documents that reference each other (cycles
+ * included) are resolved without looping, and recursive schemas stay recursive
+ * @param grammar The grammar whose JSON schemas reference documents by URI
+ * @param resolveRef Called with each document's absolute URI (without fragment)
+ * @returns The grammar with only local #/$defs references left
+ */
+export async function resolveGrammarRefs(
+  grammar: Grammar,
+  resolveRef: RefResolver,
+): Promise<Grammar> {
+  switch (grammar.type) {
+    case 'json_schema':
+      return { ...grammar, schema: await resolveSchemaRefs(grammar.schema, resolveRef) };
+    case 'lark': {
+      if (!grammar.subgrammars) {
+        return grammar;
+      }
+      const entries = await Promise.all(
+        Object.entries(grammar.subgrammars).map(
+          async ([name, subgrammar]) =>
+            [name, await resolveGrammarRefs(subgrammar, resolveRef)] as const,
+        ),
+      );
+      return { ...grammar, subgrammars: Object.fromEntries(entries) };
+    }
+    case 'any_of':
+      return {
+        ...grammar,
+        anyOf: await Promise.all(grammar.anyOf.map((g) => resolveGrammarRefs(g, resolveRef))),
+      };
+    case 'sequence':
+      return {
+        ...grammar,
+        sequence: await Promise.all(grammar.sequence.map((g) => resolveGrammarRefs(g, resolveRef))),
+      };
+    case 'lazy':
+      return { ...grammar, grammar: await resolveGrammarRefs(grammar.grammar, resolveRef) };
+    default:
+      return grammar;
+  }
+}
+
+/**
+ * Inline the external $refs of a JSON schema; see resolveGrammarRefs()
+ */
+export async function resolveSchemaRefs(schema: Schema, resolveRef: RefResolver): Promise<Schema> {
+  const existingDefs = (schema.$defs ?? {}) as Schema;
+  const keys = new Map<string, string>();
+  const queue: Array<{ uri: string; key: string }> = [];
+
+  const keyFor = (uri: string): string => {
+    let key = keys.get(uri);
+    if (key === undefined) {
+      let n = keys.size;
+      do {
+        key = `external_${n++}`;
+      } while (key in existingDefs);
+      keys.set(uri, key);
+      queue.push({ uri, key });
+    }
+    return key;
+  };
+
+  // Rewrite the refs in a document whose local refs live under `pointer`
+  const rewrite = (node: unknown, base: string | undefined, pointer: string): unknown => {
+    if (Array.isArray(node)) {
+      return node.map((item) => rewrite(item, base, pointer));
+    }
+    if (node === null || typeof node !== 'object') {
+      return node;
+    }
+    const result: Schema = {};
+    for (const [key, value] of Object.entries(node)) {
+      if (key === '$ref' && typeof value === 'string') {
+        result.$ref = rewriteRef(value, base, pointer, keyFor);
+      } else if (key === '$id' && pointer !== '') {
+        // An embedded $id would change the base of the rewritten refs
+        continue;
+      } else {
+        result[key] = rewrite(value, base, pointer);
+      }
+    }
+    return result;
+  };
+
+  const rootBase = typeof schema.$id === 'string' ? schema.$id : undefined;
+  const root = rewrite(schema, rootBase, '') as Schema;
+  if (queue.length === 0) {
+    return schema;
+  }
+
+  const defs: Schema = {};
+  for (let next = queue.shift(); next; next = queue.shift()) {
+    const document = await resolveRef(next.uri);
+    defs[next.key] = rewrite(document, next.uri, `/$defs/${next.key}`);
+  }
+  root.$defs = { ...((root.$defs as Schema | undefined) ?? {}), ...defs };
+  return root;
+}
+
+function rewriteRef(
+  ref: string,
+  base: string | undefined,
+  pointer: string,
+  keyFor: (uri: string) => string,
+): string {
+  if (ref.startsWith('#')) {
+    return pointer === '' ? ref : `#${pointer}${ref.slice(1)}`;
+  }
+  const absolute = resolveUri(ref, base);
+  const hash = absolute.indexOf('#');
+  const uri = hash < 0 ? absolute : absolute.slice(0, hash);
+  const fragment = hash < 0 ? '' : absolute.slice(hash + 1);
+  if (fragment !== '' && !fragment.startsWith('/')) {
+    throw new Error(`Cannot resolve $ref "${ref}": only JSON pointer fragments are supported`);
+  }
+  return `#/$defs/${keyFor(uri)}${fragment}`;
+}
+
+function resolveUri(ref: string, base: string | undefined): string {
+  try {
+    return new URL(ref, base).href;
+  } catch {
+    // No absolute base to resolve against; let resolveRef() interpret it
+    return ref;
+  }
+}
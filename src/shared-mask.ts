@@ -0,0 +1,48 @@
+/**
+ * Shared token-mask slot for worker pipelines.
+ * The parser writes each step's mask into a SharedArrayBuffer and bumps a
+ * generation counter, so another thread can read it without postMessage copies.
+ *
+ * Layout: Int32 generation, Int32 mask length, then one byte per token.
+ */
+
+/** Bytes reserved ahead of the mask for the generation counter and length */
+export const SHARED_MASK_HEADER_BYTES = 8;
+
+/**
+ * Allocate a shared mask slot for a vocabulary of the given size
+ * @param vocabSize Mask length, usually parser.vocabSize
+ */
+export function createSharedMaskBuffer(vocabSize: number): SharedArrayBuffer {
+  const buffer = new SharedArrayBuffer(SHARED_MASK_HEADER_BYTES + vocabSize);
+  new Int32Array(buffer, 0, 2)[1] = vocabSize;
+  return buffer;
+}
+
+/**
+ * Get the mask bytes of a shared mask slot (1 = allowed, 0 = banned)
+ * The view is live: it changes whenever the parser writes a new mask
+ */
+export function sharedMaskView(buffer: SharedArrayBuffer): Uint8Array {
+  const length = Atomics.load(new Int32Array(buffer, 0, 2), 1);
+  return new Uint8Array(buffer, SHARED_MASK_HEADER_BYTES, length);
+}
+
+/**
+ * Get the generation of the mask currently in the slot (0 = never written)
+ */
+export function sharedMaskGeneration(buffer: SharedArrayBuffer): number {
+  return Atomics.load(new Int32Array(buffer, 0, 2), 0);
+}
+
+/**
+ * Publish a new generation after the mask bytes have been written,
+ * waking any thread blocked in Atomics.wait on the counter
+ * @internal
+ */
+export function publishSharedMask(buffer: SharedArrayBuffer): number {
+  const header = new Int32Array(buffer, 0, 2);
+  const generation = Atomics.add(header, 0, 1) + 1;
+  Atomics.notify(header, 0);
+  return generation;
+}
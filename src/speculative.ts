@@ -0,0 +1,115 @@
+interface WasmSpeculativeParser {
+  target_vocab_size(): number;
+  draft_vocab_size(): number;
+  target_mask(): Uint8Array;
+  draft_mask(): Uint8Array;
+  advance_draft(draftTokenId: number): void;
+  draft_as_target_tokens(): Uint32Array;
+  commit(targetTokenIds: Uint32Array): void;
+  reject_draft(): void;
+  is_complete(): boolean;
+  free(): void;
+}
+
+/**
+ * SpeculativeGuidance keeps grammar constraints across speculative decoding
+ * when the draft and target models use different tokenizers.
+ * Create one with GuidanceParser.createSpeculative().
+ *
+ * Each round: mask and advance the draft model with draftMask() and
+ * advanceDraft(), verify draftAsTargetTokens() with the target model, then
+ * commit() the target tokens it accepted.
+ */
+export class SpeculativeGuidance {
+  private _wasmSpeculative: WasmSpeculativeParser | null;
+
+  constructor(wasmSpeculative: unknown) {
+    // Use GuidanceParser.createSpeculative() rather than calling this directly
+    this._wasmSpeculative = wasmSpeculative as WasmSpeculativeParser;
+  }
+
+  private get wasmSpeculative(): WasmSpeculativeParser {
+    if (!this._wasmSpeculative) {
+      throw new Error('SpeculativeGuidance used after free()');
+    }
+    return this._wasmSpeculative;
+  }
+
+  /**
+   * Length of the target model's mask
+   */
+  get targetVocabSize(): number {
+    return this.wasmSpeculative.target_vocab_size();
+  }
+
+  /**
+   * Length of the draft model's mask
+   */
+  get draftVocabSize(): number {
+    return this.wasmSpeculative.draft_vocab_size();
+  }
+
+  /**
+   * Get the target-vocabulary mask at the committed text
+   * @returns A Uint8Array where 1 = allowed, 0 = banned
+   */
+  targetMask(): Uint8Array {
+    return this.wasmSpeculative.target_mask();
+  }
+
+  /**
+   * Get the draft-vocabulary mask after the tokens drafted so far
+   * @returns A Uint8Array where 1 = allowed, 0 = banned
+   */
+  draftMask(): Uint8Array {
+    return this.wasmSpeculative.draft_mask();
+  }
+
+  /**
+   * Extend the proposal by a token sampled from the draft model
+   * @param draftTokenId A token ID in the draft vocabulary
+   */
+  advanceDraft(draftTokenId: number): void {
+    this.wasmSpeculative.advance_draft(draftTokenId);
+  }
+
+  /**
+   * Get the proposal re-tokenized for the target model, truncated to the
+   * longest prefix the grammar accepts
+   * @returns Target-vocabulary token IDs for the target model to verify
+   */
+  draftAsTargetTokens(): Uint32Array {
+    return this.wasmSpeculative.draft_as_target_tokens();
+  }
+
+  /**
+   * Accept target tokens, moving both vocabularies to the new text and
+   * discarding the rest of the proposal
+   * @param targetTokenIds The target tokens the target model accepted
+   */
+  commit(targetTokenIds: ArrayLike<number>): void {
+    this.wasmSpeculative.commit(Uint32Array.from(targetTokenIds));
+  }
+
+  /**
+   * Discard the proposal without committing anything
+   */
+  rejectDraft(): void {
+    this.wasmSpeculative.reject_draft();
+  }
+
+  /**
+   * Check whether the committed text is a valid complete parse
+   */
+  isComplete(): boolean {
+    return this.wasmSpeculative.is_complete();
+  }
+
+  /**
+   * Release the WASM memory of both parsers now; safe to call more than once
+   */
+  free(): void {
+    this._wasmSpeculative?.free();
+    this._wasmSpeculative = null;
+  }
+}
@@ -0,0 +1,79 @@
+import type { GuidanceParser } from './parser';
+
+/**
+ * Result of GuidanceState.step(): the new state and its mask
+ */
+export interface GuidanceStep {
+  state: GuidanceState;
+  mask: Uint8Array;
+}
+
+/**
+ * GuidanceState is an immutable handle on a parser position, for Redux-style
+ * state management and time-travel debugging. Stepping returns a new state
+ * and leaves the old one untouched, so any earlier state can be resumed.
+ * Create one with GuidanceState.initial().
+ */
+export class GuidanceState {
+  private parser: GuidanceParser;
+
+  /**
+   * Mask for the position, where 1 = allowed, 0 = banned
+   */
+  readonly mask: Uint8Array;
+
+  private constructor(parser: GuidanceParser) {
+    // Private constructor - use static initial() method
+    this.parser = parser;
+    this.mask = parser.getTokenMask();
+  }
+
+  /**
+   * Capture a parser's current position as a state
+   * The parser is copied, so later calls on it do not affect the state
+   * @param parser The parser to start from
+   */
+  static initial(parser: GuidanceParser): GuidanceState {
+    return new GuidanceState(parser.fork());
+  }
+
+  /**
+   * Get the state after a token, leaving this state unchanged
+   * The new state shares the compiled grammar and lexer caches with this one
+   * @param tokenId The token that was selected
+   * @returns The new state and its mask
+   */
+  step(tokenId: number): GuidanceStep {
+    const next = this.parser.fork();
+    try {
+      next.advance(tokenId);
+    } catch (e) {
+      next.free();
+      throw e;
+    }
+    const state = new GuidanceState(next);
+    return { state, mask: state.mask };
+  }
+
+  /**
+   * Check if the state is a valid complete parse
+   */
+  isComplete(): boolean {
+    return this.parser.isComplete();
+  }
+
+  /**
+   * Get the text accepted up to this state
+   */
+  consumedText(): string {
+    return this.parser.consumedText();
+  }
+
+  /**
+   * Release the state's WASM memory now; safe to call more than once
+   * Other states, including ones stepped from this one, are unaffected
+   */
+  free(): void {
+    this.parser.free();
+  }
+}
@@ -3,7 +3,7 @@
  * to the format expected by llguidance WASM module.
  */
 
-import type { TokenizerData } from './types';
+import type { CallbackTokenizer, TokenizerConfig, TokenizerData } from './types';
 
 /**
  * Represents a transformer.js tokenizer instance.
@@ -15,6 +15,10 @@ export interface TransformersTokenizer {
     vocab?: Map<string, number> | Record<string, number>;
     tokens_to_ids?: Map<string, number> | Record<string, number>;
     merges?: string[];
+    /** Piece scores for Unigram tokenizers */
+    scores?: number[];
+    /** Continuation prefix for WordPiece tokenizers */
+    continuing_subword_prefix?: string;
   };
   /** Direct vocab access for some tokenizer types */
   vocab?: Map<string, number> | Record<string, number>;
@@ -30,8 +34,10 @@ export interface TransformersTokenizer {
   }>;
   /** Get vocabulary method */
   getVocab?: () => Record<string, number>;
-  /** Encode method for testing */
-  encode?: (text: string) => number[];
+  /** Encode method */
+  encode?: (text: string, options?: { add_special_tokens?: boolean }) => number[];
+  /** Decode method */
+  decode?: (ids: number[], options?: { skip_special_tokens?: boolean }) => string;
   /** Special token IDs */
   eos_token_id?: number;
   bos_token_id?: number;
@@ -62,31 +68,14 @@ export interface TransformersTokenizer {
 export function extractTokenizerData(
   tokenizer: TransformersTokenizer,
 ): TokenizerData {
-  // Try to get vocabulary from various sources
-  let vocab: Record<string, number>;
-
-  if (tokenizer.getVocab) {
-    // Preferred method - direct vocab access
-    vocab = tokenizer.getVocab();
-  } else if (tokenizer.model?.tokens_to_ids) {
-    // transformer.js stores vocab as tokens_to_ids Map
-    vocab = mapToRecord(tokenizer.model.tokens_to_ids);
-  } else if (tokenizer.model?.vocab) {
-    // Some tokenizers store vocab in model
-    vocab = mapToRecord(tokenizer.model.vocab);
-  } else if (tokenizer.vocab) {
-    // Direct vocab property
-    vocab = mapToRecord(tokenizer.vocab);
-  } else {
-    throw new Error(
-      'Unable to extract vocabulary from tokenizer. ' +
-        'Ensure you are passing a valid transformer.js tokenizer instance.',
-    );
-  }
+  const vocab = extractVocab(tokenizer);
 
   // Get merges if available (for BPE tokenizers)
   const merges = tokenizer.model?.merges ?? [];
 
+  // Get piece scores if available (for Unigram tokenizers)
+  const scores = tokenizer.model?.scores;
+
   // Get added tokens (special tokens)
   const added_tokens = (tokenizer.added_tokens ?? []).map((token) => ({
     id: token.id,
@@ -107,8 +96,10 @@ export function extractTokenizerData(
   return {
     vocab,
     merges,
+    scores,
     added_tokens,
-    model_type: detectModelType(tokenizer),
+    model_type: detectModelType(tokenizer, vocab),
+    continuing_subword_prefix: tokenizer.model?.continuing_subword_prefix,
     eos_token_id,
     bos_token_id,
     pad_token_id,
@@ -116,6 +107,74 @@ export function extractTokenizerData(
   };
 }
 
+/**
+ * Adapt a transformer.js tokenizer instance for use as a live callback tokenizer,
+ * so the vocabulary does not have to be serialized and shipped to WASM.
+ *
+ * @param tokenizer The transformer.js tokenizer instance
+ * @returns A CallbackTokenizer to pass to GuidanceParser.fromTokenizer()
+ *
+ * @example
+ * ```typescript
+ * const tokenizer = await AutoTokenizer.from_pretrained('gpt2');
+ * const parser = await GuidanceParser.fromTokenizer(
+ *   createCallbackTokenizer(tokenizer),
+ *   grammar,
+ * );
+ * ```
+ */
+export function createCallbackTokenizer(
+  tokenizer: TransformersTokenizer,
+): CallbackTokenizer {
+  const { encode, decode } = tokenizer;
+  if (!encode || !decode) {
+    throw new Error('Tokenizer must provide encode() and decode() methods');
+  }
+
+  const vocab = extractVocab(tokenizer);
+  const vocabSize = Object.values(vocab).reduce((max, id) => Math.max(max, id), -1) + 1;
+
+  const eosTokenId = extractSpecialTokenId(tokenizer, vocab, 'eos');
+  if (eosTokenId === undefined) {
+    throw new Error('Unable to determine the EOS token ID of the tokenizer');
+  }
+
+  const specialTokenIds = (tokenizer.added_tokens ?? [])
+    .filter((token) => token.special)
+    .map((token) => token.id);
+
+  return {
+    encode: (text) => encode.call(tokenizer, text, { add_special_tokens: false }),
+    decode: (ids) => decode.call(tokenizer, ids, { skip_special_tokens: false }),
+    vocabSize,
+    eosTokenId,
+    specialTokenIds,
+  };
+}
+
+/**
+ * Get the vocabulary from the various places tokenizers store it
+ */
+function extractVocab(tokenizer: TransformersTokenizer): Record<string, number> {
+  if (tokenizer.getVocab) {
+    // Preferred method - direct vocab access
+    return tokenizer.getVocab();
+  } else if (tokenizer.model?.tokens_to_ids) {
+    // transformer.js stores vocab as tokens_to_ids Map
+    return mapToRecord(tokenizer.model.tokens_to_ids);
+  } else if (tokenizer.model?.vocab) {
+    // Some tokenizers store vocab in model
+    return mapToRecord(tokenizer.model.vocab);
+  } else if (tokenizer.vocab) {
+    // Direct vocab property
+    return mapToRecord(tokenizer.vocab);
+  }
+  throw new Error(
+    'Unable to extract vocabulary from tokenizer. ' +
+      'Ensure you are passing a valid transformer.js tokenizer instance.',
+  );
+}
+
 /**
  * Extract a special token ID from the tokenizer
  */
@@ -199,18 +258,31 @@ function mapToRecord(
 /**
  * Try to detect the tokenizer model type
  */
-function detectModelType(tokenizer: TransformersTokenizer): string {
+function detectModelType(
+  tokenizer: TransformersTokenizer,
+  vocab: Record<string, number>,
+): string {
   // Check if it's a BPE tokenizer (has merges)
   if (tokenizer.model?.merges && tokenizer.model.merges.length > 0) {
     return 'bpe';
   }
+  // Check if it's a Unigram tokenizer (has piece scores)
+  if (tokenizer.model?.scores && tokenizer.model.scores.length > 0) {
+    return 'unigram';
+  }
+  // Check if it's a WordPiece tokenizer (has continuation pieces)
+  const prefix = tokenizer.model?.continuing_subword_prefix ?? '##';
+  if (Object.keys(vocab).some((token) => token.startsWith(prefix))) {
+    return 'wordpiece';
+  }
   // Default to unknown
   return 'unknown';
 }
 
 /**
  * Load tokenizer data from a HuggingFace model ID.
- * This fetches the tokenizer.json file directly.
+ * This fetches the tokenizer.json file directly, along with
+ * tokenizer_config.json (when present) to identify the special tokens.
  *
  * @param modelId The HuggingFace model ID (e.g., 'gpt2', 'meta-llama/Llama-2-7b')
  * @param options Optional configuration
@@ -248,7 +320,18 @@ export async function loadTokenizerData(
   }
 
   const tokenizerJson = await response.json();
-  return parseTokenizerJson(tokenizerJson);
+  const data = parseTokenizerJson(tokenizerJson);
+
+  // tokenizer_config.json is optional; without it special tokens are guessed
+  const configResponse = await fetch(
+    `${baseUrl}/${modelId}/resolve/main/tokenizer_config.json`,
+    { headers },
+  );
+  if (configResponse.ok) {
+    data.tokenizer_config = (await configResponse.json()) as TokenizerConfig;
+  }
+
+  return data;
 }
 
 /**
@@ -257,9 +340,11 @@ export async function loadTokenizerData(
 function parseTokenizerJson(json: unknown): TokenizerData {
   const data = json as {
     model?: {
-      vocab?: Record<string, number>;
+      /** BPE/WordPiece use a map, Unigram uses [piece, score] pairs */
+      vocab?: Record<string, number> | Array<[string, number]>;
       merges?: string[];
       type?: string;
+      continuing_subword_prefix?: string;
     };
     added_tokens?: Array<{
       id: number;
@@ -276,7 +361,20 @@ function parseTokenizerJson(json: unknown): TokenizerData {
     throw new Error('Invalid tokenizer.json: missing model.vocab');
   }
 
-  const vocab = data.model.vocab;
+  // Unigram vocabularies are [piece, score] pairs indexed by token ID
+  let vocab: Record<string, number>;
+  let scores: number[] | undefined;
+  if (Array.isArray(data.model.vocab)) {
+    vocab = {};
+    scores = [];
+    for (const [id, [piece, score]] of data.model.vocab.entries()) {
+      vocab[piece] = id;
+      scores.push(score);
+    }
+  } else {
+    vocab = data.model.vocab;
+  }
+
   const added_tokens = data.added_tokens?.map((token) => ({
     id: token.id,
     content: token.content,
@@ -358,8 +456,10 @@ function parseTokenizerJson(json: unknown): TokenizerData {
   return {
     vocab,
     merges: data.model.merges ?? [],
+    scores,
     added_tokens,
     model_type: data.model.type ?? 'unknown',
+    continuing_subword_prefix: data.model.continuing_subword_prefix,
     eos_token_id,
     bos_token_id,
     pad_token_id,
@@ -0,0 +1,82 @@
+import type { Grammar, JsonSchemaGrammar, Tool, ToolCallOptions } from './types';
+
+type Schema = Record<string, unknown>;
+
+/**
+ * Build a grammar for one call of any of the given tools
+ * The output is {"name": <tool name>, "arguments": <its parameters>}, where the
+ * arguments must match the schema of the named tool. Each tool's parameters
+ * are moved under the schema's $defs, keyed by tool name, with their local
+ * $refs rewritten to match
+ * @param tools Tools in the OpenAI chat completions format
+ * @param options Tags around the call, JSON layout and generation limits
+ */
+export function toolCallGrammar(tools: Tool[], options: ToolCallOptions = {}): Grammar {
+  if (tools.length === 0) {
+    throw new Error('toolCallGrammar() needs at least one tool');
+  }
+
+  const defs: Schema = {};
+  const calls = tools.map((tool) => {
+    const { name, parameters } = tool.function;
+    if (name in defs) {
+      throw new Error(`Tool ${name} is defined more than once`);
+    }
+    defs[name] = rewriteRefs(
+      parameters ?? { type: 'object', properties: {}, additionalProperties: false },
+      `/$defs/${name}`,
+    );
+    return {
+      type: 'object',
+      properties: {
+        name: { const: name },
+        arguments: { $ref: `#/$defs/${name}` },
+      },
+      required: ['name', 'arguments'],
+      additionalProperties: false,
+    };
+  });
+
+  const call: JsonSchemaGrammar = {
+    type: 'json_schema',
+    schema: { ...(calls.length === 1 ? calls[0] : { anyOf: calls }), $defs: defs },
+    format: options.format,
+  };
+  if (options.start === undefined && options.end === undefined) {
+    return { ...call, maxTokens: options.maxTokens, stop: options.stop };
+  }
+
+  const sequence: Grammar[] = [call];
+  if (options.start) {
+    sequence.unshift(literal(options.start));
+  }
+  if (options.end) {
+    sequence.push(literal(options.end));
+  }
+  return { type: 'sequence', sequence, maxTokens: options.maxTokens, stop: options.stop };
+}
+
+/** A grammar matching exactly `text` */
+function literal(text: string): Grammar {
+  // Lark string literals take JSON string escapes
+  return { type: 'lark', grammar: `start: ${JSON.stringify(text)}` };
+}
+
+/** Point the local $refs of a schema moved to `pointer` at their new location */
+function rewriteRefs(node: unknown, pointer: string): unknown {
+  if (Array.isArray(node)) {
+    return node.map((item) => rewriteRefs(item, pointer));
+  }
+  if (node === null || typeof node !== 'object') {
+    return node;
+  }
+  const result: Schema = {};
+  for (const [key, value] of Object.entries(node)) {
+    if (key === '$ref' && typeof value === 'string' && value.startsWith('#')) {
+      result.$ref = `#${pointer}${value.slice(1)}`;
+    } else {
+      result[key] = rewriteRefs(value, pointer);
+    }
+  }
+  return result;
+}
@@ -4,6 +4,89 @@
 export interface JsonSchemaGrammar {
   type: 'json_schema';
   schema: Record<string, unknown>;
+  /** How the generated JSON is laid out */
+  format?: JsonFormatOptions;
+  /** Generate block-style YAML matching the schema instead of JSON */
+  output?: 'json' | 'yaml';
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Any JSON object, without a schema, like OpenAI's
+ * `response_format: { type: 'json_object' }`
+ */
+export interface JsonObjectGrammar {
+  type: 'json_object';
+  /** How the generated JSON is laid out */
+  format?: JsonFormatOptions;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * JSON matching a TypeScript type, e.g. 'interface Foo { name: string; age: number }'
+ * Interfaces and type aliases describing JSON are supported: primitives,
+ * literals, object types, arrays, tuples, unions, Array<T>, Record<string, T>,
+ * extends and references between declarations
+ */
+export interface TypeScriptGrammar {
+  type: 'typescript';
+  source: string;
+  /** The declaration to generate; defaults to the first one */
+  rootType?: string;
+  /** How the generated JSON is laid out */
+  format?: JsonFormatOptions;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * JSON matching the response or request body of an OpenAPI 3 operation
+ * References to components.schemas are kept, OpenAPI 3.0 nullable is
+ * honoured, and readOnly/writeOnly properties are left out where the API
+ * does not accept or return them
+ */
+export interface OpenApiGrammar {
+  type: 'openapi';
+  /** The parsed OpenAPI document */
+  document: Record<string, unknown>;
+  operationId: string;
+  /** Which payload to generate; defaults to 'response' */
+  target?: 'response' | 'requestBody';
+  /** Response status code, e.g. '200'; defaults to the first 2XX response */
+  status?: string;
+  /** Media type of the content; defaults to a JSON media type */
+  mediaType?: string;
+  /** How the generated JSON is laid out */
+  format?: JsonFormatOptions;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Layout of the JSON generated for a JSON schema grammar
+ */
+export interface JsonFormatOptions {
+  /**
+   * 'flexible' (default) allows any whitespace JSON allows, 'compact' none, and
+   * 'spaced' a single space after each separator; the last two are single-line
+   */
+  whitespace?: 'flexible' | 'compact' | 'spaced';
+  /** Separator between array items and object members, overriding whitespace */
+  itemSeparator?: string;
+  /** Separator between an object key and its value, overriding whitespace */
+  keySeparator?: string;
+  /** Emit object keys in sorted order rather than schema order */
+  sortKeys?: boolean;
 }
 
 /**
@@ -11,7 +94,17 @@ export interface JsonSchemaGrammar {
  */
 export interface RegexGrammar {
   type: 'regex';
-  pattern: string;
+  /**
+   * A JavaScript regex, matched against the whole output. ^ and $ at the ends
+   * are optional; lookaround, backreferences and word boundaries are rejected
+   */
+  pattern: string | RegExp;
+  /** Regex flags, overriding those of a RegExp pattern; only i and s matter */
+  flags?: string;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
 }
 
 /**
@@ -21,12 +114,264 @@ export interface LarkGrammar {
   type: 'lark';
   grammar: string;
   startSymbol?: string;
+  /** Grammars the Lark grammar references by name, as @name */
+  subgrammars?: Record<string, Grammar>;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * GBNF grammar definition, the format of llama.cpp grammars
+ * The grammar starts at its `root` rule
+ */
+export interface GbnfGrammar {
+  type: 'gbnf';
+  grammar: string;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * EBNF grammar definition, in ISO 14977 or W3C style
+ * The grammar starts at its first rule; `[...]` is optional and `{...}` repeated
+ */
+export interface EbnfGrammar {
+  type: 'ebnf';
+  grammar: string;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Well-formed XML, described by either an element tree or an XSD schema
+ * Text and attribute values are escaped as XML requires
+ */
+export interface XmlGrammar {
+  type: 'xml';
+  /** The root element, when not using an XSD */
+  element?: XmlElement;
+  /**
+   * An XSD schema; elements, named and anonymous types, sequence, choice,
+   * attributes, enumerations and builtin simple types are supported
+   */
+  xsd?: string;
+  /** The global XSD element to generate; defaults to the first one */
+  rootElement?: string;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * An XML element; one without text or children is written as <name/>
+ */
+export interface XmlElement {
+  name: string;
+  attributes?: XmlAttribute[];
+  /** Type of the text content */
+  text?: XmlValueType;
+  /** Child elements, in order */
+  children?: XmlChildElement[];
+}
+
+export interface XmlChildElement extends XmlElement {
+  /** Defaults to 1 */
+  minOccurs?: number;
+  /** Defaults to 1 */
+  maxOccurs?: number | 'unbounded';
+}
+
+export interface XmlAttribute {
+  name: string;
+  /** Defaults to 'string' */
+  type?: XmlValueType;
+  required?: boolean;
+}
+
+export type XmlValueType = 'string' | 'integer' | 'number' | 'boolean' | { enum: string[] };
+
+/**
+ * Output matching any one of several grammars, e.g. a JSON tool call or a
+ * plain-text refusal
+ */
+export interface AnyOfGrammar {
+  type: 'any_of';
+  anyOf: Grammar[];
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Output matching each grammar in turn, e.g. free-text reasoning, then JSON,
+ * then a closing tag; the parser moves to the next stage by itself
+ */
+export interface SequenceGrammar {
+  type: 'sequence';
+  sequence: Grammar[];
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Free text up to and including the first occurrence of a delimiter,
+ * e.g. a reasoning stage ending in '</think>'
+ */
+export interface TextUntilGrammar {
+  type: 'text_until';
+  delimiter: string;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * A built-in grammar
+ * The 'sql' preset is read-only SQL: SELECT queries with joins, subqueries,
+ * common table expressions, compound selects, grouping, ordering and limits,
+ * and optionally EXPLAIN
+ */
+export interface PresetGrammar {
+  type: 'preset';
+  preset: 'sql';
+  /** SQL dialect; defaults to 'ansi' */
+  dialect?: 'ansi' | 'sqlite' | 'postgres' | 'mysql';
+  /** Statements to allow; defaults to ['select'] */
+  statements?: Array<'select' | 'explain'>;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * A grammar enforced only between a trigger and an end tag, e.g. tool calls
+ * between '<tool_call>' and '</tool_call>' in Hermes/Qwen chat output
+ * Until the trigger appears the output is free text; the grammar then starts
+ * (whitespace before and after it is allowed), and after the end tag the text
+ * is free again until the next trigger. Without an end, generation ends with
+ * the grammar. The trigger is matched as text, not as a special token.
+ * A lazy grammar must be the root grammar; it cannot be nested in another
+ */
+export interface LazyGrammar {
+  type: 'lazy';
+  trigger: string;
+  end?: string;
+  grammar: Grammar;
 }
 
 /**
  * Union of all supported grammar types
  */
-export type Grammar = JsonSchemaGrammar | RegexGrammar | LarkGrammar;
+export type Grammar =
+  | JsonSchemaGrammar
+  | JsonObjectGrammar
+  | TypeScriptGrammar
+  | OpenApiGrammar
+  | RegexGrammar
+  | LarkGrammar
+  | GbnfGrammar
+  | EbnfGrammar
+  | XmlGrammar
+  | PresetGrammar
+  | AnyOfGrammar
+  | SequenceGrammar
+  | TextUntilGrammar
+  | LazyGrammar;
+
+/**
+ * A grammar registered with GuidanceParser.registerGrammar(), by name
+ * Accepted wherever a parser is created or reset; only the name crosses into
+ * WASM, so sending it to a worker is cheap
+ */
+export type GrammarName = `@${string}`;
+
+/**
+ * A tool in the OpenAI chat completions format
+ */
+export interface Tool {
+  type: 'function';
+  function: {
+    name: string;
+    description?: string;
+    /** JSON schema of the arguments; a tool without one takes no arguments */
+    parameters?: Record<string, unknown>;
+  };
+}
+
+/**
+ * A column for csvGrammar()
+ */
+export interface CsvColumn {
+  name: string;
+  /** Type of the values; defaults to 'string' */
+  type?: 'string' | 'integer' | 'number' | 'boolean';
+  /** A regex the values must match instead, written unquoted */
+  pattern?: string;
+}
+
+/**
+ * Options for csvGrammar()
+ */
+export interface CsvOptions {
+  /** Field separator, a single character; defaults to ',' */
+  delimiter?: string;
+  /** Quote string fields as RFC 4180 requires; defaults to true except for tabs */
+  quote?: boolean;
+  /** Start with a row of column names; defaults to true */
+  header?: boolean;
+  /** Exact number of data rows */
+  rows?: number;
+  /** Fewest data rows; defaults to `rows` or 1 */
+  minRows?: number;
+  /** Most data rows; defaults to `rows` or unlimited */
+  maxRows?: number;
+  /** Defaults to '\n' */
+  lineTerminator?: '\n' | '\r\n';
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Options for toolCallGrammar()
+ */
+export interface ToolCallOptions {
+  /** Text before the call, e.g. '<tool_call>\n' */
+  start?: string;
+  /** Text after the call, e.g. '\n</tool_call>' */
+  end?: string;
+  /** How the generated JSON is laid out */
+  format?: JsonFormatOptions;
+  /** Stop after this many tokens; stopReason() then reports 'MaxTokens' */
+  maxTokens?: number;
+  /** Strings that end generation wherever they appear, even inside free text */
+  stop?: string[];
+}
+
+/**
+ * Special token settings from a HuggingFace tokenizer_config.json
+ */
+export interface TokenizerConfig {
+  eos_token?: string | { content: string };
+  bos_token?: string | { content: string };
+  pad_token?: string | { content: string };
+  unk_token?: string | { content: string };
+  /** Added tokens keyed by token ID */
+  added_tokens_decoder?: Record<string, { content: string; special?: boolean }>;
+}
 
 /**
  * Tokenizer data in HuggingFace format
@@ -37,6 +382,8 @@ export interface TokenizerData {
   vocab: Record<string, number>;
   /** Merge rules for BPE tokenizers */
   merges?: string[];
+  /** Piece log-probabilities for Unigram (SentencePiece) tokenizers, indexed by token ID */
+  scores?: number[];
   /** Special tokens configuration */
   added_tokens?: Array<{
     id: number;
@@ -47,8 +394,12 @@ export interface TokenizerData {
     normalized: boolean;
     special: boolean;
   }>;
+  /** Additional token contents to treat as special (e.g. tool-call sentinels) */
+  special_tokens?: string[];
   /** The model type (BPE, WordPiece, etc.) */
   model_type?: string;
+  /** Prefix marking word continuations in WordPiece vocabularies (usually "##") */
+  continuing_subword_prefix?: string;
   /** End of sequence token ID */
   eos_token_id?: number;
   /** Beginning of sequence token ID */
@@ -57,6 +408,313 @@ export interface TokenizerData {
   pad_token_id?: number;
   /** Unknown token ID */
   unk_token_id?: number;
+  /**
+   * The model's tokenizer_config.json, used to resolve special tokens
+   * Explicit *_token_id fields take priority over it
+   */
+  tokenizer_config?: TokenizerConfig;
+}
+
+/**
+ * Tokenizer metadata read from a GGUF header (the tokenizer.ggml.* keys)
+ */
+export interface GgufTokenizerData {
+  /** Tokenizer family, e.g. "llama", "gpt2", "bert" or "t5" */
+  model: string;
+  /** Token strings indexed by token ID */
+  tokens: string[];
+  /** Token scores (SentencePiece models) */
+  scores?: number[];
+  /** GGUF token types (1 = normal, 2 = unknown, 3 = control, 4 = user-defined, 6 = byte) */
+  token_type?: number[];
+  /** BPE merges (gpt2 models) */
+  merges?: string[];
+  eos_token_id?: number;
+  bos_token_id?: number;
+  padding_token_id?: number;
+  unknown_token_id?: number;
+}
+
+/**
+ * A live tokenizer that the WASM module calls back into
+ * Use createCallbackTokenizer() to adapt a transformer.js tokenizer
+ */
+export interface CallbackTokenizer {
+  /** Encode text to token IDs, without adding special tokens */
+  encode(text: string): number[];
+  /** Decode token IDs to text */
+  decode(ids: number[]): string;
+  /** Number of tokens in the vocabulary */
+  vocabSize: number;
+  /** End of sequence token ID */
+  eosTokenId: number;
+  /** IDs of special/control tokens */
+  specialTokenIds?: number[];
+}
+
+/**
+ * Tokenizer/grammar compatibility report from GuidanceParser.diagnose()
+ */
+export interface Diagnostics {
+  /** Number of tokens known to the tokenizer */
+  vocabSize: number;
+  /** Number of tokens allowed at the current position */
+  allowedTokens: number;
+  /** Whether EOS is allowed at the current position */
+  eosAllowed: boolean;
+  /** Grammar or matcher error, if any */
+  error: string | null;
+  /** Current stop reason */
+  stopReason: StopReason;
+  /** Token IDs with no bytes (holes in the vocabulary) */
+  emptyTokens: number;
+  /** Number of special tokens */
+  specialTokens: number;
+  /** Byte values that no text token contains */
+  unreachableBytes: number[];
+  /** Human-readable findings, most severe first */
+  warnings: string[];
+}
+
+/**
+ * Why a Lark grammar is ambiguous, from GuidanceParser.diagnoseAmbiguity()
+ * Tokens are shown like tokenDisplay(): text quoted, special tokens as-is
+ */
+export interface AmbiguityReport {
+  /** Rules that can parse the same text several ways, with the reason */
+  ambiguousRules: string[];
+  /** Pairs of lexemes that some text matches both of, with tokens that can start it */
+  overlappingTerminals: { terminals: [string, string]; tokens: string[] }[];
+  /** Tokens that start text matching several lexemes, most contested first */
+  competingTokens: { token: string; lexemes: string[] }[];
+  /** Whether the grammar had too many lexemes for every pair to be checked */
+  truncated: boolean;
+}
+
+/**
+ * Whether a grammar compiles, from GuidanceParser.validateGrammar()
+ */
+export interface GrammarValidation {
+  /** true if the grammar compiled, i.e. errors is empty */
+  ok: boolean;
+  /** Why the grammar cannot be used */
+  errors: string[];
+  /** Problems that did not stop compilation, such as unsupported schema keywords */
+  warnings: string[];
+}
+
+/**
+ * Static estimate of a grammar's cost, from GuidanceParser.analyzeGrammar()
+ */
+export interface GrammarAnalysis {
+  /** Grammar rules: Lark nonterminals, or subschemas of a JSON schema */
+  rules: number;
+  /** Named Lark terminals */
+  terminals: number;
+  /** Distinct lexemes the lexer matches, named or inline */
+  lexemes: number;
+  /** Estimated NFA states of all lexemes together */
+  lexerStates: number;
+  /** Rules that reference themselves, directly or through other rules */
+  recursiveRules: string[];
+  /** Shapes likely to make parsing ambiguous or the lexer DFA large */
+  hints: string[];
+  /**
+   * 'low': no recursion and a small lexer; 'medium': recursive or a larger
+   * lexer; 'high': any hint, or a lexer large enough to slow the first masks
+   */
+  costClass: 'low' | 'medium' | 'high';
+}
+
+/**
+ * Token mask as the shorter of its allowed or disallowed ID lists,
+ * from GuidanceParser.getCompactMask()
+ */
+export interface CompactMask {
+  /** Whether tokenIds lists the allowed tokens or the disallowed ones */
+  representation: 'allowed' | 'disallowed';
+  /** Token IDs in ascending order; every other ID has the opposite status */
+  tokenIds: Uint32Array;
+}
+
+/**
+ * How constrained the current position is, from GuidanceParser.maskStats()
+ */
+export interface MaskStats {
+  /** Number of tokens allowed at the current position */
+  allowedTokens: number;
+  /** Number of tokens known to the tokenizer */
+  vocabSize: number;
+  /** Fraction of the vocabulary that is allowed (0 to 1) */
+  coverage: number;
+  /** log2 of the allowed-token count: bits of choice left under a uniform model */
+  entropyBits: number;
+  /** Whether EOS is allowed at the current position */
+  eosAllowed: boolean;
+}
+
+/**
+ * Why the parser stopped, from GuidanceParser.stopReason()
+ * - NotStopped: generation can continue
+ * - EndOfSentence: EOS was consumed
+ * - NoExtension: the grammar accepts nothing more
+ * - MaxTokens: a token limit set in the grammar was reached
+ * - ParserError: the grammar failed, e.g. it is too complex for the lexer or parser
+ */
+export type StopReason = 'NotStopped' | 'EndOfSentence' | 'NoExtension' | 'MaxTokens' | 'ParserError';
+
+/**
+ * Constraint counters for the generation so far, from GuidanceParser.getTelemetry()
+ */
+export interface ParserTelemetry {
+  /** Tokens accepted by advance() */
+  steps: number;
+  /** Steps whose mask was computed and allowed exactly one token */
+  forcedSteps: number;
+  /** Masks computed; repeated requests at one position are served from cache */
+  maskComputations: number;
+  /** Mean number of allowed tokens over the computed masks */
+  averageAllowedTokens: number;
+  /** Total time spent computing masks, in milliseconds */
+  maskTimeMs: number;
+}
+
+/**
+ * Timings and parser counters of the latest step, from GuidanceParser.lastStepStats()
+ * The counters cover consuming the latest token and computing the mask after
+ * it, and are all zero while the grammar is not enforced
+ */
+export interface StepStats {
+  /** Time the last computed mask took, in microseconds */
+  maskTimeUs: number;
+  /** Tokens the last computed mask allowed */
+  allowedTokens: number;
+  /** Time the last advance() took to consume its token, in microseconds */
+  advanceTimeUs: number;
+  /** Earley rows added */
+  earleyRows: number;
+  /** Earley items in those rows */
+  earleyItems: number;
+  /** Lexer states built so far, over the whole generation */
+  lexerStates: number;
+  /** Precomputed mask slices applied */
+  slicesApplied: number;
+  /** Token trie nodes walked */
+  trieNodesWalked: number;
+  /** Lexer work, in llguidance's units */
+  lexerCost: number;
+}
+
+/**
+ * A rule matched in the generated text, from GuidanceParser.getParseTree()
+ */
+export interface ParseTreeNode {
+  /** Rule name, or the capture name of a rule that has its own */
+  rule: string;
+  /** Byte offsets of the rule's text in the text the grammar consumed */
+  start: number;
+  end: number;
+  text: string;
+  /** Rules nested in this one, in text order */
+  children: ParseTreeNode[];
+}
+
+/**
+ * Options for a GuidanceParser
+ */
+export interface ParserOptions {
+  /**
+   * Length of the model's logits when padded beyond the tokenizer vocabulary
+   * (e.g. to a multiple of 64). getTokenMask() returns a mask of this length,
+   * with the padding positions always banned
+   */
+  vocabSizeOverride?: number;
+
+  /**
+   * Always mask out special/control tokens (e.g. `<|im_start|>`, padding),
+   * whatever the grammar allows. EOS is still allowed where the grammar can end
+   * @default false
+   */
+  excludeSpecialTokens?: boolean;
+
+  /**
+   * Runtime token ID of each tokenizer token, indexed by tokenizer ID, for
+   * runtimes whose logits are ordered differently from the tokenizer.
+   * Masks, advance() and tokenize() then all use the runtime IDs
+   */
+  idRemap?: Uint32Array | number[];
+
+  /**
+   * Tokens the grammar never sees, such as chat-template role markers.
+   * They are always allowed, and advancing over them leaves the grammar untouched
+   */
+  passthroughTokens?: number[];
+
+  /**
+   * Token that starts grammar enforcement (e.g. the end of the assistant header).
+   * Until it is produced, only it and the passthrough tokens are allowed
+   */
+  triggerToken?: number;
+
+  /**
+   * Bias computeLogitBias() gives allowed tokens
+   * @default 0
+   */
+  allowedBias?: number;
+
+  /**
+   * Bias computeLogitBias() gives banned tokens, for samplers that misbehave
+   * with -Infinity (e.g. -1e9 or -100)
+   * @default -Infinity
+   */
+  disallowedBias?: number;
+
+  /**
+   * Most tokens a single rollback() call may undo, for samplers that only
+   * backtrack a few steps; unlimited if unset
+   */
+  maxRollbackTokens?: number;
+
+  /**
+   * Name for this parser, prefixed as "[label] " to every error and warning it
+   * produces, to tell concurrent parsers apart; kept by fork() and createBatch()
+   */
+  label?: string;
+  /**
+   * Fetch a JSON schema document referenced by URI, so external $refs are
+   * inlined before compiling; used by the GuidanceParser constructors
+   */
+  resolveRef?: RefResolver;
+}
+
+/**
+ * Fetch the JSON schema document at an absolute URI, e.g. with fetch()
+ */
+export type RefResolver = (
+  uri: string,
+) => Record<string, unknown> | Promise<Record<string, unknown>>;
+
+/**
+ * Options for sampling a token inside WASM
+ */
+export interface SamplingOptions {
+  /**
+   * Softmax temperature; 0 always picks the most likely allowed token
+   * @default 1
+   */
+  temperature?: number;
+
+  /**
+   * Sample only among the k most likely allowed tokens (0 = no limit)
+   * @default 0
+   */
+  topK?: number;
+
+  /**
+   * Sample only among the smallest set of allowed tokens whose probability reaches p
+   * @default 1
+   */
+  topP?: number;
 }
 
 /**
@@ -4,11 +4,240 @@
  */
 
 declare module 'llguidance-wasm' {
+  /**
+   * Why the parser stopped, from LLGuidanceParser.stop_reason()
+   */
+  export type StopReason =
+    | 'NotStopped'
+    | 'EndOfSentence'
+    | 'NoExtension'
+    | 'MaxTokens'
+    | 'ParserError';
+
   /**
    * Initialize the WASM module
    */
   export default function init(): Promise<void>;
 
+  /**
+   * Drop all cached tokenizer environments
+   */
+  export function clear_tokenizer_cache(): void;
+
+  /**
+   * Size of the module's linear memory in bytes
+   */
+  export function wasm_memory_bytes(): number;
+
+  /**
+   * Convert a grammar and keep it under a name, for use as "@name"
+   */
+  export function register_grammar(name: string, grammarJson: string): void;
+
+  /**
+   * Remove a registered grammar; returns whether it was registered
+   */
+  export function unregister_grammar(name: string): boolean;
+
+  /**
+   * A tokenizer and the grammars compiled against it, for creating parsers cheaply
+   */
+  export class LLGuidanceFactory {
+    /**
+     * Set up the tokenizer
+     * @param tokenizer_json JSON string containing tokenizer configuration
+     * @param options_json Optional JSON string with parser options, applied to every parser
+     */
+    constructor(tokenizer_json: string, options_json?: string);
+
+    /**
+     * Compile a grammar, returning the reference to pass to create_parser()
+     * @param grammar_json JSON string containing the grammar definition
+     */
+    compile_grammar(grammar_json: string): number;
+
+    /**
+     * Number of grammars compiled so far
+     */
+    grammar_count(): number;
+
+    /**
+     * Create a parser at the start of a compiled grammar, without recompiling it
+     * @param grammar_ref A reference returned by compile_grammar()
+     */
+    create_parser(grammar_ref: number): LLGuidanceParser;
+
+    /**
+     * Release the WASM memory owned by this object; it must not be used afterwards
+     */
+    free(): void;
+  }
+
+  /**
+   * Independent parsers for sequences generated in parallel against one grammar
+   */
+  export class ParserBatch {
+    /**
+     * Create size sequences, each at the start of the parser's grammar
+     * @param parser Parser providing the grammar, tokenizer and options
+     * @param size Number of sequences
+     */
+    constructor(parser: LLGuidanceParser, size: number);
+
+    /**
+     * Number of sequences in the batch
+     */
+    len(): number;
+
+    /**
+     * Whether the batch has no sequences
+     */
+    is_empty(): boolean;
+
+    /**
+     * Length of each sequence's mask
+     */
+    vocab_size(): number;
+
+    /**
+     * Get the masks of all sequences, concatenated in sequence order
+     */
+    get_token_masks(): Uint8Array;
+
+    /**
+     * Advance every sequence by its sampled token, one token per sequence
+     */
+    advance(token_ids: Uint32Array): void;
+
+    /**
+     * Advance a single sequence
+     */
+    advance_sequence(index: number, token_id: number): void;
+
+    /**
+     * Undo the last tokens of a single sequence
+     */
+    rollback(index: number, num_tokens: number): void;
+
+    /**
+     * Check whether a sequence is a valid complete parse
+     */
+    is_complete(index: number): boolean;
+
+    /**
+     * Branch a sequence at its current position; returns the new sequence's index
+     */
+    fork(index: number): number;
+
+    /**
+     * Drop a sequence; later sequences move down one index
+     */
+    remove(index: number): void;
+
+    /**
+     * Restart every sequence at the beginning of the grammar
+     */
+    reset(): void;
+
+    /**
+     * Release the WASM memory owned by this object; it must not be used afterwards
+     */
+    free(): void;
+  }
+
+  /**
+   * A target-vocabulary parser and a draft-vocabulary parser kept on the same text
+   */
+  export class SpeculativeParser {
+    /**
+     * Pair two parsers for the same grammar, copied at their current position
+     * @param target Parser built with the target model's tokenizer
+     * @param draft Parser built with the draft model's tokenizer
+     */
+    constructor(target: LLGuidanceParser, draft: LLGuidanceParser);
+
+    /**
+     * Length of the target model's mask
+     */
+    target_vocab_size(): number;
+
+    /**
+     * Length of the draft model's mask
+     */
+    draft_vocab_size(): number;
+
+    /**
+     * Get the target-vocabulary mask at the committed text
+     */
+    target_mask(): Uint8Array;
+
+    /**
+     * Get the draft-vocabulary mask after the proposal so far
+     */
+    draft_mask(): Uint8Array;
+
+    /**
+     * Extend the proposal by a token sampled from the draft model
+     */
+    advance_draft(draft_token_id: number): void;
+
+    /**
+     * Re-tokenize the proposal for the target, truncated to what the grammar accepts
+     */
+    draft_as_target_tokens(): Uint32Array;
+
+    /**
+     * Advance both parsers by the accepted target tokens, discarding the rest
+     */
+    commit(target_token_ids: Uint32Array): void;
+
+    /**
+     * Discard the proposal
+     */
+    reject_draft(): void;
+
+    /**
+     * Check whether the committed text is a valid complete parse
+     */
+    is_complete(): boolean;
+
+    /**
+     * Release the WASM memory owned by this object; it must not be used afterwards
+     */
+    free(): void;
+  }
+
+  /**
+   * Incremental vocabulary loader, for handing large vocabularies to WASM in chunks
+   */
+  export class TokEnvBuilder {
+    constructor();
+
+    /**
+     * Append the next tokens, continuing from the last token ID added
+     * @param tokens Token byte strings
+     */
+    add_chunk(tokens: Uint8Array[]): void;
+
+    /**
+     * Number of tokens added so far
+     */
+    len(): number;
+
+    /**
+     * Whether no tokens have been added yet
+     */
+    is_empty(): boolean;
+
+    /**
+     * Build the token trie and a parser for the grammar, consuming the builder
+     * @param eos_token_id The end-of-sequence token ID
+     * @param grammar_json JSON string containing the grammar definition
+     * @param options_json Optional JSON string with parser options
+     */
+    finish(eos_token_id: number, grammar_json: string, options_json?: string): LLGuidanceParser;
+  }
+
   /**
    * The main parser class exposed by WASM
    */
@@ -17,40 +246,426 @@ declare module 'llguidance-wasm' {
      * Create a new parser instance
      * @param grammar_json JSON string containing the grammar definition
      * @param tokenizer_json JSON string containing the tokenizer configuration
+     * @param options_json Optional JSON string with parser options
+     */
+    constructor(grammar_json: string, tokenizer_json: string, options_json?: string);
+
+    /**
+     * Create a parser from a tokenizer.json fetched inside the WASM module
+     * @param grammar_json JSON string containing the grammar definition
+     * @param tokenizer_url URL of the tokenizer.json file
+     * @param options_json Optional JSON string with parser options
      */
-    constructor(grammar_json: string, tokenizer_json: string);
+    static from_url(
+      grammar_json: string,
+      tokenizer_url: string,
+      options_json?: string,
+    ): Promise<LLGuidanceParser>;
+
+    /**
+     * Create a parser from token byte strings indexed by token ID
+     * @param tokens The byte string of each token
+     * @param eos_token_id The end-of-sequence token ID
+     * @param grammar_json JSON string containing the grammar definition
+     * @param options_json Optional JSON string with parser options
+     */
+    static from_vocab(
+      tokens: Uint8Array[],
+      eos_token_id: number,
+      grammar_json: string,
+      options_json?: string,
+    ): LLGuidanceParser;
+
+    /**
+     * Create a parser backed by a live JavaScript tokenizer
+     * @param tokenizer Object with encode(text), decode(ids), vocabSize and eosTokenId
+     * @param grammar_json JSON string containing the grammar definition
+     * @param options_json Optional JSON string with parser options
+     */
+    static from_tokenizer(
+      tokenizer: unknown,
+      grammar_json: string,
+      options_json?: string,
+    ): LLGuidanceParser;
+
+    /**
+     * Create a parser from GGUF tokenizer metadata
+     * @param metadata_json JSON with the tokenizer.ggml.* values
+     * @param grammar_json JSON string containing the grammar definition
+     * @param options_json Optional JSON string with parser options
+     */
+    static from_gguf(
+      metadata_json: string,
+      grammar_json: string,
+      options_json?: string,
+    ): LLGuidanceParser;
+
+    /**
+     * Create a parser from a tiktoken BPE rank file
+     * @param bpe_ranks Contents of the .tiktoken file
+     * @param special_tokens_json JSON map of special token contents to IDs
+     * @param grammar_json JSON string containing the grammar definition
+     * @param options_json Optional JSON string with parser options
+     */
+    static from_tiktoken(
+      bpe_ranks: string,
+      special_tokens_json: string,
+      grammar_json: string,
+      options_json?: string,
+    ): LLGuidanceParser;
+
+    /**
+     * Create a parser from a blob returned by export_tok_env()
+     * @param blob The exported tokenizer environment
+     * @param grammar_json JSON string containing the grammar definition
+     * @param options_json Optional JSON string with parser options
+     */
+    static from_tok_env_blob(
+      blob: Uint8Array,
+      grammar_json: string,
+      options_json?: string,
+    ): LLGuidanceParser;
+
+    /**
+     * Compile a grammar without a tokenizer; keeps no parser
+     * @param grammar_json JSON string containing the grammar definition
+     * @returns JSON with ok, errors and warnings
+     */
+    static validate_grammar(grammar_json: string): string;
+
+    /**
+     * Convert and check a grammar once, for from_compiled_grammar
+     * @param grammar_json JSON string containing the grammar definition
+     */
+    static compile_grammar(grammar_json: string): Uint8Array;
+
+    /**
+     * Create a parser from a blob returned by compile_grammar()
+     * @param compiled The compiled grammar
+     * @param tokenizer_json JSON string containing tokenizer configuration
+     * @param options_json Optional JSON string with parser options
+     */
+    static from_compiled_grammar(
+      compiled: Uint8Array,
+      tokenizer_json: string,
+      options_json?: string,
+    ): LLGuidanceParser;
+
+    /**
+     * Estimate how expensive a grammar is to enforce, without compiling it
+     * @param grammar_json JSON string containing the grammar definition
+     * @returns JSON with rules, terminals, lexemes, lexer_states,
+     *   recursive_rules, hints and cost_class
+     */
+    static analyze_grammar(grammar_json: string): string;
 
     /**
      * Check if a specific token is allowed at the current position
      */
     is_token_allowed(token_id: number): boolean;
 
+    /**
+     * Ban these tokens at every position, replacing the previous deny list
+     */
+    set_token_deny_list(token_ids: Uint32Array): void;
+
+    /**
+     * Allow these tokens at every position, overriding the grammar and deny list
+     */
+    set_token_force_allow_list(token_ids: Uint32Array): void;
+
+    /**
+     * Keep only the candidate token IDs the grammar allows, in their original order
+     */
+    filter_candidates(token_ids: Uint32Array): Uint32Array;
+
     /**
      * Get the full token mask for the current position
-     * Returns a Uint8Array where 1 = allowed, 0 = banned
+     * Returns a Uint8Array where 1 = allowed, 0 = banned, or the reverse with invert
+     */
+    get_token_mask(invert?: boolean): Uint8Array;
+
+    /**
+     * Write the token mask for the current position into dest
+     * dest must hold at least vocab_size() bytes; later bytes are left untouched
+     */
+    fill_token_mask(dest: Uint8Array, invert?: boolean): void;
+
+    /**
+     * Get whichever of the allowed and disallowed ID lists is shorter
+     * Returns [representation, ids] with representation "allowed" or "disallowed"
+     */
+    get_compact_mask(): ['allowed' | 'disallowed', Uint32Array];
+
+    /**
+     * Get the changes to the mask since the last call
+     * Returns [newly_allowed, newly_disallowed]; the first call reports every allowed token
+     */
+    get_token_mask_diff(): [Uint32Array, Uint32Array];
+
+    /**
+     * Get the IDs of the tokens disallowed at the current position, in ascending order
+     * Includes padding positions and runtime IDs without a token
+     */
+    get_disallowed_tokens(): Uint32Array;
+
+    /**
+     * Set the logits of disallowed tokens to -Infinity in place
+     * Logits past the end of the mask (padding) are disallowed too
+     */
+    apply_mask(logits: Float32Array): void;
+
+    /**
+     * Sample a token from the logits among those the grammar allows
+     * Does not advance the parser
+     * @param logits The logits for the current position
+     * @param options_json Optional JSON string with temperature, top_k and top_p
      */
-    get_token_mask(): Uint8Array;
+    sample_token(logits: Float32Array, options_json?: string): number;
+
+    /**
+     * Get the IDs of the tokens allowed at the current position, in ascending order
+     */
+    get_allowed_tokens(): Uint32Array;
+
+    /**
+     * Get the token mask for the current position packed one bit per token
+     * Token i is allowed if bit (i % 32) of word (i / 32) is set
+     */
+    get_token_bitmask(): Uint32Array;
+
+    /**
+     * Get an additive logit bias for the current position
+     * Returns the allowed_bias option (default 0) for allowed tokens, and otherwise
+     * disallowed_value, the disallowed_bias option or -Infinity, in that order
+     */
+    compute_logit_bias(disallowed_value?: number): Float32Array;
 
     /**
      * Advance the parser state after a token has been selected
      */
     advance(token_id: number): void;
 
+    /**
+     * Check whether advance(token_id) would succeed, without moving the parser
+     */
+    try_advance(token_id: number): boolean;
+
+    /**
+     * Advance by every token, stopping at the first rejected one
+     */
+    advance_many(token_ids: Uint32Array): void;
+
+    /**
+     * Advance up to the first rejected token; returns its index, or -1 if all were accepted
+     */
+    validate_tokens(token_ids: Uint32Array): number;
+
+    /**
+     * Advance past bytes tokenized with the parser's tokenizer; returns the tokens
+     */
+    consume_bytes(bytes: Uint8Array): Uint32Array;
+
+    /**
+     * Advance past text tokenized with the parser's tokenizer; returns the tokens
+     */
+    consume_text(text: string): Uint32Array;
+
+    /**
+     * Un-consume the last num_tokens tokens passed to advance
+     */
+    rollback(num_tokens: number): void;
+
+    /**
+     * Copy this parser at its current position, sharing the work done so far
+     */
+    fork(): LLGuidanceParser;
+
+    /**
+     * Advance by token_id and return the mask for the next position
+     */
+    step(token_id: number): Uint8Array;
+
+    /**
+     * Get the tokens the grammar forces next, without advancing
+     */
+    get_forced_tokens(): Uint32Array;
+
+    /**
+     * Get the bytes the grammar forces next, without advancing
+     */
+    forced_bytes(): Uint8Array;
+
+    /**
+     * Get the text the grammar has consumed since its start
+     */
+    consumed_text(): string;
+
+    /**
+     * Get the bytes the grammar has consumed since its start
+     */
+    consumed_bytes(): Uint8Array;
+
+    /**
+     * Get the named captures matched so far, as [name, text] pairs
+     */
+    get_captures(): [string, string][];
+
+    /**
+     * Get the derivation tree of the text the Lark grammar consumed, as JSON
+     */
+    get_parse_tree(): string;
+
+    /**
+     * Get the temperature the grammar's annotations suggest for the next token
+     */
+    get_temperature(): number | undefined;
+
+    /**
+     * Check whether a finished string matches the grammar from its start
+     */
+    validate_text(text: string): boolean;
+
+    /**
+     * Get the forced text, or the distinct first characters of the allowed tokens
+     */
+    expected(): string[];
+
+    /**
+     * Check whether exactly one token is allowed now
+     */
+    is_forced(): boolean;
+
+    /**
+     * Check whether EOS is the only token the grammar allows now
+     */
+    must_stop(): boolean;
+
+    /**
+     * Check whether any token is allowed now, i.e. the grammar has not reached a dead end
+     */
+    can_advance(): boolean;
+
     /**
      * Check if the current state represents a valid complete parse
+     * True while the grammar is accepting, even if it could still continue
      */
     is_complete(): boolean;
 
+    /**
+     * Check whether the parser has stopped and accepts no further tokens
+     */
+    is_stopped(): boolean;
+
     /**
      * Reset the parser to its initial state
+     * Without a grammar, restarts the current grammar without recompiling it
      */
-    reset(): void;
+    reset(grammar_json?: string): void;
+
+    /**
+     * Enforce another grammar from its start until pop_grammar
+     * The current grammar is set aside at its position
+     */
+    push_grammar(grammar_json: string): void;
+
+    /**
+     * Go back to the grammar set aside by the last push_grammar
+     */
+    pop_grammar(): void;
+
+    /**
+     * Number of grammars pushed and not yet popped
+     */
+    grammar_depth(): number;
+
+    /**
+     * Enable token healing for a prompt ending in the given token
+     * Returns true if the token was rolled back and must be removed from the prompt
+     */
+    enable_token_healing(last_prompt_token: number): boolean;
+
+    /**
+     * Snapshot the parser's position as bytes
+     */
+    save_state(): Uint8Array;
+
+    /**
+     * Restore a position saved by save_state, replacing the current one
+     */
+    load_state(bytes: Uint8Array): void;
+
+    /**
+     * Report tokenizer/grammar mismatches at the current position, as JSON
+     */
+    diagnose(): string;
+
+    /**
+     * Report ambiguous rules, overlapping lexemes and the tokens they
+     * compete for in the current Lark grammar, as JSON
+     */
+    diagnose_ambiguity(): string;
+
+    /**
+     * Summarize how constrained the current position is, as JSON
+     */
+    mask_stats(): string;
+
+    /**
+     * Get the constraint telemetry for the generation so far, as JSON
+     */
+    get_telemetry(): string;
 
     /**
-     * Get the vocabulary size
+     * Get the timings of the latest mask computation and advance, as JSON
+     */
+    last_step_stats(): string;
+
+    /**
+     * Get the current stop reason
+     */
+    stop_reason(): StopReason;
+
+    /**
+     * Get the vocabulary size (the token mask length, including any padding)
      */
     vocab_size(): number;
 
+    /**
+     * Export the compiled tokenizer environment as a binary blob
+     */
+    export_tok_env(): Uint8Array;
+
+    /**
+     * Get the warnings reported while compiling the grammar
+     */
+    grammar_warnings(): string[];
+
+    /**
+     * Get the problems found while reading the tokenizer
+     */
+    tokenizer_warnings(): string[];
+
+    /**
+     * Tokenize text with the parser's tokenizer
+     */
+    tokenize(text: string): Uint32Array;
+
+    /**
+     * Decode token IDs back to text
+     */
+    detokenize(ids: Uint32Array | number[]): string;
+
+    /**
+     * Get the raw bytes of a token (special tokens start with 0xFF)
+     */
+    token_bytes(token_id: number): Uint8Array;
+
+    /**
+     * Get a printable representation of a token
+     */
+    token_display(token_id: number): string;
+
     /**
      * Check if the parser is in an error state
      */
@@ -60,6 +675,11 @@ declare module 'llguidance-wasm' {
      * Get the current error message, if any
      */
     get_error(): string | null;
+
+    /**
+     * Release the WASM memory owned by this object; it must not be used afterwards
+     */
+    free(): void;
   }
 }
 
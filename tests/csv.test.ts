@@ -0,0 +1,49 @@
+import { describe, it, expect } from 'vitest';
+import { csvGrammar } from '../src/csv';
+
+describe('csvGrammar()', () => {
+  it('should build a header and quoted rows', () => {
+    const grammar = csvGrammar(
+      [{ name: 'name' }, { name: 'age', type: 'integer' }, { name: 'id', pattern: 'a/b' }],
+      { rows: 2 },
+    );
+    expect(grammar.type).toBe('lark');
+    expect(grammar.grammar.split('\n')).toEqual([
+      'start: header row row',
+      'row: (TEXT? | QUOTED) "," INTEGER "," COLUMN_2 "\\n"',
+      'header: "name" "," "age" "," "id" "\\n"',
+      'TEXT: /[^,\\r\\n"]+/',
+      'QUOTED: /"([^"]|"")*"/',
+      'INTEGER: /-?[0-9]+/',
+      'COLUMN_2: /a\\/b/',
+    ]);
+  });
+
+  it('should leave TSV fields unquoted', () => {
+    const grammar = csvGrammar([{ name: 'a' }, { name: 'b' }], {
+      delimiter: '\t',
+      header: false,
+      minRows: 0,
+      maxRows: 2,
+    });
+    expect(grammar.grammar.split('\n')).toEqual([
+      'start: row? row?',
+      'row: TEXT? "\\t" TEXT? "\\n"',
+      'TEXT: /[^\\t\\r\\n]+/',
+    ]);
+  });
+
+  it('should quote header names that need it', () => {
+    const grammar = csvGrammar([{ name: 'a,b' }, { name: 'say "hi"' }]);
+    expect(grammar.grammar).toContain('header: "\\"a,b\\"" "," "\\"say \\"\\"hi\\"\\"\\"" "\\n"');
+    expect(() => csvGrammar([{ name: 'a\tb' }], { delimiter: '\t' })).toThrow('without quoting');
+  });
+
+  it('should reject invalid options', () => {
+    expect(() => csvGrammar([])).toThrow('at least one column');
+    expect(() => csvGrammar([{ name: 'a' }], { delimiter: ';;' })).toThrow('Invalid CSV delimiter');
+    expect(() => csvGrammar([{ name: 'a' }], { minRows: 3, maxRows: 2 })).toThrow(
+      'maxRows 2 is less than minRows 3',
+    );
+  });
+});
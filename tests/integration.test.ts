@@ -66,6 +66,1859 @@ describe('Integration Tests', () => {
       expect(parser.vocab_size()).toBeGreaterThan(0);
     });
 
+    it('should create parser from a raw tokenizer.json', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'BPE',
+          vocab: { a: 0, b: 1, ab: 2, '</s>': 3 },
+          merges: ['a b'],
+        },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.vocab_size()).toBe(4);
+
+      // The merged token is indexed by its real vocabulary ID
+      const mask = parser.get_token_mask();
+      expect(mask[0]).toBe(1);
+      expect(mask[2]).toBe(1);
+      expect(mask[1]).toBe(0);
+    });
+
+    it('should create parser from a Unigram tokenizer.json', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: ' hi' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'Unigram',
+          unk_id: 0,
+          vocab: [
+            ['<unk>', 0],
+            ['▁', -2],
+            ['h', -3],
+            ['i', -3],
+            ['▁hi', -1],
+            ['</s>', 0],
+          ],
+        },
+        added_tokens: [
+          { id: 0, content: '<unk>', special: true },
+          { id: 5, content: '</s>', special: true },
+        ],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.vocab_size()).toBe(6);
+
+      // '▁hi' decodes to ' hi', so it can start the match
+      const mask = parser.get_token_mask();
+      expect(mask[4]).toBe(1);
+      expect(mask[1]).toBe(1);
+      expect(mask[2]).toBe(0);
+    });
+
+    it('should map SentencePiece byte-fallback tokens to raw bytes', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a\\né"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'Unigram',
+          unk_id: 0,
+          byte_fallback: true,
+          vocab: [
+            ['<unk>', 0],
+            ['<0x0A>', 0],
+            ['<0xC3>', 0],
+            ['<0xA9>', 0],
+            ['▁', -2],
+            ['a', -3],
+            ['</s>', 0],
+          ],
+        },
+        added_tokens: [
+          { id: 0, content: '<unk>', special: true },
+          { id: 6, content: '</s>', special: true },
+        ],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.token_bytes(1))).toEqual([0x0a]);
+
+      parser.advance(5);
+      // The newline can only be produced by its byte-fallback token
+      expect(parser.get_token_mask()[1]).toBe(1);
+      parser.advance(1);
+      // 'é' is spelled out as two byte tokens
+      expect(parser.get_token_mask()[2]).toBe(1);
+
+      expect(Array.from(parser.tokenize('a\né'))).toEqual([5, 1, 2, 3]);
+    });
+
+    it('should create parser from a WordPiece tokenizer.json', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: 'playing' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'WordPiece',
+          vocab: { '[UNK]': 0, '[SEP]': 1, play: 2, '##ing': 3, ing: 4 },
+        },
+        added_tokens: [
+          { id: 0, content: '[UNK]', special: true },
+          { id: 1, content: '[SEP]', special: true },
+        ],
+        eos_token_id: 1,
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+
+      // 'play' starts the text, so without a space; '##ing' continues it,
+      // while 'ing' would start a new word (' ing')
+      expect(parser.get_token_mask()[2]).toBe(1);
+      parser.advance(2);
+      const mask = parser.get_token_mask();
+      expect(mask[3]).toBe(1);
+      expect(mask[4]).toBe(0);
+    });
+
+    it('should put WordPiece spaces only between words', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: '\\{"name":"john smith"\\}' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'WordPiece',
+          vocab: {
+            '[UNK]': 0,
+            '[SEP]': 1,
+            '{': 2,
+            '"': 3,
+            name: 4,
+            ':': 5,
+            john: 6,
+            smith: 7,
+            '}': 8,
+          },
+        },
+        added_tokens: [
+          { id: 0, content: '[UNK]', special: true },
+          { id: 1, content: '[SEP]', special: true },
+        ],
+        eos_token_id: 1,
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const ids = [2, 3, 4, 3, 5, 3, 6, 7, 3, 8];
+
+      // No space after an opening quote: 'name' is allowed right after '{"'
+      parser.advance_many([2, 3]);
+      expect(parser.is_token_allowed(4)).toBe(true);
+      parser.advance_many(ids.slice(2));
+
+      expect(parser.consumed_text()).toBe('{"name":"john smith"}');
+      expect(parser.detokenize(ids)).toBe('{"name":"john smith"}');
+      expect(parser.vocab_size()).toBe(9);
+    });
+
+    it('should put WordPiece spaces after punctuation, not before it', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: 'hello, world\\. next\\.' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'WordPiece',
+          vocab: { '[UNK]': 0, '[SEP]': 1, hello: 2, ',': 3, world: 4, '.': 5, next: 6 },
+        },
+        added_tokens: [
+          { id: 0, content: '[UNK]', special: true },
+          { id: 1, content: '[SEP]', special: true },
+        ],
+        eos_token_id: 1,
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const ids = [2, 3, 4, 5, 6, 5];
+
+      // ',' attaches to 'hello', and 'world' takes a space after it
+      parser.advance(2);
+      expect(parser.is_token_allowed(3)).toBe(true);
+      parser.advance(3);
+      expect(parser.is_token_allowed(4)).toBe(true);
+      parser.advance_many(ids.slice(2));
+
+      expect(parser.consumed_text()).toBe('hello, world. next.');
+      expect(parser.detokenize(ids)).toBe('hello, world. next.');
+    });
+
+    it('should decode byte-level BPE tokens to their raw bytes', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: 'é' }],
+      });
+
+      // Byte-level BPE spells the UTF-8 bytes of 'é' (C3 A9) as 'Ã©',
+      // while 'é' on its own stands for the single byte E9
+      const tokenizer = JSON.stringify({
+        model: {
+          type: 'BPE',
+          vocab: { 'Ã©': 0, 'é': 1, 'Ġ': 2, '<|endoftext|>': 3 },
+          merges: [],
+        },
+        decoder: { type: 'ByteLevel' },
+        added_tokens: [{ id: 3, content: '<|endoftext|>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const mask = parser.get_token_mask();
+
+      expect(mask[0]).toBe(1);
+      expect(mask[1]).toBe(0);
+      expect(mask[2]).toBe(0);
+    });
+
+    it('should create parser from a tokenizer.json URL', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizerJson = JSON.stringify({
+        model: { type: 'BPE', vocab: { a: 0, b: 1, ab: 2, '</s>': 3 }, merges: ['a b'] },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+      const url = `data:application/json,${encodeURIComponent(tokenizerJson)}`;
+
+      const parser = await wasm.LLGuidanceParser.from_url(grammar, url);
+      expect(parser.vocab_size()).toBe(4);
+      expect(parser.get_token_mask()[2]).toBe(1);
+
+      await expect(wasm.LLGuidanceParser.from_url(grammar, 'not a url')).rejects.toMatch(
+        /Failed to fetch/,
+      );
+    });
+
+    it('should build despite vocab entries with unpaired surrogates', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      // JSON.stringify escapes the lone surrogate as \ud83d, which is not valid UTF-8
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, '\ud83d': 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+      expect(tokenizer).toContain('\\ud83d');
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.vocab_size()).toBe(3);
+      expect(parser.get_token_mask()[0]).toBe(1);
+
+      const warnings = parser.tokenizer_warnings();
+      expect(warnings).toHaveLength(1);
+      expect(warnings[0]).toContain('surrogate');
+    });
+
+    it('should warn about byte-level tokens outside the byte alphabet', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "€"' }],
+      });
+
+      // '€' is not one of the 256 characters byte-level BPE spells bytes with
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, 'Ġa': 1, '€': 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.get_token_mask()[2]).toBe(1);
+      expect(parser.tokenizer_warnings()).toEqual([
+        '1 byte-level tokens contain characters outside the byte alphabet; ' +
+          'those characters were kept as UTF-8',
+      ]);
+    });
+
+    it('should report no grammar warnings for a clean grammar', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0 },
+        added_tokens: [{ id: 1, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.grammar_warnings()).toEqual([]);
+    });
+
+    it('should create parser from token byte strings', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      // Special tokens are marked with a leading 0xFF byte
+      const encoder = new TextEncoder();
+      const tokens = [
+        encoder.encode('a'),
+        encoder.encode('b'),
+        encoder.encode('ab'),
+        new Uint8Array([0xff, ...encoder.encode('</s>')]),
+      ];
+
+      const parser = wasm.LLGuidanceParser.from_vocab(tokens, 3, grammar);
+      expect(parser.vocab_size()).toBe(4);
+
+      const mask = parser.get_token_mask();
+      expect(mask[0]).toBe(1);
+      expect(mask[1]).toBe(0);
+      expect(mask[2]).toBe(1);
+    });
+
+    it('should create parser from a callback tokenizer', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const pieces = ['a', 'b', 'ab', '</s>'];
+      const tokenizer = {
+        encode: (text: string) => (text === 'ab' ? [2] : []),
+        decode: (ids: number[]) => ids.map((id) => pieces[id]).join(''),
+        vocabSize: pieces.length,
+        eosTokenId: 3,
+        specialTokenIds: [3],
+      };
+
+      const parser = wasm.LLGuidanceParser.from_tokenizer(tokenizer, grammar);
+      expect(parser.vocab_size()).toBe(4);
+
+      const mask = parser.get_token_mask();
+      expect(mask[0]).toBe(1);
+      expect(mask[2]).toBe(1);
+      expect(mask[1]).toBe(0);
+    });
+
+    it('should resolve EOS from tokenizer_config.json', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, '<|endoftext|>': 1, '<|im_end|>': 2 },
+        added_tokens: [
+          { id: 1, content: '<|endoftext|>', special: true },
+          { id: 2, content: '<|im_end|>', special: true },
+        ],
+        tokenizer_config: {
+          eos_token: '<|im_end|>',
+          pad_token: { content: '<|endoftext|>' },
+        },
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance(0);
+
+      // Only the configured EOS token may end the generation
+      const mask = parser.get_token_mask();
+      expect(mask[2]).toBe(1);
+      expect(mask[1]).toBe(0);
+    });
+
+    it('should include added tokens outside the base vocabulary', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" <|tool_call|>' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, '</s>': 1 },
+        added_tokens: [
+          { id: 1, content: '</s>', special: true },
+          { id: 2, content: '<|tool_call|>', special: false },
+        ],
+        special_tokens: ['<|tool_call|>'],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.vocab_size()).toBe(3);
+
+      // The user-defined special token is only allowed where the grammar names it
+      expect(parser.get_token_mask()[2]).toBe(0);
+      parser.advance(0);
+      expect(parser.get_token_mask()[2]).toBe(1);
+    });
+
+    it('should share the tokenizer environment between parsers', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, '</s>': 2 },
+        eos_token_id: 2,
+      });
+
+      const first = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: "a"' }] }),
+        tokenizer,
+      );
+      const second = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: "b"' }] }),
+        tokenizer,
+      );
+
+      // Each parser still follows its own grammar
+      expect(first.get_token_mask()[0]).toBe(1);
+      expect(second.get_token_mask()[0]).toBe(0);
+      expect(second.get_token_mask()[1]).toBe(1);
+
+      wasm.clear_tokenizer_cache();
+      expect(first.is_token_allowed(0)).toBe(true);
+    });
+
+    it('should report WASM memory and free parsers', async () => {
+      expect(wasm.wasm_memory_bytes()).toBeGreaterThan(0);
+      expect(wasm.wasm_memory_bytes() % 65536).toBe(0);
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: "a"' }] }),
+        JSON.stringify({ vocab: { a: 0 } }),
+      );
+      const fork = parser.fork();
+      parser.free();
+      expect(fork.is_token_allowed(0)).toBe(true);
+    });
+
+    it('should create parser from a tiktoken rank file', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      // base64 of 'a', 'b' and 'ab'
+      const bpeRanks = 'YQ== 0\nYg== 1\nYWI= 2\n';
+      const specialTokens = JSON.stringify({ '<|endoftext|>': 3 });
+
+      const parser = wasm.LLGuidanceParser.from_tiktoken(bpeRanks, specialTokens, grammar);
+      expect(parser.vocab_size()).toBe(4);
+
+      const mask = parser.get_token_mask();
+      expect(mask[0]).toBe(1);
+      expect(mask[1]).toBe(0);
+      expect(mask[2]).toBe(1);
+    });
+
+    it('should create parser from GGUF tokenizer metadata', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: ' hi' }],
+      });
+
+      const metadata = JSON.stringify({
+        'tokenizer.ggml.model': 'llama',
+        'tokenizer.ggml.tokens': ['<unk>', '<s>', '</s>', '▁', 'h', 'i', '▁hi'],
+        'tokenizer.ggml.scores': [0, 0, 0, -2, -3, -3, -1],
+        'tokenizer.ggml.token_type': [2, 3, 3, 1, 1, 1, 1],
+        'tokenizer.ggml.eos_token_id': 2,
+        'tokenizer.ggml.bos_token_id': 1,
+      });
+
+      const parser = wasm.LLGuidanceParser.from_gguf(metadata, grammar);
+      expect(parser.vocab_size()).toBe(7);
+
+      const mask = parser.get_token_mask();
+      expect(mask[6]).toBe(1);
+      expect(mask[3]).toBe(1);
+      expect(mask[4]).toBe(0);
+      // Control tokens are never part of the text
+      expect(mask[1]).toBe(0);
+    });
+
+    it('should pad the token mask to an overridden vocab size', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+      });
+
+      const options = JSON.stringify({ vocab_size_override: 64 });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer, options);
+      expect(parser.vocab_size()).toBe(64);
+
+      const mask = parser.get_token_mask();
+      expect(mask.length).toBe(64);
+      expect(mask[0]).toBe(1);
+      expect(mask.slice(2).every((allowed) => allowed === 0)).toBe(true);
+      expect(parser.is_token_allowed(63)).toBe(false);
+
+      const tooSmall = JSON.stringify({ vocab_size_override: 1 });
+      expect(() => new wasm.LLGuidanceParser(grammar, tokenizer, tooSmall)).toThrow();
+    });
+
+    it('should tokenize and detokenize text', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: /[a-z ]+/' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ab: 2, ' ': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const ids = parser.tokenize('ab a');
+      expect(ids).toBeInstanceOf(Uint32Array);
+      expect(parser.detokenize(ids)).toBe('ab a');
+      expect(parser.detokenize([2, 4])).toBe('ab</s>');
+      expect(() => parser.detokenize([99])).toThrow();
+    });
+
+    it('should expose token bytes and display strings', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, '\n': 1 },
+        added_tokens: [{ id: 2, content: '<|im_start|>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.token_bytes(0))).toEqual([0x61]);
+      expect(parser.token_bytes(2)[0]).toBe(0xff);
+
+      expect(parser.token_display(0)).toBe('"a"');
+      expect(parser.token_display(1)).toBe('"\\n"');
+      expect(parser.token_display(2)).toBe('<|im_start|>');
+      expect(() => parser.token_display(3)).toThrow();
+    });
+
+    it('should exclude special tokens when requested', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" <|im_start|>' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0 },
+        added_tokens: [
+          { id: 1, content: '</s>', special: true },
+          { id: 2, content: '<|im_start|>', special: true },
+        ],
+        eos_token_id: 1,
+      });
+
+      const options = JSON.stringify({ exclude_special_tokens: true });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer, options);
+      parser.advance(0);
+
+      // Banned even though the grammar asks for it
+      expect(parser.get_token_mask()[2]).toBe(0);
+      expect(parser.is_token_allowed(2)).toBe(false);
+    });
+
+    it('should export and reload the tokenizer environment', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ab: 2 },
+        merges: ['a b'],
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const original = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const blob = original.export_tok_env();
+      expect(blob).toBeInstanceOf(Uint8Array);
+
+      const restored = wasm.LLGuidanceParser.from_tok_env_blob(blob, grammar);
+      expect(restored.vocab_size()).toBe(original.vocab_size());
+      expect(Array.from(restored.get_token_mask())).toEqual(Array.from(original.get_token_mask()));
+      expect(Array.from(restored.tokenize('ab'))).toEqual([2]);
+      expect(Array.from(restored.token_bytes(3))).toEqual(Array.from(original.token_bytes(3)));
+
+      expect(() => wasm.LLGuidanceParser.from_tok_env_blob(new Uint8Array([1, 2, 3]), grammar)).toThrow();
+    });
+
+    it('should reject corrupted tokenizer environment blobs', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ab: 2 },
+        merges: ['a b'],
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const blob = new wasm.LLGuidanceParser(grammar, tokenizer).export_tok_env();
+      // Layout: magic, version, vocab size, EOS token, ...
+      const corrupt = (offset: number, value: number) => {
+        const copy = blob.slice();
+        new DataView(copy.buffer).setUint32(offset, value, true);
+        return copy;
+      };
+      const load = (bytes: Uint8Array) => () =>
+        wasm.LLGuidanceParser.from_tok_env_blob(bytes, grammar);
+
+      expect(load(blob.slice(0, blob.length - 3))).toThrow('truncated');
+      expect(load(corrupt(8, 0xffffffff))).toThrow('truncated');
+      expect(load(corrupt(12, 4))).toThrow('EOS token 4 is outside the vocabulary of 4 tokens');
+    });
+
+    it('should handle a 256k-token vocabulary', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "t262142"' }],
+      });
+
+      // 2^18 tokens, well past the 16-bit ID range, with EOS last
+      const encoder = new TextEncoder();
+      const vocabSize = 262_144;
+      const tokens: Uint8Array[] = [];
+      for (let i = 0; i < vocabSize - 1; i++) {
+        tokens.push(encoder.encode(`t${i}`));
+      }
+      tokens.push(new Uint8Array([0xff, ...encoder.encode('</s>')]));
+
+      const parser = wasm.LLGuidanceParser.from_vocab(tokens, vocabSize - 1, grammar);
+      expect(parser.vocab_size()).toBe(vocabSize);
+
+      const mask = parser.get_token_mask();
+      expect(mask.length).toBe(vocabSize);
+      expect(mask[262_142]).toBe(1);
+      expect(mask[262_141]).toBe(0);
+      expect(parser.is_token_allowed(262_142)).toBe(true);
+
+      parser.advance(262_142);
+      expect(parser.get_token_mask()[vocabSize - 1]).toBe(1);
+      expect(parser.detokenize([262_142])).toBe('t262142');
+    });
+
+    it('should heal the last prompt token', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ' ': 2, ' a': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.get_token_mask()[3]).toBe(0);
+
+      // The prompt ended in ' ', which the model would rather emit as ' a'
+      expect(parser.enable_token_healing(2)).toBe(true);
+      expect(parser.get_token_mask()[3]).toBe(1);
+      parser.advance(3);
+      // ' a' covered both the healed ' ' and the grammar, so only EOS remains
+      const mask = parser.get_token_mask();
+      expect(mask[4]).toBe(1);
+      expect(mask[0]).toBe(0);
+
+      // No longer token starts with 'b', so there is nothing to heal
+      parser.reset(grammar);
+      expect(parser.enable_token_healing(1)).toBe(false);
+    });
+
+    it('should diagnose a grammar the vocabulary cannot produce', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "\\n"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const report = JSON.parse(parser.diagnose());
+
+      expect(report.vocab_size).toBe(3);
+      expect(report.allowed_tokens).toBe(0);
+      expect(report.special_tokens).toBe(1);
+      expect(report.unreachable_bytes).toContain(0x0a);
+      expect(report.warnings.some((w: string) => w.includes("'\\n'"))).toBe(true);
+    });
+
+    it('should report overlapping terminals and ambiguous rules', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab" | name | name\nname: IDENT\nIDENT: /[a-z]+/' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ab: 2, '1': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const report = JSON.parse(parser.diagnose_ambiguity());
+
+      expect(report.ambiguous_rules).toHaveLength(1);
+      expect(report.overlapping_terminals).toHaveLength(1);
+      expect(report.overlapping_terminals[0].terminals).toEqual(['IDENT', '"ab"']);
+      expect(report.overlapping_terminals[0].tokens).toEqual(['"a"', '"ab"']);
+      expect(report.competing_tokens.map((t: { token: string }) => t.token)).toEqual([
+        '"a"',
+        '"ab"',
+      ]);
+      expect(report.truncated).toBe(false);
+    });
+
+    it('should fill a caller-provided mask buffer', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const dest = new Uint8Array(5).fill(7);
+      parser.fill_token_mask(dest);
+      expect(Array.from(dest)).toEqual([1, 0, 0, 7, 7]);
+
+      // The same buffer is reused for the next step
+      parser.advance(0);
+      parser.fill_token_mask(dest);
+      expect(Array.from(dest.subarray(0, 3))).toEqual(Array.from(parser.get_token_mask()));
+
+      expect(() => parser.fill_token_mask(new Uint8Array(2))).toThrow();
+    });
+
+    it('should apply the mask to logits in place', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+
+      // One padded logit past the vocabulary
+      const logits = new Float32Array([0.5, 1.5, 2.5, 3.5, 4.5]);
+      parser.apply_mask(logits);
+      expect(Array.from(logits)).toEqual([0.5, 1.5, -Infinity, -Infinity, -Infinity]);
+    });
+
+    it('should sample only allowed tokens', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+
+      // 'c' has the best logit but the grammar forbids it
+      const logits = new Float32Array([1, 2, 10, 0]);
+      expect(parser.sample_token(logits, JSON.stringify({ temperature: 0 }))).toBe(1);
+      expect(parser.sample_token(logits, JSON.stringify({ top_k: 1 }))).toBe(1);
+      for (let i = 0; i < 20; i++) {
+        expect([0, 1]).toContain(parser.sample_token(logits));
+      }
+
+      // Nothing finite to choose from
+      const dead = new Float32Array([-Infinity, -Infinity, 1, 1]);
+      expect(() => parser.sample_token(dead)).toThrow();
+    });
+
+    it('should pick the shorter mask representation', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const narrow = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: "a"' }] }),
+        tokenizer,
+      );
+      const [narrowKind, narrowIds] = narrow.get_compact_mask();
+      expect(narrowKind).toBe('allowed');
+      expect(Array.from(narrowIds)).toEqual([0]);
+
+      const wide = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: /[abcd]+/' }] }),
+        tokenizer,
+      );
+      const [wideKind, wideIds] = wide.get_compact_mask();
+      expect(wideKind).toBe('disallowed');
+      expect(Array.from(wideIds)).toEqual([4]);
+    });
+
+    it('should report mask changes between steps', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: ("a" | "b") "c"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const [allowed, disallowed] = parser.get_token_mask_diff();
+      expect(Array.from(allowed)).toEqual([0, 1]);
+      expect(Array.from(disallowed)).toEqual([]);
+
+      // Nothing changes without advancing
+      const [same, none] = parser.get_token_mask_diff();
+      expect(same.length + none.length).toBe(0);
+
+      parser.advance(0);
+      const [next, dropped] = parser.get_token_mask_diff();
+      expect(Array.from(next)).toEqual([2]);
+      expect(Array.from(dropped)).toEqual([0, 1]);
+    });
+
+    it('should invert the mask and list disallowed tokens', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const options = JSON.stringify({ vocab_size_override: 5 });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer, options);
+      expect(Array.from(parser.get_token_mask(true))).toEqual([0, 0, 1, 1, 1]);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0, 0]);
+
+      const dest = new Uint8Array(5);
+      parser.fill_token_mask(dest, true);
+      expect(Array.from(dest)).toEqual([0, 0, 1, 1, 1]);
+
+      // Padding is disallowed too
+      expect(Array.from(parser.get_disallowed_tokens())).toEqual([2, 3, 4]);
+    });
+
+    it('should list the allowed tokens', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const allowed = parser.get_allowed_tokens();
+      expect(allowed).toBeInstanceOf(Uint32Array);
+      expect(Array.from(allowed)).toEqual([0, 1]);
+
+      parser.advance(1);
+      expect(Array.from(parser.get_allowed_tokens())).toEqual([3]);
+
+      // Runtime IDs come back sorted after remapping
+      const remapped = new wasm.LLGuidanceParser(
+        grammar,
+        tokenizer,
+        JSON.stringify({ id_remap: [3, 1, 0, 2] }),
+      );
+      expect(Array.from(remapped.get_allowed_tokens())).toEqual([1, 3]);
+    });
+
+    it('should pack the token mask into a bitmask', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "t33" | "t2"' }],
+      });
+
+      const encoder = new TextEncoder();
+      const tokens: Uint8Array[] = [];
+      for (let i = 0; i < 40; i++) {
+        tokens.push(encoder.encode(`t${i}`));
+      }
+      tokens.push(new Uint8Array([0xff, ...encoder.encode('</s>')]));
+
+      const parser = wasm.LLGuidanceParser.from_vocab(tokens, 40, grammar);
+      const bitmask = parser.get_token_bitmask();
+      expect(bitmask).toBeInstanceOf(Uint32Array);
+      expect(bitmask.length).toBe(2);
+
+      // Every set bit matches the byte mask
+      const mask = parser.get_token_mask();
+      for (let i = 0; i < mask.length; i++) {
+        expect((bitmask[i >> 5] >>> (i & 31)) & 1).toBe(mask[i]);
+      }
+      expect(bitmask[0] & (1 << 2)).not.toBe(0);
+      expect(bitmask[1] & (1 << 1)).not.toBe(0);
+    });
+
+    it('should compute an additive logit bias', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const options = JSON.stringify({ vocab_size_override: 4 });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer, options);
+
+      const bias = parser.compute_logit_bias();
+      expect(bias).toBeInstanceOf(Float32Array);
+      expect(Array.from(bias)).toEqual([0, -Infinity, -Infinity, -Infinity]);
+      expect(Array.from(parser.compute_logit_bias(-100))).toEqual([0, -100, -100, -100]);
+
+      // Configured once at construction
+      const configured = new wasm.LLGuidanceParser(
+        grammar,
+        tokenizer,
+        JSON.stringify({ vocab_size_override: 4, allowed_bias: 5, disallowed_bias: -1e9 }),
+      );
+      expect(Array.from(configured.compute_logit_bias())).toEqual([5, -1e9, -1e9, -1e9]);
+      expect(Array.from(configured.compute_logit_bias(-1))).toEqual([5, -1, -1, -1]);
+    });
+
+    it('should compute masks for a batch of sequences', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b" | "b" "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const batch = new wasm.ParserBatch(parser, 2);
+      expect(batch.len()).toBe(2);
+      expect(Array.from(batch.get_token_masks())).toEqual([1, 1, 0, 1, 1, 0]);
+
+      // Each sequence follows its own branch
+      batch.advance(new Uint32Array([0, 1]));
+      expect(Array.from(batch.get_token_masks())).toEqual([0, 1, 0, 1, 0, 0]);
+
+      batch.advance_sequence(0, 1);
+      expect(Array.from(batch.get_token_masks())).toEqual([0, 0, 1, 1, 0, 0]);
+      expect(batch.is_complete(1)).toBe(false);
+      expect(() => batch.advance(new Uint32Array([0]))).toThrow();
+      expect(() => batch.is_complete(2)).toThrow();
+
+      // The source parser is untouched
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0]);
+
+      batch.reset();
+      expect(Array.from(batch.get_token_masks())).toEqual([1, 1, 0, 1, 1, 0]);
+    });
+
+    it('should create parsers from grammars compiled by a factory', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const factory = new wasm.LLGuidanceFactory(tokenizer);
+      const aRef = factory.compile_grammar(JSON.stringify({ grammars: [{ lark: 'start: "a"' }] }));
+      const bRef = factory.compile_grammar(JSON.stringify({ grammars: [{ lark: 'start: "b"' }] }));
+      expect(factory.grammar_count()).toBe(2);
+
+      const first = factory.create_parser(aRef);
+      const second = factory.create_parser(aRef);
+      expect(Array.from(first.get_token_mask())).toEqual([1, 0, 0]);
+      expect(Array.from(factory.create_parser(bRef).get_token_mask())).toEqual([0, 1, 0]);
+
+      // Parsers from one grammar advance independently
+      first.advance(0);
+      expect(first.is_complete()).toBe(true);
+      expect(Array.from(second.get_token_mask())).toEqual([1, 0, 0]);
+
+      expect(() => factory.create_parser(2)).toThrow();
+    });
+
+    it('should keep draft and target vocabularies in sync', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab" ("cd" | "ce")' }],
+      });
+
+      const target = new wasm.LLGuidanceParser(
+        grammar,
+        JSON.stringify({
+          vocab: { ab: 0, cd: 1, ce: 2, a: 3, b: 4, c: 5, d: 6, e: 7 },
+          added_tokens: [{ id: 8, content: '</s>', special: true }],
+        }),
+      );
+      const draft = new wasm.LLGuidanceParser(
+        grammar,
+        JSON.stringify({
+          vocab: { a: 0, b: 1, c: 2, d: 3, e: 4 },
+          added_tokens: [{ id: 5, content: '</s>', special: true }],
+        }),
+      );
+
+      const speculative = new wasm.SpeculativeParser(target, draft);
+      expect(speculative.target_vocab_size()).toBe(9);
+      expect(speculative.draft_vocab_size()).toBe(6);
+      expect(Array.from(speculative.draft_mask())).toEqual([1, 0, 0, 0, 0, 0]);
+
+      speculative.advance_draft(0);
+      speculative.advance_draft(1);
+      speculative.advance_draft(2);
+      expect(Array.from(speculative.draft_mask())).toEqual([0, 0, 0, 1, 1, 0]);
+      // "abc" in target tokens: the grammar accepts "ab" then "c"
+      expect(Array.from(speculative.draft_as_target_tokens())).toEqual([0, 5]);
+
+      // The target accepts "ab"; the draft restarts from there
+      speculative.commit(new Uint32Array([0]));
+      expect(Array.from(speculative.draft_mask())).toEqual([0, 0, 1, 0, 0, 0]);
+      expect(speculative.target_mask()[1]).toBe(1);
+
+      speculative.commit(new Uint32Array([2]));
+      expect(Array.from(speculative.target_mask())).toEqual([0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    });
+
+    it('should fork beams that share a prefix', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "x" ("a" "b" | "b" "a")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { x: 0, a: 1, b: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance(0);
+
+      const fork = parser.fork();
+      expect(Array.from(fork.get_token_mask())).toEqual(Array.from(parser.get_token_mask()));
+      fork.advance(2);
+      expect(Array.from(fork.get_token_mask())).toEqual([0, 1, 0, 0]);
+      // The original is still at the branch point
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 1, 0]);
+
+      const batch = new wasm.ParserBatch(parser, 1);
+      batch.advance(new Uint32Array([0]));
+      expect(batch.fork(0)).toBe(1);
+      batch.advance(new Uint32Array([1, 2]));
+      expect(Array.from(batch.get_token_masks())).toEqual([0, 0, 1, 0, 0, 1, 0, 0]);
+
+      batch.remove(0);
+      expect(batch.len()).toBe(1);
+      expect(Array.from(batch.get_token_masks())).toEqual([0, 1, 0, 0]);
+      expect(() => batch.remove(0)).toThrow();
+    });
+
+    it('should build a parser from vocabulary chunks', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const encoder = new TextEncoder();
+      const tokens = ['a', 'b', 'ab'].map((t) => encoder.encode(t));
+      tokens.push(new Uint8Array([0xff, ...encoder.encode('</s>')]));
+
+      const builder = new wasm.TokEnvBuilder();
+      builder.add_chunk(tokens.slice(0, 2));
+      builder.add_chunk(tokens.slice(2));
+      expect(builder.len()).toBe(4);
+
+      const parser = builder.finish(3, grammar);
+      const direct = wasm.LLGuidanceParser.from_vocab(tokens, 3, grammar);
+      expect(parser.vocab_size()).toBe(4);
+      expect(Array.from(parser.get_token_mask())).toEqual(Array.from(direct.get_token_mask()));
+      expect(Array.from(parser.tokenize('ab'))).toEqual([2]);
+    });
+
+    it('should enforce the grammar only after the trigger token', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [
+          { id: 2, content: '</s>', special: true },
+          { id: 3, content: '<|im_start|>', special: true },
+          { id: 4, content: '<|assistant|>', special: true },
+        ],
+        eos_token_id: 2,
+      });
+
+      const options = JSON.stringify({ passthrough_tokens: [3], trigger_token: 4 });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer, options);
+
+      // Scaffolding only until the trigger
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1, 1]);
+      expect(parser.is_complete()).toBe(false);
+      expect(() => parser.advance(0)).toThrow();
+      parser.advance(3);
+      parser.advance(4);
+
+      // The grammar applies from here, with the passthrough token still allowed
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0, 1, 0]);
+      parser.advance(3);
+      parser.advance(0);
+      expect(parser.get_token_mask()[2]).toBe(1);
+
+      // Resetting waits for the trigger again
+      parser.reset(grammar);
+      expect(parser.is_token_allowed(0)).toBe(false);
+    });
+
+    it('should remap token IDs to the runtime order', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      // The runtime puts 'a' at 2, 'b' at 0 and '</s>' at 1
+      const options = JSON.stringify({ id_remap: [2, 0, 1] });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer, options);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1]);
+      expect(parser.is_token_allowed(2)).toBe(true);
+      expect(parser.is_token_allowed(0)).toBe(false);
+
+      parser.advance(2);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0]);
+      parser.advance(0);
+      expect(parser.get_token_mask()[1]).toBe(1);
+
+      expect(Array.from(parser.tokenize('ab'))).toEqual([2, 0]);
+      expect(parser.detokenize([2, 0])).toBe('ab');
+
+      const duplicate = JSON.stringify({ id_remap: [0, 0, 1] });
+      expect(() => new wasm.LLGuidanceParser(grammar, tokenizer, duplicate)).toThrow();
+      const tooShort = JSON.stringify({ id_remap: [0, 1] });
+      expect(() => new wasm.LLGuidanceParser(grammar, tokenizer, tooShort)).toThrow();
+      const tooLarge = JSON.stringify({ id_remap: [0, 1, 0xffffffff] });
+      expect(() => new wasm.LLGuidanceParser(grammar, tokenizer, tooLarge)).toThrow(
+        'runtime IDs must be below'
+      );
+    });
+
+    it('should advance and return the next mask in one step', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.step(0))).toEqual([0, 1, 0]);
+      expect(Array.from(parser.step(1))).toEqual([0, 0, 1]);
+      expect(() => parser.step(0)).toThrow();
+    });
+
+    it('should return forced tokens without advancing', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab" ("c" | "d")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.get_forced_tokens())).toEqual([0, 1]);
+      // Still at the start
+      expect(parser.get_token_mask()[0]).toBe(1);
+
+      parser.advance(0);
+      parser.advance(1);
+      expect(Array.from(parser.get_forced_tokens())).toEqual([]);
+    });
+
+    it('should probe tokens without advancing', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.try_advance(0)).toBe(true);
+      expect(parser.try_advance(1)).toBe(false);
+      expect(parser.try_advance(99)).toBe(false);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0]);
+
+      parser.advance(0);
+      expect(parser.try_advance(1)).toBe(true);
+    });
+
+    it('should advance over many tokens at once', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "abc" "d"?' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance_many(new Uint32Array([0, 1, 2]));
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1, 1]);
+
+      // 'a' is rejected after it; 'd' stays consumed
+      expect(() => parser.advance_many(new Uint32Array([3, 0]))).toThrow();
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 0, 1]);
+    });
+
+    it('should report the first rejected token in a sequence', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b" "c"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.validate_tokens(new Uint32Array([0, 2, 1]))).toBe(1);
+
+      // The parser stays usable after the accepted prefix
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 0, 0]);
+      expect(parser.validate_tokens(new Uint32Array([1, 2, 3]))).toBe(-1);
+      expect(parser.is_complete()).toBe(true);
+
+      const fresh = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(fresh.validate_tokens(new Uint32Array([0, 100]))).toBe(1);
+    });
+
+    it('should consume raw text and bytes', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "Answer: " ("yes" | "no")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { Answer: 0, ':': 1, ' ': 2, yes: 3, no: 4, y: 5, e: 6, s: 7 },
+        added_tokens: [{ id: 8, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.consume_text('Answer: '))).toEqual([0, 1, 2]);
+      expect(parser.get_token_mask()[3]).toBe(1);
+
+      parser.consume_bytes(new TextEncoder().encode('ye'));
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 0, 0, 0, 0, 1, 0]);
+
+      expect(() => parser.consume_text('no')).toThrow();
+    });
+
+    it('should roll back consumed tokens', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" ("b" | "c") "d"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3, '<role>': 4 },
+        added_tokens: [{ id: 5, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        grammar,
+        tokenizer,
+        JSON.stringify({ passthrough_tokens: [4] }),
+      );
+      parser.advance(0);
+      parser.advance(4);
+      parser.advance(1);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1, 1, 0]);
+
+      // Undoes "b" and the passthrough token, leaving "a"
+      parser.rollback(2);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 1, 0, 1, 0]);
+      parser.advance(2);
+      expect(parser.get_token_mask()[3]).toBe(1);
+
+      expect(() => parser.rollback(3)).toThrow();
+      parser.rollback(2);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0, 0, 1, 0]);
+    });
+
+    it('should limit rollback depth', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b" "c"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        grammar,
+        tokenizer,
+        JSON.stringify({ max_rollback_tokens: 1 }),
+      );
+      parser.advance(0);
+      parser.advance(1);
+      expect(() => parser.rollback(2)).toThrow();
+      parser.rollback(1);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 0]);
+
+      const batch = new wasm.ParserBatch(parser, 2);
+      batch.advance_sequence(1, 0);
+      batch.advance_sequence(1, 1);
+      batch.rollback(1, 1);
+      expect(Array.from(batch.get_token_masks())).toEqual([1, 0, 0, 0, 1, 0]);
+      expect(() => batch.rollback(0, 1)).toThrow();
+    });
+
+    it('should save and restore the parser state', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" ("b" | "c") ("d" | "e")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3, e: 4 },
+        added_tokens: [{ id: 5, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance(0);
+      parser.advance(2);
+      const state = parser.save_state();
+
+      const restored = new wasm.LLGuidanceParser(grammar, tokenizer);
+      restored.load_state(state);
+      expect(Array.from(restored.get_token_mask())).toEqual([0, 0, 0, 1, 1, 0]);
+      restored.advance(3);
+      expect(restored.get_token_mask()[5]).toBe(1);
+
+      const other = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: "a"' }] }),
+        tokenizer,
+      );
+      expect(() => other.load_state(state)).toThrow();
+      expect(() => restored.load_state(new Uint8Array([1, 2, 3]))).toThrow();
+      // A failed load leaves the parser where it was
+      expect(restored.get_token_mask()[5]).toBe(1);
+    });
+
+    it('should restore a saved state over pushed grammars', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: " a" "b"' }],
+      });
+      const pushed = JSON.stringify({
+        grammars: [{ lark: 'start: "c"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ' ': 2, ' a': 3, c: 4 },
+        added_tokens: [{ id: 5, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.enable_token_healing(2)).toBe(true);
+      parser.advance(3);
+      const state = parser.save_state();
+
+      parser.push_grammar(pushed);
+      expect(() => parser.save_state()).toThrow('push_grammar');
+      parser.advance(4);
+      parser.pop_grammar();
+      parser.push_grammar(pushed);
+
+      // Back on the base grammar, healed and after ' a', wherever the stack was
+      parser.load_state(state);
+      expect(parser.grammar_depth()).toBe(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 0, 0, 0, 0]);
+      parser.advance(1);
+      expect(parser.is_complete()).toBe(true);
+    });
+
+    it('should report the consumed text', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "hi" " " "there"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { hi: 0, ' ': 1, there: 2, '<role>': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        grammar,
+        tokenizer,
+        JSON.stringify({ passthrough_tokens: [3] }),
+      );
+      expect(parser.consumed_text()).toBe('');
+
+      parser.advance(0);
+      parser.advance(3);
+      parser.advance(1);
+      expect(parser.consumed_text()).toBe('hi ');
+
+      parser.advance(2);
+      expect(parser.consumed_text()).toBe('hi there');
+      expect(Array.from(parser.consumed_bytes())).toEqual(Array.from(new TextEncoder().encode('hi there')));
+
+      parser.rollback(2);
+      expect(parser.consumed_text()).toBe('hi');
+
+      parser.advance(1);
+      parser.advance(2);
+      parser.advance(4);
+      expect(parser.consumed_text()).toBe('hi there');
+    });
+
+    it('should expand imports from the Lark common library', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '.': 1, a: 2, ' ': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+      const lark = (grammar: string) => JSON.stringify({ grammars: [{ lark: grammar }] });
+
+      const parser = new wasm.LLGuidanceParser(
+        lark('start: NUMBER+\n%import common (NUMBER, WS)\n%ignore WS'),
+        tokenizer,
+      );
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 1, 0]);
+      parser.advance(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 1, 1]);
+
+      const aliased = new wasm.LLGuidanceParser(
+        lark('start: N\n%import common.INT -> N'),
+        tokenizer,
+      );
+      expect(Array.from(aliased.get_token_mask())).toEqual([1, 0, 0, 0, 0]);
+
+      expect(() => new wasm.LLGuidanceParser(lark('%import common.FOO'), tokenizer)).toThrow(
+        'Unknown terminal common.FOO',
+      );
+      expect(() => new wasm.LLGuidanceParser(lark('%import mylib.X'), tokenizer)).toThrow(
+        'only Lark\'s common library is bundled',
+      );
+    });
+
+    it('should report named captures', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "x=" num\nnum[capture="num"]: /[0-9]+/' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { 'x=': 0, '4': 1, '2': 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.get_captures()).toEqual([]);
+
+      parser.advance(0);
+      parser.advance(1);
+      parser.advance(2);
+      parser.advance(3);
+      expect(parser.get_captures()).toEqual([['num', '42']]);
+    });
+
+    it('should export the parse tree of a Lark grammar', async () => {
+      const lark = [
+        'start: assign+',
+        'assign: NAME "=" value ";"',
+        'value: NAME | NUMBER',
+        'NAME: /[a-z]+/',
+        'NUMBER: /[0-9]+/',
+      ].join('\n');
+      const grammar = JSON.stringify({ grammars: [{ lark }] });
+
+      const tokenizer = JSON.stringify({
+        vocab: { x: 0, y: 1, '=': 2, ';': 3, '1': 4, '2': 5 },
+        added_tokens: [{ id: 6, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      for (const token of [0, 2, 4, 5, 3, 1, 2, 0, 3, 6]) {
+        parser.advance(token);
+      }
+
+      const node = (rule: string, start: number, text: string, children: unknown[] = []) => ({
+        rule,
+        start,
+        end: start + text.length,
+        text,
+        children,
+      });
+      expect(JSON.parse(parser.get_parse_tree())).toEqual(
+        node('start', 0, 'x=12;y=x;', [
+          node('assign', 0, 'x=12;', [node('value', 2, '12')]),
+          node('assign', 5, 'y=x;', [node('value', 7, 'x')]),
+        ]),
+      );
+
+      const json = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ json_schema: { type: 'integer' } }] }),
+        tokenizer,
+      );
+      expect(() => json.get_parse_tree()).toThrow('needs a Lark grammar');
+    });
+
+    it('should report the temperature annotated on the current region', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "k=" val\nval[temperature=0.8]: /[a-z]+/' }],
+      });
+      const tokenizer = JSON.stringify({
+        vocab: { 'k=': 0, a: 1, b: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.get_temperature()).toBeUndefined();
+      parser.advance(0);
+      expect(parser.get_temperature()).toBeCloseTo(0.8);
+      parser.advance(1);
+      parser.advance(2);
+      expect(parser.get_temperature()).toBeCloseTo(0.8);
+      parser.rollback(3);
+      expect(parser.get_temperature()).toBeUndefined();
+    });
+
+    it('should validate finished text', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: '[0-9]+' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '2': 1, '12': 2, a: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance(0);
+      expect(parser.validate_text('12')).toBe(true);
+      expect(parser.validate_text('121')).toBe(true);
+      expect(parser.validate_text('')).toBe(false);
+      expect(parser.validate_text('1a')).toBe(false);
+      // The parser is still after '1'
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 1, 0, 1]);
+    });
+
+    it('should validate text against the base grammar and its stop sequences', async () => {
+      const grammar = JSON.stringify({ grammars: [{ rx: '[0-9]+' }], stop: ['2'] });
+
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '2': 1, '12': 2, a: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      // '2' ends the text, so nothing may follow it
+      expect(parser.validate_text('12')).toBe(true);
+      expect(parser.validate_text('121')).toBe(false);
+
+      parser.push_grammar(JSON.stringify({ grammars: [{ rx: 'a+' }] }));
+      expect(parser.validate_text('1')).toBe(true);
+      expect(parser.validate_text('a')).toBe(false);
+    });
+
+    it('should report what the grammar expects next', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "{" ("}" | "\\"k\\": 1}")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { '{': 0, '}': 1, '"': 2, '"k': 3, k: 4, '": 1}': 5 },
+        added_tokens: [{ id: 6, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.expected()).toEqual(['{']);
+
+      parser.advance(0);
+      expect(parser.expected()).toEqual(['"', '}']);
+
+      parser.advance(3);
+      expect(parser.expected()).toEqual(['": 1}']);
+    });
+
+    it('should report forced bytes and forced steps', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab" ("c" | "d")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3, ab: 4 },
+        added_tokens: [{ id: 5, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      // The text is fixed, but 'a' and 'ab' are both allowed
+      expect(new TextDecoder().decode(parser.forced_bytes())).toBe('ab');
+      expect(parser.is_forced()).toBe(false);
+
+      parser.advance(0);
+      expect(new TextDecoder().decode(parser.forced_bytes())).toBe('b');
+      expect(parser.is_forced()).toBe(true);
+
+      parser.advance(1);
+      expect(parser.forced_bytes().length).toBe(0);
+      expect(parser.is_forced()).toBe(false);
+    });
+
+    it('should detect dead ends', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.can_advance()).toBe(true);
+
+      // Banning the only continuation leaves nothing to sample
+      parser.advance(0);
+      parser.set_token_deny_list(new Uint32Array([1]));
+      expect(parser.can_advance()).toBe(false);
+
+      parser.set_token_deny_list(new Uint32Array([]));
+      expect(parser.can_advance()).toBe(true);
+      parser.advance(1);
+      parser.advance(2);
+      expect(parser.can_advance()).toBe(false);
+    });
+
+    it('should report when only EOS is allowed', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b"?' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.must_stop()).toBe(false);
+
+      // Could stop here, but 'b' is still allowed
+      parser.advance(0);
+      expect(parser.get_token_mask()[2]).toBe(1);
+      expect(parser.must_stop()).toBe(false);
+
+      parser.advance(1);
+      expect(parser.must_stop()).toBe(true);
+    });
+
+    it('should layer deny and force-allow lists on the grammar', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [
+          { id: 3, content: '</s>', special: true },
+          { id: 4, content: '<|cancel|>', special: true },
+        ],
+        eos_token_id: 3,
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0, 0]);
+
+      parser.set_token_deny_list(new Uint32Array([1]));
+      parser.set_token_force_allow_list(new Uint32Array([4]));
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0, 0, 1]);
+      expect(parser.is_token_allowed(1)).toBe(false);
+
+      // The cancellation token does not disturb the grammar
+      parser.advance(4);
+      expect(parser.get_token_mask()[0]).toBe(1);
+
+      parser.set_token_deny_list(new Uint32Array([]));
+      expect(parser.get_token_mask()[1]).toBe(1);
+      expect(() => parser.set_token_deny_list(new Uint32Array([99]))).toThrow();
+    });
+
+    it('should filter candidate tokens', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const kept = parser.filter_candidates(new Uint32Array([2, 1, 99, 0]));
+      expect(kept).toBeInstanceOf(Uint32Array);
+      expect(Array.from(kept)).toEqual([1, 0]);
+    });
+
+    it('should check single tokens without computing the mask first', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab" | "ba"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, ab: 2, c: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      // Fresh parsers, so no mask is cached yet
+      const check = (tokenId: number) =>
+        new wasm.LLGuidanceParser(grammar, tokenizer).is_token_allowed(tokenId);
+      const mask = Array.from(new wasm.LLGuidanceParser(grammar, tokenizer).get_token_mask());
+      for (let id = 0; id < mask.length; id++) {
+        expect(check(id)).toBe(mask[id] === 1);
+      }
+      expect(check(99)).toBe(false);
+    });
+
+    it('should reuse the mask within a step and refresh it after advancing', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "ab"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.is_token_allowed(0)).toBe(true);
+      expect(parser.is_token_allowed(1)).toBe(false);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0]);
+
+      parser.advance(0);
+      expect(parser.is_token_allowed(0)).toBe(false);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 0]);
+
+      parser.reset(grammar);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0]);
+    });
+
+    it('should collect telemetry for the generation', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" ("b" | "c")' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.get_token_mask();
+      parser.advance(0);
+      // The second request at the same position is served from cache
+      parser.get_token_mask();
+      parser.get_token_mask();
+      parser.advance(1);
+
+      const telemetry = JSON.parse(parser.get_telemetry());
+      expect(telemetry.steps).toBe(2);
+      expect(telemetry.forced_steps).toBe(1);
+      expect(telemetry.mask_computations).toBe(2);
+      expect(telemetry.average_allowed_tokens).toBeCloseTo(1.5);
+      expect(telemetry.mask_time_ms).toBeGreaterThanOrEqual(0);
+
+      parser.reset(grammar);
+      expect(JSON.parse(parser.get_telemetry()).steps).toBe(0);
+    });
+
+    it('should report the latest step stats', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: ("a" | "b") "c"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.get_token_mask();
+      let stats = JSON.parse(parser.last_step_stats());
+      expect(stats.allowed_tokens).toBe(2);
+      expect(stats.mask_time_us).toBeGreaterThanOrEqual(0);
+
+      parser.advance(0);
+      parser.get_token_mask();
+      stats = JSON.parse(parser.last_step_stats());
+      expect(stats.allowed_tokens).toBe(1);
+      expect(stats.advance_time_us).toBeGreaterThanOrEqual(0);
+    });
+
+    it('should report Earley and lexer counters for the last step', async () => {
+      const lark = [
+        'start: item ("," item)*',
+        'item: NAME "=" (NAME | NUMBER)',
+        'NAME: /[a-z]+/',
+        'NUMBER: /[0-9]+/',
+      ].join('\n');
+      const grammar = JSON.stringify({ grammars: [{ lark }] });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, '=': 2, ',': 3, '1': 4, ab: 5 },
+        added_tokens: [{ id: 6, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      for (const token of [5, 2, 4, 3, 0]) {
+        parser.advance(token);
+      }
+      parser.get_token_mask();
+      const stats = JSON.parse(parser.last_step_stats());
+      expect(stats.earley_rows).toBeGreaterThan(0);
+      expect(stats.earley_items).toBeGreaterThan(0);
+      expect(stats.lexer_states).toBeGreaterThan(0);
+      expect(stats.trie_nodes_walked).toBeGreaterThan(0);
+      expect(stats.lexer_cost).toBeGreaterThan(0);
+
+      // The kept parser catches up for the next step
+      parser.advance(2);
+      expect(JSON.parse(parser.last_step_stats()).earley_rows).toBeGreaterThan(0);
+    });
+
+    it('should report mask statistics', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" | "b" | "c" | "d"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3, e: 4, f: 5, g: 6 },
+        added_tokens: [{ id: 7, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const stats = JSON.parse(parser.mask_stats());
+      expect(stats.allowed_tokens).toBe(4);
+      expect(stats.vocab_size).toBe(8);
+      expect(stats.coverage).toBeCloseTo(0.5);
+      expect(stats.entropy_bits).toBeCloseTo(2);
+      expect(stats.eos_allowed).toBe(false);
+
+      parser.advance(0);
+      const done = JSON.parse(parser.mask_stats());
+      expect(done.allowed_tokens).toBe(1);
+      expect(done.entropy_bits).toBe(0);
+      expect(done.eos_allowed).toBe(true);
+    });
+
     it('should get token mask', async () => {
       const grammar = JSON.stringify({
         grammars: [{ lark: 'start: "a"' }],
@@ -132,6 +1985,26 @@ describe('Integration Tests', () => {
       expect(() => parser.reset(newGrammar)).not.toThrow();
     });
 
+    it('should reset to the start of the current grammar without a new one', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const initialMask = Array.from(parser.get_token_mask());
+
+      parser.advance(0);
+      expect(Array.from(parser.get_token_mask())).not.toEqual(initialMask);
+
+      parser.reset();
+      expect(Array.from(parser.get_token_mask())).toEqual(initialMask);
+      expect(() => parser.advance(0)).not.toThrow();
+    });
+
     it('should report stop reason', async () => {
       const grammar = JSON.stringify({
         grammars: [{ lark: 'start: "a"' }],
@@ -146,6 +2019,754 @@ describe('Integration Tests', () => {
 
       expect(typeof stopReason).toBe('string');
     });
+
+    it('should report completion in an accepting state that can continue', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a" "b"?' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1 },
+        added_tokens: [{ id: 2, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.is_complete()).toBe(false);
+
+      parser.advance(0);
+      expect(parser.is_complete()).toBe(true);
+      expect(parser.is_stopped()).toBe(false);
+
+      parser.advance(2);
+      expect(parser.is_complete()).toBe(true);
+      expect(parser.is_stopped()).toBe(true);
+    });
+
+    it('should end generation at a stop sequence', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: /[a-z\\n]*/' }],
+        stop: ['\n\n'],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, '\n': 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance(0);
+      parser.advance(2);
+      expect(parser.is_stopped()).toBe(false);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 1, 1]);
+
+      parser.advance(2);
+      expect(parser.is_stopped()).toBe(true);
+      expect(parser.is_complete()).toBe(true);
+      expect(parser.stop_reason()).toBe('EndOfSentence');
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1]);
+      expect(() => parser.advance(1)).toThrow();
+
+      // Rolling back past the sequence lets generation continue
+      parser.rollback(1);
+      expect(parser.is_stopped()).toBe(false);
+      expect(parser.get_token_mask()[1]).toBe(1);
+    });
+
+    it('should check single tokens against a stop sequence', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: /[a-z\\n]*/' }],
+        stop: ['\n\n'],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, '\n': 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      // No mask is computed, so these check the tokens on their own
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance(0);
+      parser.advance(2);
+      parser.advance(2);
+      expect(parser.is_token_allowed(1)).toBe(false);
+      expect(parser.is_token_allowed(3)).toBe(true);
+      expect(Array.from(parser.filter_candidates(new Uint32Array([0, 1, 2, 3])))).toEqual([3]);
+    });
+
+    it('should force nothing past a stop sequence inside a grammar', async () => {
+      const grammar = JSON.stringify({
+        grammars: [
+          {
+            json_schema: {
+              type: 'object',
+              properties: { a: { type: 'string' }, b: { type: 'string' } },
+              required: ['a', 'b'],
+              additionalProperties: false,
+            },
+          },
+        ],
+        stop: ['x"'],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { '{': 0, '"': 1, a: 2, ':': 3, x: 4, ',': 5, b: 6, '}': 7 },
+        added_tokens: [{ id: 8, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance_many([0, 1, 2, 1, 3, 1, 4]);
+      expect(parser.is_stopped()).toBe(false);
+
+      // The schema forces ',"b":"' next, but the stop sequence ends the text first
+      parser.advance(1);
+      expect(parser.is_stopped()).toBe(true);
+      expect(parser.forced_bytes().length).toBe(0);
+      expect(Array.from(parser.get_forced_tokens())).toEqual([]);
+      expect(parser.is_forced()).toBe(true);
+    });
+
+    it('should compile stop sequences into free-text grammars', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, '\n': 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      for (const source of [{ regex: '[a-z\\n]*' }, { text_until: 'b' }]) {
+        const grammar = JSON.stringify({ grammars: [source], stop: ['\n\n'] });
+        const compiled = wasm.LLGuidanceParser.compile_grammar(grammar);
+
+        // Drop the stored sequence (its length and two bytes) so that only
+        // the grammar itself can end the text there
+        const withoutStop = new Uint8Array(compiled.length - 6);
+        withoutStop.set(compiled.subarray(0, compiled.length - 10));
+
+        const parser = wasm.LLGuidanceParser.from_compiled_grammar(withoutStop, tokenizer);
+        parser.advance(0);
+        parser.advance(2);
+        expect(parser.get_token_mask()[0]).toBe(1);
+        parser.advance(2);
+        expect(parser.is_complete()).toBe(true);
+        expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1]);
+      }
+    });
+
+    it('should end a regex at a stop sequence in the middle of a match', async () => {
+      const grammar = JSON.stringify({ grammars: [{ regex: 'abcdef' }], stop: ['c'] });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0, b: 1, c: 2, d: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      parser.advance_many([0, 1]);
+      expect(parser.get_token_mask()[2]).toBe(1);
+
+      parser.advance(2);
+      expect(parser.is_complete()).toBe(true);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 0, 1]);
+    });
+
+    it('should honor max_tokens from the grammar input', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0 },
+        added_tokens: [{ id: 1, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: /a+/', max_tokens: 2 }] }),
+        tokenizer,
+      );
+      parser.advance(0);
+      expect(parser.stop_reason()).toBe('NotStopped');
+      parser.advance(0);
+      parser.get_token_mask();
+      expect(parser.stop_reason()).toBe('MaxTokens');
+
+      // The overall limit is tighter than the grammar's own
+      const overall = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: /a+/', max_tokens: 5 }], max_tokens: 1 }),
+        tokenizer,
+      );
+      overall.advance(0);
+      overall.get_token_mask();
+      expect(overall.stop_reason()).toBe('MaxTokens');
+    });
+
+    it('should compose named sub-grammars', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { x: 0, '1': 1, '2': 2, a: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({
+          grammars: [{ lark: 'start: "x" @digit' }, { name: 'digit', rx: '[0-9]' }],
+        }),
+        tokenizer,
+      );
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0, 0, 0]);
+      parser.advance(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 1, 0, 0]);
+
+      // Sub-grammars that cannot be reached are rejected
+      const compose = (grammars: unknown[]) =>
+        new wasm.LLGuidanceParser(JSON.stringify({ grammars }), tokenizer);
+      expect(() => compose([{ lark: 'start: "x" @digit' }, { rx: '[0-9]' }])).toThrow('name');
+      expect(() => compose([{ lark: 'start: "x"' }, { name: 'digit', rx: '[0-9]' }])).toThrow(
+        'never referenced',
+      );
+      expect(() => compose([{ rx: 'x' }, { name: 'digit', rx: '[0-9]' }])).toThrow('root grammar');
+    });
+
+    it('should accept GBNF grammars', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { yes: 0, no: 1, '!': 2, a: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({
+          grammars: [{ gbnf: 'root ::= answer [!]{0,2}\nanswer ::= "yes" | "no"' }],
+        }),
+        tokenizer,
+      );
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0, 0]);
+      parser.advance(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0, 1]);
+
+      const gbnf = (grammar: string) =>
+        new wasm.LLGuidanceParser(JSON.stringify({ grammars: [{ gbnf: grammar }] }), tokenizer);
+      expect(() => gbnf('answer ::= "yes"')).toThrow('root rule');
+      expect(() => gbnf('root ::= answer')).toThrow('never defined');
+    });
+
+    it('should accept EBNF grammars', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '+': 1, '-': 2, a: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      // ISO style, with an optional sign and a repeated tail
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({
+          grammars: [{ ebnf: 'sum = [ "-" ], "1", { "+", "1" } ;' }],
+        }),
+        tokenizer,
+      );
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 1, 0, 0]);
+      parser.advance(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 0, 0, 1]);
+
+      // W3C style, with no terminators
+      const w3c = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ ebnf: "sum ::= one ('+' one)*\none ::= '1'" }] }),
+        tokenizer,
+      );
+      expect(Array.from(w3c.get_token_mask())).toEqual([1, 0, 0, 0, 0]);
+
+      const ebnf = (grammar: string) =>
+        new wasm.LLGuidanceParser(JSON.stringify({ grammars: [{ ebnf: grammar }] }), tokenizer);
+      expect(() => ebnf('sum = one ;')).toThrow('never defined');
+      expect(() => ebnf('sum = "1" - "2" ;')).toThrow('not supported');
+    });
+
+    it('should allow any alternative of a grammar union', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '{}': 0, no: 1, '!': 2, a: 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const grammar = JSON.stringify({
+        grammars: [{ any_of: [{ json_schema: { type: 'object' } }, { lark: 'start: "no" "!"?' }] }],
+      });
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0, 0]);
+      parser.advance(1);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0, 1]);
+
+      const json = new wasm.LLGuidanceParser(grammar, tokenizer);
+      json.advance(0);
+      expect(Array.from(json.get_token_mask())).toEqual([0, 0, 0, 0, 1]);
+
+      expect(
+        () => new wasm.LLGuidanceParser(JSON.stringify({ grammars: [{ any_of: [] }] }), tokenizer),
+      ).toThrow('at least one alternative');
+    });
+
+    it('should move through the stages of a grammar sequence', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { hm: 0, '</think>': 1, '{}': 2, '</answer>': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({
+          grammars: [
+            {
+              sequence: [
+                { text_until: '</think>' },
+                { json_schema: { type: 'object' } },
+                { lark: 'start: "</answer>"' },
+              ],
+            },
+          ],
+        }),
+        tokenizer,
+      );
+      parser.advance(0);
+      parser.advance(0);
+      parser.advance(1);
+      // The reasoning stage ended at its delimiter
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0, 0]);
+      parser.advance(2);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1, 0]);
+      parser.advance(3);
+      expect(parser.is_complete()).toBe(true);
+
+      const empty = JSON.stringify({ grammars: [{ sequence: [] }] });
+      expect(() => new wasm.LLGuidanceParser(empty, tokenizer)).toThrow('at least one stage');
+    });
+
+    it('should enforce a lazy grammar only between its trigger and end tag', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { hi: 0, '<tool_call>': 1, '{}': 2, '</tool_call>': 3 },
+        added_tokens: [{ id: 4, content: '</s>', special: true }],
+      });
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({
+          grammars: [{ lark: 'start: "{}"' }],
+          trigger: { start: '<tool_call>', end: '</tool_call>' },
+        }),
+        tokenizer,
+      );
+
+      // Free text, which may end right away
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 1, 1, 1]);
+      parser.advance(0);
+      parser.advance(1);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0, 0]);
+      parser.advance(2);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1, 0]);
+      parser.advance(3);
+      // Free again, until the next trigger
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 1, 1, 1]);
+
+      const once = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ lark: 'start: "{}"' }], trigger: { start: '<tool_call>' } }),
+        tokenizer,
+      );
+      once.advance(1);
+      once.advance(2);
+      expect(Array.from(once.get_token_mask())).toEqual([0, 0, 0, 0, 1]);
+    });
+
+    it('should lay out JSON as the format options ask', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '{': 0, '"a"': 1, ':': 2, ' ': 3, '1': 4, '}': 5, '"b"': 6, ',': 7 },
+        added_tokens: [{ id: 8, content: '</s>', special: true }],
+      });
+      const schema = {
+        type: 'object',
+        properties: { b: { const: 1 }, a: { const: 1 } },
+        required: ['a', 'b'],
+        additionalProperties: false,
+      };
+
+      const json = (options: unknown) =>
+        new wasm.LLGuidanceParser(
+          JSON.stringify({ grammars: [{ json_schema: schema, options }] }),
+          tokenizer,
+        );
+
+      // Compact output has no whitespace; sorted keys put "a" first
+      const compact = json({ whitespace: 'compact', sort_keys: true });
+      compact.advance(0);
+      expect(Array.from(compact.get_token_mask())).toEqual([0, 1, 0, 0, 0, 0, 0, 0, 0]);
+      compact.advance(1);
+      compact.advance(2);
+      expect(compact.is_token_allowed(3)).toBe(false);
+
+      // Schema order puts "b" first
+      const ordered = json({ whitespace: 'compact' });
+      ordered.advance(0);
+      expect(Array.from(ordered.get_token_mask())).toEqual([0, 0, 0, 0, 0, 0, 1, 0, 0]);
+
+      expect(() => json({ whitespace: 'pretty' })).toThrow();
+    });
+
+    it('should accept any JSON object in json_object mode', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '{': 0, '"x"': 1, ':': 2, '[]': 3, '}': 4, '[': 5 },
+        added_tokens: [{ id: 6, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ json_object: true, options: { whitespace: 'compact' } }] }),
+        tokenizer,
+      );
+      // The top level must be an object, but its values can be anything
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 0, 0, 0, 0, 0, 0]);
+      for (const token of [0, 1, 2, 3, 4]) {
+        parser.advance(token);
+      }
+      expect(parser.is_complete()).toBe(true);
+
+      const disabled = JSON.stringify({ grammars: [{ json_object: false }] });
+      expect(() => new wasm.LLGuidanceParser(disabled, tokenizer)).toThrow('json_object');
+    });
+
+    it('should generate JSON matching a TypeScript type', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '{': 0, '"kind"': 1, ':': 2, '"a"': 3, '"b"': 4, '}': 5, '"c"': 6 },
+        added_tokens: [{ id: 7, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({
+          grammars: [
+            {
+              typescript: "type Kind = 'a' | 'b';\ninterface Item { kind: Kind }",
+              root_type: 'Item',
+              options: { whitespace: 'compact' },
+            },
+          ],
+        }),
+        tokenizer,
+      );
+      parser.advance(0);
+      parser.advance(1);
+      parser.advance(2);
+      // Only the literals of the Kind union
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 0, 1, 1, 0, 0, 0]);
+
+      const ts = (typescript: string) =>
+        new wasm.LLGuidanceParser(JSON.stringify({ grammars: [{ typescript }] }), tokenizer);
+      expect(() => ts('interface Item { kind: Kind }')).toThrow('not declared');
+      expect(() => ts('type F = { f(): void }')).toThrow('no JSON equivalent');
+    });
+
+    it('should generate the payloads of an OpenAPI operation', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '{': 0, '"id"': 1, ':': 2, '1': 3, '}': 4, '"name"': 5, '"x"': 6 },
+        added_tokens: [{ id: 7, content: '</s>', special: true }],
+      });
+      const document = {
+        openapi: '3.0.3',
+        paths: {
+          '/pets': {
+            post: {
+              operationId: 'createPet',
+              requestBody: {
+                content: { 'application/json': { schema: { $ref: '#/components/schemas/Pet' } } },
+              },
+              responses: {
+                '201': {
+                  content: { 'application/json': { schema: { $ref: '#/components/schemas/Pet' } } },
+                },
+              },
+            },
+          },
+        },
+        components: {
+          schemas: {
+            Pet: {
+              type: 'object',
+              required: ['id', 'name'],
+              properties: {
+                id: { type: 'integer', readOnly: true },
+                name: { type: 'string' },
+              },
+            },
+          },
+        },
+      };
+      const parser = (target: string) =>
+        new wasm.LLGuidanceParser(
+          JSON.stringify({
+            grammars: [
+              {
+                openapi: document,
+                operation_id: 'createPet',
+                target,
+                options: { whitespace: 'compact' },
+              },
+            ],
+          }),
+          tokenizer,
+        );
+
+      // The server assigns the id, so only responses contain it
+      const response = parser('response');
+      response.advance(0);
+      expect(Array.from(response.get_token_mask())).toEqual([0, 1, 0, 0, 0, 0, 0, 0]);
+      const request = parser('request_body');
+      request.advance(0);
+      expect(Array.from(request.get_token_mask())).toEqual([0, 0, 0, 0, 0, 1, 0, 0]);
+
+      const missing = () =>
+        new wasm.LLGuidanceParser(
+          JSON.stringify({ grammars: [{ openapi: document, operation_id: 'deletePet' }] }),
+          tokenizer,
+        );
+      expect(missing).toThrow('OpenAPI operation deletePet not found');
+    });
+
+    it('should generate well-formed XML', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '<note': 0, '>': 1, 'a': 2, '<b>': 3, '&lt;': 4, '</note>': 5, '& ': 6 },
+        added_tokens: [{ id: 7, content: '</s>', special: true }],
+      });
+      const xsd = `<?xml version="1.0"?>
+        <xs:schema xmlns:xs="http://www.w3.org/2001/XMLSchema">
+          <xs:element name="note" type="xs:string"/>
+        </xs:schema>`;
+
+      for (const xml of [{ name: 'note', text: 'string' }, { xsd }]) {
+        const grammar = JSON.stringify({ grammars: [{ xml }] });
+        const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+        parser.advance(0);
+        parser.advance(1);
+        // Markup characters in the text only as references
+        expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0, 1, 1, 0, 0]);
+        parser.advance(4);
+        parser.advance(5);
+        expect(parser.is_complete()).toBe(true);
+      }
+
+      const xsdGrammar = (xsd: string) =>
+        new wasm.LLGuidanceParser(JSON.stringify({ grammars: [{ xml: { xsd } }] }), tokenizer);
+      const dateSchema = '<xs:schema><xs:element name="a" type="xs:date"/></xs:schema>';
+      expect(() => xsdGrammar(dateSchema)).toThrow('XSD type date is not supported');
+    });
+
+    it('should generate YAML matching a JSON schema', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { 'name:': 0, ' ': 1, '"x"': 2, '\n': 3, 'tags:': 4, '  -': 5, '7': 6, '{': 7 },
+        added_tokens: [{ id: 8, content: '</s>', special: true }],
+      });
+      const schema = {
+        type: 'object',
+        properties: {
+          name: { type: 'string' },
+          tags: { type: 'array', items: { type: 'integer' }, minItems: 1 },
+        },
+        required: ['name', 'tags'],
+      };
+      const parser = new wasm.LLGuidanceParser(
+        JSON.stringify({ grammars: [{ json_schema: schema, format: 'yaml' }] }),
+        tokenizer,
+      );
+      for (const token of [0, 1, 2, 3, 4, 3]) {
+        parser.advance(token);
+      }
+      // A block sequence (' ' starts its indentation), not a flow one
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 1, 0, 0, 0, 1, 0, 0, 0]);
+      for (const token of [5, 1, 6, 3]) {
+        parser.advance(token);
+      }
+      expect(parser.is_complete()).toBe(true);
+
+      const withOptions = () =>
+        new wasm.LLGuidanceParser(
+          JSON.stringify({
+            grammars: [{ json_schema: schema, format: 'yaml', options: { sort_keys: true } }],
+          }),
+          tokenizer,
+        );
+      expect(withOptions).toThrow('JSON format options do not apply to YAML output');
+    });
+
+    it('should generate read-only SQL from the sql preset', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { SELECT: 0, ' ': 1, '*': 2, FROM: 3, t: 4, ';': 5, DELETE: 6 },
+        added_tokens: [{ id: 7, content: '</s>', special: true }],
+      });
+      const sql = (preset: Record<string, unknown>) =>
+        new wasm.LLGuidanceParser(
+          JSON.stringify({ grammars: [{ preset: 'sql', ...preset }] }),
+          tokenizer,
+        );
+
+      const parser = sql({ dialect: 'sqlite' });
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0, 0, 0, 0, 0]);
+      for (const token of [0, 1, 2, 1, 3, 1, 4, 5]) {
+        parser.advance(token);
+      }
+      expect(parser.is_complete()).toBe(true);
+
+      expect(() => sql({ statements: ['delete'] })).toThrow('Unknown SQL statement delete');
+      expect(() => sql({ dialect: 'oracle' })).toThrow('Unknown SQL dialect oracle');
+    });
+
+    it('should read rx patterns with JavaScript regex semantics', async () => {
+      // '٣' is a digit to a Unicode-aware \d but not to JavaScript
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '2': 1, a: 2, '٣': 3, A: 4 },
+        added_tokens: [{ id: 5, content: '</s>', special: true }],
+      });
+      const rx = (pattern: string, flags?: string) =>
+        new wasm.LLGuidanceParser(
+          JSON.stringify({ grammars: [{ rx: pattern, flags }] }),
+          tokenizer,
+        );
+
+      const parser = rx('^(?<year>\\d{2})$');
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0, 0, 0]);
+      parser.advance(0);
+      parser.advance(1);
+      expect(parser.is_complete()).toBe(true);
+
+      expect(Array.from(rx('a', 'i').get_token_mask())).toEqual([0, 0, 1, 0, 1, 0]);
+      expect(() => rx('(?=1)\\d')).toThrow('lookahead is not supported');
+      expect(() => rx('(1)\\1')).toThrow('backreferences are not supported');
+    });
+
+    it('should validate grammars without a tokenizer', async () => {
+      const validate = (grammar: Record<string, unknown>) =>
+        JSON.parse(
+          wasm.LLGuidanceParser.validate_grammar(JSON.stringify({ grammars: [grammar] })),
+        );
+
+      expect(validate({ lark: 'start: "a"+' })).toEqual({ ok: true, errors: [], warnings: [] });
+
+      const broken = validate({ lark: 'start: missing' });
+      expect(broken.ok).toBe(false);
+      expect(broken.errors).toHaveLength(1);
+      expect(broken.errors[0]).toContain('missing');
+
+      expect(validate({ rx: '(?=a)' }).errors[0]).toContain('lookahead is not supported');
+    });
+
+    it('should load a precompiled grammar with its stop sequences', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ rx: '^\\d+$' }],
+        stop: ['2'],
+      });
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '2': 1, a: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+
+      const compiled = wasm.LLGuidanceParser.compile_grammar(grammar);
+      expect(new TextDecoder().decode(compiled.slice(0, 4))).toBe('LLGG');
+
+      const original = new wasm.LLGuidanceParser(grammar, tokenizer);
+      const loaded = wasm.LLGuidanceParser.from_compiled_grammar(compiled, tokenizer);
+      expect(Array.from(loaded.get_token_mask())).toEqual(Array.from(original.get_token_mask()));
+      expect(Array.from(loaded.get_token_mask())).toEqual([1, 1, 0, 0]);
+      loaded.advance(0);
+      loaded.advance(1);
+      expect(loaded.is_stopped()).toBe(true);
+
+      const broken = JSON.stringify({ grammars: [{ lark: 'start: missing' }] });
+      expect(() => wasm.LLGuidanceParser.compile_grammar(broken)).toThrow();
+      const garbage = new Uint8Array([1, 2]);
+      expect(() => wasm.LLGuidanceParser.from_compiled_grammar(garbage, tokenizer)).toThrow(
+        'Not a compiled grammar',
+      );
+    });
+
+    it('should estimate grammar cost statically', async () => {
+      const analyze = (grammar: Record<string, unknown>) =>
+        JSON.parse(wasm.LLGuidanceParser.analyze_grammar(JSON.stringify({ grammars: [grammar] })));
+
+      const regex = analyze({ rx: '[0-9]{3}' });
+      expect(regex).toMatchObject({ rules: 1, lexemes: 1, lexer_states: 3, cost_class: 'low' });
+
+      const expr = analyze({
+        lark: 'start: expr\nexpr: expr "+" expr | NUMBER\nNUMBER: /[0-9]+/',
+      });
+      expect(expr).toMatchObject({ rules: 2, terminals: 1, recursive_rules: ['expr'] });
+      expect(expr.hints[0]).toContain('Rule expr is both left and right recursive');
+      expect(expr.cost_class).toBe('high');
+
+      const blowup = analyze({ lark: 'start: /[ab]*a[ab]{20}/' });
+      expect(blowup.hints[0]).toContain('lexer DFA can grow exponentially');
+
+      const tree = analyze({
+        json_schema: {
+          $ref: '#/$defs/Node',
+          $defs: {
+            Node: { type: 'object', properties: { child: { $ref: '#/$defs/Node' } } },
+          },
+        },
+      });
+      expect(tree.recursive_rules).toEqual(['#/$defs/Node']);
+      expect(tree.cost_class).toBe('medium');
+    });
+
+    it('should create and reset parsers from registered grammars', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '2': 1, a: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+      wasm.register_grammar('digits', JSON.stringify({ grammars: [{ rx: '^\\d+$' }] }));
+      wasm.register_grammar('letters', JSON.stringify({ grammars: [{ rx: 'a+' }] }));
+
+      const parser = new wasm.LLGuidanceParser('@digits', tokenizer);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0]);
+      parser.reset('@letters');
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0]);
+
+      expect(wasm.unregister_grammar('digits')).toBe(true);
+      expect(wasm.unregister_grammar('digits')).toBe(false);
+      expect(() => new wasm.LLGuidanceParser('@digits', tokenizer)).toThrow(
+        'No grammar is registered as "digits"',
+      );
+      expect(() => wasm.register_grammar('@letters', '{}')).toThrow('Invalid grammar name');
+    });
+
+    it('should switch grammars mid-generation and keep the output continuous', async () => {
+      const tokenizer = JSON.stringify({
+        vocab: { '1': 0, '2': 1, a: 2 },
+        added_tokens: [{ id: 3, content: '</s>', special: true }],
+      });
+      const digits = JSON.stringify({ grammars: [{ rx: '^\\d+$' }] });
+      const letters = JSON.stringify({ grammars: [{ rx: 'a+' }] });
+
+      const parser = new wasm.LLGuidanceParser(digits, tokenizer);
+      parser.advance(0);
+      parser.push_grammar(letters);
+      expect(parser.grammar_depth()).toBe(1);
+      expect(Array.from(parser.get_token_mask())).toEqual([0, 0, 1, 0]);
+      parser.advance(2);
+      parser.advance(2);
+      parser.rollback(1);
+      expect(() => parser.rollback(2)).toThrow('Cannot roll back past push_grammar');
+
+      parser.pop_grammar();
+      expect(parser.grammar_depth()).toBe(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 1]);
+      parser.advance(1);
+      expect(parser.consumed_text()).toBe('1a2');
+      expect(() => parser.rollback(2)).toThrow('Cannot roll back past pop_grammar');
+      expect(() => parser.pop_grammar()).toThrow('No grammar has been pushed');
+
+      parser.push_grammar(letters);
+      parser.reset();
+      expect(parser.grammar_depth()).toBe(0);
+      expect(Array.from(parser.get_token_mask())).toEqual([1, 1, 0, 0]);
+    });
+
+    it('should report stable stop reason names', async () => {
+      const grammar = JSON.stringify({
+        grammars: [{ lark: 'start: "a"' }],
+      });
+
+      const tokenizer = JSON.stringify({
+        vocab: { a: 0 },
+        added_tokens: [{ id: 1, content: '</s>', special: true }],
+      });
+
+      const parser = new wasm.LLGuidanceParser(grammar, tokenizer);
+      expect(parser.stop_reason()).toBe('NotStopped');
+
+      parser.advance(0);
+      parser.advance(1);
+      expect(parser.stop_reason()).toBe('EndOfSentence');
+      expect(parser.is_complete()).toBe(true);
+    });
   });
 
   describe('Simulated integration flow', () => {
@@ -3,21 +3,207 @@ import type { TokenizerData, Grammar } from '../src/types';
 
 // Mock the WASM module for unit tests
 vi.mock('../pkg/llguidance_wasm', () => {
+  class MockLLGuidanceParser {
+    is_token_allowed = vi.fn().mockReturnValue(true);
+    set_token_deny_list = vi.fn();
+    set_token_force_allow_list = vi.fn();
+    filter_candidates = vi.fn((ids: Uint32Array) => ids.filter((id) => id % 2 === 0));
+    get_token_mask = vi.fn().mockReturnValue(new Uint8Array(100).fill(1));
+    fill_token_mask = vi.fn((dest: Uint8Array) => dest.fill(1, 0, 100));
+    apply_mask = vi.fn((logits: Float32Array) => logits.fill(-Infinity, 1));
+    sample_token = vi.fn().mockReturnValue(3);
+    get_compact_mask = vi.fn().mockReturnValue(['disallowed', new Uint32Array([7])]);
+    get_token_mask_diff = vi.fn().mockReturnValue([new Uint32Array([4]), new Uint32Array([2, 3])]);
+    get_disallowed_tokens = vi.fn().mockReturnValue(new Uint32Array([1, 3]));
+    get_allowed_tokens = vi.fn().mockReturnValue(new Uint32Array([0, 2]));
+    get_token_bitmask = vi.fn().mockReturnValue(new Uint32Array(4).fill(0xffffffff));
+    compute_logit_bias = vi.fn().mockReturnValue(new Float32Array(100));
+    advance = vi.fn();
+    try_advance = vi.fn((id: number) => id !== 99);
+    advance_many = vi.fn();
+    validate_tokens = vi.fn((ids: Uint32Array) => ids.indexOf(99));
+    consume_text = vi.fn().mockReturnValue(new Uint32Array([0, 3]));
+    consume_bytes = vi.fn().mockReturnValue(new Uint32Array([0, 3]));
+    rollback = vi.fn();
+    get_forced_tokens = vi.fn().mockReturnValue(new Uint32Array([5, 6]));
+    is_complete = vi.fn().mockReturnValue(false);
+    is_stopped = vi.fn().mockReturnValue(false);
+    must_stop = vi.fn().mockReturnValue(false);
+    can_advance = vi.fn().mockReturnValue(true);
+    stop_reason = vi.fn().mockReturnValue('NotStopped');
+    forced_bytes = vi.fn().mockReturnValue(new Uint8Array([123, 34]));
+    is_forced = vi.fn().mockReturnValue(true);
+    expected = vi.fn().mockReturnValue(['"', ',', '}']);
+    consumed_text = vi.fn().mockReturnValue('{"a"');
+    consumed_bytes = vi.fn().mockReturnValue(new Uint8Array([123, 34, 97, 34]));
+    get_captures = vi.fn().mockReturnValue([
+      ['name', 'Ada'],
+      ['age', '36'],
+    ]);
+    get_parse_tree = vi.fn().mockReturnValue(
+      JSON.stringify({
+        rule: 'start',
+        start: 0,
+        end: 3,
+        text: 'x=1',
+        children: [{ rule: 'value', start: 2, end: 3, text: '1', children: [] }],
+      }),
+    );
+    validate_text = vi.fn((text: string) => text === 'hello');
+    get_temperature = vi.fn().mockReturnValue(0.8);
+    step = vi.fn().mockReturnValue(new Uint8Array(100));
+    fork = vi.fn(() => new MockLLGuidanceParser());
+    reset = vi.fn();
+    push_grammar = vi.fn();
+    pop_grammar = vi.fn();
+    grammar_depth = vi.fn().mockReturnValue(1);
+    free = vi.fn();
+    vocab_size = vi.fn().mockReturnValue(100);
+    export_tok_env = vi.fn().mockReturnValue(new Uint8Array([0x4c, 0x4c, 0x47, 0x54]));
+    enable_token_healing = vi.fn().mockReturnValue(true);
+    save_state = vi.fn().mockReturnValue(new Uint8Array([0x4c, 0x4c, 0x47, 0x53]));
+    load_state = vi.fn();
+    diagnose = vi.fn().mockReturnValue(
+      JSON.stringify({
+        vocab_size: 100,
+        allowed_tokens: 0,
+        eos_allowed: false,
+        error: null,
+        stop_reason: 'NotStopped',
+        empty_tokens: 0,
+        special_tokens: 1,
+        unreachable_bytes: [10],
+        warnings: ['No token is allowed at the current position'],
+      }),
+    );
+    diagnose_ambiguity = vi.fn().mockReturnValue(
+      JSON.stringify({
+        ambiguous_rules: ['Rule start has the same alternative twice, so every match is ambiguous'],
+        overlapping_terminals: [{ terminals: ['IDENT', '"if"'], tokens: ['"if"', '"i"'] }],
+        competing_tokens: [{ token: '"if"', lexemes: ['IDENT', '"if"'] }],
+        truncated: false,
+      }),
+    );
+    mask_stats = vi.fn().mockReturnValue(
+      JSON.stringify({
+        allowed_tokens: 4,
+        vocab_size: 100,
+        coverage: 0.04,
+        entropy_bits: 2,
+        eos_allowed: false,
+      }),
+    );
+    last_step_stats = vi.fn().mockReturnValue(
+      JSON.stringify({
+        mask_time_us: 120.5,
+        allowed_tokens: 7,
+        advance_time_us: 30,
+        earley_rows: 2,
+        earley_items: 9,
+        lexer_states: 14,
+        slices_applied: 1,
+        trie_nodes_walked: 40,
+        lexer_cost: 250,
+      }),
+    );
+    get_telemetry = vi.fn().mockReturnValue(
+      JSON.stringify({
+        steps: 3,
+        forced_steps: 1,
+        mask_computations: 3,
+        average_allowed_tokens: 12.5,
+        mask_time_ms: 0.75,
+      }),
+    );
+    grammar_warnings = vi.fn().mockReturnValue(['unsupported keyword: format']);
+    tokenizer_warnings = vi.fn().mockReturnValue([]);
+    tokenize = vi.fn().mockReturnValue(new Uint32Array([0, 3, 1]));
+    detokenize = vi.fn().mockReturnValue('hello world');
+    token_bytes = vi.fn().mockReturnValue(new Uint8Array([104, 105]));
+    token_display = vi.fn().mockReturnValue('"hi"');
+
+    static from_url = vi.fn(async () => new MockLLGuidanceParser());
+    static from_vocab = vi.fn(() => new MockLLGuidanceParser());
+    static from_tokenizer = vi.fn(() => new MockLLGuidanceParser());
+    static from_tiktoken = vi.fn(() => new MockLLGuidanceParser());
+    static from_gguf = vi.fn(() => new MockLLGuidanceParser());
+    static from_tok_env_blob = vi.fn(() => new MockLLGuidanceParser());
+    static compile_grammar = vi.fn(() => new Uint8Array([0x4c, 0x4c, 0x47, 0x47]));
+    static from_compiled_grammar = vi.fn(() => new MockLLGuidanceParser());
+    static analyze_grammar = vi.fn(() =>
+      JSON.stringify({
+        rules: 2,
+        terminals: 1,
+        lexemes: 3,
+        lexer_states: 12,
+        recursive_rules: ['expr'],
+        hints: [],
+        cost_class: 'medium',
+      }),
+    );
+    static validate_grammar = vi.fn(() =>
+      JSON.stringify({ ok: true, errors: [], warnings: ['ignored keyword'] }),
+    );
+  }
+
   return {
     default: vi.fn(),
-    LLGuidanceParser: class MockLLGuidanceParser {
-      is_token_allowed = vi.fn().mockReturnValue(true);
-      get_token_mask = vi.fn().mockReturnValue(new Uint8Array(100).fill(1));
+    clear_tokenizer_cache: vi.fn(),
+    wasm_memory_bytes: vi.fn().mockReturnValue(1 << 20),
+    register_grammar: vi.fn(),
+    unregister_grammar: vi.fn().mockReturnValue(true),
+    ParserBatch: class MockParserBatch {
+      private size: number;
+      constructor(_parser: unknown, size: number) {
+        this.size = size;
+      }
+      len = vi.fn(() => this.size);
+      is_empty = vi.fn().mockReturnValue(false);
+      vocab_size = vi.fn().mockReturnValue(100);
+      get_token_masks = vi.fn(() => new Uint8Array(this.size * 100).fill(1));
       advance = vi.fn();
+      advance_sequence = vi.fn();
+      rollback = vi.fn();
       is_complete = vi.fn().mockReturnValue(false);
+      fork = vi.fn(() => this.size++);
+      remove = vi.fn(() => this.size--);
       reset = vi.fn();
-      vocab_size = vi.fn().mockReturnValue(100);
+      free = vi.fn();
+    },
+    SpeculativeParser: class MockSpeculativeParser {
+      target_vocab_size = vi.fn().mockReturnValue(100);
+      draft_vocab_size = vi.fn().mockReturnValue(50);
+      target_mask = vi.fn(() => new Uint8Array(100).fill(1));
+      draft_mask = vi.fn(() => new Uint8Array(50).fill(1));
+      advance_draft = vi.fn();
+      draft_as_target_tokens = vi.fn().mockReturnValue(new Uint32Array([7, 8]));
+      commit = vi.fn();
+      reject_draft = vi.fn();
+      is_complete = vi.fn().mockReturnValue(false);
+      free = vi.fn();
     },
+    TokEnvBuilder: class MockTokEnvBuilder {
+      add_chunk = vi.fn();
+      len = vi.fn().mockReturnValue(0);
+      is_empty = vi.fn().mockReturnValue(true);
+      finish = vi.fn(() => new MockLLGuidanceParser());
+    },
+    LLGuidanceFactory: class MockLLGuidanceFactory {
+      private count = 0;
+      compile_grammar = vi.fn(() => this.count++);
+      grammar_count = vi.fn(() => this.count);
+      create_parser = vi.fn(() => new MockLLGuidanceParser());
+      free = vi.fn();
+    },
+    LLGuidanceParser: MockLLGuidanceParser,
   };
 });
 
 // Import after mock setup
 import { GuidanceParser } from '../src/parser';
+import { GuidanceFactory } from '../src/factory';
+import { GuidanceState } from '../src/state';
+import { createSharedMaskBuffer, sharedMaskGeneration, sharedMaskView } from '../src/shared-mask';
 
 describe('GuidanceParser', () => {
   const mockTokenizer: TokenizerData = {
@@ -77,6 +263,175 @@ describe('GuidanceParser', () => {
     });
   });
 
+  describe('fromUrl()', () => {
+    it('should create a parser from a tokenizer.json URL', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const url = 'https://huggingface.co/gpt2/resolve/main/tokenizer.json';
+      const parser = await GuidanceParser.fromUrl(regexGrammar, url);
+      expect(parser).toBeDefined();
+      expect(parser.vocabSize).toBe(100);
+      expect(wasm.LLGuidanceParser.from_url).toHaveBeenLastCalledWith(
+        expect.any(String),
+        url,
+        undefined,
+      );
+    });
+  });
+
+  describe('fromVocab()', () => {
+    it('should create a parser from token byte strings', async () => {
+      const encoder = new TextEncoder();
+      const tokens = ['hello', 'world', '!', ' '].map((t) => encoder.encode(t));
+      const parser = await GuidanceParser.fromVocab(tokens, 2, regexGrammar);
+      expect(parser).toBeDefined();
+      expect(parser.vocabSize).toBe(100);
+    });
+
+    it('should pass parser options to the WASM module', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      await GuidanceParser.fromVocab(tokens, 0, regexGrammar, { vocabSizeOverride: 128 });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        expect.any(String),
+        JSON.stringify({ vocab_size_override: 128 }),
+      );
+    });
+
+    it('should pass excludeSpecialTokens to the WASM module', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      await GuidanceParser.fromVocab(tokens, 0, regexGrammar, { excludeSpecialTokens: true });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        expect.any(String),
+        JSON.stringify({ exclude_special_tokens: true }),
+      );
+    });
+
+    it('should pass idRemap to the WASM module as a plain array', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a'), new TextEncoder().encode('b')];
+      await GuidanceParser.fromVocab(tokens, 0, regexGrammar, { idRemap: new Uint32Array([1, 0]) });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        expect.any(String),
+        JSON.stringify({ id_remap: [1, 0] }),
+      );
+    });
+
+    it('should pass chat-template options to the WASM module', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      await GuidanceParser.fromVocab(tokens, 0, regexGrammar, {
+        passthroughTokens: [5, 6],
+        triggerToken: 7,
+      });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        expect.any(String),
+        JSON.stringify({ passthrough_tokens: [5, 6], trigger_token: 7 }),
+      );
+    });
+
+    it('should pass the rollback limit to the WASM module', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      await GuidanceParser.fromVocab(tokens, 0, regexGrammar, { maxRollbackTokens: 4 });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        expect.any(String),
+        JSON.stringify({ max_rollback_tokens: 4 }),
+      );
+    });
+
+    it('should pass logit bias values to the WASM module', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      await GuidanceParser.fromVocab(tokens, 0, regexGrammar, { allowedBias: 1, disallowedBias: -100 });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        expect.any(String),
+        JSON.stringify({ allowed_bias: 1, disallowed_bias: -100 }),
+      );
+    });
+  });
+
+  describe('fromVocabChunked()', () => {
+    it('should create a parser from token byte strings in chunks', async () => {
+      const encoder = new TextEncoder();
+      const tokens = ['hello', 'world', '!', ' '].map((t) => encoder.encode(t));
+      const parser = await GuidanceParser.fromVocabChunked(tokens, 2, regexGrammar, undefined, 3);
+      expect(parser).toBeDefined();
+      expect(parser.vocabSize).toBe(100);
+    });
+  });
+
+  describe('fromTokenizer()', () => {
+    it('should create a parser from a callback tokenizer', async () => {
+      const parser = await GuidanceParser.fromTokenizer(
+        {
+          encode: () => [0],
+          decode: () => 'hello',
+          vocabSize: 4,
+          eosTokenId: 2,
+        },
+        regexGrammar,
+      );
+      expect(parser).toBeDefined();
+    });
+  });
+
+  describe('fromTiktoken()', () => {
+    it('should create a parser from a tiktoken rank file', async () => {
+      const parser = await GuidanceParser.fromTiktoken(
+        'YQ== 0\nYg== 1',
+        { '<|endoftext|>': 2 },
+        regexGrammar,
+      );
+      expect(parser).toBeDefined();
+    });
+  });
+
+  describe('fromGguf()', () => {
+    it('should create a parser from GGUF tokenizer metadata', async () => {
+      const parser = await GuidanceParser.fromGguf(
+        { model: 'llama', tokens: ['<unk>', '<s>', '</s>', '▁hello'], eos_token_id: 2 },
+        regexGrammar,
+      );
+      expect(parser).toBeDefined();
+    });
+  });
+
+  describe('exportTokEnv() / fromTokEnvBlob()', () => {
+    it('should round-trip the tokenizer environment', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const blob = parser.exportTokEnv();
+      expect(blob).toBeInstanceOf(Uint8Array);
+
+      const restored = await GuidanceParser.fromTokEnvBlob(blob, regexGrammar);
+      expect(restored.vocabSize).toBe(100);
+    });
+  });
+
+  describe('clearTokenizerCache()', () => {
+    it('should clear the cache without error', async () => {
+      await expect(GuidanceParser.clearTokenizerCache()).resolves.toBeUndefined();
+    });
+  });
+
+  describe('wasmMemoryBytes()', () => {
+    it('should report the WASM memory size', async () => {
+      await expect(GuidanceParser.wasmMemoryBytes()).resolves.toBe(1 << 20);
+    });
+  });
+
   describe('isTokenAllowed()', () => {
     it('should return true for allowed tokens', async () => {
       const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
@@ -84,6 +439,28 @@ describe('GuidanceParser', () => {
     });
   });
 
+  describe('setTokenDenyList() / setTokenForceAllowList()', () => {
+    it('should pass the lists to the WASM module as Uint32Arrays', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      parser.setTokenDenyList([1, 2]);
+      parser.setTokenForceAllowList([3]);
+      const wasmParser = (
+        parser as unknown as {
+          wasmParser: Record<'set_token_deny_list' | 'set_token_force_allow_list', ReturnType<typeof vi.fn>>;
+        }
+      ).wasmParser;
+      expect(wasmParser.set_token_deny_list).toHaveBeenLastCalledWith(new Uint32Array([1, 2]));
+      expect(wasmParser.set_token_force_allow_list).toHaveBeenLastCalledWith(new Uint32Array([3]));
+    });
+  });
+
+  describe('filterCandidates()', () => {
+    it('should return the allowed candidates', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.filterCandidates([4, 3, 2]))).toEqual([4, 2]);
+    });
+  });
+
   describe('getTokenMask()', () => {
     it('should return a Uint8Array mask', async () => {
       const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
@@ -93,6 +470,117 @@ describe('GuidanceParser', () => {
     });
   });
 
+  describe('getTokenMaskAsync()', () => {
+    it('should resolve to the token mask', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const pending = parser.getTokenMaskAsync();
+      expect(pending).toBeInstanceOf(Promise);
+      const mask = await pending;
+      expect(mask.length).toBe(100);
+    });
+
+    it('should let queued tasks run before computing', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      let ranFirst = false;
+      setTimeout(() => {
+        ranFirst = true;
+      }, 0);
+      await parser.getTokenMaskAsync();
+      expect(ranFirst).toBe(true);
+    });
+  });
+
+  describe('fillTokenMask()', () => {
+    it('should write the mask into the given buffer', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const dest = new Uint8Array(100);
+      parser.fillTokenMask(dest);
+      expect(dest.every((v) => v === 1)).toBe(true);
+    });
+  });
+
+  describe('applyMask()', () => {
+    it('should mask the logits in place', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const logits = new Float32Array([1, 2, 3]);
+      parser.applyMask(logits);
+      expect(Array.from(logits)).toEqual([1, -Infinity, -Infinity]);
+    });
+  });
+
+  describe('sampleToken()', () => {
+    it('should pass sampling options to the WASM module', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const logits = new Float32Array(100);
+      expect(parser.sampleToken(logits, { temperature: 0.7, topK: 40 })).toBe(3);
+      expect(
+        (parser as unknown as { wasmParser: { sample_token: ReturnType<typeof vi.fn> } }).wasmParser
+          .sample_token,
+      ).toHaveBeenLastCalledWith(logits, JSON.stringify({ temperature: 0.7, top_k: 40 }));
+    });
+  });
+
+  describe('getCompactMask()', () => {
+    it('should return the tagged ID list', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const compact = parser.getCompactMask();
+      expect(compact.representation).toBe('disallowed');
+      expect(Array.from(compact.tokenIds)).toEqual([7]);
+    });
+  });
+
+  describe('getTokenMaskDiff()', () => {
+    it('should return the allowed and disallowed changes', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const diff = parser.getTokenMaskDiff();
+      expect(Array.from(diff.allowed)).toEqual([4]);
+      expect(Array.from(diff.disallowed)).toEqual([2, 3]);
+    });
+  });
+
+  describe('getDisallowedTokens()', () => {
+    it('should return the banned token IDs', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.getDisallowedTokens())).toEqual([1, 3]);
+    });
+  });
+
+  describe('getAllowedTokens()', () => {
+    it('should return the allowed token IDs', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.getAllowedTokens())).toEqual([0, 2]);
+    });
+  });
+
+  describe('writeSharedMask()', () => {
+    it('should write into the shared slot and bump the generation', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const buffer = createSharedMaskBuffer(100);
+      expect(parser.writeSharedMask(buffer)).toBe(1);
+      expect(sharedMaskGeneration(buffer)).toBe(1);
+      expect(sharedMaskView(buffer).every((v) => v === 1)).toBe(true);
+      expect(parser.writeSharedMask(buffer)).toBe(2);
+    });
+  });
+
+  describe('getTokenBitmask()', () => {
+    it('should return a packed Uint32Array mask', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const bitmask = parser.getTokenBitmask();
+      expect(bitmask).toBeInstanceOf(Uint32Array);
+      expect(bitmask.length).toBe(4);
+    });
+  });
+
+  describe('computeLogitBias()', () => {
+    it('should return a Float32Array bias', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const bias = parser.computeLogitBias(-1e9);
+      expect(bias).toBeInstanceOf(Float32Array);
+      expect(bias.length).toBe(100);
+    });
+  });
+
   describe('advance()', () => {
     it('should advance parser state without error', async () => {
       const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
@@ -100,6 +588,65 @@ describe('GuidanceParser', () => {
     });
   });
 
+  describe('tryAdvance()', () => {
+    it('should report whether the token would be accepted', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.tryAdvance(0)).toBe(true);
+      expect(parser.tryAdvance(99)).toBe(false);
+    });
+  });
+
+  describe('advanceMany()', () => {
+    it('should pass the tokens as a Uint32Array', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      parser.advanceMany([0, 1, 2]);
+      expect(
+        (parser as unknown as { wasmParser: { advance_many: ReturnType<typeof vi.fn> } }).wasmParser
+          .advance_many,
+      ).toHaveBeenLastCalledWith(new Uint32Array([0, 1, 2]));
+    });
+  });
+
+  describe('validateTokens()', () => {
+    it('should return the index of the first rejected token', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.validateTokens([0, 1, 99, 2])).toBe(2);
+      expect(parser.validateTokens([0, 1])).toBe(-1);
+    });
+  });
+
+  describe('consumeText() / consumeBytes()', () => {
+    it('should return the consumed tokens', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.consumeText('hello world'))).toEqual([0, 3]);
+      expect(Array.from(parser.consumeBytes(new TextEncoder().encode('hi')))).toEqual([0, 3]);
+    });
+  });
+
+  describe('rollback()', () => {
+    it('should pass the token count through', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      parser.advance(0);
+      expect(() => parser.rollback(1)).not.toThrow();
+    });
+  });
+
+  describe('step()', () => {
+    it('should advance and return the next mask', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const mask = parser.step(0);
+      expect(mask).toBeInstanceOf(Uint8Array);
+      expect(mask.length).toBe(100);
+    });
+  });
+
+  describe('getForcedTokens()', () => {
+    it('should return the forced token IDs', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.getForcedTokens())).toEqual([5, 6]);
+    });
+  });
+
   describe('isComplete()', () => {
     it('should return completion status', async () => {
       const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
@@ -107,6 +654,772 @@ describe('GuidanceParser', () => {
     });
   });
 
+  describe('createBatch()', () => {
+    it('should return a batch exposing concatenated masks', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const batch = await parser.createBatch(4);
+      expect(batch.size).toBe(4);
+
+      const masks = batch.getTokenMasks();
+      expect(masks.length).toBe(400);
+      expect(batch.sequenceMask(masks, 3).length).toBe(100);
+      expect(() => batch.advance([0, 1, 2, 3])).not.toThrow();
+    });
+
+    it('should fork and remove beams', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const batch = await parser.createBatch(2);
+      expect(batch.fork(0)).toBe(2);
+      expect(batch.size).toBe(3);
+      batch.remove(1);
+      expect(batch.size).toBe(2);
+    });
+
+    it('should roll back a single sequence', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const batch = await parser.createBatch(2);
+      batch.advanceSequence(1, 0);
+      expect(() => batch.rollback(1, 1)).not.toThrow();
+    });
+
+    it('should throw after free()', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const batch = await parser.createBatch(2);
+      batch.free();
+      batch.free();
+      expect(() => batch.getTokenMasks()).toThrow('GuidanceBatch used after free()');
+    });
+  });
+
+  describe('createSpeculative()', () => {
+    it('should pair the target and draft vocabularies', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const draft = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const speculative = await parser.createSpeculative(draft);
+      expect(speculative.targetVocabSize).toBe(100);
+      expect(speculative.draftVocabSize).toBe(50);
+      expect(speculative.draftMask().length).toBe(50);
+
+      speculative.advanceDraft(3);
+      expect(Array.from(speculative.draftAsTargetTokens())).toEqual([7, 8]);
+      expect(() => speculative.commit([7])).not.toThrow();
+    });
+  });
+
+  describe('fork()', () => {
+    it('should return an independent parser', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const fork = parser.fork();
+      expect(fork).not.toBe(parser);
+      expect(fork.vocabSize).toBe(100);
+    });
+  });
+
+  describe('clone()', () => {
+    it('should duplicate the parser state', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const copy = parser.clone();
+      expect(copy).not.toBe(parser);
+      expect(copy.vocabSize).toBe(100);
+    });
+  });
+
+  describe('forcedBytes()', () => {
+    it('should return the forced bytes', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(new TextDecoder().decode(parser.forcedBytes())).toBe('{"');
+    });
+  });
+
+  describe('isForced()', () => {
+    it('should return whether a single token is allowed', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.isForced()).toBe(true);
+    });
+  });
+
+  describe('consumedText() / consumedBytes()', () => {
+    it('should return what the grammar consumed', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.consumedText()).toBe('{"a"');
+      expect(new TextDecoder().decode(parser.consumedBytes())).toBe('{"a"');
+    });
+  });
+
+  describe('getCaptures()', () => {
+    it('should return the captures as a Map', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const captures = parser.getCaptures();
+      expect(captures).toBeInstanceOf(Map);
+      expect(captures.get('name')).toBe('Ada');
+      expect(captures.get('age')).toBe('36');
+    });
+  });
+
+  describe('getParseTree()', () => {
+    it('should return the parsed tree', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const tree = parser.getParseTree();
+      expect(tree.rule).toBe('start');
+      expect(tree.children).toEqual([{ rule: 'value', start: 2, end: 3, text: '1', children: [] }]);
+    });
+  });
+
+  describe('getTemperature()', () => {
+    it('should return the temperature the grammar suggests', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.getTemperature()).toBe(0.8);
+    });
+  });
+
+  describe('expected()', () => {
+    it('should return the expected literals', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.expected()).toEqual(['"', ',', '}']);
+    });
+  });
+
+  describe('validateText()', () => {
+    it('should return whether the text matches', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.validateText('hello')).toBe(true);
+      expect(parser.validateText('nope')).toBe(false);
+    });
+  });
+
+  describe('isStopped()', () => {
+    it('should return whether the parser has stopped', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.isStopped()).toBe(false);
+    });
+  });
+
+  describe('stop', () => {
+    it('should accept stop sequences on any grammar type', async () => {
+      const grammar: Grammar = { type: 'lark', grammar: 'start: /.*/', stop: ['\n\n'] };
+      const parser = await GuidanceParser.create(grammar, mockTokenizer);
+      expect(parser.isComplete()).toBe(false);
+    });
+  });
+
+  describe('maxTokens', () => {
+    it('should accept a token limit on any grammar type', async () => {
+      const grammar: Grammar = { type: 'regex', pattern: '[a-z]+', maxTokens: 16 };
+      const parser = await GuidanceParser.create(grammar, mockTokenizer);
+      expect(parser.stopReason()).toBe('NotStopped');
+    });
+  });
+
+  describe('subgrammars', () => {
+    it('should pass named sub-grammars after the root grammar', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'lark',
+        grammar: 'start: "n=" @num',
+        subgrammars: { num: { type: 'regex', pattern: '[0-9]+' } },
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            { lark: 'start: "n=" @num', start: 'start' },
+            { rx: '[0-9]+', name: 'num' },
+          ],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('gbnf', () => {
+    it('should pass a GBNF grammar through unchanged', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = { type: 'gbnf', grammar: 'root ::= "a"+', maxTokens: 8 };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ gbnf: 'root ::= "a"+', max_tokens: 8 }] }),
+        undefined,
+      );
+    });
+  });
+
+  describe('ebnf', () => {
+    it('should pass an EBNF grammar through unchanged', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = { type: 'ebnf', grammar: 'word = "a", { "a" } ;' };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ ebnf: 'word = "a", { "a" } ;' }] }),
+        undefined,
+      );
+    });
+  });
+
+  describe('anyOf', () => {
+    it('should pass the alternatives as one union', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'any_of',
+        anyOf: [
+          { type: 'regex', pattern: 'no' },
+          {
+            type: 'lark',
+            grammar: 'start: @num',
+            subgrammars: { num: { type: 'regex', pattern: '[0-9]+' } },
+          },
+        ],
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            { any_of: [{ rx: 'no' }, { lark: 'start: @num', start: 'start' }] },
+            { rx: '[0-9]+', name: 'num' },
+          ],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('sequence', () => {
+    it('should pass the stages in order', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'sequence',
+        sequence: [
+          { type: 'text_until', delimiter: '</think>' },
+          { type: 'json_schema', schema: { type: 'object' } },
+        ],
+        maxTokens: 64,
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            {
+              sequence: [{ text_until: '</think>' }, { json_schema: { type: 'object' } }],
+              max_tokens: 64,
+            },
+          ],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('lazy', () => {
+    it('should convert the wrapped grammar and its trigger', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'lazy',
+        trigger: '<tool_call>',
+        end: '</tool_call>',
+        grammar: { type: 'json_schema', schema: { type: 'object' }, stop: ['<|im_end|>'] },
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [{ json_schema: { type: 'object' } }],
+          stop: ['<|im_end|>'],
+          trigger: { start: '<tool_call>', end: '</tool_call>' },
+        }),
+        undefined,
+      );
+    });
+
+    it('should reject a lazy grammar nested in another grammar', async () => {
+      const tokens = [new TextEncoder().encode('a')];
+      const lazy: Grammar = { type: 'lazy', trigger: '<a>', grammar: regexGrammar };
+      await expect(
+        GuidanceParser.fromVocab(tokens, 0, { type: 'any_of', anyOf: [lazy, regexGrammar] }),
+      ).rejects.toThrow('cannot be nested');
+    });
+  });
+
+  describe('resolveRef', () => {
+    it('should inline external refs before compiling', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'json_schema',
+        schema: { $ref: 'https://example.com/name.json' },
+      };
+      const resolveRef = vi.fn(async () => ({ type: 'string' }));
+      await GuidanceParser.fromVocab(tokens, 0, grammar, { resolveRef });
+      expect(resolveRef).toHaveBeenCalledWith('https://example.com/name.json');
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            {
+              json_schema: {
+                $ref: '#/$defs/external_0',
+                $defs: { external_0: { type: 'string' } },
+              },
+            },
+          ],
+        }),
+        '{}',
+      );
+    });
+  });
+
+  describe('json format', () => {
+    it('should pass format options with the schema', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'json_schema',
+        schema: { type: 'object' },
+        format: { whitespace: 'compact', sortKeys: true },
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            {
+              json_schema: { type: 'object' },
+              options: { whitespace: 'compact', sort_keys: true },
+            },
+          ],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('json_object', () => {
+    it('should request any JSON object', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = { type: 'json_object', maxTokens: 32 };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ json_object: true, max_tokens: 32 }] }),
+        undefined,
+      );
+    });
+  });
+
+  describe('typescript', () => {
+    it('should pass the TypeScript source and root type', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'typescript',
+        source: 'interface Foo { name: string; age: number }',
+        rootType: 'Foo',
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            { typescript: 'interface Foo { name: string; age: number }', root_type: 'Foo' },
+          ],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('openapi', () => {
+    it('should pass the document and operation selection', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const document = { openapi: '3.1.0', paths: {} };
+      const grammar: Grammar = {
+        type: 'openapi',
+        document,
+        operationId: 'createPet',
+        target: 'requestBody',
+        mediaType: 'application/json',
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            {
+              openapi: document,
+              operation_id: 'createPet',
+              target: 'request_body',
+              media_type: 'application/json',
+            },
+          ],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('xml', () => {
+    it('should convert the element tree to snake_case', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'xml',
+        element: {
+          name: 'list',
+          attributes: [{ name: 'id', type: 'integer', required: true }],
+          children: [{ name: 'item', text: 'string', minOccurs: 0, maxOccurs: 'unbounded' }],
+        },
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [
+            {
+              xml: {
+                name: 'list',
+                attributes: [{ name: 'id', type: 'integer', required: true }],
+                children: [
+                  { name: 'item', text: 'string', min_occurs: 0, max_occurs: 'unbounded' },
+                ],
+              },
+            },
+          ],
+        }),
+        undefined,
+      );
+    });
+
+    it('should pass an XSD schema and root element', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const xsd = '<xs:schema><xs:element name="a" type="xs:string"/></xs:schema>';
+      await GuidanceParser.fromVocab(tokens, 0, { type: 'xml', xsd, rootElement: 'a' });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ xml: { xsd, root_element: 'a' } }] }),
+        undefined,
+      );
+    });
+  });
+
+  describe('yaml output', () => {
+    it('should pass the output format of a JSON schema', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const schema = { type: 'object', properties: { name: { type: 'string' } } };
+      await GuidanceParser.fromVocab(tokens, 0, { type: 'json_schema', schema, output: 'yaml' });
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ json_schema: schema, format: 'yaml' }] }),
+        undefined,
+      );
+    });
+  });
+
+  describe('preset', () => {
+    it('should pass the SQL preset options', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = {
+        type: 'preset',
+        preset: 'sql',
+        dialect: 'sqlite',
+        statements: ['select'],
+      };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({
+          grammars: [{ preset: 'sql', dialect: 'sqlite', statements: ['select'] }],
+        }),
+        undefined,
+      );
+    });
+  });
+
+  describe('regex', () => {
+    it('should pass the source and flags of a RegExp', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = { type: 'regex', pattern: /^(?<id>\d+)\/x$/i };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ rx: '^(?<id>\\d+)\\/x$', flags: 'i' }] }),
+        undefined,
+      );
+    });
+
+    it('should let explicit flags override those of the RegExp', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const tokens = [new TextEncoder().encode('a')];
+      const grammar: Grammar = { type: 'regex', pattern: /a.b/is, flags: 's' };
+      await GuidanceParser.fromVocab(tokens, 0, grammar);
+      expect(wasm.LLGuidanceParser.from_vocab).toHaveBeenLastCalledWith(
+        tokens,
+        0,
+        JSON.stringify({ grammars: [{ rx: 'a.b', flags: 's' }] }),
+        undefined,
+      );
+    });
+  });
+
+  describe('validateGrammar()', () => {
+    it('should compile the converted grammar without a tokenizer', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const result = await GuidanceParser.validateGrammar({ type: 'regex', pattern: '[a-z]+' });
+      expect(wasm.LLGuidanceParser.validate_grammar).toHaveBeenLastCalledWith(
+        JSON.stringify({ grammars: [{ rx: '[a-z]+' }] }),
+      );
+      expect(result).toEqual({ ok: true, errors: [], warnings: ['ignored keyword'] });
+    });
+
+    it('should report a grammar that cannot be converted as an error', async () => {
+      const resolveRef = vi.fn(async () => {
+        throw new Error('offline');
+      });
+      const grammar: Grammar = {
+        type: 'json_schema',
+        schema: { $ref: 'https://example.com/schema.json' },
+      };
+      const result = await GuidanceParser.validateGrammar(grammar, { resolveRef });
+      expect(result.ok).toBe(false);
+      expect(result.errors).toHaveLength(1);
+      expect(result.errors[0]).toContain('offline');
+    });
+  });
+
+  describe('compileGrammar()', () => {
+    it('should compile the converted grammar and load the blob with a tokenizer', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const compiled = await GuidanceParser.compileGrammar(regexGrammar);
+      expect(wasm.LLGuidanceParser.compile_grammar).toHaveBeenLastCalledWith(
+        JSON.stringify({ grammars: [{ rx: '[a-z]+' }] }),
+      );
+
+      const parser = await GuidanceParser.fromCompiledGrammar(compiled, mockTokenizer, {
+        excludeSpecialTokens: true,
+      });
+      expect(parser).toBeInstanceOf(GuidanceParser);
+      expect(wasm.LLGuidanceParser.from_compiled_grammar).toHaveBeenLastCalledWith(
+        compiled,
+        JSON.stringify(mockTokenizer),
+        JSON.stringify({ exclude_special_tokens: true }),
+      );
+    });
+  });
+
+  describe('analyzeGrammar()', () => {
+    it('should analyze the converted grammar and camelCase the report', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      const grammar: Grammar = { type: 'lark', grammar: 'start: expr' };
+      const report = await GuidanceParser.analyzeGrammar(grammar);
+      expect(wasm.LLGuidanceParser.analyze_grammar).toHaveBeenLastCalledWith(
+        JSON.stringify({ grammars: [{ lark: 'start: expr', start: 'start' }] }),
+      );
+      expect(report).toEqual({
+        rules: 2,
+        terminals: 1,
+        lexemes: 3,
+        lexerStates: 12,
+        recursiveRules: ['expr'],
+        hints: [],
+        costClass: 'medium',
+      });
+    });
+  });
+
+  describe('registerGrammar()', () => {
+    it('should register the converted grammar and pass names through as is', async () => {
+      const wasm = await import('../pkg/llguidance_wasm.js');
+      await GuidanceParser.registerGrammar('invoice_v2', regexGrammar);
+      expect(wasm.register_grammar).toHaveBeenLastCalledWith(
+        'invoice_v2',
+        JSON.stringify({ grammars: [{ rx: '[a-z]+' }] }),
+      );
+
+      const parser = await GuidanceParser.create('@invoice_v2', mockTokenizer);
+      const wasmParser = (parser as unknown as { wasmParser: { reset: ReturnType<typeof vi.fn> } })
+        .wasmParser;
+      parser.reset('@invoice_v2');
+      expect(wasmParser.reset).toHaveBeenLastCalledWith('@invoice_v2');
+
+      expect(await GuidanceParser.unregisterGrammar('invoice_v2')).toBe(true);
+      expect(wasm.unregister_grammar).toHaveBeenLastCalledWith('invoice_v2');
+    });
+  });
+
+  describe('stopReason()', () => {
+    it('should return the stop reason', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.stopReason()).toBe('NotStopped');
+    });
+  });
+
+  describe('mustStop()', () => {
+    it('should return whether only EOS is allowed', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.mustStop()).toBe(false);
+    });
+  });
+
+  describe('canAdvance()', () => {
+    it('should return whether any token is allowed', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.canAdvance()).toBe(true);
+    });
+  });
+
+  describe('enableTokenHealing()', () => {
+    it('should report whether the prompt token was rolled back', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.enableTokenHealing(3)).toBe(true);
+    });
+  });
+
+  describe('saveState() / loadState()', () => {
+    it('should pass the snapshot through', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const state = parser.saveState();
+      expect(Array.from(state.subarray(0, 4))).toEqual([0x4c, 0x4c, 0x47, 0x53]);
+      expect(() => parser.loadState(state)).not.toThrow();
+    });
+  });
+
+  describe('diagnose()', () => {
+    it('should return a camelCase report', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const report = parser.diagnose();
+      expect(report.vocabSize).toBe(100);
+      expect(report.allowedTokens).toBe(0);
+      expect(report.unreachableBytes).toEqual([10]);
+      expect(report.warnings).toHaveLength(1);
+    });
+  });
+
+  describe('diagnoseAmbiguity()', () => {
+    it('should return a camelCase report', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const report = parser.diagnoseAmbiguity();
+      expect(report.ambiguousRules).toHaveLength(1);
+      expect(report.overlappingTerminals[0].terminals).toEqual(['IDENT', '"if"']);
+      expect(report.competingTokens).toEqual([{ token: '"if"', lexemes: ['IDENT', '"if"'] }]);
+      expect(report.truncated).toBe(false);
+    });
+  });
+
+  describe('maskStats()', () => {
+    it('should return camelCase mask statistics', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.maskStats()).toEqual({
+        allowedTokens: 4,
+        vocabSize: 100,
+        coverage: 0.04,
+        entropyBits: 2,
+        eosAllowed: false,
+      });
+    });
+  });
+
+  describe('getTelemetry()', () => {
+    it('should return camelCase telemetry', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.getTelemetry()).toEqual({
+        steps: 3,
+        forcedSteps: 1,
+        maskComputations: 3,
+        averageAllowedTokens: 12.5,
+        maskTimeMs: 0.75,
+      });
+    });
+  });
+
+  describe('lastStepStats()', () => {
+    it('should return camelCase step stats', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.lastStepStats()).toEqual({
+        maskTimeUs: 120.5,
+        allowedTokens: 7,
+        advanceTimeUs: 30,
+        earleyRows: 2,
+        earleyItems: 9,
+        lexerStates: 14,
+        slicesApplied: 1,
+        trieNodesWalked: 40,
+        lexerCost: 250,
+      });
+    });
+  });
+
+  describe('grammarWarnings', () => {
+    it('should return the grammar compilation warnings', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.grammarWarnings).toEqual(['unsupported keyword: format']);
+    });
+  });
+
+  describe('tokenizerWarnings()', () => {
+    it('should return the tokenizer warnings', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.tokenizerWarnings()).toEqual([]);
+    });
+  });
+
+  describe('tokenize()', () => {
+    it('should return token IDs', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.tokenize('hello world'))).toEqual([0, 3, 1]);
+    });
+  });
+
+  describe('detokenize()', () => {
+    it('should decode token IDs to text', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.detokenize([0, 3, 1])).toBe('hello world');
+    });
+  });
+
+  describe('tokenBytes()', () => {
+    it('should return the token bytes', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(Array.from(parser.tokenBytes(0))).toEqual([104, 105]);
+    });
+  });
+
+  describe('tokenDisplay()', () => {
+    it('should return a printable token', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      expect(parser.tokenDisplay(0)).toBe('"hi"');
+    });
+  });
+
   describe('reset()', () => {
     it('should reset parser state without error', async () => {
       const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
@@ -114,5 +1427,122 @@ describe('GuidanceParser', () => {
       expect(() => parser.reset()).not.toThrow();
     });
   });
+
+  describe('pushGrammar()', () => {
+    it('should pass the converted grammar and pop it again', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const wasmParser = (
+        parser as unknown as {
+          wasmParser: Record<'push_grammar' | 'pop_grammar', ReturnType<typeof vi.fn>>;
+        }
+      ).wasmParser;
+
+      parser.pushGrammar({ type: 'lark', grammar: 'start: "{}"' });
+      expect(wasmParser.push_grammar).toHaveBeenLastCalledWith(
+        JSON.stringify({ grammars: [{ lark: 'start: "{}"', start: 'start' }] }),
+      );
+      expect(parser.grammarDepth).toBe(1);
+
+      parser.popGrammar();
+      expect(wasmParser.pop_grammar).toHaveBeenCalledTimes(1);
+    });
+  });
+
+  describe('label option', () => {
+    it('should prefix WASM errors with the label', () => {
+      const parser = GuidanceParser.fromWasm(
+        {
+          advance: () => {
+            throw 'Token ID 9 is not allowed';
+          },
+          free: vi.fn(),
+        },
+        'worker-3',
+      );
+      expect(() => parser.advance(9)).toThrow('[worker-3] Token ID 9 is not allowed');
+
+      parser.free();
+      expect(() => parser.advance(0)).toThrow('[worker-3] GuidanceParser used after free()');
+    });
+
+    it('should prefix warnings and carry over to forks', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer, { label: 'a' });
+      expect(parser.grammarWarnings).toEqual(['[a] unsupported keyword: format']);
+      expect(parser.getTokenMask().length).toBe(100);
+
+      const fork = parser.fork();
+      fork.free();
+      expect(() => fork.getTokenMask()).toThrow('[a] GuidanceParser used after free()');
+    });
+  });
+
+  describe('free()', () => {
+    it('should be idempotent and make later calls throw', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      parser.free();
+      expect(() => parser.free()).not.toThrow();
+      expect(() => parser.dispose()).not.toThrow();
+      expect(() => parser.getTokenMask()).toThrow('GuidanceParser used after free()');
+    });
+
+    it('should leave forks usable', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const fork = parser.fork();
+      parser.free();
+      expect(fork.vocabSize).toBe(100);
+    });
+  });
+
+  describe('GuidanceState', () => {
+    it('should step to a new state without changing the old one', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const initial = GuidanceState.initial(parser);
+      expect(initial.mask.length).toBe(100);
+
+      const { state, mask } = initial.step(0);
+      expect(state).not.toBe(initial);
+      expect(mask).toBe(state.mask);
+
+      type Internals = { parser: { wasmParser: { advance: ReturnType<typeof vi.fn> } } };
+      const wasmParser = (initial as unknown as Internals).parser.wasmParser;
+      expect(wasmParser.advance).not.toHaveBeenCalled();
+      expect(() => initial.step(1)).not.toThrow();
+    });
+
+    it('should free the new parser when a step is rejected', async () => {
+      const parser = await GuidanceParser.create(regexGrammar, mockTokenizer);
+      const initial = GuidanceState.initial(parser);
+      const fork = vi.spyOn(GuidanceParser.prototype, 'fork');
+      const advance = vi.spyOn(GuidanceParser.prototype, 'advance').mockImplementationOnce(() => {
+        throw new Error('Token ID 99 is not allowed');
+      });
+
+      expect(() => initial.step(99)).toThrow('not allowed');
+      expect(() => fork.mock.results[0].value.getTokenMask()).toThrow('used after free()');
+      fork.mockRestore();
+      advance.mockRestore();
+    });
+  });
+
+  describe('GuidanceFactory', () => {
+    it('should compile grammars and create parsers from them', async () => {
+      const factory = await GuidanceFactory.create(mockTokenizer);
+      const regexRef = factory.compileGrammar(regexGrammar);
+      const larkRef = factory.compileGrammar(larkGrammar);
+      expect(regexRef).toBe(0);
+      expect(larkRef).toBe(1);
+      expect(factory.grammarCount).toBe(2);
+
+      const parser = factory.createParser(larkRef);
+      expect(parser).toBeInstanceOf(GuidanceParser);
+      expect(parser.vocabSize).toBe(100);
+    });
+
+    it('should throw after free()', async () => {
+      const factory = await GuidanceFactory.create(mockTokenizer);
+      factory.free();
+      expect(() => factory.compileGrammar(regexGrammar)).toThrow('GuidanceFactory used after free()');
+    });
+  });
 });
 
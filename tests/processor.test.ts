@@ -100,6 +100,36 @@ describe('GuidanceLogitsProcessor', () => {
       expect(result[5]).toBe(-Infinity);
       expect(result[0]).toBe(-Infinity);
     });
+
+    it('should speculate on token IDs beyond 65,535', () => {
+      const isAllowedMock = mockParser.isTokenAllowed as ReturnType<typeof vi.fn>;
+      isAllowedMock.mockImplementation((tokenId: number) => tokenId === 200_000);
+
+      const logits = new Float32Array(256_000).fill(-10);
+      logits[255_999] = 10;
+      logits[200_000] = 5;
+      logits[70_000] = 3;
+
+      const result = processor.process([], logits);
+
+      expect(isAllowedMock.mock.calls.map(([id]) => id)).toEqual([255_999, 200_000]);
+      expect(result[200_000]).toBe(5);
+      expect(result[255_999]).toBe(-Infinity);
+    });
+
+    it('should ban logits past the end of the mask', () => {
+      (mockParser.isTokenAllowed as ReturnType<typeof vi.fn>).mockReturnValue(false);
+      (mockParser.getTokenMask as ReturnType<typeof vi.fn>).mockReturnValue(
+        new Uint8Array(100).fill(1),
+      );
+
+      const logits = new Float32Array(128).fill(1);
+      const result = processor.process([], logits);
+
+      expect(result[99]).toBe(1);
+      expect(result[100]).toBe(-Infinity);
+      expect(result[127]).toBe(-Infinity);
+    });
   });
 
   describe('onToken()', () => {
@@ -0,0 +1,84 @@
+import { describe, it, expect, vi } from 'vitest';
+import { resolveGrammarRefs, resolveSchemaRefs } from '../src/refs';
+
+describe('external $ref resolution', () => {
+  it('should inline a referenced document into $defs', async () => {
+    const resolveRef = vi.fn(async () => ({
+      $id: 'https://example.com/address.json',
+      type: 'object',
+      properties: { city: { $ref: '#/$defs/city' } },
+      $defs: { city: { type: 'string' } },
+    }));
+    const schema = await resolveSchemaRefs(
+      { type: 'object', properties: { home: { $ref: 'https://example.com/address.json' } } },
+      resolveRef,
+    );
+    expect(resolveRef).toHaveBeenCalledWith('https://example.com/address.json');
+    expect(schema).toEqual({
+      type: 'object',
+      properties: { home: { $ref: '#/$defs/external_0' } },
+      $defs: {
+        external_0: {
+          type: 'object',
+          properties: { city: { $ref: '#/$defs/external_0/$defs/city' } },
+          $defs: { city: { type: 'string' } },
+        },
+      },
+    });
+  });
+
+  it('should resolve relative refs and fetch each document once', async () => {
+    const documents: Record<string, Record<string, unknown>> = {
+      'https://example.com/a.json': { properties: { b: { $ref: 'b.json' } } },
+      'https://example.com/b.json': { properties: { a: { $ref: 'a.json#/properties' } } },
+    };
+    const resolveRef = vi.fn((uri: string) => documents[uri]);
+    const schema = await resolveSchemaRefs(
+      { $id: 'https://example.com/root.json', $ref: 'a.json' },
+      resolveRef,
+    );
+    // a.json and b.json reference each other, but each is fetched once
+    expect(resolveRef).toHaveBeenCalledTimes(2);
+    expect(schema.$ref).toBe('#/$defs/external_0');
+    expect(schema.$defs).toEqual({
+      external_0: { properties: { b: { $ref: '#/$defs/external_1' } } },
+      external_1: { properties: { a: { $ref: '#/$defs/external_0/properties' } } },
+    });
+  });
+
+  it('should leave schemas without external refs unchanged', async () => {
+    const schema = { type: 'object', $defs: { x: { type: 'string' } }, $ref: '#/$defs/x' };
+    const resolveRef = vi.fn();
+    expect(await resolveSchemaRefs(schema, resolveRef)).toBe(schema);
+    expect(resolveRef).not.toHaveBeenCalled();
+  });
+
+  it('should reject anchor fragments', async () => {
+    await expect(
+      resolveSchemaRefs({ $ref: 'https://example.com/a.json#anchor' }, () => ({})),
+    ).rejects.toThrow('JSON pointer');
+  });
+
+  it('should resolve schemas nested in other grammars', async () => {
+    const grammar = await resolveGrammarRefs(
+      {
+        type: 'sequence',
+        sequence: [
+          { type: 'text_until', delimiter: '</think>' },
+          { type: 'json_schema', schema: { $ref: 'https://example.com/answer.json' } },
+        ],
+      },
+      () => ({ type: 'string' }),
+    );
+    expect(grammar).toEqual({
+      type: 'sequence',
+      sequence: [
+        { type: 'text_until', delimiter: '</think>' },
+        {
+          type: 'json_schema',
+          schema: { $ref: '#/$defs/external_0', $defs: { external_0: { type: 'string' } } },
+        },
+      ],
+    });
+  });
+});
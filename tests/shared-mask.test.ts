@@ -0,0 +1,31 @@
+import { describe, it, expect } from 'vitest';
+import {
+  createSharedMaskBuffer,
+  publishSharedMask,
+  sharedMaskGeneration,
+  sharedMaskView,
+  SHARED_MASK_HEADER_BYTES,
+} from '../src/shared-mask';
+
+describe('shared mask slots', () => {
+  it('should allocate a slot with room for the header and mask', () => {
+    const buffer = createSharedMaskBuffer(10);
+    expect(buffer.byteLength).toBe(SHARED_MASK_HEADER_BYTES + 10);
+    expect(sharedMaskView(buffer).length).toBe(10);
+    expect(sharedMaskGeneration(buffer)).toBe(0);
+  });
+
+  it('should bump the generation on publish', () => {
+    const buffer = createSharedMaskBuffer(4);
+    sharedMaskView(buffer).set([1, 0, 1, 0]);
+    expect(publishSharedMask(buffer)).toBe(1);
+    expect(publishSharedMask(buffer)).toBe(2);
+    expect(sharedMaskGeneration(buffer)).toBe(2);
+  });
+
+  it('should share the mask bytes between views', () => {
+    const buffer = createSharedMaskBuffer(3);
+    sharedMaskView(buffer)[1] = 1;
+    expect(Array.from(sharedMaskView(buffer))).toEqual([0, 1, 0]);
+  });
+});
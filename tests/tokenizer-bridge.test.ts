@@ -1,5 +1,9 @@
 import { describe, it, expect, vi } from 'vitest';
-import { extractTokenizerData, type TransformersTokenizer } from '../src/tokenizer-bridge';
+import {
+  createCallbackTokenizer,
+  extractTokenizerData,
+  type TransformersTokenizer,
+} from '../src/tokenizer-bridge';
 
 describe('extractTokenizerData', () => {
   describe('with getVocab method', () => {
@@ -138,6 +142,30 @@ describe('extractTokenizerData', () => {
       expect(result.model_type).toBe('bpe');
     });
 
+    it('should detect Unigram tokenizer from scores', () => {
+      const mockTokenizer: TransformersTokenizer = {
+        vocab: { '<unk>': 0, '▁a': 1 },
+        model: {
+          scores: [0, -1.5],
+        },
+      };
+
+      const result = extractTokenizerData(mockTokenizer);
+
+      expect(result.model_type).toBe('unigram');
+      expect(result.scores).toEqual([0, -1.5]);
+    });
+
+    it('should detect WordPiece tokenizer from continuation pieces', () => {
+      const mockTokenizer: TransformersTokenizer = {
+        vocab: { '[UNK]': 0, play: 1, '##ing': 2 },
+      };
+
+      const result = extractTokenizerData(mockTokenizer);
+
+      expect(result.model_type).toBe('wordpiece');
+    });
+
     it('should return unknown for tokenizers without merges', () => {
       const mockTokenizer: TransformersTokenizer = {
         vocab: { a: 0 },
@@ -150,3 +178,34 @@ describe('extractTokenizerData', () => {
   });
 });
 
+describe('createCallbackTokenizer', () => {
+  it('should wrap encode/decode without special tokens', () => {
+    const encode = vi.fn().mockReturnValue([1, 2]);
+    const decode = vi.fn().mockReturnValue('ab');
+    const mockTokenizer: TransformersTokenizer = {
+      vocab: { a: 0, b: 1, ab: 2, '</s>': 3 },
+      added_tokens: [{ id: 3, content: '</s>', special: true }],
+      encode,
+      decode,
+    };
+
+    const result = createCallbackTokenizer(mockTokenizer);
+
+    expect(result.vocabSize).toBe(4);
+    expect(result.eosTokenId).toBe(3);
+    expect(result.specialTokenIds).toEqual([3]);
+    expect(result.encode('ab')).toEqual([1, 2]);
+    expect(encode).toHaveBeenCalledWith('ab', { add_special_tokens: false });
+    expect(result.decode([2])).toBe('ab');
+  });
+
+  it('should throw without encode/decode methods', () => {
+    const mockTokenizer: TransformersTokenizer = {
+      vocab: { a: 0 },
+    };
+
+    expect(() => createCallbackTokenizer(mockTokenizer)).toThrow(
+      'Tokenizer must provide encode() and decode() methods',
+    );
+  });
+});
@@ -0,0 +1,78 @@
+import { describe, it, expect } from 'vitest';
+import { toolCallGrammar } from '../src/tools';
+import type { Tool } from '../src/types';
+
+const weather: Tool = {
+  type: 'function',
+  function: {
+    name: 'get_weather',
+    parameters: {
+      type: 'object',
+      properties: { location: { $ref: '#/$defs/location' } },
+      required: ['location'],
+      $defs: { location: { type: 'string' } },
+    },
+  },
+};
+const time: Tool = { type: 'function', function: { name: 'get_time' } };
+
+describe('toolCallGrammar()', () => {
+  it('should constrain the arguments to the named tool', () => {
+    expect(toolCallGrammar([weather, time])).toEqual({
+      type: 'json_schema',
+      schema: {
+        anyOf: [
+          {
+            type: 'object',
+            properties: {
+              name: { const: 'get_weather' },
+              arguments: { $ref: '#/$defs/get_weather' },
+            },
+            required: ['name', 'arguments'],
+            additionalProperties: false,
+          },
+          {
+            type: 'object',
+            properties: {
+              name: { const: 'get_time' },
+              arguments: { $ref: '#/$defs/get_time' },
+            },
+            required: ['name', 'arguments'],
+            additionalProperties: false,
+          },
+        ],
+        $defs: {
+          get_weather: {
+            type: 'object',
+            properties: { location: { $ref: '#/$defs/get_weather/$defs/location' } },
+            required: ['location'],
+            $defs: { location: { type: 'string' } },
+          },
+          get_time: { type: 'object', properties: {}, additionalProperties: false },
+        },
+      },
+    });
+  });
+
+  it('should wrap the call in tags', () => {
+    const grammar = toolCallGrammar([time], {
+      start: '<tool_call>\n',
+      end: '\n</tool_call>',
+      maxTokens: 100,
+    });
+    expect(grammar).toMatchObject({
+      type: 'sequence',
+      sequence: [
+        { type: 'lark', grammar: 'start: "<tool_call>\\n"' },
+        { type: 'json_schema' },
+        { type: 'lark', grammar: 'start: "\\n</tool_call>"' },
+      ],
+      maxTokens: 100,
+    });
+  });
+
+  it('should reject duplicate and missing tools', () => {
+    expect(() => toolCallGrammar([])).toThrow('at least one tool');
+    expect(() => toolCallGrammar([time, time])).toThrow('Tool get_time is defined more than once');
+  });
+});